@@ -2,10 +2,11 @@ use std::ops::{Add, Mul};
 
 use anyhow::Result;
 use cgmath::{Quaternion, Vector3};
+use serde::Deserialize;
 
 use super::*;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Animation {
     pub length_seconds: f32,
     pub speed: f32,
@@ -13,7 +14,7 @@ pub struct Animation {
     pub state: AnimationState,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Channel {
     pub node_id: GameNodeId,
     pub property: gltf::animation::Property,
@@ -29,7 +30,7 @@ pub struct AnimationState {
     pub loop_type: LoopType,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
 pub enum LoopType {
     Once,
     Wrap,
@@ -57,6 +58,7 @@ pub fn step_animations(scene: &mut Scene, delta_time_seconds: f32) -> Result<()>
         Translation(Vector3<f32>),
         Scale(Vector3<f32>),
         Rotation(Quaternion<f32>),
+        MorphTargetWeights(Vec<f32>),
     }
 
     let mut ops: Vec<(GameNodeId, Op)> = Vec::new();
@@ -109,7 +111,14 @@ pub fn step_animations(scene: &mut Scene, delta_time_seconds: f32) -> Result<()>
                     previous_key_frame,
                     next_key_frame,
                 )?)),
-                _ => None,
+                gltf::animation::Property::MorphTargetWeights => {
+                    Some(Op::MorphTargetWeights(get_weights_at_moment(
+                        channel,
+                        animation_time_seconds,
+                        previous_key_frame,
+                        next_key_frame,
+                    )?))
+                }
             } {
                 ops.push((channel.node_id, op));
             }
@@ -128,6 +137,9 @@ pub fn step_animations(scene: &mut Scene, delta_time_seconds: f32) -> Result<()>
                 Op::Rotation(rotation) => {
                     transform.set_rotation(rotation);
                 }
+                Op::MorphTargetWeights(weights) => {
+                    node.morph_target_weights = weights;
+                }
             }
         }
     }
@@ -287,6 +299,95 @@ fn get_quat_at_moment(
     })
 }
 
+// each keyframe holds one weight per morph target, or three per target for cubic splines
+fn get_weights_at_moment(
+    channel: &Channel,
+    animation_time_seconds: f32,
+    previous_keyframe: Option<KeyframeTime>,
+    next_keyframe: Option<KeyframeTime>,
+) -> Result<Vec<f32>> {
+    let keyframe_values: &[f32] = bytemuck::cast_slice(&channel.keyframe_values_u8);
+    let keyframe_count = channel.keyframe_timings.len();
+    let values_per_keyframe = keyframe_values.len() / keyframe_count;
+    let get_keyframe = |index: usize| {
+        &keyframe_values[(index * values_per_keyframe)..((index + 1) * values_per_keyframe)]
+    };
+
+    Ok(match channel.interpolation_type {
+        gltf::animation::Interpolation::CubicSpline => {
+            let target_count = values_per_keyframe / 3;
+            // in-tangents, then values, then out-tangents
+            let get_cubic_keyframe_values = |index: usize| {
+                let keyframe = get_keyframe(index);
+                (0..target_count)
+                    .map(|target_index| {
+                        [
+                            keyframe[target_index],
+                            keyframe[target_count + target_index],
+                            keyframe[2 * target_count + target_index],
+                        ]
+                    })
+                    .collect::<Vec<_>>()
+            };
+            match previous_keyframe {
+                Some(previous_keyframe) => {
+                    let (next_keyframe, interpolation_factor) = match next_keyframe {
+                        Some(next_keyframe) => (
+                            next_keyframe,
+                            (animation_time_seconds - previous_keyframe.time)
+                                / (next_keyframe.time - previous_keyframe.time),
+                        ),
+                        None => (previous_keyframe, 1.0),
+                    };
+                    let keyframe_length = next_keyframe.time - previous_keyframe.time;
+                    get_cubic_keyframe_values(previous_keyframe.index)
+                        .into_iter()
+                        .zip(get_cubic_keyframe_values(next_keyframe.index))
+                        .map(|(previous_keyframe_value, next_keyframe_value)| {
+                            do_cubic_interpolation(
+                                previous_keyframe_value,
+                                next_keyframe_value,
+                                keyframe_length,
+                                interpolation_factor,
+                            )
+                        })
+                        .collect()
+                }
+                None => get_cubic_keyframe_values(0)
+                    .iter()
+                    .map(|value| value[1])
+                    .collect(),
+            }
+        }
+        gltf::animation::Interpolation::Linear => match previous_keyframe {
+            Some(previous_keyframe) => {
+                let (next_keyframe, interpolation_factor) = match next_keyframe {
+                    Some(next_keyframe) => (
+                        next_keyframe,
+                        (animation_time_seconds - previous_keyframe.time)
+                            / (next_keyframe.time - previous_keyframe.time),
+                    ),
+                    None => (previous_keyframe, 1.0),
+                };
+                get_keyframe(previous_keyframe.index)
+                    .iter()
+                    .zip(get_keyframe(next_keyframe.index))
+                    .map(|(previous_weight, next_weight)| {
+                        previous_weight + (next_weight - previous_weight) * interpolation_factor
+                    })
+                    .collect()
+            }
+            None => get_keyframe(0).to_vec(),
+        },
+        gltf::animation::Interpolation::Step => get_keyframe(
+            previous_keyframe
+                .map(|keyframe| keyframe.index)
+                .unwrap_or(0),
+        )
+        .to_vec(),
+    })
+}
+
 fn get_nearby_keyframes(
     keyframe_times: &[f32],
     animation_time_seconds: f32,
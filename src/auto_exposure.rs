@@ -0,0 +1,264 @@
+use anyhow::Result;
+use wgpu::util::DeviceExt;
+
+// the range of the luminance histogram, in log2 units. anything darker lands in a bin that's ignored
+const MIN_LOG2_LUMINANCE: f32 = -8.0;
+const MAX_LOG2_LUMINANCE: f32 = 12.0;
+const HISTOGRAM_BIN_COUNT: usize = 256;
+const HISTOGRAM_WORKGROUP_SIZE: u32 = 16;
+
+#[derive(Debug, Copy, Clone)]
+pub struct AutoExposureSettings {
+    // the measured scene brightness is clamped to this range, in ev100
+    pub min_ev: f32,
+    pub max_ev: f32,
+    // per second, how quickly the exposure follows the scene when it gets brighter or darker
+    pub bright_adaptation_speed: f32,
+    pub dark_adaptation_speed: f32,
+    // in stops, added on top of the measured exposure
+    pub exposure_compensation_ev: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct AutoExposureConfigUniform {
+    min_log2_luminance: f32,
+    log2_luminance_range: f32,
+    min_ev: f32,
+    max_ev: f32,
+    bright_adaptation_speed: f32,
+    dark_adaptation_speed: f32,
+    frame_time_seconds: f32,
+    exposure_compensation: f32,
+}
+
+// measures the average luminance of the shading texture on the gpu and writes the matching exposure
+// into the tone mapping config, see auto_exposure.wgsl
+pub struct AutoExposure {
+    histogram_pipeline: wgpu::ComputePipeline,
+    average_pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    histogram_buffer: wgpu::Buffer,
+    state_buffer: wgpu::Buffer,
+    config_buffer: wgpu::Buffer,
+}
+
+impl AutoExposure {
+    pub fn new(
+        device: &wgpu::Device,
+        shading_texture_view: &wgpu::TextureView,
+        tone_mapping_config_buffer: &wgpu::Buffer,
+    ) -> Result<Self> {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Auto Exposure Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                std::fs::read_to_string("./src/shaders/auto_exposure.wgsl")?.into(),
+            ),
+        });
+
+        let storage_buffer_entry = |binding: u32| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                storage_buffer_entry(1),
+                storage_buffer_entry(2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_buffer_entry(4),
+            ],
+            label: Some("auto_exposure_bind_group_layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Auto Exposure Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let histogram_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Auto Exposure Histogram Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "build_histogram",
+        });
+        let average_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Auto Exposure Average Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "average_histogram",
+        });
+
+        let histogram_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Auto Exposure Histogram Buffer"),
+            contents: bytemuck::cast_slice(&[0u32; HISTOGRAM_BIN_COUNT]),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        // the ev100 the exposure has adapted to so far and a flag that's set once the first frame was
+        // measured. starts zeroed so the first measurement is used right away, reset does the same
+        let state_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Auto Exposure State Buffer"),
+            contents: bytemuck::cast_slice(&[0u32; 2]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let config_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Auto Exposure Config Buffer"),
+            contents: &[0u8; std::mem::size_of::<AutoExposureConfigUniform>()],
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group = Self::make_bind_group(
+            device,
+            &bind_group_layout,
+            shading_texture_view,
+            &histogram_buffer,
+            &state_buffer,
+            &config_buffer,
+            tone_mapping_config_buffer,
+        );
+
+        Ok(Self {
+            histogram_pipeline,
+            average_pipeline,
+            bind_group_layout,
+            bind_group,
+            histogram_buffer,
+            state_buffer,
+            config_buffer,
+        })
+    }
+
+    // the shading texture gets recreated when the window or the render scale changes
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        shading_texture_view: &wgpu::TextureView,
+        tone_mapping_config_buffer: &wgpu::Buffer,
+    ) {
+        self.bind_group = Self::make_bind_group(
+            device,
+            &self.bind_group_layout,
+            shading_texture_view,
+            &self.histogram_buffer,
+            &self.state_buffer,
+            &self.config_buffer,
+            tone_mapping_config_buffer,
+        );
+    }
+
+    // forget the adapted exposure so the next measurement is used right away instead of being faded into
+    pub fn reset(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.state_buffer, 0, bytemuck::cast_slice(&[0u32; 2]));
+    }
+
+    pub fn update(
+        &self,
+        queue: &wgpu::Queue,
+        settings: &AutoExposureSettings,
+        frame_time_seconds: f32,
+    ) {
+        queue.write_buffer(
+            &self.config_buffer,
+            0,
+            bytemuck::cast_slice(&[AutoExposureConfigUniform {
+                min_log2_luminance: MIN_LOG2_LUMINANCE,
+                log2_luminance_range: MAX_LOG2_LUMINANCE - MIN_LOG2_LUMINANCE,
+                min_ev: settings.min_ev,
+                max_ev: settings.max_ev,
+                bright_adaptation_speed: settings.bright_adaptation_speed,
+                dark_adaptation_speed: settings.dark_adaptation_speed,
+                frame_time_seconds,
+                exposure_compensation: 2.0f32.powf(settings.exposure_compensation_ev),
+            }]),
+        );
+    }
+
+    // must run after the shading texture is complete and before the tone mapping pass
+    pub fn dispatch(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        (shading_texture_width, shading_texture_height): (u32, u32),
+    ) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Auto Exposure Encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Auto Exposure Compute Pass"),
+            });
+            compute_pass.set_bind_group(0, &self.bind_group, &[]);
+            compute_pass.set_pipeline(&self.histogram_pipeline);
+            compute_pass.dispatch_workgroups(
+                (shading_texture_width + HISTOGRAM_WORKGROUP_SIZE - 1) / HISTOGRAM_WORKGROUP_SIZE,
+                (shading_texture_height + HISTOGRAM_WORKGROUP_SIZE - 1) / HISTOGRAM_WORKGROUP_SIZE,
+                1,
+            );
+            compute_pass.set_pipeline(&self.average_pipeline);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    fn make_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        shading_texture_view: &wgpu::TextureView,
+        histogram_buffer: &wgpu::Buffer,
+        state_buffer: &wgpu::Buffer,
+        config_buffer: &wgpu::Buffer,
+        tone_mapping_config_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(shading_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: histogram_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: state_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: config_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: tone_mapping_config_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("auto_exposure_bind_group"),
+        })
+    }
+}
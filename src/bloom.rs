@@ -0,0 +1,271 @@
+use anyhow::Result;
+
+const BLOOM_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const MAX_BLOOM_MIP_COUNT: u32 = 8;
+// the smallest mip is kept at least this big so the 13 tap downsample doesn't just smear the edges
+const MIN_BLOOM_MIP_SIZE: u32 = 4;
+
+#[derive(Debug, Copy, Clone)]
+pub struct BloomSettings {
+    // 0 to 1, how much the wider, lower resolution mips contribute to the final glow
+    pub radius: f32,
+    // 0 to 1, the fraction of the shaded image that's replaced by the bloom
+    pub intensity: f32,
+}
+
+// blurs the shading texture by downsampling it into a mip chain and upsampling it back up.
+// there's no threshold, the result is mixed with the shaded image by the tone mapping pass
+pub struct Bloom {
+    downsample_first_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+    // views into a single mip chain texture, mip 0 is half of the shading texture's resolution
+    mip_views: Vec<wgpu::TextureView>,
+    // one per mip, samples only that mip
+    mip_bind_groups: Vec<wgpu::BindGroup>,
+    sampler: wgpu::Sampler,
+}
+
+impl Bloom {
+    pub fn new(
+        device: &wgpu::Device,
+        single_texture_bind_group_layout: &wgpu::BindGroupLayout,
+        shading_texture_size: (u32, u32),
+    ) -> Result<Self> {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Bloom Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                std::fs::read_to_string("./src/shaders/bloom.wgsl")?.into(),
+            ),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Pipeline Layout"),
+            bind_group_layouts: &[single_texture_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let make_pipeline = |label: &str, entry_point: &str, blend: Option<wgpu::BlendState>| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: BLOOM_TEXTURE_FORMAT,
+                        blend,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let downsample_first_pipeline = make_pipeline(
+            "Bloom Downsample First Pipeline",
+            "downsample_first_fs_main",
+            None,
+        );
+        let downsample_pipeline =
+            make_pipeline("Bloom Downsample Pipeline", "downsample_fs_main", None);
+        // lerps between the larger mip and the upsampled smaller one by the blend constant (the radius),
+        // which keeps the total energy the same at every level
+        let upsample_blend_component = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Constant,
+            dst_factor: wgpu::BlendFactor::OneMinusConstant,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let upsample_pipeline = make_pipeline(
+            "Bloom Upsample Pipeline",
+            "upsample_fs_main",
+            Some(wgpu::BlendState {
+                color: upsample_blend_component,
+                alpha: upsample_blend_component,
+            }),
+        );
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let (mip_views, mip_bind_groups) = Self::make_mip_chain(
+            device,
+            single_texture_bind_group_layout,
+            &sampler,
+            shading_texture_size,
+        );
+
+        Ok(Self {
+            downsample_first_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            mip_views,
+            mip_bind_groups,
+            sampler,
+        })
+    }
+
+    // the shading texture gets recreated when the window or the render scale changes
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        single_texture_bind_group_layout: &wgpu::BindGroupLayout,
+        shading_texture_size: (u32, u32),
+    ) {
+        let (mip_views, mip_bind_groups) = Self::make_mip_chain(
+            device,
+            single_texture_bind_group_layout,
+            &self.sampler,
+            shading_texture_size,
+        );
+        self.mip_views = mip_views;
+        self.mip_bind_groups = mip_bind_groups;
+    }
+
+    // the blurred result, to be sampled by the tone mapping pass
+    pub fn output_view(&self) -> &wgpu::TextureView {
+        &self.mip_views[0]
+    }
+
+    pub fn output_sampler(&self) -> &wgpu::Sampler {
+        &self.sampler
+    }
+
+    // must run after the shading texture is complete and before the tone mapping pass
+    pub fn render(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        shading_texture_bind_group: &wgpu::BindGroup,
+        settings: &BloomSettings,
+    ) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Bloom Encoder"),
+        });
+
+        for (mip, mip_view) in self.mip_views.iter().enumerate() {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Bloom Downsample Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: mip_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            if mip == 0 {
+                render_pass.set_pipeline(&self.downsample_first_pipeline);
+                render_pass.set_bind_group(0, shading_texture_bind_group, &[]);
+            } else {
+                render_pass.set_pipeline(&self.downsample_pipeline);
+                render_pass.set_bind_group(0, &self.mip_bind_groups[mip - 1], &[]);
+            }
+            render_pass.draw(0..3, 0..1);
+        }
+
+        let radius = settings.radius as f64;
+        for mip in (1..self.mip_views.len()).rev() {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Bloom Upsample Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.mip_views[mip - 1],
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&self.upsample_pipeline);
+            render_pass.set_blend_constant(wgpu::Color {
+                r: radius,
+                g: radius,
+                b: radius,
+                a: radius,
+            });
+            render_pass.set_bind_group(0, &self.mip_bind_groups[mip], &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    fn make_mip_chain(
+        device: &wgpu::Device,
+        single_texture_bind_group_layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        (shading_texture_width, shading_texture_height): (u32, u32),
+    ) -> (Vec<wgpu::TextureView>, Vec<wgpu::BindGroup>) {
+        let size = wgpu::Extent3d {
+            width: (shading_texture_width / 2).max(1),
+            height: (shading_texture_height / 2).max(1),
+            depth_or_array_layers: 1,
+        };
+        let mut mip_count = 1;
+        while mip_count < MAX_BLOOM_MIP_COUNT
+            && (size.width.min(size.height) >> mip_count) >= MIN_BLOOM_MIP_SIZE
+        {
+            mip_count += 1;
+        }
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("bloom_mip_chain_texture"),
+            size,
+            mip_level_count: mip_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: BLOOM_TEXTURE_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        });
+        let mip_views: Vec<_> = (0..mip_count)
+            .map(|mip| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("bloom_mip_view"),
+                    base_mip_level: mip,
+                    mip_level_count: std::num::NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let mip_bind_groups = mip_views
+            .iter()
+            .map(|mip_view| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: single_texture_bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(mip_view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(sampler),
+                        },
+                    ],
+                    label: Some("bloom_mip_bind_group"),
+                })
+            })
+            .collect();
+        (mip_views, mip_bind_groups)
+    }
+}
@@ -80,10 +80,14 @@ impl GpuBuffer {
         &self.src
     }
 
-    pub fn _stride(&self) -> usize {
+    pub fn stride(&self) -> usize {
         self.stride
     }
 
+    pub fn usage(&self) -> wgpu::BufferUsages {
+        self.usage
+    }
+
     pub fn length(&self) -> usize {
         self.length
     }
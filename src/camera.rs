@@ -1,5 +1,5 @@
 use super::*;
-use cgmath::{Deg, Euler, InnerSpace, Matrix4, Quaternion, Rad, Vector3};
+use cgmath::{Deg, Euler, InnerSpace, Matrix4, Quaternion, Rad, Vector2, Vector3, Vector4};
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Camera {
@@ -28,6 +28,8 @@ pub struct ShaderCameraView {
     pub position: Vector3<f32>,
     pub near_plane_distance: f32,
     pub far_plane_distance: f32,
+    // in ndc, already applied to proj. zero unless temporal anti-aliasing is on
+    pub jitter: Vector2<f32>,
 }
 
 #[repr(C)]
@@ -39,7 +41,9 @@ pub struct CameraUniform {
     position: [f32; 4],
     near_plane_distance: f32,
     far_plane_distance: f32,
-    padding: [f32; 2], // TODO: remove
+    jitter: [f32; 2],
+    // unjittered, for the motion vectors
+    previous_view_proj: [[f32; 4]; 4],
 }
 
 impl CameraUniform {
@@ -51,9 +55,16 @@ impl CameraUniform {
             position: [0.0; 4],
             near_plane_distance: 0.0,
             far_plane_distance: 0.0,
-            padding: [0.0; 2],
+            jitter: [0.0; 2],
+            previous_view_proj: Matrix4::one().into(),
         }
     }
+
+    // by default the previous view is the same as the current one, so there's no camera motion
+    pub fn with_previous_view(mut self, previous_view: ShaderCameraView) -> Self {
+        self.previous_view_proj = previous_view.unjittered_view_proj().into();
+        self
+    }
 }
 
 impl ShaderCameraView {
@@ -64,14 +75,17 @@ impl ShaderCameraView {
         far_plane_distance: f32,
         fov_y: Rad<f32>,
         reverse_z: bool,
+        jitter: Vector2<f32>,
     ) -> Self {
-        let proj = make_perspective_proj_matrix(
-            near_plane_distance,
-            far_plane_distance,
-            fov_y,
-            aspect_ratio,
-            reverse_z,
-        );
+        // shifts the whole image by the jitter after the perspective divide
+        let proj = Matrix4::from_translation(jitter.extend(0.0))
+            * make_perspective_proj_matrix(
+                near_plane_distance,
+                far_plane_distance,
+                fov_y,
+                aspect_ratio,
+                reverse_z,
+            );
         let rotation_only_matrix = clear_translation_from_matrix(transform.matrix());
         let rotation_only_view = rotation_only_matrix.inverse_transform().unwrap();
         let view = transform.matrix().inverse_transform().unwrap();
@@ -83,29 +97,26 @@ impl ShaderCameraView {
             position,
             near_plane_distance,
             far_plane_distance,
+            jitter,
         }
-        // orthographic instead of perspective:
-        // build_directional_light_camera_view(
-        //     Vector3::new(-0.5, -0.5, 0.1).normalize(),
-        //     100.0,
-        //     100.0,
-        //     100.0,
-        // )
-        // .into()
+    }
+
+    pub fn unjittered_view_proj(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(-self.jitter.extend(0.0)) * self.proj * self.view
     }
 }
 
 impl From<ShaderCameraView> for CameraUniform {
-    fn from(
-        ShaderCameraView {
+    fn from(camera_view: ShaderCameraView) -> CameraUniform {
+        let ShaderCameraView {
             proj,
             view,
             rotation_only_view,
             position,
             near_plane_distance,
             far_plane_distance,
-        }: ShaderCameraView,
-    ) -> CameraUniform {
+            jitter,
+        } = camera_view;
         Self {
             proj: proj.into(),
             view: view.into(),
@@ -113,7 +124,8 @@ impl From<ShaderCameraView> for CameraUniform {
             position: [position.x, position.y, position.z, 1.0],
             near_plane_distance,
             far_plane_distance,
-            padding: [0.0; 2],
+            jitter: jitter.into(),
+            previous_view_proj: camera_view.unjittered_view_proj().into(),
         }
     }
 }
@@ -148,36 +160,114 @@ pub fn build_cubemap_face_camera_views(
             far_plane_distance,
             Deg(90.0).into(),
             reverse_z,
+            Vector2::new(0.0, 0.0),
         )
     })
     .collect()
 }
 
-pub fn build_directional_light_camera_view(
+// far distance of each cascade along the camera's view direction, the first cascade starts at near_plane_distance.
+// split_lambda blends between uniform splits (0.0) and logarithmic splits (1.0),
+// see https://developer.nvidia.com/gpugems/gpugems3/part-ii-light-and-shadows/chapter-10-parallel-split-shadow-maps-programmable-gpus
+pub fn build_shadow_cascade_splits(
+    near_plane_distance: f32,
+    far_plane_distance: f32,
+    cascade_count: u32,
+    split_lambda: f32,
+) -> Vec<f32> {
+    let n = near_plane_distance;
+    let f = far_plane_distance;
+    (1..=cascade_count)
+        .map(|cascade_index| {
+            let t = cascade_index as f32 / cascade_count as f32;
+            let uniform_split = n + (f - n) * t;
+            let log_split = n * (f / n).powf(t);
+            split_lambda * log_split + (1.0 - split_lambda) * uniform_split
+        })
+        .collect()
+}
+
+// fits an orthographic view around each slice of the camera frustum between consecutive splits.
+// the views are sized from a bounding sphere so they don't change size when the camera rotates, and the
+// centers are snapped to shadow map texels so the shadows don't shimmer when the camera moves
+#[allow(clippy::too_many_arguments)]
+pub fn build_directional_light_cascade_camera_views(
     direction: Vector3<f32>,
-    width: f32,
-    height: f32,
+    camera_transform: crate::transform::Transform,
+    aspect_ratio: f32,
+    fov_y: Rad<f32>,
+    near_plane_distance: f32,
+    splits: &[f32],
     depth: f32,
-) -> ShaderCameraView {
-    let proj = make_orthographic_proj_matrix(width, height, -depth / 2.0, depth / 2.0, false);
+    shadow_map_resolution: u32,
+) -> Vec<ShaderCameraView> {
+    let camera_matrix = camera_transform.matrix();
     let rotation_only_view = direction_vector_to_coordinate_frame_matrix(direction)
         .inverse_transform()
         .unwrap();
-    let view = rotation_only_view;
-    ShaderCameraView {
-        proj,
-        view,
-        rotation_only_view,
-        position: Vector3::new(0.0, 0.0, 0.0),
-        near_plane_distance: -depth / 2.0,
-        far_plane_distance: depth / 2.0,
-    }
+    let frustum_slice_corners = |near_distance: f32, far_distance: f32| {
+        let tan_half_fov_y = (fov_y.0 / 2.0).tan();
+        [near_distance, far_distance]
+            .into_iter()
+            .flat_map(move |distance| {
+                let half_height = distance * tan_half_fov_y;
+                let half_width = half_height * aspect_ratio;
+                [(-1.0, -1.0), (-1.0, 1.0), (1.0, -1.0), (1.0, 1.0)].map(|(x, y)| {
+                    (camera_matrix * Vector4::new(x * half_width, y * half_height, -distance, 1.0))
+                        .truncate()
+                })
+            })
+    };
+
+    std::iter::once(near_plane_distance)
+        .chain(splits.iter().copied())
+        .zip(splits.iter().copied())
+        .map(|(near_distance, far_distance)| {
+            let corners: Vec<_> = frustum_slice_corners(near_distance, far_distance).collect();
+            let center = corners
+                .iter()
+                .fold(Vector3::new(0.0, 0.0, 0.0), |acc, corner| acc + corner)
+                / corners.len() as f32;
+            let radius = corners
+                .iter()
+                .map(|corner| (corner - center).magnitude())
+                .fold(0.0, f32::max);
+            // round up so floating point error doesn't change the size from frame to frame
+            let radius = (radius * 16.0).ceil() / 16.0;
+
+            let texel_size = 2.0 * radius / shadow_map_resolution as f32;
+            let light_space_center = (rotation_only_view * center.extend(1.0)).truncate();
+            let snapped_light_space_center = Vector3::new(
+                (light_space_center.x / texel_size).floor() * texel_size,
+                (light_space_center.y / texel_size).floor() * texel_size,
+                light_space_center.z,
+            );
+            let view = Matrix4::from_translation(-snapped_light_space_center) * rotation_only_view;
+            let position = (rotation_only_view.inverse_transform().unwrap()
+                * snapped_light_space_center.extend(1.0))
+            .truncate();
+
+            ShaderCameraView {
+                proj: make_orthographic_proj_matrix(
+                    2.0 * radius,
+                    2.0 * radius,
+                    -depth / 2.0,
+                    depth / 2.0,
+                    false,
+                ),
+                view,
+                rotation_only_view,
+                position,
+                near_plane_distance: -depth / 2.0,
+                far_plane_distance: depth / 2.0,
+                jitter: Vector2::new(0.0, 0.0),
+            }
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
-    use cgmath::Vector4;
-
     use super::*;
 
     #[test]
@@ -198,4 +288,89 @@ mod tests {
         println!("{:?}", persp_div(reg_proj_pos));
         assert_eq!(true, true);
     }
+
+    #[test]
+    fn projection_jitter() {
+        let transform = TransformBuilder::new()
+            .position(Vector3::new(1.0, 2.0, 3.0))
+            .build();
+        let jitter = Vector2::new(0.25 / 1920.0, -0.5 / 1080.0);
+        let make_view = |jitter| {
+            ShaderCameraView::from_transform(
+                transform,
+                16.0 / 9.0,
+                0.1,
+                100.0,
+                Deg(60.0).into(),
+                true,
+                jitter,
+            )
+        };
+        let unjittered_view = make_view(Vector2::new(0.0, 0.0));
+        let jittered_view = make_view(jitter);
+
+        let world_position = Vector4::new(2.0, 1.5, -4.0, 1.0);
+        let ndc = |clip_position: Vector4<f32>| clip_position.truncate() / clip_position.w;
+        let unjittered_ndc = ndc(unjittered_view.proj * unjittered_view.view * world_position);
+        let jittered_ndc = ndc(jittered_view.proj * jittered_view.view * world_position);
+        assert!((jittered_ndc.x - unjittered_ndc.x - jitter.x).abs() < 1e-6);
+        assert!((jittered_ndc.y - unjittered_ndc.y - jitter.y).abs() < 1e-6);
+        // the depth isn't affected
+        assert!((jittered_ndc.z - unjittered_ndc.z).abs() < 1e-6);
+
+        let removed_jitter_ndc = ndc(jittered_view.unjittered_view_proj() * world_position);
+        assert!((removed_jitter_ndc - unjittered_ndc).magnitude() < 1e-6);
+    }
+
+    #[test]
+    fn shadow_cascade_splits() {
+        let uniform_splits = build_shadow_cascade_splits(1.0, 101.0, 4, 0.0);
+        assert_eq!(uniform_splits, vec![26.0, 51.0, 76.0, 101.0]);
+
+        let log_splits = build_shadow_cascade_splits(1.0, 10000.0, 4, 1.0);
+        for (split, expected) in log_splits.iter().zip([10.0, 100.0, 1000.0, 10000.0]) {
+            assert!((split - expected).abs() / expected < 0.0001);
+        }
+    }
+
+    #[test]
+    fn shadow_cascades_cover_the_view_frustum() {
+        let camera_transform = TransformBuilder::new()
+            .position(Vector3::new(3.0, 2.0, -7.0))
+            .rotation(make_quat_from_axis_angle(
+                Vector3::new(0.0, 1.0, 0.0),
+                Deg(30.0).into(),
+            ))
+            .build();
+        let fov_y: Rad<f32> = Deg(45.0).into();
+        let aspect_ratio = 16.0 / 9.0;
+        let near_plane_distance = 0.5;
+        let splits = build_shadow_cascade_splits(near_plane_distance, 100.0, 3, 0.75);
+        let views = build_directional_light_cascade_camera_views(
+            Vector3::new(0.3, -1.0, 0.2).normalize(),
+            camera_transform,
+            aspect_ratio,
+            fov_y,
+            near_plane_distance,
+            &splits,
+            1000.0,
+            2048,
+        );
+        assert_eq!(views.len(), 3);
+
+        // points on the far edge of every cascade should land inside of its shadow map
+        let half_texel = 1.0 / 2048.0;
+        for (view, split) in views.iter().zip(splits.iter()) {
+            let half_height = split * (fov_y.0 / 2.0).tan();
+            let half_width = half_height * aspect_ratio;
+            for (x, y) in [(-1.0, -1.0), (-1.0, 1.0), (1.0, -1.0), (1.0, 1.0)] {
+                let world_position = camera_transform.matrix()
+                    * Vector4::new(x * half_width, y * half_height, -split, 1.0);
+                let light_space_position = view.proj * view.view * world_position;
+                assert!(light_space_position.x.abs() <= 1.0 + 2.0 * half_texel);
+                assert!(light_space_position.y.abs() <= 1.0 + 2.0 * half_texel);
+                assert!((0.0..=1.0).contains(&light_space_position.z));
+            }
+        }
+    }
 }
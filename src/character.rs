@@ -115,12 +115,18 @@ impl Character {
         }
     }
 
-    pub fn handle_hit(&self, scene: &mut Scene, collider_handle: ColliderHandle) {
-        if let Some(bone_index) = self.collision_box_colliders.iter().enumerate().find_map(
+    pub fn has_collider(&self, collider_handle: ColliderHandle) -> bool {
+        self.collision_box_colliders.contains(&collider_handle)
+    }
+
+    // returns true if the collider belongs to this character
+    pub fn handle_hit(&self, scene: &mut Scene, collider_handle: ColliderHandle) -> bool {
+        let hit_bone_index = self.collision_box_colliders.iter().enumerate().find_map(
             |(bone_index, bone_collider_handle)| {
                 (*bone_collider_handle == collider_handle).then_some(bone_index)
             },
-        ) {
+        );
+        if let Some(bone_index) = hit_bone_index {
             if let Some(node) = scene.get_node_mut(self.collision_box_nodes[bone_index]) {
                 node.mesh = Some(GameNodeMesh {
                     mesh_indices: node
@@ -140,9 +146,21 @@ impl Character {
                         color: Vector3::new(1.0, 0.0, 0.0),
                     },
                     wireframe: true,
+                    cast_shadows: false,
+                    receive_shadows: false,
                 })
             }
         }
+        hit_bone_index.is_some()
+    }
+
+    pub fn destroy(&self, scene: &mut Scene, physics_state: &mut PhysicsState) {
+        for node_id in self.collision_box_nodes.iter().copied() {
+            scene.remove_node(node_id);
+        }
+        for collider_handle in self.collision_box_colliders.iter().copied() {
+            physics_state.remove_collider(collider_handle);
+        }
     }
 
     fn enable_collision_box_display(&mut self, scene: &mut Scene) {
@@ -166,6 +184,8 @@ impl Character {
                         color: Vector3::new(rand::random(), rand::random(), rand::random()),
                     },
                     wireframe: true,
+                    cast_shadows: false,
+                    receive_shadows: false,
                 })
             }
         }
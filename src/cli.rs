@@ -0,0 +1,311 @@
+use anyhow::{bail, Result};
+use clap::{ArgEnum, Parser};
+
+use super::*;
+
+#[derive(Debug, Parser)]
+#[clap(about = "wgpu sandbox renderer")]
+pub struct CliArgs {
+    /// Level description file
+    #[clap(long, default_value = DEFAULT_LEVEL_PATH)]
+    pub level: String,
+
+    /// glTF file to load in place of the level's base asset
+    #[clap(long)]
+    pub gltf: Option<String>,
+
+    /// Skybox preset to use in place of the level's skybox
+    #[clap(long, arg_enum)]
+    pub skybox: Option<SkyboxPreset>,
+
+    /// Equirectangular .hdr image to use as the skybox and environment map
+    #[clap(long)]
+    pub skybox_hdr: Option<String>,
+
+    /// Window width in physical pixels
+    #[clap(long, default_value_t = 1920)]
+    pub width: u32,
+
+    /// Window height in physical pixels
+    #[clap(long, default_value_t = 1080)]
+    pub height: u32,
+
+    /// Borderless fullscreen on the current monitor
+    #[clap(long)]
+    pub fullscreen: bool,
+
+    /// Pick an exclusive fullscreen video mode from a list at startup
+    #[clap(long)]
+    pub select_video_mode: bool,
+
+    /// Initial render scale, can be changed at runtime
+    #[clap(long, default_value_t = INITIAL_RENDER_SCALE)]
+    pub render_scale: f32,
+
+    /// Anti-aliasing mode, can be changed at runtime. Supersampling is done with --render-scale
+    #[clap(long, arg_enum, default_value = "none")]
+    pub anti_aliasing: AntiAliasingMode,
+
+    /// MSAA sample count for the meshes (1 or 4), limited to what the adapter supports
+    #[clap(long, default_value_t = INITIAL_MSAA_SAMPLE_COUNT)]
+    pub msaa: u32,
+
+    /// Draw the opaque meshes with GPU frustum culling and indirect draws
+    #[clap(long)]
+    pub gpu_driven: bool,
+
+    /// Initial tone mapping exposure, can be changed at runtime
+    #[clap(long, default_value_t = INITIAL_TONE_MAPPING_EXPOSURE)]
+    pub exposure: f32,
+
+    /// Start with shadows disabled
+    #[clap(long)]
+    pub no_shadows: bool,
+
+    /// Start with bloom disabled
+    #[clap(long)]
+    pub no_bloom: bool,
+
+    /// Tone mapping operator to use in place of the level's, can be changed at runtime
+    #[clap(long, arg_enum)]
+    pub tone_mapper: Option<ToneMappingOperator>,
+
+    /// .cube 3D LUT to use for color grading in place of the level's
+    #[clap(long)]
+    pub color_grading_lut: Option<String>,
+
+    /// How much the wider bloom mips contribute, from 0.0 to 1.0
+    #[clap(long, default_value_t = INITIAL_BLOOM_RADIUS)]
+    pub bloom_radius: f32,
+
+    /// Fraction of the shaded image that's replaced by bloom, from 0.0 to 1.0
+    #[clap(long, default_value_t = INITIAL_BLOOM_INTENSITY)]
+    pub bloom_intensity: f32,
+
+    /// Start with screen space ambient occlusion disabled
+    #[clap(long)]
+    pub no_ssao: bool,
+
+    /// World space distance at which geometry still occludes in the SSAO pass
+    #[clap(long, default_value_t = INITIAL_SSAO_RADIUS)]
+    pub ssao_radius: f32,
+
+    /// How dark fully occluded areas get with SSAO, from 0.0 to 1.0
+    #[clap(long, default_value_t = INITIAL_SSAO_INTENSITY)]
+    pub ssao_intensity: f32,
+
+    /// Start with automatic exposure disabled, the exposure is then set by --exposure
+    #[clap(long)]
+    pub no_auto_exposure: bool,
+
+    /// Lower bound of the scene brightness measured by auto exposure, in EV100
+    #[clap(long, default_value_t = INITIAL_AUTO_EXPOSURE_MIN_EV, allow_hyphen_values = true)]
+    pub auto_exposure_min_ev: f32,
+
+    /// Upper bound of the scene brightness measured by auto exposure, in EV100
+    #[clap(long, default_value_t = INITIAL_AUTO_EXPOSURE_MAX_EV, allow_hyphen_values = true)]
+    pub auto_exposure_max_ev: f32,
+
+    /// How quickly auto exposure adapts when the scene gets brighter, per second
+    #[clap(long, default_value_t = INITIAL_AUTO_EXPOSURE_BRIGHT_ADAPTATION_SPEED)]
+    pub auto_exposure_bright_speed: f32,
+
+    /// How quickly auto exposure adapts when the scene gets darker, per second
+    #[clap(long, default_value_t = INITIAL_AUTO_EXPOSURE_DARK_ADAPTATION_SPEED)]
+    pub auto_exposure_dark_speed: f32,
+
+    /// Number of shadow cascades for directional lights
+    #[clap(long, default_value_t = INITIAL_SHADOW_CASCADE_COUNT)]
+    pub shadow_cascades: u32,
+
+    /// Blend between uniform (0.0) and logarithmic (1.0) shadow cascade splits
+    #[clap(long, default_value_t = INITIAL_SHADOW_CASCADE_SPLIT_LAMBDA)]
+    pub shadow_cascade_split_lambda: f32,
+
+    /// Distance from the camera at which directional light shadows end
+    #[clap(long, default_value_t = INITIAL_SHADOW_DISTANCE)]
+    pub shadow_distance: f32,
+
+    /// Fraction of each shadow cascade that is blended into the next one, 0.0 disables blending
+    #[clap(long, default_value_t = INITIAL_SHADOW_CASCADE_BLEND_FRACTION)]
+    pub shadow_cascade_blend: f32,
+}
+
+#[derive(Debug, Copy, Clone, ArgEnum)]
+pub enum SkyboxPreset {
+    // src: https://github.com/JoeyDeVries/LearnOpenGL/tree/master/resources/textures/skybox
+    Mountains,
+    // src: http://www.hdrlabs.com/sibl/archive/
+    NewportLoft,
+    // src: http://www.hdrlabs.com/sibl/archive/
+    Milkyway,
+    // src: me
+    Photosphere,
+}
+
+impl CliArgs {
+    pub fn validate(&self) -> Result<()> {
+        if self.skybox.is_some() && self.skybox_hdr.is_some() {
+            bail!("--skybox and --skybox-hdr can't be used together");
+        }
+        if self.fullscreen && self.select_video_mode {
+            bail!("--fullscreen and --select-video-mode can't be used together");
+        }
+        if self.width == 0 || self.height == 0 {
+            bail!("Window size must be non-zero");
+        }
+        if !(0.1..=4.0).contains(&self.render_scale) {
+            bail!("Render scale must be between 0.1 and 4.0");
+        }
+        if !SUPPORTED_MSAA_SAMPLE_COUNTS.contains(&self.msaa) {
+            bail!(
+                "MSAA sample count must be one of {:?}",
+                SUPPORTED_MSAA_SAMPLE_COUNTS
+            );
+        }
+        if !(0.0..=20.0).contains(&self.exposure) {
+            bail!("Exposure must be between 0.0 and 20.0");
+        }
+        if !(0.0..=1.0).contains(&self.bloom_radius) {
+            bail!("Bloom radius must be between 0.0 and 1.0");
+        }
+        if !(0.0..=1.0).contains(&self.bloom_intensity) {
+            bail!("Bloom intensity must be between 0.0 and 1.0");
+        }
+        if self.ssao_radius <= 0.0 {
+            bail!("SSAO radius must be greater than 0.0");
+        }
+        if !(0.0..=1.0).contains(&self.ssao_intensity) {
+            bail!("SSAO intensity must be between 0.0 and 1.0");
+        }
+        if self.auto_exposure_min_ev >= self.auto_exposure_max_ev {
+            bail!("Auto exposure min EV must be less than the max EV");
+        }
+        if self.auto_exposure_bright_speed <= 0.0 || self.auto_exposure_dark_speed <= 0.0 {
+            bail!("Auto exposure adaptation speeds must be greater than 0.0");
+        }
+        if !(1..=MAX_SHADOW_CASCADES as u32).contains(&self.shadow_cascades) {
+            bail!(
+                "Shadow cascade count must be between 1 and {}",
+                MAX_SHADOW_CASCADES
+            );
+        }
+        if !(0.0..=1.0).contains(&self.shadow_cascade_split_lambda) {
+            bail!("Shadow cascade split lambda must be between 0.0 and 1.0");
+        }
+        if self.shadow_distance <= SHADOW_CASCADE_NEAR_DISTANCE {
+            bail!(
+                "Shadow distance must be greater than {}",
+                SHADOW_CASCADE_NEAR_DISTANCE
+            );
+        }
+        if !(0.0..=0.5).contains(&self.shadow_cascade_blend) {
+            bail!("Shadow cascade blend must be between 0.0 and 0.5");
+        }
+        Ok(())
+    }
+
+    // expects the level returned by load_level, which already has the overrides applied
+    pub fn renderer_settings(&self, level: &LevelDescription) -> RendererSettings {
+        RendererSettings {
+            render_scale: self.render_scale,
+            anti_aliasing_mode: self.anti_aliasing,
+            msaa_sample_count: self.msaa,
+            gpu_driven_rendering: self.gpu_driven,
+            tone_mapping_exposure: self.exposure,
+            tone_mapping_operator: level.tone_mapping.operator,
+            enable_bloom: !self.no_bloom,
+            bloom: BloomSettings {
+                radius: self.bloom_radius,
+                intensity: self.bloom_intensity,
+            },
+            enable_ssao: !self.no_ssao,
+            ssao: SsaoSettings {
+                radius: self.ssao_radius,
+                intensity: self.ssao_intensity,
+            },
+            enable_shadows: !self.no_shadows,
+            enable_auto_exposure: !self.no_auto_exposure,
+            auto_exposure: AutoExposureSettings {
+                min_ev: self.auto_exposure_min_ev,
+                max_ev: self.auto_exposure_max_ev,
+                bright_adaptation_speed: self.auto_exposure_bright_speed,
+                dark_adaptation_speed: self.auto_exposure_dark_speed,
+                exposure_compensation_ev: 0.0,
+            },
+            shadow_cascade_count: self.shadow_cascades,
+            shadow_cascade_split_lambda: self.shadow_cascade_split_lambda,
+            shadow_distance: self.shadow_distance,
+            shadow_cascade_blend_fraction: self.shadow_cascade_blend,
+        }
+    }
+
+    pub fn load_level(&self) -> Result<LevelDescription> {
+        let mut level = LevelDescription::load(&self.level)?;
+        if let Some(gltf_path) = &self.gltf {
+            level.replace_base_asset(gltf_path);
+        }
+        if let Some(skybox_preset) = self.skybox {
+            level.skybox = skybox_preset.description();
+        }
+        if let Some(tone_mapper) = self.tone_mapper {
+            level.tone_mapping.operator = tone_mapper;
+        }
+        if let Some(color_grading_lut_path) = &self.color_grading_lut {
+            level.tone_mapping.color_grading_lut_path = Some(color_grading_lut_path.clone());
+        }
+        if let Some(skybox_hdr_path) = &self.skybox_hdr {
+            level.skybox = SkyboxDescription {
+                background: SkyboxBackgroundDescription::Equirectangular {
+                    image_path: skybox_hdr_path.clone(),
+                },
+                hdr_environment: Some(SkyboxHDREnvironmentDescription::Equirectangular {
+                    image_path: skybox_hdr_path.clone(),
+                }),
+            };
+        }
+        Ok(level)
+    }
+}
+
+impl SkyboxPreset {
+    pub fn description(self) -> SkyboxDescription {
+        let equirectangular =
+            |background_path: &str, hdr_environment_path: &str| SkyboxDescription {
+                background: SkyboxBackgroundDescription::Equirectangular {
+                    image_path: background_path.to_string(),
+                },
+                hdr_environment: Some(SkyboxHDREnvironmentDescription::Equirectangular {
+                    image_path: hdr_environment_path.to_string(),
+                }),
+            };
+        match self {
+            SkyboxPreset::Mountains => SkyboxDescription {
+                background: SkyboxBackgroundDescription::Cube {
+                    face_image_paths: [
+                        "./src/textures/skybox/right.jpg",
+                        "./src/textures/skybox/left.jpg",
+                        "./src/textures/skybox/top.jpg",
+                        "./src/textures/skybox/bottom.jpg",
+                        "./src/textures/skybox/front.jpg",
+                        "./src/textures/skybox/back.jpg",
+                    ]
+                    .map(String::from),
+                },
+                hdr_environment: None,
+            },
+            SkyboxPreset::NewportLoft => equirectangular(
+                "./src/textures/newport_loft/background.jpg",
+                "./src/textures/newport_loft/radiance.hdr",
+            ),
+            SkyboxPreset::Milkyway => equirectangular(
+                "./src/textures/milkyway/background.jpg",
+                "./src/textures/milkyway/radiance.hdr",
+            ),
+            SkyboxPreset::Photosphere => equirectangular(
+                "./src/textures/photosphere_skybox.jpg",
+                "./src/textures/photosphere_skybox_small.jpg",
+            ),
+        }
+    }
+}
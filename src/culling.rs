@@ -0,0 +1,165 @@
+use std::ops::Range;
+
+use cgmath::{InnerSpace, Matrix4, Vector3, Vector4};
+
+use super::*;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vector3<f32>,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    // conservative sphere around an object-space aabb after it's been transformed into world space
+    pub fn from_transformed_aabb(
+        (min, max): (Vector3<f32>, Vector3<f32>),
+        transform: &crate::transform::Transform,
+    ) -> Self {
+        let matrix = transform.matrix();
+        let center = (matrix * ((min + max) / 2.0).extend(1.0)).truncate();
+        // taken from the matrix since the global transforms only have it baked into their matrix
+        let max_scale = matrix
+            .x
+            .truncate()
+            .magnitude()
+            .max(matrix.y.truncate().magnitude())
+            .max(matrix.z.truncate().magnitude());
+        Self {
+            center,
+            radius: (max - min).magnitude() / 2.0 * max_scale,
+        }
+    }
+}
+
+// the six clip planes of a view-projection matrix, pointing inwards.
+// see https://www.gamedevs.org/uploads/fast-extraction-viewing-frustum-planes-from-world-view-projection-matrix.pdf
+#[derive(Debug, Copy, Clone)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    // expects wgpu's clip space, where depth goes from 0 to 1
+    pub fn from_view_proj(view_proj: Matrix4<f32>) -> Self {
+        let row = |i: usize| {
+            Vector4::new(
+                view_proj.x[i],
+                view_proj.y[i],
+                view_proj.z[i],
+                view_proj.w[i],
+            )
+        };
+        let planes = [
+            row(3) + row(0), // left
+            row(3) - row(0), // right
+            row(3) + row(1), // bottom
+            row(3) - row(1), // top
+            row(2),          // near
+            row(3) - row(2), // far
+        ]
+        .map(|plane| plane / plane.truncate().magnitude());
+        Self { planes }
+    }
+
+    pub fn planes(&self) -> [Vector4<f32>; 6] {
+        self.planes
+    }
+
+    pub fn contains_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(sphere.center) + plane.w >= -sphere.radius)
+    }
+}
+
+impl From<ShaderCameraView> for Frustum {
+    fn from(view: ShaderCameraView) -> Self {
+        Self::from_view_proj(view.proj * view.view)
+    }
+}
+
+// runs of consecutive visible instances. the instances are drawn in place instead of being compacted
+// since the shaders look up per-instance data like morph target weights by instance_index
+pub fn get_instance_ranges(instance_visibility: impl IntoIterator<Item = bool>) -> Vec<Range<u32>> {
+    let mut ranges: Vec<Range<u32>> = Vec::new();
+    for (instance_index, is_visible) in instance_visibility.into_iter().enumerate() {
+        if !is_visible {
+            continue;
+        }
+        let instance_index = instance_index as u32;
+        match ranges.last_mut() {
+            Some(range) if range.end == instance_index => range.end += 1,
+            _ => ranges.push(instance_index..instance_index + 1),
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::{Deg, Point3};
+
+    #[test]
+    fn frustum_contains_sphere() {
+        let view = Matrix4::look_at_rh(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vector3::new(0.0, 1.0, 0.0),
+        );
+        let proj = make_perspective_proj_matrix(1.0, 100.0, Deg(90.0).into(), 1.0, false);
+        let frustum = Frustum::from_view_proj(proj * view);
+
+        let sphere = |x: f32, y: f32, z: f32, radius: f32| BoundingSphere {
+            center: Vector3::new(x, y, z),
+            radius,
+        };
+        assert!(frustum.contains_sphere(&sphere(0.0, 0.0, -10.0, 1.0)));
+        // behind the camera
+        assert!(!frustum.contains_sphere(&sphere(0.0, 0.0, 10.0, 1.0)));
+        // past the far plane, but overlapping it
+        assert!(!frustum.contains_sphere(&sphere(0.0, 0.0, -110.0, 1.0)));
+        assert!(frustum.contains_sphere(&sphere(0.0, 0.0, -100.5, 1.0)));
+        // outside of the 90 degree fov, unless it's big enough to poke in
+        assert!(!frustum.contains_sphere(&sphere(12.0, 0.0, -10.0, 1.0)));
+        assert!(frustum.contains_sphere(&sphere(12.0, 0.0, -10.0, 2.0)));
+        assert!(!frustum.contains_sphere(&sphere(0.0, -12.0, -10.0, 1.0)));
+
+        let instance_bounds = [
+            sphere(0.0, 0.0, -10.0, 1.0),
+            sphere(0.0, 0.0, 10.0, 1.0),
+            sphere(0.0, 0.0, -20.0, 1.0),
+            sphere(0.0, 0.0, -30.0, 1.0),
+            sphere(0.0, 0.0, 30.0, 1.0),
+        ];
+        assert_eq!(
+            get_instance_ranges(
+                instance_bounds
+                    .iter()
+                    .map(|bounds| frustum.contains_sphere(bounds))
+            ),
+            vec![0..1, 2..4]
+        );
+    }
+
+    #[test]
+    fn bounding_sphere_from_global_transform() {
+        let parent = TransformBuilder::new()
+            .position(Vector3::new(10.0, 0.0, 0.0))
+            .scale(Vector3::new(3.0, 3.0, 3.0))
+            .build();
+        let child = TransformBuilder::new()
+            .position(Vector3::new(0.0, 1.0, 0.0))
+            .build();
+        // the same way get_global_transform_for_node combines them
+        let global_transform = crate::transform::Transform::new() * parent * child;
+        let bounds = BoundingSphere::from_transformed_aabb(
+            (Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0)),
+            &global_transform,
+        );
+        assert!((bounds.center - Vector3::new(10.0, 3.0, 0.0)).magnitude() < 1e-5);
+        assert!((bounds.radius - 3.0 * 3.0f32.sqrt()).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::VecDeque;
+
+use anyhow::Result;
+use cgmath::{Quaternion, Rad, Vector3};
+
+use super::*;
+
+// how often the path to the player is recomputed while chasing
+const REPATH_INTERVAL_SECONDS: f32 = 0.5;
+// (0, 1], higher means the enemy turns towards its heading more quickly
+const TURN_LERP_FACTOR: f32 = 0.2;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EnemyState {
+    Idle,
+    Chasing,
+    Attacking,
+}
+
+// indices into Scene::animations
+#[derive(Debug, Copy, Clone, Default)]
+pub struct EnemyAnimations {
+    pub idle: Option<usize>,
+    pub walk: Option<usize>,
+    pub attack: Option<usize>,
+}
+
+pub struct Enemy {
+    pub character: Character,
+    root_node_id: GameNodeId,
+    // all of the nodes of the enemy's copy of the asset
+    node_ids: Vec<GameNodeId>,
+    animations: EnemyAnimations,
+    health: f32,
+    speed: f32,
+    aggro_range: f32,
+    attack_range: f32,
+    base_rotation: Quaternion<f32>,
+
+    state: Option<EnemyState>,
+    path: VecDeque<Vector3<f32>>,
+    repath_timer_seconds: f32,
+}
+
+impl EnemyAnimations {
+    pub fn new(description: &EnemiesDescription, loaded_asset: &LoadedLevelAsset) -> Result<Self> {
+        let animation_index = |index: Option<usize>| -> Result<Option<usize>> {
+            index
+                .map(|index| {
+                    if index >= loaded_asset.animation_count {
+                        anyhow::bail!(
+                            "Enemy asset {:?} doesn't have an animation with index {}",
+                            description.asset,
+                            index
+                        );
+                    }
+                    Ok(loaded_asset.animation_index_offset + index)
+                })
+                .transpose()
+        };
+        Ok(Self {
+            idle: animation_index(description.idle_animation)?,
+            walk: animation_index(description.walk_animation)?,
+            attack: animation_index(description.attack_animation)?,
+        })
+    }
+}
+
+impl Enemy {
+    pub fn new(
+        scene: &mut Scene,
+        physics_state: &mut PhysicsState,
+        renderer_state: &mut RendererState,
+        loaded_asset: &LoadedLevelAsset,
+        description: &EnemiesDescription,
+        spawn_position: Vector3<f32>,
+        cube_mesh: &BasicMesh,
+    ) -> Result<Self> {
+        let root_node_id = loaded_asset.node_id(description.root_node)?;
+        let animations = EnemyAnimations::new(description, loaded_asset)?;
+        let base_rotation = match scene.get_node_mut(root_node_id) {
+            Some(root_node) => {
+                root_node.transform.set_position(spawn_position);
+                root_node.transform.rotation()
+            }
+            None => Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        };
+        let character = Character::new(
+            scene,
+            physics_state,
+            renderer_state,
+            root_node_id,
+            loaded_asset.skin_index_offset + description.skin,
+            cube_mesh,
+        );
+        let mut result = Self {
+            character,
+            root_node_id,
+            node_ids: loaded_asset.node_ids.clone(),
+            animations,
+            health: description.health,
+            speed: description.speed,
+            aggro_range: description.aggro_range,
+            attack_range: description.attack_range,
+            base_rotation,
+
+            state: None,
+            path: VecDeque::new(),
+            repath_timer_seconds: 0.0,
+        };
+        result.set_state(scene, EnemyState::Idle);
+        Ok(result)
+    }
+
+    // returns true if the enemy died from the damage
+    pub fn take_damage(&mut self, amount: f32) -> bool {
+        let was_alive = self.health > 0.0;
+        self.health -= amount;
+        was_alive && self.health <= 0.0
+    }
+
+    pub fn destroy(&self, scene: &mut Scene, physics_state: &mut PhysicsState) {
+        for animation_index in [
+            self.animations.idle,
+            self.animations.walk,
+            self.animations.attack,
+        ]
+        .iter()
+        .flatten()
+        {
+            if let Some(animation) = scene.animations.get_mut(*animation_index) {
+                animation.state.is_playing = false;
+            }
+        }
+        self.character.destroy(scene, physics_state);
+        for node_id in self.node_ids.iter().copied() {
+            scene.remove_node(node_id);
+        }
+    }
+
+    pub fn position(&self, scene: &Scene) -> Vector3<f32> {
+        scene
+            .get_node(self.root_node_id)
+            .map(|node| node.transform.position())
+            .unwrap_or_else(|| Vector3::new(0.0, 0.0, 0.0))
+    }
+
+    pub fn update(
+        &mut self,
+        scene: &mut Scene,
+        nav_grid: &NavGrid,
+        player_position: Vector3<f32>,
+        delta_time_seconds: f32,
+    ) {
+        let position = self.position(scene);
+        let to_player = Vector3::new(
+            player_position.x - position.x,
+            0.0,
+            player_position.z - position.z,
+        );
+        let distance_to_player = to_player.magnitude();
+
+        let mut new_state = if distance_to_player <= self.attack_range {
+            EnemyState::Attacking
+        } else if distance_to_player <= self.aggro_range {
+            EnemyState::Chasing
+        } else {
+            EnemyState::Idle
+        };
+
+        let mut heading = None;
+        if new_state == EnemyState::Chasing {
+            // also throttled when no path was found, so an unreachable player doesn't run the search every frame
+            self.repath_timer_seconds -= delta_time_seconds;
+            if self.repath_timer_seconds <= 0.0 {
+                self.path = nav_grid
+                    .find_path(position, player_position)
+                    .unwrap_or_default()
+                    .into();
+                self.repath_timer_seconds = REPATH_INTERVAL_SECONDS;
+            }
+            let new_position = self.follow_path(position, delta_time_seconds);
+            let displacement = new_position - position;
+            if displacement.magnitude2() > 0.0 {
+                heading = Some(displacement);
+                if let Some(root_node) = scene.get_node_mut(self.root_node_id) {
+                    root_node.transform.set_position(new_position);
+                }
+            } else {
+                // the player isn't reachable
+                new_state = EnemyState::Idle;
+            }
+        } else {
+            self.path.clear();
+            self.repath_timer_seconds = 0.0;
+        }
+        if new_state == EnemyState::Attacking && distance_to_player > 0.0 {
+            heading = Some(to_player);
+        }
+
+        if let Some(heading) = heading {
+            self.turn_towards(scene, heading);
+        }
+        self.set_state(scene, new_state);
+    }
+
+    // must be called after the animations are stepped so the colliders follow the bones
+    pub fn update_colliders(&mut self, scene: &mut Scene, physics_state: &mut PhysicsState) {
+        self.character.update(scene, physics_state);
+    }
+
+    // moves along the xz plane, the enemy keeps its spawn height
+    fn follow_path(&mut self, position: Vector3<f32>, delta_time_seconds: f32) -> Vector3<f32> {
+        let mut remaining_distance = self.speed * delta_time_seconds;
+        let mut new_position = position;
+        while remaining_distance > 0.0 {
+            let waypoint = match self.path.front() {
+                Some(waypoint) => *waypoint,
+                None => break,
+            };
+            let to_waypoint = Vector3::new(
+                waypoint.x - new_position.x,
+                0.0,
+                waypoint.z - new_position.z,
+            );
+            let distance_to_waypoint = to_waypoint.magnitude();
+            if distance_to_waypoint <= remaining_distance {
+                new_position += to_waypoint;
+                remaining_distance -= distance_to_waypoint;
+                self.path.pop_front();
+            } else {
+                new_position += to_waypoint * (remaining_distance / distance_to_waypoint);
+                remaining_distance = 0.0;
+            }
+        }
+        new_position
+    }
+
+    // the model is assumed to face +z, as is the glTF convention
+    fn turn_towards(&self, scene: &mut Scene, heading: Vector3<f32>) {
+        let yaw = Rad(heading.x.atan2(heading.z));
+        let target_rotation =
+            make_quat_from_axis_angle(Vector3::new(0.0, 1.0, 0.0), yaw) * self.base_rotation;
+        if let Some(root_node) = scene.get_node_mut(self.root_node_id) {
+            root_node.transform.set_rotation(
+                root_node
+                    .transform
+                    .rotation()
+                    .nlerp(target_rotation, TURN_LERP_FACTOR),
+            );
+        }
+    }
+
+    fn set_state(&mut self, scene: &mut Scene, new_state: EnemyState) {
+        if self.state == Some(new_state) {
+            return;
+        }
+        self.state = Some(new_state);
+        let active_animation = match new_state {
+            EnemyState::Idle => self.animations.idle,
+            EnemyState::Chasing => self.animations.walk,
+            EnemyState::Attacking => self.animations.attack,
+        };
+        for animation_index in [
+            self.animations.idle,
+            self.animations.walk,
+            self.animations.attack,
+        ]
+        .iter()
+        .flatten()
+        {
+            if let Some(animation) = scene.animations.get_mut(*animation_index) {
+                let is_active = active_animation == Some(*animation_index);
+                if is_active && !animation.state.is_playing {
+                    animation.state.current_time_seconds = 0.0;
+                }
+                animation.state.is_playing = is_active;
+                animation.state.loop_type = LoopType::Wrap;
+            }
+        }
+    }
+}
@@ -1,120 +1,41 @@
 use super::*;
 
 use anyhow::Result;
-use cgmath::{Deg, Rad, Vector3, Vector4};
+use cgmath::{Rad, Vector3};
 use rapier3d::prelude::*;
 use winit::event::{ElementState, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent};
 
 pub const INITIAL_RENDER_SCALE: f32 = 1.0;
+pub const INITIAL_MSAA_SAMPLE_COUNT: u32 = 1;
 pub const INITIAL_TONE_MAPPING_EXPOSURE: f32 = 0.3;
-pub const INITIAL_BLOOM_THRESHOLD: f32 = 0.8;
-pub const INITIAL_BLOOM_RAMP_SIZE: f32 = 0.2;
+pub const INITIAL_AUTO_EXPOSURE_MIN_EV: f32 = -2.0;
+pub const INITIAL_AUTO_EXPOSURE_MAX_EV: f32 = 12.0;
+pub const INITIAL_AUTO_EXPOSURE_BRIGHT_ADAPTATION_SPEED: f32 = 3.0;
+pub const INITIAL_AUTO_EXPOSURE_DARK_ADAPTATION_SPEED: f32 = 1.0;
+pub const INITIAL_BLOOM_RADIUS: f32 = 0.75;
+pub const INITIAL_BLOOM_INTENSITY: f32 = 0.04;
+pub const INITIAL_SSAO_RADIUS: f32 = 0.5;
+pub const INITIAL_SSAO_INTENSITY: f32 = 1.0;
+pub const INITIAL_SHADOW_CASCADE_COUNT: u32 = 4;
+pub const INITIAL_SHADOW_CASCADE_SPLIT_LAMBDA: f32 = 0.75;
+pub const INITIAL_SHADOW_DISTANCE: f32 = 100.0;
+pub const INITIAL_SHADOW_CASCADE_BLEND_FRACTION: f32 = 0.1;
 pub const ARENA_SIDE_LENGTH: f32 = 25.0;
 // pub const LIGHT_COLOR_A: Vector3<f32> = Vector3::new(0.996, 0.973, 0.663);
 // pub const LIGHT_COLOR_B: Vector3<f32> = Vector3::new(0.25, 0.973, 0.663);
 
-// linear colors, not srgb
-pub const DIRECTIONAL_LIGHT_COLOR_A: Vector3<f32> = Vector3::new(0.84922975, 0.81581426, 0.8832506);
-pub const DIRECTIONAL_LIGHT_COLOR_B: Vector3<f32> = Vector3::new(0.81115574, 0.77142686, 0.8088144);
-pub const POINT_LIGHT_COLOR: Vector3<f32> = Vector3::new(0.93126976, 0.7402633, 0.49407062);
-// pub const LIGHT_COLOR_C: Vector3<f32> =
-//     Vector3::new(from_srgb(0.631), from_srgb(0.565), from_srgb(0.627));
+pub const PLAYER_MAX_HEALTH: f32 = 100.0;
+pub const ENEMY_CONTACT_DAMAGE_PER_SECOND: f32 = 20.0;
+pub const REVOLVER_DAMAGE: f32 = 34.0;
 
 pub const COLLISION_GROUP_BASE: u32 = 1;
 pub const COLLISION_GROUP_PLAYER_SHOOTABLE: u32 = COLLISION_GROUP_BASE << 1;
 pub const COLLISION_GROUP_PLAYER_UNSHOOTABLE: u32 = COLLISION_GROUP_PLAYER_SHOOTABLE << 1;
 
-#[allow(clippy::let_and_return)]
-fn get_gltf_path() -> &'static str {
-    // let gltf_path = "/home/david/Downloads/adamHead/adamHead.gltf";
-    // let gltf_path = "/home/david/Downloads/free_low_poly_forest/scene_2.glb";
-    // let gltf_path = "/home/david/Downloads/free_low_poly_forest/scene.gltf";
-    // let gltf_path = "./src/models/gltf/TextureCoordinateTest/TextureCoordinateTest.gltf";
-    // let gltf_path = "./src/models/gltf/SimpleMeshes/SimpleMeshes.gltf";
-    // let gltf_path = "./src/models/gltf/Triangle/Triangle.gltf";
-    // let gltf_path = "./src/models/gltf/TriangleWithoutIndices/TriangleWithoutIndices.gltf";
-    // let gltf_path = "./src/models/gltf/Sponza/Sponza.gltf";
-    // let gltf_path = "./src/models/gltf/EnvironmentTest/EnvironmentTest.gltf";
-    // let gltf_path = "./src/models/gltf/Arrow/Arrow.gltf";
-    // let gltf_path = "./src/models/gltf/DamagedHelmet/DamagedHelmet.gltf";
-    // let gltf_path = "./src/models/gltf/VertexColorTest/VertexColorTest.gltf";
-    // let gltf_path = "./src/models/gltf/Revolver/revolver_low_poly.gltf";
-    // let gltf_path =
-    //     "/home/david/Programming/glTF-Sample-Models/2.0/BoomBoxWithAxes/glTF/BoomBoxWithAxes.gltf";
-    // let gltf_path =
-    //     "./src/models/gltf/TextureLinearInterpolationTest/TextureLinearInterpolationTest.glb";
-    // let gltf_path = "../glTF-Sample-Models/2.0/RiggedFigure/glTF/RiggedFigure.gltf";
-    // let gltf_path = "../glTF-Sample-Models/2.0/RiggedSimple/glTF/RiggedSimple.gltf";
-    // let gltf_path = "../glTF-Sample-Models/2.0/CesiumMan/glTF/CesiumMan.gltf";
-    // let gltf_path = "../glTF-Sample-Models/2.0/Fox/glTF/Fox.gltf";
-    // let gltf_path = "../glTF-Sample-Models/2.0/RecursiveSkeletons/glTF/RecursiveSkeletons.gltf";
-    // let gltf_path = "../glTF-Sample-Models/2.0/BrainStem/glTF/BrainStem.gltf";
-    // let gltf_path =
-    //     "/home/david/Programming/glTF-Sample-Models/2.0/BoxAnimated/glTF/BoxAnimated.gltf";
-    // let gltf_path = "/home/david/Programming/glTF-Sample-Models/2.0/InterpolationTest/glTF/InterpolationTest.gltf";
-    // let gltf_path = "./src/models/gltf/VC/VC.gltf";
-    // let gltf_path =
-    //     "../glTF-Sample-Models-master/2.0/InterpolationTest/glTF/InterpolationTest.gltf";
-
-    // https://www.cgtrader.com/free-3d-models/character/sci-fi-character/legendary-robot-free-low-poly-3d-model
-    let gltf_path = "./src/models/gltf/LegendaryRobot/Legendary_Robot.gltf";
-
-    gltf_path
-}
-
-pub fn get_skybox_path() -> (
-    SkyboxBackground<'static>,
-    Option<SkyboxHDREnvironment<'static>>,
-) {
-    // Mountains
-    // src: https://github.com/JoeyDeVries/LearnOpenGL/tree/master/resources/textures/skybox
-    let _skybox_background = SkyboxBackground::Cube {
-        face_image_paths: [
-            "./src/textures/skybox/right.jpg",
-            "./src/textures/skybox/left.jpg",
-            "./src/textures/skybox/top.jpg",
-            "./src/textures/skybox/bottom.jpg",
-            "./src/textures/skybox/front.jpg",
-            "./src/textures/skybox/back.jpg",
-        ],
-    };
-    let _skybox_hdr_environment: Option<SkyboxHDREnvironment> = None;
-
-    // Newport Loft
-    // src: http://www.hdrlabs.com/sibl/archive/
-    let _skybox_background = SkyboxBackground::Equirectangular {
-        image_path: "./src/textures/newport_loft/background.jpg",
-    };
-    let _skybox_hdr_environment: Option<SkyboxHDREnvironment> =
-        Some(SkyboxHDREnvironment::Equirectangular {
-            image_path: "./src/textures/newport_loft/radiance.hdr",
-        });
-
-    // Milkyway
-    // src: http://www.hdrlabs.com/sibl/archive/
-    let skybox_background = SkyboxBackground::Equirectangular {
-        image_path: "./src/textures/milkyway/background.jpg",
-    };
-    let skybox_hdr_environment: Option<SkyboxHDREnvironment> =
-        Some(SkyboxHDREnvironment::Equirectangular {
-            image_path: "./src/textures/milkyway/radiance.hdr",
-        });
-
-    // My photosphere pic
-    // src: me
-    let _skybox_background = SkyboxBackground::Equirectangular {
-        image_path: "./src/textures/photosphere_skybox.jpg",
-    };
-    let _skybox_hdr_environment: Option<SkyboxHDREnvironment> =
-        Some(SkyboxHDREnvironment::Equirectangular {
-            image_path: "./src/textures/photosphere_skybox_small.jpg",
-        });
-
-    (skybox_background, skybox_hdr_environment)
-}
-
 pub fn init_game_state(
     mut scene: Scene,
+    level: &LevelDescription,
+    damage_vignette: DamageVignette,
     renderer_state: &mut RendererState,
     logger: &mut Logger,
 ) -> Result<GameState> {
@@ -124,58 +45,58 @@ pub fn init_game_state(
 
     let mut physics_state = PhysicsState::new();
 
+    // the first asset was already loaded into the scene by init_scene
+    let mut loaded_assets: Vec<LoadedLevelAsset> = level
+        .assets
+        .first()
+        .map(|asset| LoadedLevelAsset {
+            name: asset.name.clone(),
+            node_ids: scene.nodes().map(|node| node.id()).collect(),
+            skin_index_offset: 0,
+            skin_count: scene.skins.len(),
+            animation_index_offset: 0,
+            animation_count: scene.animations.len(),
+        })
+        .into_iter()
+        .collect();
+
     let player_controller = PlayerController::new(
         &mut physics_state,
-        6.0,
-        Vector3::new(8.0, 30.0, -13.0),
-        ControlledViewDirection {
-            horizontal: Deg(180.0).into(),
-            vertical: Rad(0.0),
-        },
+        level.player_spawn.speed,
+        level.player_spawn.position.into(),
+        level.player_spawn.view_direction(),
     );
     let player_node_id = scene.add_node(GameNodeDesc::default()).id();
 
     // add lights to the scene
-    let directional_lights = vec![
-        DirectionalLightComponent {
-            position: Vector3::new(1.0, 5.0, -10.0) * 10.0,
-            direction: (-Vector3::new(1.0, 5.0, -10.0)).normalize(),
-            color: DIRECTIONAL_LIGHT_COLOR_A,
-            intensity: 1.0,
-        },
-        DirectionalLightComponent {
-            position: Vector3::new(-1.0, 10.0, 10.0) * 10.0,
-            direction: (-Vector3::new(-1.0, 10.0, 10.0)).normalize(),
-            color: DIRECTIONAL_LIGHT_COLOR_B,
-            intensity: 1.0,
-        },
-    ];
-    // let directional_lights: Vec<DirectionalLightComponent> = vec![];
+    let directional_lights: Vec<DirectionalLightComponent> = level
+        .directional_lights
+        .iter()
+        .map(DirectionalLightComponent::from)
+        .collect();
 
-    let point_lights: Vec<(transform::Transform, Vector3<f32>, f32)> = vec![
-        (
-            TransformBuilder::new()
-                .scale(Vector3::new(0.05, 0.05, 0.05))
-                .position(Vector3::new(0.0, 12.0, 0.0))
-                .build(),
-            POINT_LIGHT_COLOR,
-            1.0,
-        ),
-        // (
-        //     TransformBuilder::new()
-        //         .scale(Vector3::new(0.1, 0.1, 0.1))
-        //         .position(Vector3::new(0.0, 15.0, 0.0))
-        //         .build(),
-        //     LIGHT_COLOR_B,
-        //     1.0,
-        // ),
-    ];
-    // let point_lights: Vec<(transform::Transform, Vector3<f32>, f32)> = vec![];
+    let point_lights: Vec<(transform::Transform, Vector3<f32>, f32, f32, f32, f32)> = level
+        .point_lights
+        .iter()
+        .map(|point_light| {
+            (
+                TransformBuilder::new()
+                    .scale(point_light.marker_scale * Vector3::new(1.0, 1.0, 1.0))
+                    .position(point_light.position.into())
+                    .build(),
+                point_light.color.into(),
+                point_light.intensity,
+                point_light.size,
+                point_light.range,
+                point_light.shadow_update_interval_seconds,
+            )
+        })
+        .collect();
 
     let point_light_unlit_mesh_index = renderer_state.bind_basic_unlit_mesh(&sphere_mesh);
     let mut point_light_node_ids: Vec<GameNodeId> = Vec::new();
     let mut point_light_components: Vec<PointLightComponent> = Vec::new();
-    for (transform, color, intensity) in point_lights {
+    for (transform, color, intensity, size, range, shadow_update_interval_seconds) in point_lights {
         let node_id = scene
             .add_node(
                 GameNodeDescBuilder::new()
@@ -185,6 +106,8 @@ pub fn init_game_state(
                             color: color * intensity,
                         },
                         wireframe: false,
+                        cast_shadows: false,
+                        receive_shadows: false,
                     }))
                     .transform(transform)
                     .build(),
@@ -193,11 +116,33 @@ pub fn init_game_state(
         point_light_node_ids.push(node_id);
         point_light_components.push(PointLightComponent {
             node_id,
-            color: POINT_LIGHT_COLOR,
+            color,
             intensity,
+            size,
+            range,
+            shadow_update_interval_seconds,
         });
     }
 
+    // merge the rest of the level's assets into the scene
+    for asset in level.assets.iter().skip(1) {
+        loaded_assets.push(merge_level_asset(
+            &mut scene,
+            renderer_state,
+            asset,
+            logger,
+        )?);
+    }
+    for (asset, loaded_asset) in level.assets.iter().zip(loaded_assets.iter()) {
+        apply_level_asset_settings(
+            &mut scene,
+            &mut physics_state,
+            renderer_state,
+            asset,
+            loaded_asset,
+        );
+    }
+
     // rotate the animated character 90 deg
     // if let Some(node_0) = scene._get_node_mut_by_index(0) {
     // node_0.transform.set_rotation(make_quat_from_axis_angle(
@@ -208,10 +153,16 @@ pub fn init_game_state(
     // node_0.transform.set_position(Vector3::new(2.0, 0.0, 0.0));
     // }
     // let node_0_id = scene._get_node_by_index(0).unwrap().id();
-    if let Some(animation_5) = scene.animations.get_mut(11) {
-        animation_5.speed = 0.25;
-        animation_5.state.is_playing = true;
-        animation_5.state.loop_type = LoopType::Wrap;
+    for animation_desc in &level.animations {
+        let loaded_asset = find_loaded_level_asset(&loaded_assets, &animation_desc.asset)?;
+        if let Some(animation) = scene
+            .animations
+            .get_mut(loaded_asset.animation_index_offset + animation_desc.index)
+        {
+            animation.speed = animation_desc.speed;
+            animation.state.is_playing = animation_desc.is_playing;
+            animation.state.loop_type = animation_desc.loop_type;
+        }
     }
     // scene.remove_node(node_0_id);
 
@@ -302,22 +253,64 @@ pub fn init_game_state(
         .id();
     scene.remove_node(test_object_node_id);
 
-    let legendary_robot_root_node_id = scene._get_node_by_index(53).unwrap().id();
-    scene
-        .get_node_mut(legendary_robot_root_node_id)
-        .unwrap()
-        .transform
-        .set_position(Vector3::new(2.0, 0.0, 0.0));
+    let character = match &level.character {
+        Some(character_desc) => {
+            let loaded_asset = find_loaded_level_asset(&loaded_assets, &character_desc.asset)?;
+            let root_node_id = loaded_asset.node_id(character_desc.root_node)?;
+            if let Some(position) = character_desc.position {
+                if let Some(root_node) = scene.get_node_mut(root_node_id) {
+                    root_node.transform.set_position(position.into());
+                }
+            }
+            Some(Character::new(
+                &mut scene,
+                &mut physics_state,
+                renderer_state,
+                root_node_id,
+                loaded_asset.skin_index_offset + character_desc.skin,
+                &cube_mesh,
+            ))
+        }
+        None => None,
+    };
 
-    let legendary_robot_skin_index = 0;
-    let legendary_robot = Character::new(
-        &mut scene,
-        &mut physics_state,
-        renderer_state,
-        legendary_robot_root_node_id,
-        legendary_robot_skin_index,
-        &cube_mesh,
-    );
+    let enemy_spawn_points: Vec<Vector3<f32>> = level
+        .enemies
+        .iter()
+        .flat_map(|enemies_desc| enemies_desc.spawn_points.iter())
+        .map(|spawn_point| Vector3::from(*spawn_point))
+        .collect();
+    // only the enemies need the nav grid, skip generating it otherwise
+    let nav_grid = if enemy_spawn_points.is_empty() {
+        NavGrid::new(
+            0.0,
+            0.0,
+            level.navigation.cell_size,
+            0,
+            0,
+            level.navigation.floor_height,
+        )
+    } else {
+        NavGrid::from_static_colliders(&physics_state, &level.navigation, &enemy_spawn_points)
+    };
+
+    let mut enemies: Vec<Enemy> = Vec::new();
+    if let Some(enemies_desc) = &level.enemies {
+        let asset = find_loaded_level_asset(&loaded_assets, &enemies_desc.asset)?;
+        for spawn_point in enemy_spawn_points.iter().copied() {
+            // every enemy needs its own skeleton and animation states, the meshes are shared
+            let loaded_asset = duplicate_level_asset(&mut scene, asset);
+            enemies.push(Enemy::new(
+                &mut scene,
+                &mut physics_state,
+                renderer_state,
+                &loaded_asset,
+                enemies_desc,
+                spawn_point,
+                &cube_mesh,
+            )?);
+        }
+    }
 
     // add floor to scene
     let big_checkerboard_texture_img = {
@@ -429,8 +422,7 @@ pub fn init_game_state(
         ball_node_ids.push(node.id());
     }
 
-    let physics_ball_count = 500;
-    let physics_balls: Vec<_> = (0..physics_ball_count)
+    let physics_balls: Vec<_> = (0..level.physics_balls.initial_count)
         .into_iter()
         .map(|_| {
             PhysicsBall::new_random(
@@ -465,43 +457,45 @@ pub fn init_game_state(
     // );
 
     // create the floor and add it to the scene
-    let floor_pbr_mesh_index = renderer_state.bind_basic_pbr_mesh(
-        &plane_mesh,
-        &PbrMaterial {
-            base_color: Some(&big_checkerboard_texture),
-            ..Default::default()
-        },
-        Default::default(),
-    )?;
-    let floor_transform = TransformBuilder::new()
-        .scale(Vector3::new(ARENA_SIDE_LENGTH, 1.0, ARENA_SIDE_LENGTH))
+    if let Some(floor) = &level.floor {
+        let floor_pbr_mesh_index = renderer_state.bind_basic_pbr_mesh(
+            &plane_mesh,
+            &PbrMaterial {
+                base_color: Some(&big_checkerboard_texture),
+                ..Default::default()
+            },
+            Default::default(),
+        )?;
+        let floor_transform = TransformBuilder::new()
+            .scale(Vector3::new(floor.side_length, 1.0, floor.side_length))
+            .build();
+        let _floor_node = scene.add_node(
+            GameNodeDescBuilder::new()
+                .mesh(Some(GameNodeMesh::from_pbr_mesh_index(
+                    floor_pbr_mesh_index,
+                )))
+                .transform(floor_transform)
+                .build(),
+        );
+        let floor_thickness = 0.1;
+        let floor_collider = ColliderBuilder::cuboid(
+            floor_transform.scale().x,
+            floor_thickness / 2.0,
+            floor_transform.scale().z,
+        )
+        .translation(vector![
+            floor_transform.position().x / 2.0,
+            floor_transform.position().y - floor_thickness / 2.0,
+            floor_transform.position().z / 2.0
+        ])
+        .collision_groups(
+            InteractionGroups::all().with_memberships(!COLLISION_GROUP_PLAYER_UNSHOOTABLE),
+        )
+        .friction(1.0)
+        .restitution(1.0)
         .build();
-    let _floor_node = scene.add_node(
-        GameNodeDescBuilder::new()
-            .mesh(Some(GameNodeMesh::from_pbr_mesh_index(
-                floor_pbr_mesh_index,
-            )))
-            .transform(floor_transform)
-            .build(),
-    );
-    let floor_thickness = 0.1;
-    let floor_collider = ColliderBuilder::cuboid(
-        floor_transform.scale().x,
-        floor_thickness / 2.0,
-        floor_transform.scale().z,
-    )
-    .translation(vector![
-        floor_transform.position().x / 2.0,
-        floor_transform.position().y - floor_thickness / 2.0,
-        floor_transform.position().z / 2.0
-    ])
-    .collision_groups(
-        InteractionGroups::all().with_memberships(!COLLISION_GROUP_PLAYER_UNSHOOTABLE),
-    )
-    .friction(1.0)
-    .restitution(1.0)
-    .build();
-    physics_state.collider_set.insert(floor_collider);
+        physics_state.collider_set.insert(floor_collider);
+    }
 
     // create the checkerboarded bouncing ball and add it to the scene
     let (bouncing_ball_node_id, bouncing_ball_body_handle) = {
@@ -556,173 +550,47 @@ pub fn init_game_state(
     };
     scene.remove_node(bouncing_ball_node_id);
 
-    // add crosshair to scene
-    let crosshair_texture_img = {
-        let thickness = 8;
-        let gap = 30;
-        let bar_length = 14;
-        let texture_size = 512;
-        let mut img = image::RgbaImage::new(texture_size, texture_size);
-        for x in 0..img.width() {
-            for y in 0..img.height() {
-                let d_x = ((x as i32) - ((texture_size / 2) as i32)).abs();
-                let d_y = ((y as i32) - ((texture_size / 2) as i32)).abs();
-
-                let inside_vertical_bar =
-                    d_x < thickness / 2 && d_y > gap / 2 && d_y < gap + bar_length;
-                let inside_horizontal_bar =
-                    d_y < thickness / 2 && d_x > gap / 2 && d_x < gap + bar_length;
-
-                img.put_pixel(
-                    x,
-                    y,
-                    if inside_vertical_bar || inside_horizontal_bar {
-                        [255, 255, 255, 255].into()
-                    } else {
-                        [0, 0, 0, 0].into()
-                    },
-                );
-            }
+    let revolver = match &level.revolver {
+        Some(revolver_desc) => {
+            let loaded_asset = find_loaded_level_asset(&loaded_assets, &revolver_desc.asset)?;
+            let model_node_id = match revolver_desc.model_node {
+                Some(model_node) => loaded_asset.node_id(model_node)?,
+                None => *loaded_asset.node_ids.last().ok_or_else(|| {
+                    anyhow::anyhow!("Revolver asset {:?} has no nodes", revolver_desc.asset)
+                })?,
+            };
+            let animation_index = match revolver_desc.fire_animation {
+                Some(fire_animation) => loaded_asset.animation_index_offset + fire_animation,
+                None => {
+                    if loaded_asset.animation_count == 0 {
+                        anyhow::bail!("Revolver asset {:?} has no animations", revolver_desc.asset);
+                    }
+                    loaded_asset.animation_index_offset + loaded_asset.animation_count - 1
+                }
+            };
+            Some(Revolver::new(
+                &mut scene,
+                player_node_id,
+                model_node_id,
+                animation_index,
+                revolver_desc.transform.into(),
+            ))
         }
-        img
-    };
-    let crosshair_texture = Texture::from_decoded_image(
-        &renderer_state.base.device,
-        &renderer_state.base.queue,
-        &crosshair_texture_img,
-        crosshair_texture_img.dimensions(),
-        Some("crosshair_texture"),
-        None,
-        false,
-        &texture::SamplerDescriptor(wgpu::SamplerDescriptor {
-            // mag_filter: wgpu::FilterMode::Nearest,
-            // min_filter: wgpu::FilterMode::Nearest,
-            // mipmap_filter: wgpu::FilterMode::Nearest,
-            ..texture::SamplerDescriptor::default().0
-        }),
-    )?;
-    let crosshair_quad = BasicMesh {
-        vertices: vec![[1.0, 1.0], [1.0, -1.0], [-1.0, -1.0], [-1.0, 1.0]]
-            .iter()
-            .map(|position| Vertex {
-                position: [0.0, position[1], position[0]],
-                normal: [0.0, 0.0, 1.0],
-                tex_coords: [0.5 * (position[0] + 1.0), 0.5 * (1.0 - position[1])],
-                tangent: [1.0, 0.0, 0.0],
-                bitangent: [0.0, -1.0, 0.0],
-                color: [1.0, 1.0, 1.0, 1.0],
-                bone_indices: [0, 1, 2, 3],
-                bone_weights: [1.0, 0.0, 0.0, 0.0],
-            })
-            .collect(),
-        indices: vec![0, 2, 1, 0, 3, 2],
+        None => None,
     };
-    let pbr_mesh_index = renderer_state.bind_basic_pbr_mesh(
-        &crosshair_quad,
-        &PbrMaterial {
-            ambient_occlusion: Some(&Texture::from_color(
-                &renderer_state.base.device,
-                &renderer_state.base.queue,
-                [0, 0, 0, 0],
-            )?),
-            metallic_roughness: Some(&Texture::from_color(
-                &renderer_state.base.device,
-                &renderer_state.base.queue,
-                [0, 0, 255, 0],
-            )?),
-            base_color: Some(&crosshair_texture),
-            emissive: Some(&crosshair_texture),
-            ..Default::default()
-        },
-        Default::default(),
-    )?;
-    let crosshair_color = Vector3::new(1.0, 0.0, 0.0);
-    let crosshair_node_id = scene
-        .add_node(
-            GameNodeDescBuilder::new()
-                .mesh(Some(GameNodeMesh {
-                    mesh_indices: vec![pbr_mesh_index],
-                    mesh_type: GameNodeMeshType::Pbr {
-                        material_override: Some(DynamicPbrParams {
-                            emissive_factor: crosshair_color,
-                            base_color_factor: Vector4::new(0.0, 0.0, 0.0, 1.0),
-                            alpha_cutoff: 0.5,
-                            ..Default::default()
-                        }),
-                    },
-                    wireframe: false,
-                }))
-                .build(),
-        )
-        .id();
-
-    // merge revolver scene into current scene
-    // let (document, buffers, images) =
-    //     gltf::import("./src/models/gltf/Revolver/revolver_low_poly.gltf")?;
-    {
-        let (document, buffers, images) =
-            gltf::import("./src/models/gltf/ColtPython/colt_python.gltf")?;
-        validate_animation_property_counts(&document, logger);
-        let (other_scene, other_render_buffers) =
-            build_scene(&renderer_state.base, (&document, &buffers, &images))?;
-        scene.merge_scene(renderer_state, other_scene, other_render_buffers);
-    }
-
-    let revolver_model_node_id = scene.nodes().last().unwrap().id();
-    let animation_index = scene.animations.len() - 1;
-    let revolver = Revolver::new(
-        &mut scene,
-        player_node_id,
-        revolver_model_node_id,
-        animation_index,
-        // revolver model
-        // TransformBuilder::new()
-        //     .position(Vector3::new(0.21, -0.09, -1.0))
-        //     .rotation(make_quat_from_axis_angle(
-        //         Vector3::new(0.0, 1.0, 0.0),
-        //         Deg(180.0).into(),
-        //     ))
-        //     .scale(0.17f32 * Vector3::new(1.0, 1.0, 1.0))
-        //     .build(),
-        // colt python model
-        TransformBuilder::new()
-            .position(Vector3::new(0.21, -0.13, -1.0))
-            .rotation(
-                make_quat_from_axis_angle(Vector3::new(0.0, 1.0, 0.0), Deg(180.0).into())
-                    * make_quat_from_axis_angle(Vector3::new(0.0, 1.0, 0.0), Rad(0.1)),
-            )
-            .scale(2.0f32 * Vector3::new(1.0, 1.0, 1.0))
-            .build(),
-    );
-
-    {
-        let skip_nodes = scene.node_count();
-        let (document, buffers, images) =
-            gltf::import("./src/models/gltf/TestLevel/test_level.gltf")?;
-        validate_animation_property_counts(&document, logger);
-        let (other_scene, other_render_buffers) =
-            build_scene(&renderer_state.base, (&document, &buffers, &images))?;
-        scene.merge_scene(renderer_state, other_scene, other_render_buffers);
-
-        let test_level_node_ids: Vec<_> = scene
-            .nodes()
-            .skip(skip_nodes)
-            .map(|node| node.id())
-            .collect();
-        for node_id in test_level_node_ids {
-            if let Some(_mesh) = scene.get_node_mut(node_id).unwrap().mesh.as_mut() {
-                // _mesh.wireframe = true;
-            }
-            physics_state.add_static_box(&scene, renderer_state, node_id);
-        }
-    }
 
     let mut audio_manager = AudioManager::new()?;
 
-    let bgm_data =
-        AudioManager::decode_mp3(audio_manager.device_sample_rate(), "./src/sounds/bgm.mp3")?;
-    let bgm_sound_index = audio_manager.add_sound(&bgm_data, 0.5, false, None);
-    audio_manager.play_sound(bgm_sound_index);
+    let bgm_sound_index = match &level.music_path {
+        Some(music_path) => {
+            let bgm_data =
+                AudioManager::decode_mp3(audio_manager.device_sample_rate(), music_path)?;
+            let bgm_sound_index = audio_manager.add_sound(&bgm_data, 0.5, false, None);
+            audio_manager.play_sound(bgm_sound_index);
+            Some(bgm_sound_index)
+        }
+        None => None,
+    };
 
     let gunshot_sound_data = AudioManager::decode_wav(
         audio_manager.device_sample_rate(),
@@ -732,6 +600,8 @@ pub fn init_game_state(
 
     // logger.log(&format!("{:?}", &revolver));
 
+    logger.log("Press Enter to start");
+
     Ok(GameState {
         scene,
         time_tracker: None,
@@ -756,9 +626,9 @@ pub fn init_game_state(
         ball_pbr_mesh_index,
 
         ball_spawner_acc: 0.0,
+        ball_spawn_interval_seconds: level.physics_balls.spawn_interval_seconds,
 
         test_object_node_id,
-        crosshair_node_id,
         revolver,
 
         bouncing_ball_node_id,
@@ -769,11 +639,52 @@ pub fn init_game_state(
         physics_balls,
         mouse_button_pressed: false,
 
-        character: legendary_robot,
+        character,
+        enemies,
+        nav_grid,
         player_controller,
+
+        phase: GamePhase::Menu,
+        player_health: PLAYER_MAX_HEALTH,
+        score: 0,
+        fps_counter: FpsCounter::default(),
+        damage_vignette,
+        ui: UiDrawList::new(),
+        level: level.clone(),
     })
 }
 
+// rebuilds the scene and game state from the level file, the renderer is kept
+pub fn restart_game(
+    game_state: &mut GameState,
+    renderer_state: &mut RendererState,
+    logger: &mut Logger,
+) -> Result<()> {
+    let level = game_state.level.clone();
+    let (scene, render_buffers) = init_scene(&mut renderer_state.base, &level, logger)?;
+    let previous_render_buffers = renderer_state.replace_buffers(render_buffers);
+    let mut damage_vignette = game_state.damage_vignette;
+    damage_vignette.clear();
+    let mut new_game_state =
+        match init_game_state(scene, &level, damage_vignette, renderer_state, logger) {
+            Ok(new_game_state) => new_game_state,
+            Err(err) => {
+                // the old scene's mesh indices point into the old buffers
+                renderer_state.replace_buffers(previous_render_buffers);
+                return Err(err);
+            }
+        };
+    new_game_state
+        .player_controller
+        .keep_window_focus_from(&game_state.player_controller);
+    new_game_state.phase = GamePhase::Playing;
+    new_game_state.fps_counter = game_state.fps_counter;
+    *game_state = new_game_state;
+    renderer_state.reset_frame_history();
+    logger.log("Game restarted");
+    Ok(())
+}
+
 pub fn process_device_input(
     game_state: &mut GameState,
     event: &winit::event::DeviceEvent,
@@ -823,11 +734,26 @@ pub fn process_window_input(
                 VirtualKeyCode::R => {
                     renderer_state.increment_exposure(true, logger);
                 }
+                VirtualKeyCode::U => {
+                    renderer_state.toggle_auto_exposure();
+                }
+                VirtualKeyCode::O => {
+                    renderer_state.cycle_tone_mapping_operator(logger);
+                }
+                VirtualKeyCode::G => {
+                    renderer_state.toggle_color_grading(logger);
+                }
                 VirtualKeyCode::T => {
-                    renderer_state.increment_bloom_threshold(false, logger);
+                    renderer_state.increment_bloom_intensity(false, logger);
                 }
                 VirtualKeyCode::Y => {
-                    renderer_state.increment_bloom_threshold(true, logger);
+                    renderer_state.increment_bloom_intensity(true, logger);
+                }
+                VirtualKeyCode::H => {
+                    renderer_state.increment_bloom_radius(false, logger);
+                }
+                VirtualKeyCode::J => {
+                    renderer_state.increment_bloom_radius(true, logger);
                 }
                 VirtualKeyCode::P => {
                     game_state.toggle_animations();
@@ -838,13 +764,38 @@ pub fn process_window_input(
                 VirtualKeyCode::B => {
                     renderer_state.toggle_bloom();
                 }
+                VirtualKeyCode::K => {
+                    renderer_state.toggle_ssao();
+                }
+                VirtualKeyCode::N => {
+                    renderer_state.cycle_anti_aliasing_mode(logger);
+                }
                 VirtualKeyCode::F => {
                     renderer_state.toggle_wireframe_mode();
                 }
+                VirtualKeyCode::Return => match game_state.phase {
+                    GamePhase::Menu => {
+                        game_state.start_playing(logger);
+                    }
+                    GamePhase::Dead => {
+                        if let Err(err) = restart_game(game_state, renderer_state, logger) {
+                            logger.log(&format!("Error: failed to restart the game: {:?}", err));
+                        }
+                    }
+                    GamePhase::Playing => {}
+                },
+                VirtualKeyCode::F3 => {
+                    game_state.fps_counter.toggle();
+                }
                 VirtualKeyCode::C => {
-                    game_state
-                        .character
-                        .toggle_collision_box_display(&mut game_state.scene);
+                    if let Some(character) = game_state.character.as_mut() {
+                        character.toggle_collision_box_display(&mut game_state.scene);
+                    }
+                    for enemy in game_state.enemies.iter_mut() {
+                        enemy
+                            .character
+                            .toggle_collision_box_display(&mut game_state.scene);
+                    }
                 }
                 _ => {}
             }
@@ -927,9 +878,39 @@ pub fn update_game_state(
         frame_time_seconds = max_delay_catchup_seconds;
     }
     game_state.state_update_time_accumulator += frame_time_seconds;
+    game_state
+        .fps_counter
+        .on_frame(time_tracker.last_frame_time_seconds());
+    game_state
+        .damage_vignette
+        .on_frame(time_tracker.last_frame_time_seconds());
 
     game_state.physics_state.step();
 
+    // enemies hurt the player while any of their colliders touch the player
+    if game_state.is_playing() {
+        let player_collider_handle = game_state.player_controller.collider_handle;
+        let is_touching_enemy = game_state
+            .physics_state
+            .narrow_phase
+            .contacts_with(player_collider_handle)
+            .filter(|contact_pair| contact_pair.has_any_active_contact)
+            .any(|contact_pair| {
+                let other_collider_handle = if contact_pair.collider1 == player_collider_handle {
+                    contact_pair.collider2
+                } else {
+                    contact_pair.collider1
+                };
+                game_state
+                    .enemies
+                    .iter()
+                    .any(|enemy| enemy.character.has_collider(other_collider_handle))
+            });
+        if is_touching_enemy {
+            game_state.damage_player(ENEMY_CONTACT_DAMAGE_PER_SECOND * frame_time_seconds, logger);
+        }
+    }
+
     game_state
         .player_controller
         .update(&mut game_state.physics_state);
@@ -1056,7 +1037,9 @@ pub fn update_game_state(
 
     // remove physics balls over time
     game_state.ball_spawner_acc += frame_time_seconds;
-    let rate = 0.1;
+    let rate = game_state
+        .ball_spawn_interval_seconds
+        .unwrap_or(f32::INFINITY);
     let prev_ball_count = game_state.physics_balls.len();
     while game_state.ball_spawner_acc > rate {
         // let new_ball = BallComponent::rand();
@@ -1107,28 +1090,21 @@ pub fn update_game_state(
         .iter()
         .for_each(|physics_ball| physics_ball.update(&mut game_state.scene, physics_state));
 
-    if let Some(crosshair_node) = game_state.scene.get_node_mut(game_state.crosshair_node_id) {
-        crosshair_node.transform = new_player_transform
-            * TransformBuilder::new()
-                .position(Vector3::new(0.0, 0.0, -NEAR_PLANE_DISTANCE * 2.0))
-                .rotation(make_quat_from_axis_angle(
-                    Vector3::new(0.0, 1.0, 0.0),
-                    Deg(90.0).into(),
-                ))
-                .scale(
-                    (1080.0 / renderer_state.base.window_size.height as f32)
-                        * 0.0001f32
-                        * Vector3::new(1.0, 1.0, 1.0),
-                )
-                .build();
+    if let Some(revolver) = game_state.revolver.as_mut() {
+        revolver.update(
+            game_state.player_controller.view_direction,
+            &mut game_state.scene,
+        );
     }
 
-    game_state.revolver.update(
-        game_state.player_controller.view_direction,
-        &mut game_state.scene,
-    );
-
-    if game_state.mouse_button_pressed && game_state.revolver.fire(&mut game_state.scene) {
+    let fired = game_state.is_playing()
+        && game_state.mouse_button_pressed
+        && game_state
+            .revolver
+            .as_mut()
+            .map(|revolver| revolver.fire(&mut game_state.scene))
+            .unwrap_or(false);
+    if fired {
         game_state
             .audio_manager
             .play_sound(game_state.gunshot_sound_index);
@@ -1191,9 +1167,36 @@ pub fn update_game_state(
                     game_state.physics_balls.remove(ball_index);
                 }
             }
-            game_state
-                .character
-                .handle_hit(&mut game_state.scene, collider_handle);
+            if let Some(character) = game_state.character.as_ref() {
+                character.handle_hit(&mut game_state.scene, collider_handle);
+            }
+            let hit_enemy_index = game_state.enemies.iter().position(|enemy| {
+                enemy
+                    .character
+                    .handle_hit(&mut game_state.scene, collider_handle)
+            });
+            if let Some(enemy_index) = hit_enemy_index {
+                if game_state.enemies[enemy_index].take_damage(REVOLVER_DAMAGE) {
+                    let enemy = game_state.enemies.remove(enemy_index);
+                    enemy.destroy(&mut game_state.scene, &mut game_state.physics_state);
+                    game_state.on_enemy_killed(logger);
+                }
+            }
+        }
+    }
+
+    let player_position = game_state
+        .player_controller
+        .position(&game_state.physics_state);
+    // enemies stand still until the game starts and after the player dies
+    if game_state.is_playing() {
+        for enemy in game_state.enemies.iter_mut() {
+            enemy.update(
+                &mut game_state.scene,
+                &game_state.nav_grid,
+                player_position,
+                frame_time_seconds,
+            );
         }
     }
 
@@ -1205,16 +1208,37 @@ pub fn update_game_state(
         }
     }
 
-    game_state
-        .character
-        .update(scene, &mut game_state.physics_state);
+    if let Some(character) = game_state.character.as_mut() {
+        character.update(scene, &mut game_state.physics_state);
+    }
+    for enemy in game_state.enemies.iter_mut() {
+        enemy.update_colliders(scene, &mut game_state.physics_state);
+    }
+
+    game_state.ui = build_hud(
+        game_state,
+        (
+            renderer_state.base.window_size.width,
+            renderer_state.base.window_size.height,
+        ),
+    );
 }
 
 pub fn init_scene(
     base_renderer_state: &mut BaseRendererState,
+    level: &LevelDescription,
     logger: &mut Logger,
 ) -> Result<(Scene, RenderBuffers)> {
-    let (document, buffers, images) = gltf::import(get_gltf_path())?;
-    validate_animation_property_counts(&document, logger);
-    build_scene(base_renderer_state, (&document, &buffers, &images))
+    match level.assets.first() {
+        Some(asset) => load_level_asset_scene(base_renderer_state, asset, logger),
+        None => Ok((
+            Scene::new(vec![], vec![], vec![], Default::default()),
+            RenderBuffers {
+                binded_pbr_meshes: vec![],
+                binded_unlit_meshes: vec![],
+                binded_wireframe_meshes: vec![],
+                textures: vec![],
+            },
+        )),
+    }
 }
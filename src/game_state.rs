@@ -1,5 +1,13 @@
 use super::*;
 
+// menu -> playing -> dead -> playing (after a restart)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GamePhase {
+    Menu,
+    Playing,
+    Dead,
+}
+
 pub struct GameState {
     pub scene: Scene,
     pub time_tracker: Option<TimeTracker>,
@@ -7,7 +15,7 @@ pub struct GameState {
     pub is_playing_animations: bool,
 
     pub audio_manager: AudioManager,
-    pub bgm_sound_index: usize,
+    pub bgm_sound_index: Option<usize>,
     pub gunshot_sound_index: usize,
     pub gunshot_sound_data: SoundData,
 
@@ -25,10 +33,10 @@ pub struct GameState {
     pub ball_pbr_mesh_index: usize,
 
     pub ball_spawner_acc: f32,
+    pub ball_spawn_interval_seconds: Option<f32>,
 
     pub test_object_node_id: GameNodeId,
-    pub crosshair_node_id: GameNodeId,
-    pub revolver: Revolver,
+    pub revolver: Option<Revolver>,
 
     pub bouncing_ball_node_id: GameNodeId,
     pub bouncing_ball_body_handle: RigidBodyHandle,
@@ -38,8 +46,20 @@ pub struct GameState {
     pub physics_balls: Vec<PhysicsBall>,
     pub mouse_button_pressed: bool,
 
-    pub character: Character,
+    pub character: Option<Character>,
+    pub enemies: Vec<Enemy>,
+    pub nav_grid: NavGrid,
     pub player_controller: PlayerController,
+
+    pub phase: GamePhase,
+    pub player_health: f32,
+    pub score: u32,
+    pub fps_counter: FpsCounter,
+    pub damage_vignette: DamageVignette,
+    // rebuilt at the end of every update, drawn on top of the frame by the renderer
+    pub ui: UiDrawList,
+    // kept around so the scene can be rebuilt on restart
+    pub level: LevelDescription,
 }
 
 impl GameState {
@@ -59,4 +79,35 @@ impl GameState {
     pub fn toggle_animations(&mut self) {
         self.is_playing_animations = !self.is_playing_animations;
     }
+
+    pub fn is_playing(&self) -> bool {
+        self.phase == GamePhase::Playing
+    }
+
+    pub fn start_playing(&mut self, logger: &mut Logger) {
+        if self.phase == GamePhase::Menu {
+            self.phase = GamePhase::Playing;
+            logger.log("Game started");
+        }
+    }
+
+    pub fn damage_player(&mut self, amount: f32, logger: &mut Logger) {
+        if !self.is_playing() {
+            return;
+        }
+        self.player_health = (self.player_health - amount).max(0.0);
+        self.damage_vignette.on_damage();
+        if self.player_health <= 0.0 {
+            self.phase = GamePhase::Dead;
+            logger.log(&format!(
+                "You died! Final score: {}. Press Enter to restart",
+                self.score
+            ));
+        }
+    }
+
+    pub fn on_enemy_killed(&mut self, logger: &mut Logger) {
+        self.score += 1;
+        logger.log(&format!("Enemy killed! Score: {}", self.score));
+    }
 }
@@ -26,7 +26,7 @@ pub fn run(
                 logger.on_frame_completed();
 
                 update_game_state(&mut game_state, &renderer_state, &mut logger);
-                renderer_state.update(&mut game_state, &mut logger);
+                renderer_state.update(RenderInputs::from(&game_state), &mut logger);
 
                 let last_log_time_clone = last_log_time;
                 let mut write_logs = || {
@@ -47,7 +47,7 @@ pub fn run(
                     _ => {}
                 }
 
-                match renderer_state.render(&game_state) {
+                match renderer_state.render(RenderInputs::from(&game_state)) {
                     Ok(_) => {}
                     // Reconfigure the surface if lost
                     Err(wgpu::SurfaceError::Lost) => {
@@ -1,7 +1,8 @@
 use std::collections::HashMap;
+use std::rc::Rc;
 
 use anyhow::{bail, Result};
-use cgmath::{abs_diff_eq, Matrix4, Vector2, Vector3, Vector4};
+use cgmath::{abs_diff_eq, ElementWise, Matrix4, Vector2, Vector3, Vector4};
 
 use super::*;
 
@@ -12,6 +13,7 @@ pub fn build_scene(
         &Vec<gltf::buffer::Data>,
         &Vec<gltf::image::Data>,
     ),
+    logger: &mut Logger,
 ) -> Result<(Scene, RenderBuffers)> {
     let device = &base_renderer_state.device;
     let queue = &base_renderer_state.queue;
@@ -42,41 +44,6 @@ pub fn build_scene(
 
             let (image_pixels, texture_format) = get_image_pixels(image_data, srgb)?;
 
-            let gltf_sampler = texture.sampler();
-            let default_sampler = SamplerDescriptor::default();
-            let address_mode_u = sampler_wrapping_mode_to_wgpu(gltf_sampler.wrap_s());
-            let address_mode_v = sampler_wrapping_mode_to_wgpu(gltf_sampler.wrap_t());
-            let mag_filter = gltf_sampler
-                .mag_filter()
-                .map(|gltf_mag_filter| match gltf_mag_filter {
-                    gltf::texture::MagFilter::Nearest => wgpu::FilterMode::Nearest,
-                    gltf::texture::MagFilter::Linear => wgpu::FilterMode::Linear,
-                })
-                .unwrap_or(default_sampler.mag_filter);
-            let (min_filter, mipmap_filter) = gltf_sampler
-                .min_filter()
-                .map(|gltf_min_filter| match gltf_min_filter {
-                    gltf::texture::MinFilter::Nearest => {
-                        (wgpu::FilterMode::Nearest, default_sampler.mipmap_filter)
-                    }
-                    gltf::texture::MinFilter::Linear => {
-                        (wgpu::FilterMode::Linear, default_sampler.mipmap_filter)
-                    }
-                    gltf::texture::MinFilter::NearestMipmapNearest => {
-                        (wgpu::FilterMode::Nearest, wgpu::FilterMode::Nearest)
-                    }
-                    gltf::texture::MinFilter::LinearMipmapNearest => {
-                        (wgpu::FilterMode::Linear, wgpu::FilterMode::Nearest)
-                    }
-                    gltf::texture::MinFilter::NearestMipmapLinear => {
-                        (wgpu::FilterMode::Nearest, wgpu::FilterMode::Linear)
-                    }
-                    gltf::texture::MinFilter::LinearMipmapLinear => {
-                        (wgpu::FilterMode::Linear, wgpu::FilterMode::Linear)
-                    }
-                })
-                .unwrap_or((default_sampler.min_filter, default_sampler.mipmap_filter));
-
             Texture::from_decoded_image(
                 device,
                 queue,
@@ -94,14 +61,7 @@ pub fn build_scene(
                 //     mipmap_filter: wgpu::FilterMode::Nearest,
                 //     ..Default::default()
                 // }),
-                &SamplerDescriptor(wgpu::SamplerDescriptor {
-                    address_mode_u,
-                    address_mode_v,
-                    mag_filter,
-                    min_filter,
-                    mipmap_filter,
-                    ..Default::default()
-                }),
+                &gltf_sampler_to_wgpu(texture.sampler()),
             )
         })
         .collect::<Result<Vec<_>, _>>()?;
@@ -131,25 +91,18 @@ pub fn build_scene(
     let mut pbr_mesh_vertices: Vec<Vec<Vertex>> = Vec::new();
     // gltf node index -> game node
     let mut node_mesh_links: HashMap<usize, Vec<usize>> = HashMap::new();
+    // gltf material index -> textures bind group of the primitives without morph targets
+    let mut material_textures_bind_groups: HashMap<
+        Option<usize>,
+        (Rc<wgpu::BindGroup>, DynamicPbrParams),
+    > = HashMap::new();
 
     for (binded_pbr_mesh_index, (mesh, primitive_group)) in meshes
         .iter()
         .flat_map(|mesh| mesh.primitives().map(|prim| (&meshes[mesh.index()], prim)))
-        .filter(|(_, prim)| {
-            prim.mode() == gltf::mesh::Mode::Triangles
-                && (prim.material().alpha_mode() == gltf::material::AlphaMode::Opaque
-                    || prim.material().alpha_mode() == gltf::material::AlphaMode::Mask)
-        })
+        .filter(|(_, prim)| prim.mode() == gltf::mesh::Mode::Triangles)
         .enumerate()
     {
-        let (textures_bind_group, dynamic_pbr_params) = build_textures_bind_group(
-            device,
-            queue,
-            &primitive_group.material(),
-            &textures,
-            pbr_textures_bind_group_layout,
-        )?;
-
         let (
             vertices,
             vertex_buffer,
@@ -159,6 +112,39 @@ pub fn build_scene(
             wireframe_index_buffer_format,
             bounding_box,
         ) = build_geometry_buffers(device, &primitive_group, buffers)?;
+
+        let (morph_targets_buffer, morph_target_count) =
+            build_morph_targets_buffer(device, &primitive_group, buffers, vertices.len())?;
+
+        // the morph targets are bound with the textures, so only the primitives without them
+        // can share their material's bind group
+        let material_index = primitive_group.material().index();
+        let (textures_bind_group, dynamic_pbr_params) =
+            match material_textures_bind_groups.get(&material_index) {
+                Some(shared_textures_bind_group) if morph_target_count == 0 => {
+                    shared_textures_bind_group.clone()
+                }
+                _ => {
+                    let (textures_bind_group, dynamic_pbr_params) = build_textures_bind_group(
+                        device,
+                        queue,
+                        &primitive_group.material(),
+                        &textures,
+                        images,
+                        &morph_targets_buffer,
+                        pbr_textures_bind_group_layout,
+                        logger,
+                    )?;
+                    let textures_bind_group = Rc::new(textures_bind_group);
+                    if morph_target_count == 0 {
+                        material_textures_bind_groups.insert(
+                            material_index,
+                            (textures_bind_group.clone(), dynamic_pbr_params),
+                        );
+                    }
+                    (textures_bind_group, dynamic_pbr_params)
+                }
+            };
         let initial_instances: Vec<_> = scene_nodes
             .iter()
             .filter(|node| node.mesh().is_some() && node.mesh().unwrap().index() == mesh.index())
@@ -183,9 +169,7 @@ pub fn build_scene(
         let alpha_mode = match primitive_group.material().alpha_mode() {
             gltf::material::AlphaMode::Opaque => crate::renderer::AlphaMode::Opaque,
             gltf::material::AlphaMode::Mask => crate::renderer::AlphaMode::Mask,
-            gltf::material::AlphaMode::Blend => {
-                todo!("Alpha blending isn't yet supported")
-            }
+            gltf::material::AlphaMode::Blend => crate::renderer::AlphaMode::Blend,
         };
 
         for gltf_node in initial_instances.iter() {
@@ -206,6 +190,8 @@ pub fn build_scene(
             textures_bind_group,
             primitive_mode,
             alpha_mode,
+            double_sided: primitive_group.material().double_sided(),
+            morph_target_count,
         });
 
         binded_wireframe_meshes.push(BindedWireframeMesh {
@@ -234,7 +220,14 @@ pub fn build_scene(
                         material_override: None,
                     },
                     wireframe: false,
+                    cast_shadows: true,
+                    receive_shadows: true,
                 }),
+            morph_target_weights: node
+                .weights()
+                .or_else(|| node.mesh().and_then(|mesh| mesh.weights()))
+                .map(|weights| weights.to_vec())
+                .unwrap_or_default(),
         })
         .collect();
 
@@ -413,7 +406,12 @@ fn validate_channel_data_type(channel: &gltf::animation::Channel) -> Result<()>
             }
         }
         gltf::animation::Property::MorphTargetWeights => {
-            bail!("MorphTargetWeights not supported")
+            if dimensions != gltf::accessor::Dimensions::Scalar {
+                bail!("Expected scalar data but found: {:?}", dimensions);
+            }
+            if data_type != gltf::accessor::DataType::F32 {
+                bail!("Expected f32 data but found: {:?}", data_type);
+            }
         }
     };
     Ok(())
@@ -497,6 +495,50 @@ fn texture_format_to_wgpu(format: gltf::image::Format, srgb: bool) -> Result<wgp
     }
 }
 
+fn gltf_sampler_to_wgpu(gltf_sampler: gltf::texture::Sampler) -> SamplerDescriptor<'static> {
+    let default_sampler = SamplerDescriptor::default();
+    let address_mode_u = sampler_wrapping_mode_to_wgpu(gltf_sampler.wrap_s());
+    let address_mode_v = sampler_wrapping_mode_to_wgpu(gltf_sampler.wrap_t());
+    let mag_filter = gltf_sampler
+        .mag_filter()
+        .map(|gltf_mag_filter| match gltf_mag_filter {
+            gltf::texture::MagFilter::Nearest => wgpu::FilterMode::Nearest,
+            gltf::texture::MagFilter::Linear => wgpu::FilterMode::Linear,
+        })
+        .unwrap_or(default_sampler.mag_filter);
+    let (min_filter, mipmap_filter) = gltf_sampler
+        .min_filter()
+        .map(|gltf_min_filter| match gltf_min_filter {
+            gltf::texture::MinFilter::Nearest => {
+                (wgpu::FilterMode::Nearest, default_sampler.mipmap_filter)
+            }
+            gltf::texture::MinFilter::Linear => {
+                (wgpu::FilterMode::Linear, default_sampler.mipmap_filter)
+            }
+            gltf::texture::MinFilter::NearestMipmapNearest => {
+                (wgpu::FilterMode::Nearest, wgpu::FilterMode::Nearest)
+            }
+            gltf::texture::MinFilter::LinearMipmapNearest => {
+                (wgpu::FilterMode::Linear, wgpu::FilterMode::Nearest)
+            }
+            gltf::texture::MinFilter::NearestMipmapLinear => {
+                (wgpu::FilterMode::Nearest, wgpu::FilterMode::Linear)
+            }
+            gltf::texture::MinFilter::LinearMipmapLinear => {
+                (wgpu::FilterMode::Linear, wgpu::FilterMode::Linear)
+            }
+        })
+        .unwrap_or((default_sampler.min_filter, default_sampler.mipmap_filter));
+    SamplerDescriptor(wgpu::SamplerDescriptor {
+        address_mode_u,
+        address_mode_v,
+        mag_filter,
+        min_filter,
+        mipmap_filter,
+        ..Default::default()
+    })
+}
+
 fn sampler_wrapping_mode_to_wgpu(wrapping_mode: gltf::texture::WrappingMode) -> wgpu::AddressMode {
     match wrapping_mode {
         gltf::texture::WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
@@ -535,16 +577,36 @@ fn build_textures_bind_group(
     queue: &wgpu::Queue,
     material: &gltf::material::Material,
     textures: &[Texture],
+    images: &[gltf::image::Data],
+    morph_targets_buffer: &GpuBuffer,
     five_texture_bind_group_layout: &wgpu::BindGroupLayout,
+    logger: &mut Logger,
 ) -> Result<(wgpu::BindGroup, DynamicPbrParams)> {
     let pbr_info = material.pbr_metallic_roughness();
 
+    // the factors are baked into the converted textures so they're left at 1.0 below
+    let specular_glossiness_textures = match material.pbr_specular_glossiness() {
+        Some(spec_gloss) => {
+            let converted_textures =
+                build_specular_glossiness_textures(device, queue, &spec_gloss, images)?;
+            if converted_textures.is_none() {
+                logger.log(&format!(
+                    "Material {:?} uses different texture coordinate sets for its diffuse and specular-glossiness textures, falling back to its metallic-roughness parameters",
+                    material.name().unwrap_or("unnamed")
+                ));
+            }
+            converted_textures
+        }
+        None => None,
+    };
+
     let material_diffuse_texture = pbr_info.base_color_texture().map(|info| info.texture());
     // material_diffuse_texture = None;
     let auto_generated_diffuse_texture;
-    let diffuse_texture = match material_diffuse_texture {
-        Some(diffuse_texture) => &textures[diffuse_texture.index()],
-        None => {
+    let diffuse_texture = match (&specular_glossiness_textures, material_diffuse_texture) {
+        (Some((converted_diffuse_texture, _)), _) => converted_diffuse_texture,
+        (None, Some(diffuse_texture)) => &textures[diffuse_texture.index()],
+        (None, None) => {
             auto_generated_diffuse_texture =
                 Texture::from_color(device, queue, [255, 255, 255, 255])?;
             &auto_generated_diffuse_texture
@@ -556,9 +618,13 @@ fn build_textures_bind_group(
         .map(|info| info.texture());
     // material_metallic_roughness_map = None;
     let auto_generated_metallic_roughness_map;
-    let metallic_roughness_map = match material_metallic_roughness_map {
-        Some(metallic_roughness_map) => &textures[metallic_roughness_map.index()],
-        None => {
+    let metallic_roughness_map = match (
+        &specular_glossiness_textures,
+        material_metallic_roughness_map,
+    ) {
+        (Some((_, converted_metallic_roughness_map)), _) => converted_metallic_roughness_map,
+        (None, Some(metallic_roughness_map)) => &textures[metallic_roughness_map.index()],
+        (None, None) => {
             auto_generated_metallic_roughness_map =
                 Texture::from_color(device, queue, [255, 255, 255, 255])?;
             &auto_generated_metallic_roughness_map
@@ -642,15 +708,29 @@ fn build_textures_bind_group(
                 binding: 9,
                 resource: wgpu::BindingResource::Sampler(&ambient_occlusion_map.sampler),
             },
+            wgpu::BindGroupEntry {
+                binding: 10,
+                resource: morph_targets_buffer.src().as_entire_binding(),
+            },
         ],
         label: Some("InstancedMeshComponent textures_bind_group"),
     });
 
+    let (base_color_factor, metallic_factor, roughness_factor) = match specular_glossiness_textures
+    {
+        Some(_) => (Vector4::new(1.0, 1.0, 1.0, 1.0), 1.0, 1.0),
+        None => (
+            Vector4::from(pbr_info.base_color_factor()),
+            pbr_info.metallic_factor(),
+            pbr_info.roughness_factor(),
+        ),
+    };
+
     let dynamic_pbr_params = DynamicPbrParams {
-        base_color_factor: Vector4::from(pbr_info.base_color_factor()),
+        base_color_factor,
         emissive_factor: Vector3::from(material.emissive_factor()),
-        metallic_factor: pbr_info.metallic_factor(),
-        roughness_factor: pbr_info.roughness_factor(),
+        metallic_factor,
+        roughness_factor,
         normal_scale: material
             .normal_texture()
             .map(|info| info.scale())
@@ -663,11 +743,216 @@ fn build_textures_bind_group(
             gltf::material::AlphaMode::Mask => material.alpha_cutoff().unwrap_or(0.5),
             _ => DynamicPbrParams::default().alpha_cutoff,
         },
+        ..Default::default()
     };
 
     Ok((textures_bind_group, dynamic_pbr_params))
 }
 
+// converts KHR_materials_pbrSpecularGlossiness into a base color texture and a
+// metallic-roughness texture so it can go through the regular pbr shader. returns None when the two
+// textures use different texture coordinate sets since they can't be combined pixel by pixel, the
+// material then falls back to its metallic-roughness parameters.
+// conversion from https://github.com/KhronosGroup/glTF/tree/main/extensions/2.0/Archived/KHR_materials_pbrSpecularGlossiness/examples/convert-between-workflows
+fn build_specular_glossiness_textures(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    spec_gloss: &gltf::material::PbrSpecularGlossiness,
+    images: &[gltf::image::Data],
+) -> Result<Option<(Texture, Texture)>> {
+    let diffuse_texture_info = spec_gloss.diffuse_texture();
+    let specular_glossiness_texture_info = spec_gloss.specular_glossiness_texture();
+    if let (Some(diffuse_texture_info), Some(specular_glossiness_texture_info)) =
+        (&diffuse_texture_info, &specular_glossiness_texture_info)
+    {
+        if diffuse_texture_info.tex_coord() != specular_glossiness_texture_info.tex_coord() {
+            return Ok(None);
+        }
+    }
+    // the baked textures are sampled like the diffuse texture, or the specular-glossiness one if
+    // there's no diffuse texture
+    let sampler = diffuse_texture_info
+        .as_ref()
+        .or(specular_glossiness_texture_info.as_ref())
+        .map(|info| gltf_sampler_to_wgpu(info.texture().sampler()))
+        .unwrap_or_default();
+
+    let load_image = |texture: Option<gltf::texture::Texture>| -> Result<Option<image::RgbaImage>> {
+        texture
+            .map(|texture| {
+                let image_data = &images[texture.source().index()];
+                let (image_pixels, texture_format) = get_image_pixels(image_data, true)?;
+                if texture_format != wgpu::TextureFormat::Rgba8UnormSrgb {
+                    bail!(
+                        "Unsupported specular-glossiness texture format: {:?}",
+                        image_data.format
+                    );
+                }
+                image::RgbaImage::from_raw(image_data.width, image_data.height, image_pixels)
+                    .ok_or_else(|| anyhow::anyhow!("Failed to decode specular-glossiness image"))
+            })
+            .transpose()
+    };
+    let diffuse_image = load_image(diffuse_texture_info.map(|info| info.texture()))?;
+    let specular_glossiness_image =
+        load_image(specular_glossiness_texture_info.map(|info| info.texture()))?;
+
+    let (width, height) = [&diffuse_image, &specular_glossiness_image]
+        .iter()
+        .filter_map(|image| image.as_ref().map(|image| image.dimensions()))
+        .fold((1, 1), |(width, height), (image_width, image_height)| {
+            (width.max(image_width), height.max(image_height))
+        });
+    let resize = |image: Option<image::RgbaImage>| {
+        image.map(|image| {
+            if image.dimensions() == (width, height) {
+                image
+            } else {
+                image::imageops::resize(&image, width, height, image::imageops::Triangle)
+            }
+        })
+    };
+    let diffuse_image = resize(diffuse_image);
+    let specular_glossiness_image = resize(specular_glossiness_image);
+
+    let diffuse_factor = Vector4::from(spec_gloss.diffuse_factor());
+    let specular_factor = Vector3::from(spec_gloss.specular_factor());
+    let glossiness_factor = spec_gloss.glossiness_factor();
+
+    let mut base_color_image = image::RgbaImage::new(width, height);
+    let mut metallic_roughness_image = image::RgbaImage::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let diffuse = diffuse_image
+                .as_ref()
+                .map(|image| {
+                    let [r, g, b, a] = image.get_pixel(x, y).0;
+                    Vector4::new(
+                        _to_srgb(r as f32 / 255.0),
+                        _to_srgb(g as f32 / 255.0),
+                        _to_srgb(b as f32 / 255.0),
+                        a as f32 / 255.0,
+                    )
+                })
+                .unwrap_or_else(|| Vector4::new(1.0, 1.0, 1.0, 1.0))
+                .mul_element_wise(diffuse_factor);
+            let (specular, glossiness) = specular_glossiness_image
+                .as_ref()
+                .map(|image| {
+                    let [r, g, b, a] = image.get_pixel(x, y).0;
+                    (
+                        Vector3::new(
+                            _to_srgb(r as f32 / 255.0),
+                            _to_srgb(g as f32 / 255.0),
+                            _to_srgb(b as f32 / 255.0),
+                        ),
+                        a as f32 / 255.0,
+                    )
+                })
+                .unwrap_or_else(|| (Vector3::new(1.0, 1.0, 1.0), 1.0));
+            let specular = specular.mul_element_wise(specular_factor);
+            let glossiness = glossiness * glossiness_factor;
+
+            let (base_color, metallic) =
+                specular_glossiness_to_metallic_roughness(diffuse.truncate(), specular);
+
+            base_color_image.put_pixel(
+                x,
+                y,
+                image::Rgba([
+                    (_from_srgb(base_color.x) * 255.0).round() as u8,
+                    (_from_srgb(base_color.y) * 255.0).round() as u8,
+                    (_from_srgb(base_color.z) * 255.0).round() as u8,
+                    (diffuse.w.clamp(0.0, 1.0) * 255.0).round() as u8,
+                ]),
+            );
+            metallic_roughness_image.put_pixel(
+                x,
+                y,
+                image::Rgba([
+                    0,
+                    ((1.0 - glossiness).clamp(0.0, 1.0) * 255.0).round() as u8,
+                    (metallic * 255.0).round() as u8,
+                    255,
+                ]),
+            );
+        }
+    }
+
+    let base_color_texture = Texture::from_decoded_image(
+        device,
+        queue,
+        &base_color_image,
+        (width, height),
+        Some("specular-glossiness converted base color texture"),
+        wgpu::TextureFormat::Rgba8UnormSrgb.into(),
+        true,
+        &sampler,
+    )?;
+    let metallic_roughness_texture = Texture::from_decoded_image(
+        device,
+        queue,
+        &metallic_roughness_image,
+        (width, height),
+        Some("specular-glossiness converted metallic roughness texture"),
+        wgpu::TextureFormat::Rgba8Unorm.into(),
+        true,
+        &sampler,
+    )?;
+
+    Ok(Some((base_color_texture, metallic_roughness_texture)))
+}
+
+// returns (base color, metallic), all in linear space
+fn specular_glossiness_to_metallic_roughness(
+    diffuse: Vector3<f32>,
+    specular: Vector3<f32>,
+) -> (Vector3<f32>, f32) {
+    const DIELECTRIC_SPECULAR: f32 = 0.04;
+    const EPSILON: f32 = 1e-6;
+
+    let perceived_brightness =
+        |c: Vector3<f32>| (0.299 * c.x * c.x + 0.587 * c.y * c.y + 0.114 * c.z * c.z).sqrt();
+
+    let one_minus_specular_strength = 1.0 - specular.x.max(specular.y).max(specular.z);
+    let diffuse_brightness = perceived_brightness(diffuse);
+    let specular_brightness = perceived_brightness(specular);
+
+    let metallic = if specular_brightness < DIELECTRIC_SPECULAR {
+        0.0
+    } else {
+        let a = DIELECTRIC_SPECULAR;
+        let b = diffuse_brightness * one_minus_specular_strength / (1.0 - DIELECTRIC_SPECULAR)
+            + specular_brightness
+            - 2.0 * DIELECTRIC_SPECULAR;
+        let c = DIELECTRIC_SPECULAR - specular_brightness;
+        let discriminant = (b * b - 4.0 * a * c).max(0.0);
+        ((-b + discriminant.sqrt()) / (2.0 * a)).clamp(0.0, 1.0)
+    };
+
+    let base_color_from_diffuse = diffuse
+        * (one_minus_specular_strength
+            / (1.0 - DIELECTRIC_SPECULAR)
+            / (1.0 - metallic).max(EPSILON));
+    let base_color_from_specular = (specular
+        - Vector3::new(1.0, 1.0, 1.0) * (DIELECTRIC_SPECULAR * (1.0 - metallic)))
+        / metallic.max(EPSILON);
+    let base_color = lerp_vec(
+        base_color_from_diffuse,
+        base_color_from_specular,
+        metallic * metallic,
+    );
+
+    (
+        Vector3::new(
+            base_color.x.clamp(0.0, 1.0),
+            base_color.y.clamp(0.0, 1.0),
+            base_color.z.clamp(0.0, 1.0),
+        ),
+        metallic,
+    )
+}
+
 pub fn get_buffer_slice_from_accessor(
     accessor: gltf::Accessor,
     buffers: &[gltf::buffer::Data],
@@ -1109,11 +1394,12 @@ pub fn build_geometry_buffers(
         })
         .collect();
 
+    // copied into the arenas of GpuDrivenMeshes
     let vertex_buffer = GpuBuffer::from_bytes(
         device,
         bytemuck::cast_slice(&vertices_with_all_data),
         std::mem::size_of::<Vertex>(),
-        wgpu::BufferUsages::VERTEX,
+        wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
     );
 
     let into_index_buffer = |indices: &Vec<u32>| {
@@ -1142,7 +1428,7 @@ pub fn build_geometry_buffers(
                     wgpu::IndexFormat::Uint16 => std::mem::size_of::<u16>(),
                     wgpu::IndexFormat::Uint32 => std::mem::size_of::<u32>(),
                 },
-                wgpu::BufferUsages::INDEX,
+                wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_SRC,
             ),
             index_buffer_format,
         )
@@ -1177,6 +1463,67 @@ pub fn build_geometry_buffers(
     ))
 }
 
+// returns the buffer and the number of morph targets in it
+fn build_morph_targets_buffer(
+    device: &wgpu::Device,
+    primitive_group: &gltf::mesh::Primitive,
+    buffers: &[gltf::buffer::Data],
+    vertex_count: usize,
+) -> Result<(GpuBuffer, usize)> {
+    let get_displacements = |accessor: Option<gltf::Accessor>| {
+        accessor
+            .map(|accessor| {
+                let data_type = accessor.data_type();
+                let dimensions = accessor.dimensions();
+                if dimensions != gltf::accessor::Dimensions::Vec3 {
+                    bail!("Expected vec3 data but found: {:?}", dimensions);
+                }
+                if data_type != gltf::accessor::DataType::F32 {
+                    bail!("Expected f32 data but found: {:?}", data_type);
+                }
+                let displacements_u8 = get_buffer_slice_from_accessor(accessor, buffers);
+                let displacements = bytemuck::cast_slice::<_, [f32; 3]>(&displacements_u8).to_vec();
+                if displacements.len() != vertex_count {
+                    bail!(
+                        "Expected a morph target displacement for every vertex but found: vertex_count({:?}) != displacement_count({:?})",
+                        vertex_count,
+                        displacements.len()
+                    );
+                }
+                Ok(displacements)
+            })
+            .transpose()
+    };
+
+    let mut displacements: Vec<GpuMorphTargetDisplacement> = Vec::new();
+    for morph_target in primitive_group.morph_targets() {
+        let positions = get_displacements(morph_target.positions())?;
+        let normals = get_displacements(morph_target.normals())?;
+        let tangents = get_displacements(morph_target.tangents())?;
+        let to_vec4 = |displacements: &Option<Vec<[f32; 3]>>, vertex_index: usize| {
+            displacements
+                .as_ref()
+                .map(|displacements| {
+                    let [x, y, z] = displacements[vertex_index];
+                    [x, y, z, 0.0]
+                })
+                .unwrap_or([0.0, 0.0, 0.0, 0.0])
+        };
+        displacements.extend(
+            (0..vertex_count).map(|vertex_index| GpuMorphTargetDisplacement {
+                position: to_vec4(&positions, vertex_index),
+                normal: to_vec4(&normals, vertex_index),
+                tangent: to_vec4(&tangents, vertex_index),
+            }),
+        );
+    }
+
+    Ok((
+        make_morph_targets_buffer(device, vertex_count, &displacements),
+        primitive_group.morph_targets().len(),
+    ))
+}
+
 #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
 pub struct ChannelPropertyStr<'a>(&'a str);
 
@@ -0,0 +1,788 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+
+use anyhow::Result;
+use bytemuck::Zeroable;
+use wgpu::util::DeviceExt;
+
+use super::*;
+
+const CULLING_WORKGROUP_SIZE: u32 = 64;
+// marks the instance slots that aren't culled, see gpu_culling.wgsl
+const NO_DRAW_COMMAND: u32 = u32::MAX;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuCullingConfig {
+    // pointing inwards, see Frustum in culling.rs
+    frustum_planes: [[f32; 4]; 6],
+    // the buffers are bound whole so they can be longer than this
+    instance_count: u32,
+    // 1 for the shadow passes
+    shadow_casters_only: u32,
+    padding: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuInstanceCullingInfo {
+    bounding_sphere: [f32; 4],
+    draw_command_index: u32,
+    cast_shadows: u32,
+    padding: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuDrawIndexedIndirect {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+// where a mesh's geometry ended up in the arenas
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct ArenaMesh {
+    index_format: wgpu::IndexFormat,
+    first_index: u32,
+    index_count: u32,
+    base_vertex: i32,
+}
+
+// the byte offsets to copy a mesh's vertex and index buffers to
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct ArenaPlacement {
+    vertex_offset: u64,
+    index_offset: u64,
+    // padded to 4 bytes like the mesh's own index buffer
+    index_size: u64,
+}
+
+// in bytes
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ArenaSizes {
+    vertex: u64,
+    u16_index: u64,
+    u32_index: u64,
+}
+
+// meshes: the vertex count, index format and index count of each binded pbr mesh, None for the ones
+// that aren't gpu-driven. the meshes are packed one after the other, and since buffer copies need 4 byte
+// aligned sizes and offsets, a u16 mesh with an odd index count leaves 2 bytes of padding behind it
+fn place_meshes_in_arenas(
+    meshes: &[Option<(usize, wgpu::IndexFormat, usize)>],
+) -> (Vec<Option<(ArenaMesh, ArenaPlacement)>>, ArenaSizes) {
+    let align =
+        |size: u64| (size + wgpu::COPY_BUFFER_ALIGNMENT - 1) & !(wgpu::COPY_BUFFER_ALIGNMENT - 1);
+    let vertex_size = std::mem::size_of::<Vertex>() as u64;
+
+    let mut sizes = ArenaSizes::default();
+    let placements = meshes
+        .iter()
+        .map(|mesh| {
+            let (vertex_count, index_format, index_count) = (*mesh)?;
+            let (index_arena_size, index_size) = match index_format {
+                wgpu::IndexFormat::Uint16 => {
+                    (&mut sizes.u16_index, std::mem::size_of::<u16>() as u64)
+                }
+                wgpu::IndexFormat::Uint32 => {
+                    (&mut sizes.u32_index, std::mem::size_of::<u32>() as u64)
+                }
+            };
+            let placement = ArenaPlacement {
+                vertex_offset: sizes.vertex,
+                index_offset: *index_arena_size,
+                index_size: align(index_count as u64 * index_size),
+            };
+            sizes.vertex += vertex_count as u64 * vertex_size;
+            *index_arena_size += placement.index_size;
+            Some((
+                ArenaMesh {
+                    index_format,
+                    first_index: (placement.index_offset / index_size) as u32,
+                    index_count: index_count as u32,
+                    base_vertex: (placement.vertex_offset / vertex_size) as i32,
+                },
+                placement,
+            ))
+        })
+        .collect();
+    (placements, sizes)
+}
+
+// the meshes that can share a multi draw, they're drawn with the same pipeline and bind groups
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+struct DrawGroupKey {
+    opaque: bool,
+    double_sided: bool,
+    textures_bind_group: *const wgpu::BindGroup,
+    bones_bind_group_offsets: [u32; 4],
+    index_format: wgpu::IndexFormat,
+}
+
+// a run of draw commands that's drawn with the pipeline and the bind groups of one of its meshes
+#[derive(Debug, Clone)]
+pub struct GpuDrivenDrawGroup {
+    pub binded_pbr_mesh_index: usize,
+    // the previous model transforms are indexed by instance_index, which starts at the mesh's slice,
+    // so their offset is always 0
+    pub bones_bind_group_offsets: [u32; 4],
+    index_format: wgpu::IndexFormat,
+    draw_commands: Range<u32>,
+}
+
+// the indices of the meshes with the same key, in the order of their first mesh. None for the meshes
+// that aren't drawn
+fn group_meshes<K: Copy + Eq + std::hash::Hash>(keys: &[Option<K>]) -> Vec<(K, Vec<usize>)> {
+    let mut group_indices: HashMap<K, usize> = HashMap::new();
+    let mut groups: Vec<(K, Vec<usize>)> = vec![];
+    for (mesh_index, key) in keys.iter().enumerate() {
+        let key = match key {
+            Some(key) => *key,
+            None => continue,
+        };
+        let group_index = *group_indices.entry(key).or_insert_with(|| {
+            groups.push((key, vec![]));
+            groups.len() - 1
+        });
+        groups[group_index].1.push(mesh_index);
+    }
+    groups
+}
+
+// the geometry of every binded pbr mesh copied into one vertex buffer and one index buffer per index format
+struct Arenas {
+    vertex_buffer: wgpu::Buffer,
+    u16_index_buffer: wgpu::Buffer,
+    u32_index_buffer: wgpu::Buffer,
+    // by binded pbr mesh index, None for the meshes that aren't gpu-driven
+    meshes: Vec<Option<ArenaMesh>>,
+}
+
+// the optional gpu-driven path for the opaque and alpha masked pbr meshes, in the camera and the shadow
+// passes. the meshes' geometry is merged into shared arenas and their instances are uploaded once per
+// frame into a single buffer, which replaces the meshes' own instance buffers. every pass frustum culls
+// it with a compute pass into the indirect draw commands, and the meshes that share a pipeline,
+// material and bones are drawn together with one multi_draw_indexed_indirect when the device supports it.
+// the meshes with morph targets or alpha blending take the regular path
+pub struct GpuDrivenMeshes {
+    multi_draw_indirect: bool,
+    culling_pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    // recreated when one of its buffers is reallocated
+    bind_group: Option<wgpu::BindGroup>,
+    config_buffer: wgpu::Buffer,
+    arenas: Option<Arenas>,
+    // all instances of all meshes, laid out like the previous model transforms of RendererState
+    // so that the culled ones can be bound with the same dynamic offsets
+    instances_buffer: GpuBuffer,
+    instance_culling_infos_buffer: GpuBuffer,
+    culled_instances_buffer: GpuBuffer,
+    culled_previous_model_transforms_buffer: GpuBuffer,
+    // one per gpu-driven mesh with instances, in the order of the draw groups. reset to draw
+    // no instances before every culling pass
+    draw_commands: Vec<GpuDrawIndexedIndirect>,
+    draw_commands_buffer: GpuBuffer,
+    draw_groups: Vec<GpuDrivenDrawGroup>,
+}
+
+impl GpuDrivenMeshes {
+    // requested with the device when the adapter has them. the culled instances of each mesh are
+    // drawn from its own slice through first_instance, multi draws are optional
+    pub const OPTIONAL_FEATURES: wgpu::Features =
+        wgpu::Features::INDIRECT_FIRST_INSTANCE.union(wgpu::Features::MULTI_DRAW_INDIRECT);
+
+    pub fn is_supported(adapter: &wgpu::Adapter, device: &wgpu::Device) -> bool {
+        adapter.get_downlevel_capabilities().flags.contains(
+            wgpu::DownlevelFlags::COMPUTE_SHADERS | wgpu::DownlevelFlags::INDIRECT_EXECUTION,
+        ) && device
+            .features()
+            .contains(wgpu::Features::INDIRECT_FIRST_INSTANCE)
+    }
+
+    pub fn supports_multi_draw(device: &wgpu::Device) -> bool {
+        device
+            .features()
+            .contains(wgpu::Features::MULTI_DRAW_INDIRECT)
+    }
+
+    // morph targets are looked up by vertex_index, which would be offset by the mesh's base vertex in
+    // the arena, and the blended meshes need to be sorted on the cpu
+    pub fn can_draw_mesh(binded_pbr_mesh: &BindedPbrMesh) -> bool {
+        binded_pbr_mesh.morph_target_count == 0 && binded_pbr_mesh.alpha_mode != AlphaMode::Blend
+    }
+
+    pub fn new(device: &wgpu::Device) -> Result<Self> {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("GPU Culling Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                std::fs::read_to_string("./src/shaders/gpu_culling.wgsl")?.into(),
+            ),
+        });
+
+        let storage_buffer_entry = |binding: u32, read_only: bool| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_buffer_entry(1, true),
+                storage_buffer_entry(2, true),
+                storage_buffer_entry(3, true),
+                storage_buffer_entry(4, false),
+                storage_buffer_entry(5, false),
+                storage_buffer_entry(6, false),
+            ],
+            label: Some("gpu_culling_bind_group_layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("GPU Culling Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let culling_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("GPU Culling Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "cull_instances",
+        });
+
+        let config_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("GPU Culling Config Buffer"),
+            contents: bytemuck::cast_slice(&[GpuCullingConfig::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let storage_buffer = |stride: usize, usage: wgpu::BufferUsages| {
+            GpuBuffer::empty(
+                device,
+                1,
+                stride,
+                wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | usage,
+            )
+        };
+
+        Ok(Self {
+            multi_draw_indirect: Self::supports_multi_draw(device),
+            culling_pipeline,
+            bind_group_layout,
+            bind_group: None,
+            config_buffer,
+            arenas: None,
+            instances_buffer: storage_buffer(
+                std::mem::size_of::<GpuPbrMeshInstance>(),
+                wgpu::BufferUsages::empty(),
+            ),
+            instance_culling_infos_buffer: storage_buffer(
+                std::mem::size_of::<GpuInstanceCullingInfo>(),
+                wgpu::BufferUsages::empty(),
+            ),
+            culled_instances_buffer: storage_buffer(
+                std::mem::size_of::<GpuPbrMeshInstance>(),
+                wgpu::BufferUsages::VERTEX,
+            ),
+            culled_previous_model_transforms_buffer: storage_buffer(
+                std::mem::size_of::<GpuMatrix4>(),
+                wgpu::BufferUsages::empty(),
+            ),
+            draw_commands: vec![],
+            draw_commands_buffer: storage_buffer(
+                std::mem::size_of::<GpuDrawIndexedIndirect>(),
+                wgpu::BufferUsages::INDIRECT,
+            ),
+            draw_groups: vec![],
+        })
+    }
+
+    // the arenas point into the meshes' own buffers, so they must be dropped when the render buffers
+    // are replaced. they're rebuilt by the next update
+    pub fn invalidate_arenas(&mut self) {
+        self.arenas = None;
+        self.draw_commands.clear();
+        self.draw_groups.clear();
+    }
+
+    // instances: by binded pbr mesh index, each instance with its world space bounds and whether it casts shadows.
+    // previous_model_transform_offsets: where each mesh's slice starts in previous_model_transforms_buffer, in bytes.
+    // bones_bind_group_offsets: the dynamic offsets of each mesh in the regular bones bind group.
+    // returns true when culled_previous_model_transforms_buffer was reallocated
+    #[allow(clippy::too_many_arguments)]
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        binded_pbr_meshes: &[BindedPbrMesh],
+        instances: &[Vec<(GpuPbrMeshInstance, BoundingSphere, bool)>],
+        previous_model_transform_offsets: &[u32],
+        bones_bind_group_offsets: &[[u32; 4]],
+        previous_model_transforms_buffer: &GpuBuffer,
+        previous_model_transforms_buffer_resized: bool,
+        logger: &mut Logger,
+    ) -> bool {
+        // the binded pbr meshes are only ever appended to, the arenas are dropped by
+        // invalidate_arenas when they're replaced
+        if self
+            .arenas
+            .as_ref()
+            .map(|arenas| arenas.meshes.len() != binded_pbr_meshes.len())
+            .unwrap_or(true)
+        {
+            self.arenas = Some(Self::make_arenas(device, queue, binded_pbr_meshes));
+            logger.log(&format!(
+                "Copied {:?} pbr meshes into the gpu-driven arenas",
+                binded_pbr_meshes.len()
+            ));
+        }
+        let arenas = self.arenas.as_ref().unwrap();
+
+        let groups = group_meshes(
+            &instances
+                .iter()
+                .enumerate()
+                .map(|(mesh_index, mesh_instances)| {
+                    let arena_mesh =
+                        arenas.meshes[mesh_index].filter(|_| !mesh_instances.is_empty())?;
+                    let binded_pbr_mesh = &binded_pbr_meshes[mesh_index];
+                    let mut offsets = bones_bind_group_offsets[mesh_index];
+                    offsets[3] = 0;
+                    Some(DrawGroupKey {
+                        opaque: binded_pbr_mesh.alpha_mode == AlphaMode::Opaque,
+                        double_sided: binded_pbr_mesh.double_sided,
+                        textures_bind_group: Rc::as_ptr(&binded_pbr_mesh.textures_bind_group),
+                        bones_bind_group_offsets: offsets,
+                        index_format: arena_mesh.index_format,
+                    })
+                })
+                .collect::<Vec<_>>(),
+        );
+
+        let mut draw_command_indices = vec![NO_DRAW_COMMAND; binded_pbr_meshes.len()];
+        self.draw_commands.clear();
+        self.draw_groups.clear();
+        for (key, mesh_indices) in groups {
+            let first_draw_command = self.draw_commands.len() as u32;
+            for &mesh_index in &mesh_indices {
+                let arena_mesh = arenas.meshes[mesh_index].unwrap();
+                draw_command_indices[mesh_index] = self.draw_commands.len() as u32;
+                self.draw_commands.push(GpuDrawIndexedIndirect {
+                    index_count: arena_mesh.index_count,
+                    instance_count: 0,
+                    first_index: arena_mesh.first_index,
+                    base_vertex: arena_mesh.base_vertex,
+                    first_instance: previous_model_transform_offsets[mesh_index]
+                        / std::mem::size_of::<GpuMatrix4>() as u32,
+                });
+            }
+            self.draw_groups.push(GpuDrivenDrawGroup {
+                binded_pbr_mesh_index: mesh_indices[0],
+                bones_bind_group_offsets: key.bones_bind_group_offsets,
+                index_format: key.index_format,
+                draw_commands: first_draw_command..self.draw_commands.len() as u32,
+            });
+        }
+
+        let slot_count = previous_model_transforms_buffer.length();
+        let mut flattened_instances = vec![GpuPbrMeshInstance::zeroed(); slot_count];
+        let mut instance_culling_infos = vec![
+            GpuInstanceCullingInfo {
+                draw_command_index: NO_DRAW_COMMAND,
+                ..Zeroable::zeroed()
+            };
+            slot_count
+        ];
+        for (mesh_index, mesh_instances) in instances.iter().enumerate() {
+            let draw_command_index = draw_command_indices[mesh_index];
+            if draw_command_index == NO_DRAW_COMMAND {
+                continue;
+            }
+            let instance_offset =
+                self.draw_commands[draw_command_index as usize].first_instance as usize;
+            for (slot_index, (instance, bounds, cast_shadows)) in mesh_instances
+                .iter()
+                .enumerate()
+                .map(|(index, instance)| (instance_offset + index, instance))
+            {
+                flattened_instances[slot_index] = *instance;
+                instance_culling_infos[slot_index] = GpuInstanceCullingInfo {
+                    bounding_sphere: bounds.center.extend(bounds.radius).into(),
+                    draw_command_index,
+                    cast_shadows: *cast_shadows as u32,
+                    padding: [0; 2],
+                };
+            }
+        }
+
+        let mut resized = previous_model_transforms_buffer_resized;
+        resized |=
+            self.instances_buffer
+                .write(device, queue, bytemuck::cast_slice(&flattened_instances));
+        resized |= self.instance_culling_infos_buffer.write(
+            device,
+            queue,
+            bytemuck::cast_slice(&instance_culling_infos),
+        );
+        resized |= self.draw_commands_buffer.write(
+            device,
+            queue,
+            bytemuck::cast_slice(&self.draw_commands),
+        );
+        // only written by the culling pass, they just need to be as long as the inputs
+        let mut culled_previous_model_transforms_resized = false;
+        for (buffer, input_buffer, buffer_resized) in [
+            (
+                &mut self.culled_instances_buffer,
+                &self.instances_buffer,
+                &mut resized,
+            ),
+            (
+                &mut self.culled_previous_model_transforms_buffer,
+                previous_model_transforms_buffer,
+                &mut culled_previous_model_transforms_resized,
+            ),
+        ] {
+            if buffer.capacity_bytes() < input_buffer.length_bytes() {
+                *buffer = GpuBuffer::empty(
+                    device,
+                    input_buffer.capacity_bytes() / buffer.stride(),
+                    buffer.stride(),
+                    buffer.usage(),
+                );
+                *buffer_resized = true;
+            }
+        }
+
+        if resized || culled_previous_model_transforms_resized || self.bind_group.is_none() {
+            fn buffer_entry(binding: u32, buffer: &wgpu::Buffer) -> wgpu::BindGroupEntry<'_> {
+                wgpu::BindGroupEntry {
+                    binding,
+                    resource: buffer.as_entire_binding(),
+                }
+            }
+            self.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.bind_group_layout,
+                entries: &[
+                    buffer_entry(0, &self.config_buffer),
+                    buffer_entry(1, self.instances_buffer.src()),
+                    buffer_entry(2, self.instance_culling_infos_buffer.src()),
+                    buffer_entry(3, previous_model_transforms_buffer.src()),
+                    buffer_entry(4, self.culled_instances_buffer.src()),
+                    buffer_entry(5, self.culled_previous_model_transforms_buffer.src()),
+                    buffer_entry(6, self.draw_commands_buffer.src()),
+                ],
+                label: Some("gpu_culling_bind_group"),
+            }));
+        }
+        culled_previous_model_transforms_resized
+    }
+
+    // fills the indirect draw commands with the instances that are inside the frustum. must run after
+    // update and before the meshes are drawn, and again before every pass that's drawn with another view
+    pub fn cull(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        frustum: &Frustum,
+        shadow_casters_only: bool,
+    ) {
+        let bind_group = match &self.bind_group {
+            Some(bind_group) => bind_group,
+            None => return,
+        };
+        let instance_count = self.instance_culling_infos_buffer.length() as u32;
+        queue.write_buffer(
+            &self.config_buffer,
+            0,
+            bytemuck::cast_slice(&[GpuCullingConfig {
+                frustum_planes: frustum.planes().map(|plane| plane.into()),
+                instance_count,
+                shadow_casters_only: shadow_casters_only as u32,
+                padding: [0; 2],
+            }]),
+        );
+        queue.write_buffer(
+            self.draw_commands_buffer.src(),
+            0,
+            bytemuck::cast_slice(&self.draw_commands),
+        );
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("GPU Culling Encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("GPU Culling Compute Pass"),
+            });
+            compute_pass.set_pipeline(&self.culling_pipeline);
+            compute_pass.set_bind_group(0, bind_group, &[]);
+            compute_pass.dispatch_workgroups(instance_count.div_ceil(CULLING_WORKGROUP_SIZE), 1, 1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    // the compacted previous model transforms of the visible instances, for a copy of the bones bind group
+    pub fn culled_previous_model_transforms_buffer(&self) -> &GpuBuffer {
+        &self.culled_previous_model_transforms_buffer
+    }
+
+    // false for the meshes that have to be drawn on the regular path, including the ones
+    // that were binded since the last update
+    pub fn draws_mesh(&self, binded_pbr_mesh_index: usize) -> bool {
+        self.arenas
+            .as_ref()
+            .and_then(|arenas| arenas.meshes.get(binded_pbr_mesh_index).copied().flatten())
+            .is_some()
+    }
+
+    pub fn draw_groups(&self) -> &[GpuDrivenDrawGroup] {
+        &self.draw_groups
+    }
+
+    // expects the pipeline and the bind groups of the group's mesh to be set already, with the
+    // culled previous model transforms in place of the regular ones
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, group: &GpuDrivenDrawGroup) {
+        let arenas = match &self.arenas {
+            Some(arenas) => arenas,
+            None => return,
+        };
+        render_pass.set_vertex_buffer(0, arenas.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.culled_instances_buffer.src().slice(..));
+        render_pass.set_index_buffer(
+            match group.index_format {
+                wgpu::IndexFormat::Uint16 => arenas.u16_index_buffer.slice(..),
+                wgpu::IndexFormat::Uint32 => arenas.u32_index_buffer.slice(..),
+            },
+            group.index_format,
+        );
+        let draw_command_size = std::mem::size_of::<GpuDrawIndexedIndirect>() as u64;
+        if self.multi_draw_indirect {
+            render_pass.multi_draw_indexed_indirect(
+                self.draw_commands_buffer.src(),
+                group.draw_commands.start as u64 * draw_command_size,
+                group.draw_commands.len() as u32,
+            );
+        } else {
+            for draw_command_index in group.draw_commands.clone() {
+                render_pass.draw_indexed_indirect(
+                    self.draw_commands_buffer.src(),
+                    draw_command_index as u64 * draw_command_size,
+                );
+            }
+        }
+    }
+
+    // the copies are done on the gpu from the meshes' own buffers
+    fn make_arenas(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        binded_pbr_meshes: &[BindedPbrMesh],
+    ) -> Arenas {
+        let (placements, sizes) = place_meshes_in_arenas(
+            &binded_pbr_meshes
+                .iter()
+                .map(|binded_pbr_mesh| {
+                    let geometry_buffers = &binded_pbr_mesh.geometry_buffers;
+                    Self::can_draw_mesh(binded_pbr_mesh).then(|| {
+                        (
+                            geometry_buffers.vertex_buffer.length(),
+                            geometry_buffers.index_buffer_format,
+                            geometry_buffers.index_buffer.length(),
+                        )
+                    })
+                })
+                .collect::<Vec<_>>(),
+        );
+
+        let make_arena = |label: &str, size: u64, usage: wgpu::BufferUsages| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size.max(wgpu::COPY_BUFFER_ALIGNMENT),
+                usage: usage | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        let vertex_buffer = make_arena(
+            "GPU-Driven Vertex Arena",
+            sizes.vertex,
+            wgpu::BufferUsages::VERTEX,
+        );
+        let u16_index_buffer = make_arena(
+            "GPU-Driven U16 Index Arena",
+            sizes.u16_index,
+            wgpu::BufferUsages::INDEX,
+        );
+        let u32_index_buffer = make_arena(
+            "GPU-Driven U32 Index Arena",
+            sizes.u32_index,
+            wgpu::BufferUsages::INDEX,
+        );
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("GPU-Driven Arenas Encoder"),
+        });
+        for (binded_pbr_mesh, (arena_mesh, placement)) in binded_pbr_meshes
+            .iter()
+            .zip(placements.iter())
+            .filter_map(|(binded_pbr_mesh, placement)| Some((binded_pbr_mesh, (*placement)?)))
+        {
+            let geometry_buffers = &binded_pbr_mesh.geometry_buffers;
+            encoder.copy_buffer_to_buffer(
+                geometry_buffers.vertex_buffer.src(),
+                0,
+                &vertex_buffer,
+                placement.vertex_offset,
+                geometry_buffers.vertex_buffer.length_bytes() as u64,
+            );
+            encoder.copy_buffer_to_buffer(
+                geometry_buffers.index_buffer.src(),
+                0,
+                match arena_mesh.index_format {
+                    wgpu::IndexFormat::Uint16 => &u16_index_buffer,
+                    wgpu::IndexFormat::Uint32 => &u32_index_buffer,
+                },
+                placement.index_offset,
+                // the index buffers are padded to 4 bytes when they're created
+                placement.index_size,
+            );
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        Arenas {
+            vertex_buffer,
+            u16_index_buffer,
+            u32_index_buffer,
+            meshes: placements
+                .into_iter()
+                .map(|placement| placement.map(|(arena_mesh, _)| arena_mesh))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn place_meshes_in_arenas_packs_mixed_index_formats() {
+        let vertex_size = std::mem::size_of::<Vertex>() as u64;
+        let (placements, sizes) = place_meshes_in_arenas(&[
+            Some((4, wgpu::IndexFormat::Uint16, 3)),
+            None,
+            Some((5, wgpu::IndexFormat::Uint32, 3)),
+            Some((6, wgpu::IndexFormat::Uint16, 6)),
+            Some((7, wgpu::IndexFormat::Uint32, 9)),
+        ]);
+
+        assert_eq!(
+            placements,
+            vec![
+                Some((
+                    ArenaMesh {
+                        index_format: wgpu::IndexFormat::Uint16,
+                        first_index: 0,
+                        index_count: 3,
+                        base_vertex: 0,
+                    },
+                    ArenaPlacement {
+                        vertex_offset: 0,
+                        index_offset: 0,
+                        // 3 u16 indices padded from 6 to 8 bytes
+                        index_size: 8,
+                    }
+                )),
+                None,
+                Some((
+                    ArenaMesh {
+                        index_format: wgpu::IndexFormat::Uint32,
+                        first_index: 0,
+                        index_count: 3,
+                        base_vertex: 4,
+                    },
+                    ArenaPlacement {
+                        vertex_offset: 4 * vertex_size,
+                        index_offset: 0,
+                        index_size: 12,
+                    }
+                )),
+                Some((
+                    ArenaMesh {
+                        index_format: wgpu::IndexFormat::Uint16,
+                        // after the padding of the first mesh
+                        first_index: 4,
+                        index_count: 6,
+                        base_vertex: 9,
+                    },
+                    ArenaPlacement {
+                        vertex_offset: 9 * vertex_size,
+                        index_offset: 8,
+                        index_size: 12,
+                    }
+                )),
+                Some((
+                    ArenaMesh {
+                        index_format: wgpu::IndexFormat::Uint32,
+                        first_index: 3,
+                        index_count: 9,
+                        base_vertex: 15,
+                    },
+                    ArenaPlacement {
+                        vertex_offset: 15 * vertex_size,
+                        index_offset: 12,
+                        index_size: 36,
+                    }
+                )),
+            ]
+        );
+        assert_eq!(
+            sizes,
+            ArenaSizes {
+                vertex: 22 * vertex_size,
+                u16_index: 20,
+                u32_index: 48,
+            }
+        );
+    }
+
+    #[test]
+    fn group_meshes_keeps_the_order_of_the_first_meshes() {
+        assert_eq!(
+            group_meshes(&[Some('a'), None, Some('b'), Some('a'), Some('c'), Some('b')]),
+            vec![('a', vec![0, 3]), ('b', vec![2, 5]), ('c', vec![4])]
+        );
+        assert_eq!(group_meshes::<char>(&[None, None]), vec![]);
+    }
+
+    #[test]
+    fn place_meshes_in_arenas_keeps_copies_aligned() {
+        let (placements, sizes) = place_meshes_in_arenas(
+            &(1..8)
+                .map(|index_count| Some((3, wgpu::IndexFormat::Uint16, index_count)))
+                .collect::<Vec<_>>(),
+        );
+        for (_, placement) in placements.iter().flatten() {
+            assert_eq!(placement.index_offset % wgpu::COPY_BUFFER_ALIGNMENT, 0);
+            assert_eq!(placement.index_size % wgpu::COPY_BUFFER_ALIGNMENT, 0);
+        }
+        assert_eq!(sizes.u16_index % wgpu::COPY_BUFFER_ALIGNMENT, 0);
+        assert_eq!(sizes.u32_index, 0);
+    }
+}
@@ -0,0 +1,299 @@
+use anyhow::Result;
+use cgmath::{InnerSpace, Vector2, Vector4};
+
+use super::*;
+
+// the hud is laid out for a 1080p window and scaled to match the actual window height
+const HUD_REFERENCE_WINDOW_HEIGHT: f32 = 1080.0;
+const HUD_MARGIN: f32 = 24.0;
+const HUD_TEXT_SCALE: f32 = 4.0;
+const HUD_TITLE_TEXT_SCALE: f32 = 10.0;
+
+const HEALTH_BAR_SIZE: Vector2<f32> = Vector2::new(320.0, 28.0);
+const HEALTH_BAR_BORDER: f32 = 3.0;
+
+const CROSSHAIR_THICKNESS: f32 = 4.0;
+const CROSSHAIR_GAP: f32 = 8.0;
+const CROSSHAIR_BAR_LENGTH: f32 = 10.0;
+
+// how long the damage vignette takes to fade out after the player stops taking damage
+const DAMAGE_VIGNETTE_FADE_SECONDS: f32 = 0.6;
+const DAMAGE_VIGNETTE_IMAGE_SIZE: u32 = 64;
+// distances from the center of the screen where the vignette starts and where it's fully opaque,
+// 1 is the middle of the edges
+const DAMAGE_VIGNETTE_INNER_RADIUS: f32 = 0.6;
+const DAMAGE_VIGNETTE_OUTER_RADIUS: f32 = 1.3;
+
+// (0, 1], higher means the fps counter reacts more quickly
+const FRAME_TIME_SMOOTHING_FACTOR: f32 = 0.05;
+
+const WHITE: Vector4<f32> = Vector4::new(1.0, 1.0, 1.0, 1.0);
+const RED: Vector4<f32> = Vector4::new(1.0, 0.0, 0.0, 1.0);
+const HEALTH_BAR_BACKGROUND_COLOR: Vector4<f32> = Vector4::new(0.0, 0.0, 0.0, 0.6);
+const HEALTH_BAR_FILL_COLOR: Vector4<f32> = Vector4::new(0.8, 0.05, 0.05, 0.9);
+const OVERLAY_BACKGROUND_COLOR: Vector4<f32> = Vector4::new(0.0, 0.0, 0.0, 0.6);
+const DAMAGE_VIGNETTE_COLOR: Vector4<f32> = Vector4::new(0.7, 0.0, 0.0, 0.8);
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FpsCounter {
+    pub is_visible: bool,
+    average_frame_time_seconds: Option<f32>,
+}
+
+impl FpsCounter {
+    pub fn toggle(&mut self) {
+        self.is_visible = !self.is_visible;
+    }
+
+    pub fn on_frame(&mut self, frame_time_seconds: f32) {
+        self.average_frame_time_seconds = Some(match self.average_frame_time_seconds {
+            Some(average) => lerp(average, frame_time_seconds, FRAME_TIME_SMOOTHING_FACTOR),
+            None => frame_time_seconds,
+        });
+    }
+
+    fn text(&self) -> Option<String> {
+        self.average_frame_time_seconds
+            .filter(|average| *average > 0.0)
+            .map(|average| format!("FPS {:.0}\n{:.2} MS", 1.0 / average, average * 1000.0))
+    }
+}
+
+// reddens the edges of the screen while the player is taking damage. the image is added to the
+// renderer once and kept across restarts
+#[derive(Debug, Copy, Clone)]
+pub struct DamageVignette {
+    image: UiImageId,
+    // 1 right after taking damage, fades out to 0
+    intensity: f32,
+}
+
+impl DamageVignette {
+    pub fn new(renderer_state: &mut RendererState) -> Result<Self> {
+        Ok(Self {
+            image: renderer_state
+                .add_ui_image(&make_damage_vignette_image(), "damage_vignette_texture")?,
+            intensity: 0.0,
+        })
+    }
+
+    pub fn on_damage(&mut self) {
+        self.intensity = 1.0;
+    }
+
+    pub fn on_frame(&mut self, frame_time_seconds: f32) {
+        self.intensity =
+            (self.intensity - frame_time_seconds / DAMAGE_VIGNETTE_FADE_SECONDS).max(0.0);
+    }
+
+    pub fn clear(&mut self) {
+        self.intensity = 0.0;
+    }
+}
+
+// white, transparent in the middle and opaque towards the corners. stretched over the whole screen
+fn make_damage_vignette_image() -> image::RgbaImage {
+    image::RgbaImage::from_fn(
+        DAMAGE_VIGNETTE_IMAGE_SIZE,
+        DAMAGE_VIGNETTE_IMAGE_SIZE,
+        |x, y| {
+            // [-1, 1] from one edge to the other, sampled at the texel centers
+            let to_edge =
+                |coord: u32| (coord as f32 + 0.5) / DAMAGE_VIGNETTE_IMAGE_SIZE as f32 * 2.0 - 1.0;
+            let distance = Vector2::new(to_edge(x), to_edge(y)).magnitude();
+            let alpha = ((distance - DAMAGE_VIGNETTE_INNER_RADIUS)
+                / (DAMAGE_VIGNETTE_OUTER_RADIUS - DAMAGE_VIGNETTE_INNER_RADIUS))
+                .clamp(0.0, 1.0);
+            image::Rgba([255, 255, 255, (alpha * 255.0).round() as u8])
+        },
+    )
+}
+
+// builds everything that's drawn on top of the 3d view for the current game phase
+pub fn build_hud(game_state: &GameState, (window_width, window_height): (u32, u32)) -> UiDrawList {
+    let mut ui = UiDrawList::new();
+    let window_size = Vector2::new(window_width as f32, window_height as f32);
+    let scale = window_size.y / HUD_REFERENCE_WINDOW_HEIGHT;
+    let margin = HUD_MARGIN * scale;
+    let text_scale = (HUD_TEXT_SCALE * scale).round().max(1.0);
+
+    match game_state.phase {
+        GamePhase::Menu => {
+            add_overlay(
+                &mut ui,
+                window_size,
+                scale,
+                "WGPU SANDBOX",
+                &["PRESS ENTER TO START".to_string()],
+            );
+        }
+        GamePhase::Playing => {
+            add_damage_vignette(&mut ui, game_state.damage_vignette, window_size);
+            add_crosshair(&mut ui, window_size, scale);
+            add_health_bar(&mut ui, game_state, window_size, scale, text_scale);
+
+            ui.text(
+                Vector2::new(margin, margin),
+                text_scale,
+                WHITE,
+                &format!("SCORE {}", game_state.score),
+            );
+            if !game_state.enemies.is_empty() {
+                let line_height = UiDrawList::text_size("A", text_scale).y * 1.5;
+                ui.text(
+                    Vector2::new(margin, margin + line_height),
+                    text_scale,
+                    WHITE,
+                    &format!("ENEMIES {}", game_state.enemies.len()),
+                );
+            }
+        }
+        GamePhase::Dead => {
+            add_overlay(
+                &mut ui,
+                window_size,
+                scale,
+                "YOU DIED",
+                &[
+                    format!("SCORE {}", game_state.score),
+                    "PRESS ENTER TO RESTART".to_string(),
+                ],
+            );
+        }
+    }
+
+    if game_state.fps_counter.is_visible {
+        if let Some(fps_text) = game_state.fps_counter.text() {
+            let text_size = UiDrawList::text_size(&fps_text, text_scale);
+            ui.text(
+                Vector2::new(window_size.x - margin - text_size.x, margin),
+                text_scale,
+                WHITE,
+                &fps_text,
+            );
+        }
+    }
+
+    ui
+}
+
+fn add_damage_vignette(
+    ui: &mut UiDrawList,
+    damage_vignette: DamageVignette,
+    window_size: Vector2<f32>,
+) {
+    if damage_vignette.intensity <= 0.0 {
+        return;
+    }
+    let mut tint = DAMAGE_VIGNETTE_COLOR;
+    tint.w *= damage_vignette.intensity;
+    ui.image(
+        Vector2::new(0.0, 0.0),
+        window_size,
+        damage_vignette.image,
+        tint,
+    );
+}
+
+fn add_crosshair(ui: &mut UiDrawList, window_size: Vector2<f32>, scale: f32) {
+    let center = window_size / 2.0;
+    let thickness = (CROSSHAIR_THICKNESS * scale).round().max(1.0);
+    let gap = CROSSHAIR_GAP * scale;
+    let length = CROSSHAIR_BAR_LENGTH * scale;
+    let horizontal_bar_size = Vector2::new(length, thickness);
+    let vertical_bar_size = Vector2::new(thickness, length);
+    // left, right, top, bottom
+    ui.rect(
+        Vector2::new(center.x - gap - length, center.y - thickness / 2.0),
+        horizontal_bar_size,
+        RED,
+    );
+    ui.rect(
+        Vector2::new(center.x + gap, center.y - thickness / 2.0),
+        horizontal_bar_size,
+        RED,
+    );
+    ui.rect(
+        Vector2::new(center.x - thickness / 2.0, center.y - gap - length),
+        vertical_bar_size,
+        RED,
+    );
+    ui.rect(
+        Vector2::new(center.x - thickness / 2.0, center.y + gap),
+        vertical_bar_size,
+        RED,
+    );
+}
+
+fn add_health_bar(
+    ui: &mut UiDrawList,
+    game_state: &GameState,
+    window_size: Vector2<f32>,
+    scale: f32,
+    text_scale: f32,
+) {
+    let margin = HUD_MARGIN * scale;
+    let border = HEALTH_BAR_BORDER * scale;
+    let bar_size = HEALTH_BAR_SIZE * scale;
+    let bar_position = Vector2::new(margin, window_size.y - margin - bar_size.y);
+    let health_fraction = (game_state.player_health / PLAYER_MAX_HEALTH).clamp(0.0, 1.0);
+
+    ui.rect(bar_position, bar_size, HEALTH_BAR_BACKGROUND_COLOR);
+    ui.rect(
+        bar_position + Vector2::new(border, border),
+        Vector2::new(
+            (bar_size.x - border * 2.0) * health_fraction,
+            bar_size.y - border * 2.0,
+        ),
+        HEALTH_BAR_FILL_COLOR,
+    );
+
+    let health_text = format!("HP {}", game_state.player_health.ceil());
+    let text_size = UiDrawList::text_size(&health_text, text_scale);
+    ui.text(
+        Vector2::new(
+            bar_position.x + bar_size.x + margin / 2.0,
+            bar_position.y + (bar_size.y - text_size.y) / 2.0,
+        ),
+        text_scale,
+        WHITE,
+        &health_text,
+    );
+}
+
+// darkens the whole screen and shows a title with a few lines of text underneath, all centered
+fn add_overlay(
+    ui: &mut UiDrawList,
+    window_size: Vector2<f32>,
+    scale: f32,
+    title: &str,
+    lines: &[String],
+) {
+    ui.rect(
+        Vector2::new(0.0, 0.0),
+        window_size,
+        OVERLAY_BACKGROUND_COLOR,
+    );
+
+    let title_scale = (HUD_TITLE_TEXT_SCALE * scale).round().max(1.0);
+    let text_scale = (HUD_TEXT_SCALE * scale).round().max(1.0);
+    let title_size = UiDrawList::text_size(title, title_scale);
+    let mut y = window_size.y / 2.0 - title_size.y;
+    ui.text(
+        Vector2::new((window_size.x - title_size.x) / 2.0, y),
+        title_scale,
+        WHITE,
+        title,
+    );
+    y += title_size.y * 1.5;
+    for line in lines {
+        let line_size = UiDrawList::text_size(line, text_scale);
+        ui.text(
+            Vector2::new((window_size.x - line_size.x) / 2.0, y),
+            text_scale,
+            WHITE,
+            line,
+        );
+        y += line_size.y * 2.0;
+    }
+}
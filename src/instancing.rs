@@ -0,0 +1,277 @@
+use super::*;
+
+// a node's instance of a mesh, with the global transform that was computed for it
+#[derive(Debug, Copy, Clone)]
+pub struct NodeInstance<'a> {
+    pub node: &'a GameNode,
+    pub transform: crate::transform::Transform,
+}
+
+// the instances of every mesh in the scene, bucketed by the index of the mesh that draws them.
+// within a bucket the instances are in the same order as scene.nodes()
+#[derive(Debug)]
+pub struct SceneInstances<'a> {
+    // by binded pbr mesh index
+    pub pbr: Vec<Vec<NodeInstance<'a>>>,
+    // by binded unlit mesh index
+    pub unlit: Vec<Vec<NodeInstance<'a>>>,
+    // by the source mesh index of the binded wireframe meshes, see SceneInstances::wireframe
+    pbr_wireframe: Vec<Vec<NodeInstance<'a>>>,
+    unlit_wireframe: Vec<Vec<NodeInstance<'a>>>,
+}
+
+impl<'a> SceneInstances<'a> {
+    // a single pass over the nodes, so the cost doesn't grow with the number of meshes times the number of nodes.
+    // in wireframe mode every mesh is drawn with the wireframe meshes, otherwise only the wireframe nodes are
+    pub fn gather(
+        scene: &'a Scene,
+        pbr_mesh_count: usize,
+        unlit_mesh_count: usize,
+        wireframe_mode: bool,
+    ) -> Self {
+        let mut instances = Self {
+            pbr: vec![vec![]; pbr_mesh_count],
+            unlit: vec![vec![]; unlit_mesh_count],
+            pbr_wireframe: vec![vec![]; pbr_mesh_count],
+            unlit_wireframe: vec![vec![]; unlit_mesh_count],
+        };
+        for (node, transform) in scene.nodes_with_global_transforms() {
+            let mesh = match &node.mesh {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            let buckets = match (mesh.mesh_type, mesh.wireframe || wireframe_mode) {
+                (GameNodeMeshType::Pbr { .. }, false) => &mut instances.pbr,
+                (GameNodeMeshType::Unlit { .. }, false) => &mut instances.unlit,
+                (GameNodeMeshType::Pbr { .. }, true) => &mut instances.pbr_wireframe,
+                (GameNodeMeshType::Unlit { .. }, true) => &mut instances.unlit_wireframe,
+            };
+            for (i, mesh_index) in mesh.mesh_indices.iter().enumerate() {
+                // a node that lists a mesh more than once still gets a single instance of it
+                if mesh.mesh_indices[..i].contains(mesh_index) {
+                    continue;
+                }
+                if let Some(bucket) = buckets.get_mut(*mesh_index) {
+                    bucket.push(NodeInstance { node, transform });
+                }
+            }
+        }
+        instances
+    }
+
+    pub fn wireframe(
+        &self,
+        source_mesh_type: &MeshType,
+        source_mesh_index: usize,
+    ) -> &[NodeInstance<'a>] {
+        let buckets = match source_mesh_type {
+            MeshType::Pbr => &self.pbr_wireframe,
+            MeshType::Unlit => &self.unlit_wireframe,
+        };
+        buckets
+            .get(source_mesh_index)
+            .map(|bucket| bucket.as_slice())
+            .unwrap_or(&[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Instant;
+
+    use cgmath::{Matrix4, Vector3};
+
+    use super::*;
+
+    // a 4-ary tree of nodes with a mix of pbr, unlit, wireframe and mesh-less nodes
+    fn make_scene(node_count: usize, mesh_count: usize) -> Scene {
+        let nodes = (0..node_count)
+            .map(|node_index| {
+                let mesh_type = if node_index % 11 == 0 {
+                    GameNodeMeshType::Unlit {
+                        color: Vector3::new(1.0, 0.0, 0.0),
+                    }
+                } else {
+                    GameNodeMeshType::Pbr {
+                        material_override: None,
+                    }
+                };
+                GameNodeDescBuilder::new()
+                    .transform(
+                        TransformBuilder::new()
+                            .position(Vector3::new(node_index as f32 * 0.1, 1.0, -2.0))
+                            .scale(Vector3::new(1.0, 1.0 + (node_index % 3) as f32, 1.0))
+                            .build(),
+                    )
+                    .mesh((node_index % 7 != 0).then(|| GameNodeMesh {
+                        mesh_type,
+                        mesh_indices: vec![node_index % mesh_count, (node_index * 3) % mesh_count],
+                        wireframe: node_index % 13 == 0,
+                        cast_shadows: true,
+                        receive_shadows: true,
+                    }))
+                    .build()
+            })
+            .collect();
+        let parent_index_map: HashMap<_, _> = (1..node_count)
+            .map(|node_index| (node_index, (node_index - 1) / 4))
+            .collect();
+        Scene::new(nodes, vec![], vec![], parent_index_map)
+    }
+
+    // what RendererState::update did before: a scan over all the nodes for every mesh
+    fn gather_per_mesh(
+        scene: &Scene,
+        mesh_count: usize,
+        is_unlit: bool,
+    ) -> Vec<Vec<(GameNodeId, Matrix4<f32>)>> {
+        (0..mesh_count)
+            .map(|mesh_index| {
+                scene
+                    .nodes()
+                    .filter(|node| match &node.mesh {
+                        Some(GameNodeMesh {
+                            mesh_indices,
+                            mesh_type,
+                            wireframe: false,
+                            ..
+                        }) => {
+                            matches!(mesh_type, GameNodeMeshType::Unlit { .. }) == is_unlit
+                                && mesh_indices.contains(&mesh_index)
+                        }
+                        _ => false,
+                    })
+                    .map(|node| {
+                        (
+                            node.id(),
+                            scene.get_global_transform_for_node(node.id()).matrix(),
+                        )
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn to_comparable(buckets: &[Vec<NodeInstance>]) -> Vec<Vec<(GameNodeId, Matrix4<f32>)>> {
+        buckets
+            .iter()
+            .map(|bucket| {
+                bucket
+                    .iter()
+                    .map(|instance| (instance.node.id(), instance.transform.matrix()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gather_matches_per_mesh_scan() {
+        let mesh_count = 9;
+        let scene = make_scene(500, mesh_count);
+        let instances = SceneInstances::gather(&scene, mesh_count, mesh_count, false);
+
+        assert_eq!(
+            to_comparable(&instances.pbr),
+            gather_per_mesh(&scene, mesh_count, false)
+        );
+        assert_eq!(
+            to_comparable(&instances.unlit),
+            gather_per_mesh(&scene, mesh_count, true)
+        );
+        assert!(instances.pbr.iter().any(|bucket| !bucket.is_empty()));
+        assert!(instances.unlit.iter().any(|bucket| !bucket.is_empty()));
+
+        // the root node has no mesh, node 13 is a pbr wireframe node with meshes 4 and 3
+        assert!(instances
+            .wireframe(&MeshType::Pbr, 4)
+            .iter()
+            .any(|instance| instance.node.id() == scene.nodes().nth(13).unwrap().id()));
+
+        let wireframe_instances = SceneInstances::gather(&scene, mesh_count, mesh_count, true);
+        assert!(wireframe_instances
+            .pbr
+            .iter()
+            .all(|bucket| bucket.is_empty()));
+        assert!(wireframe_instances
+            .unlit
+            .iter()
+            .all(|bucket| bucket.is_empty()));
+        let wireframe_instance_count: usize = (0..mesh_count)
+            .map(|mesh_index| {
+                wireframe_instances
+                    .wireframe(&MeshType::Pbr, mesh_index)
+                    .len()
+                    + wireframe_instances
+                        .wireframe(&MeshType::Unlit, mesh_index)
+                        .len()
+            })
+            .sum();
+        let instance_count: usize = instances
+            .pbr
+            .iter()
+            .chain(instances.unlit.iter())
+            .map(|bucket| bucket.len())
+            .sum::<usize>()
+            + (0..mesh_count)
+                .map(|mesh_index| {
+                    instances.wireframe(&MeshType::Pbr, mesh_index).len()
+                        + instances.wireframe(&MeshType::Unlit, mesh_index).len()
+                })
+                .sum::<usize>();
+        assert_eq!(wireframe_instance_count, instance_count);
+    }
+
+    // too slow for debug builds: cargo test --release gather_benchmark -- --ignored
+    #[test]
+    #[ignore]
+    fn gather_benchmark() {
+        let iterations = 10;
+        for (node_count, mesh_count) in [(10_000, 100), (50_000, 100), (50_000, 1000)] {
+            let scene = make_scene(node_count, mesh_count);
+
+            let start = Instant::now();
+            for _ in 0..iterations {
+                SceneInstances::gather(&scene, mesh_count, mesh_count, false);
+            }
+            let gather_time = start.elapsed() / iterations;
+
+            let start = Instant::now();
+            for _ in 0..iterations {
+                gather_per_mesh(&scene, mesh_count, false);
+                gather_per_mesh(&scene, mesh_count, true);
+            }
+            let per_mesh_time = start.elapsed() / iterations;
+
+            assert!(
+                gather_time < per_mesh_time,
+                "{node_count} nodes, {mesh_count} meshes: single pass {gather_time:?}, per mesh scan {per_mesh_time:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn gather_deduplicates_and_skips_out_of_range_meshes() {
+        let scene = Scene::new(
+            vec![GameNodeDescBuilder::new()
+                .mesh(Some(GameNodeMesh {
+                    mesh_indices: vec![1, 1, 5, 0],
+                    ..GameNodeMesh::from_pbr_mesh_index(0)
+                }))
+                .build()],
+            vec![],
+            vec![],
+            HashMap::new(),
+        );
+        let instances = SceneInstances::gather(&scene, 2, 0, false);
+        assert_eq!(
+            instances
+                .pbr
+                .iter()
+                .map(|bucket| bucket.len())
+                .collect::<Vec<_>>(),
+            vec![1, 1]
+        );
+        assert!(instances.unlit.is_empty());
+    }
+}
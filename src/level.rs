@@ -0,0 +1,651 @@
+use anyhow::{bail, Result};
+use cgmath::{Deg, Euler, Quaternion, Vector3};
+use serde::Deserialize;
+
+use super::*;
+
+pub const DEFAULT_LEVEL_PATH: &str = "./src/levels/test_level.ron";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LevelDescription {
+    // the first asset is loaded by init_scene, the rest are merged into it by init_game_state
+    pub assets: Vec<LevelAssetDescription>,
+    pub skybox: SkyboxDescription,
+    pub player_spawn: PlayerSpawnDescription,
+    #[serde(default)]
+    pub point_lights: Vec<PointLightDescription>,
+    #[serde(default)]
+    pub directional_lights: Vec<DirectionalLightDescription>,
+    #[serde(default)]
+    pub floor: Option<FloorDescription>,
+    #[serde(default)]
+    pub character: Option<CharacterDescription>,
+    #[serde(default)]
+    pub enemies: Option<EnemiesDescription>,
+    // used by the enemies to find their way around the static colliders
+    #[serde(default)]
+    pub navigation: NavGridDescription,
+    #[serde(default)]
+    pub revolver: Option<RevolverDescription>,
+    #[serde(default)]
+    pub physics_balls: PhysicsBallsDescription,
+    #[serde(default)]
+    pub animations: Vec<AnimationDescription>,
+    #[serde(default)]
+    pub music_path: Option<String>,
+    #[serde(default)]
+    pub tone_mapping: ToneMappingDescription,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LevelAssetDescription {
+    pub name: String,
+    pub path: String,
+    // applied on top of the transforms of the asset's root nodes
+    #[serde(default)]
+    pub transform: Option<TransformDescription>,
+    // registers a static box collider around every mesh in the asset
+    #[serde(default)]
+    pub static_colliders: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SkyboxDescription {
+    pub background: SkyboxBackgroundDescription,
+    #[serde(default)]
+    pub hdr_environment: Option<SkyboxHDREnvironmentDescription>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub enum SkyboxBackgroundDescription {
+    Cube { face_image_paths: [String; 6] },
+    Equirectangular { image_path: String },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub enum SkyboxHDREnvironmentDescription {
+    Equirectangular { image_path: String },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PlayerSpawnDescription {
+    pub position: [f32; 3],
+    // degrees
+    #[serde(default)]
+    pub horizontal_rotation: f32,
+    // degrees
+    #[serde(default)]
+    pub vertical_rotation: f32,
+    pub speed: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PointLightDescription {
+    pub position: [f32; 3],
+    // linear color, not srgb
+    pub color: [f32; 3],
+    pub intensity: f32,
+    // scale of the unlit sphere that marks the light's position
+    #[serde(default = "default_point_light_marker_scale")]
+    pub marker_scale: f32,
+    // radius of the emitter, controls the softness of the shadows
+    #[serde(default = "default_point_light_size")]
+    pub size: f32,
+    // distance at which the light fades out. smaller ranges make the light cheaper since it touches
+    // fewer clusters
+    #[serde(default = "default_point_light_range")]
+    pub range: f32,
+    // seconds, throttles how often the shadow map is re-rendered while the light or the scene around it moves
+    #[serde(default)]
+    pub shadow_update_interval_seconds: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DirectionalLightDescription {
+    pub position: [f32; 3],
+    // doesn't need to be normalized
+    pub direction: [f32; 3],
+    // linear color, not srgb
+    pub color: [f32; 3],
+    pub intensity: f32,
+    // angular diameter in radians, controls the softness of the shadows
+    #[serde(default = "default_directional_light_size")]
+    pub size: f32,
+    // seconds, throttles how often the shadow cascades are re-rendered while the camera or the scene moves
+    #[serde(default)]
+    pub shadow_update_interval_seconds: f32,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ToneMappingDescription {
+    #[serde(default)]
+    pub operator: ToneMappingOperator,
+    // .cube file, applied after tone mapping
+    #[serde(default)]
+    pub color_grading_lut_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FloorDescription {
+    pub side_length: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CharacterDescription {
+    pub asset: String,
+    // node and skin indices are relative to the asset's gltf file
+    pub root_node: usize,
+    pub skin: usize,
+    #[serde(default)]
+    pub position: Option<[f32; 3]>,
+}
+
+// each spawn point gets its own copy of the asset
+#[derive(Debug, Clone, Deserialize)]
+pub struct EnemiesDescription {
+    pub asset: String,
+    // node, skin and animation indices are relative to the asset's gltf file
+    pub root_node: usize,
+    pub skin: usize,
+    pub spawn_points: Vec<[f32; 3]>,
+    #[serde(default)]
+    pub idle_animation: Option<usize>,
+    #[serde(default)]
+    pub walk_animation: Option<usize>,
+    #[serde(default)]
+    pub attack_animation: Option<usize>,
+    // units per second
+    #[serde(default = "default_enemy_speed")]
+    pub speed: f32,
+    // the enemy notices the player when they get closer than this
+    #[serde(default = "default_enemy_aggro_range")]
+    pub aggro_range: f32,
+    #[serde(default = "default_enemy_attack_range")]
+    pub attack_range: f32,
+    #[serde(default = "default_enemy_health")]
+    pub health: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NavGridDescription {
+    pub cell_size: f32,
+    // height of the ground that the agents walk on
+    pub floor_height: f32,
+    pub agent_radius: f32,
+    pub agent_height: f32,
+    // colliders lower than this don't block the agent
+    pub step_height: f32,
+    // extra cells around the bounds of the static colliders
+    pub padding_cells: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RevolverDescription {
+    pub asset: String,
+    // defaults to the last node of the asset
+    #[serde(default)]
+    pub model_node: Option<usize>,
+    // defaults to the last animation of the asset
+    #[serde(default)]
+    pub fire_animation: Option<usize>,
+    // relative to the player's camera
+    pub transform: TransformDescription,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PhysicsBallsDescription {
+    #[serde(default)]
+    pub initial_count: usize,
+    // a new ball is spawned every spawn_interval_seconds, None disables spawning
+    #[serde(default)]
+    pub spawn_interval_seconds: Option<f32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AnimationDescription {
+    pub asset: String,
+    // relative to the asset's gltf file
+    pub index: usize,
+    #[serde(default = "default_animation_speed")]
+    pub speed: f32,
+    #[serde(default = "default_animation_loop_type")]
+    pub loop_type: LoopType,
+    #[serde(default)]
+    pub is_playing: bool,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct TransformDescription {
+    pub position: [f32; 3],
+    // euler angles in degrees
+    pub rotation: [f32; 3],
+    pub scale: [f32; 3],
+}
+
+// where an asset ended up after it was merged into the scene
+#[derive(Debug, Clone)]
+pub struct LoadedLevelAsset {
+    pub name: String,
+    // same order as the nodes in the gltf file
+    pub node_ids: Vec<GameNodeId>,
+    pub skin_index_offset: usize,
+    pub skin_count: usize,
+    pub animation_index_offset: usize,
+    pub animation_count: usize,
+}
+
+fn default_point_light_marker_scale() -> f32 {
+    0.05
+}
+
+fn default_point_light_size() -> f32 {
+    0.1
+}
+
+fn default_point_light_range() -> f32 {
+    20.0
+}
+
+fn default_directional_light_size() -> f32 {
+    0.02
+}
+
+fn default_enemy_speed() -> f32 {
+    1.5
+}
+
+fn default_enemy_aggro_range() -> f32 {
+    20.0
+}
+
+// close enough for the enemy's colliders to touch the player
+fn default_enemy_attack_range() -> f32 {
+    0.6
+}
+
+fn default_enemy_health() -> f32 {
+    100.0
+}
+
+fn default_animation_speed() -> f32 {
+    1.0
+}
+
+fn default_animation_loop_type() -> LoopType {
+    LoopType::Once
+}
+
+impl LevelDescription {
+    pub fn load(path: &str) -> Result<Self> {
+        let level_file_string = std::fs::read_to_string(path)
+            .map_err(|err| anyhow::anyhow!("Failed to read level file {}: {}", path, err))?;
+        let level: Self = ron::from_str(&level_file_string)
+            .map_err(|err| anyhow::anyhow!("Failed to parse level file {}: {}", path, err))?;
+        level.validate()?;
+        Ok(level)
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (asset_index, asset) in self.assets.iter().enumerate() {
+            if self.assets[..asset_index]
+                .iter()
+                .any(|other_asset| other_asset.name == asset.name)
+            {
+                bail!("Level contains multiple assets named {:?}", asset.name);
+            }
+        }
+        let asset_references = self
+            .character
+            .iter()
+            .map(|character| &character.asset)
+            .chain(self.enemies.iter().map(|enemies| &enemies.asset))
+            .chain(self.revolver.iter().map(|revolver| &revolver.asset))
+            .chain(self.animations.iter().map(|animation| &animation.asset));
+        for asset_name in asset_references {
+            if !self.assets.iter().any(|asset| asset.name == *asset_name) {
+                bail!("Level references unknown asset {:?}", asset_name);
+            }
+        }
+        // the range is also the far plane of the light's shadow map
+        for point_light in &self.point_lights {
+            if point_light.range <= POINT_SHADOW_MAP_NEAR_PLANE_DISTANCE {
+                bail!(
+                    "Point light range must be greater than {:?} but found: {:?}",
+                    POINT_SHADOW_MAP_NEAR_PLANE_DISTANCE,
+                    point_light.range
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // swaps out the gltf file of the first asset. anything that referenced the old file by
+    // node or animation index is dropped since the indices won't line up anymore
+    pub fn replace_base_asset(&mut self, path: &str) {
+        match self.assets.first_mut() {
+            Some(base_asset) => {
+                let base_asset_name = base_asset.name.clone();
+                base_asset.path = path.to_string();
+                base_asset.transform = None;
+                if matches!(&self.character, Some(character) if character.asset == base_asset_name)
+                {
+                    self.character = None;
+                }
+                if matches!(&self.enemies, Some(enemies) if enemies.asset == base_asset_name) {
+                    self.enemies = None;
+                }
+                if matches!(&self.revolver, Some(revolver) if revolver.asset == base_asset_name) {
+                    self.revolver = None;
+                }
+                self.animations
+                    .retain(|animation| animation.asset != base_asset_name);
+            }
+            None => self.assets.push(LevelAssetDescription {
+                name: String::from("base"),
+                path: path.to_string(),
+                transform: None,
+                static_colliders: false,
+            }),
+        }
+    }
+
+    pub fn skybox(&self) -> (SkyboxBackground<'_>, Option<SkyboxHDREnvironment<'_>>) {
+        let background = match &self.skybox.background {
+            SkyboxBackgroundDescription::Cube { face_image_paths } => SkyboxBackground::Cube {
+                face_image_paths: [
+                    &face_image_paths[0],
+                    &face_image_paths[1],
+                    &face_image_paths[2],
+                    &face_image_paths[3],
+                    &face_image_paths[4],
+                    &face_image_paths[5],
+                ],
+            },
+            SkyboxBackgroundDescription::Equirectangular { image_path } => {
+                SkyboxBackground::Equirectangular { image_path }
+            }
+        };
+        let hdr_environment =
+            self.skybox
+                .hdr_environment
+                .as_ref()
+                .map(|hdr_environment| match hdr_environment {
+                    SkyboxHDREnvironmentDescription::Equirectangular { image_path } => {
+                        SkyboxHDREnvironment::Equirectangular { image_path }
+                    }
+                });
+        (background, hdr_environment)
+    }
+
+    pub fn color_grading_lut(&self) -> Result<Option<ColorGradingLut>> {
+        self.tone_mapping
+            .color_grading_lut_path
+            .as_deref()
+            .map(ColorGradingLut::load)
+            .transpose()
+    }
+}
+
+impl PlayerSpawnDescription {
+    pub fn view_direction(&self) -> ControlledViewDirection {
+        ControlledViewDirection {
+            horizontal: Deg(self.horizontal_rotation).into(),
+            vertical: Deg(self.vertical_rotation).into(),
+        }
+    }
+}
+
+impl From<&DirectionalLightDescription> for DirectionalLightComponent {
+    fn from(light: &DirectionalLightDescription) -> Self {
+        Self {
+            position: light.position.into(),
+            direction: Vector3::from(light.direction).normalize(),
+            color: light.color.into(),
+            intensity: light.intensity,
+            size: light.size,
+            shadow_update_interval_seconds: light.shadow_update_interval_seconds,
+        }
+    }
+}
+
+impl Default for NavGridDescription {
+    fn default() -> Self {
+        Self {
+            cell_size: 0.5,
+            floor_height: 0.0,
+            agent_radius: 0.3,
+            agent_height: 1.8,
+            step_height: 0.3,
+            padding_cells: 4,
+        }
+    }
+}
+
+impl Default for TransformDescription {
+    fn default() -> Self {
+        Self {
+            position: [0.0, 0.0, 0.0],
+            rotation: [0.0, 0.0, 0.0],
+            scale: [1.0, 1.0, 1.0],
+        }
+    }
+}
+
+impl From<TransformDescription> for crate::transform::Transform {
+    fn from(transform: TransformDescription) -> Self {
+        let [rot_x, rot_y, rot_z] = transform.rotation;
+        TransformBuilder::new()
+            .position(transform.position.into())
+            .rotation(Quaternion::from(Euler::new(
+                Deg(rot_x),
+                Deg(rot_y),
+                Deg(rot_z),
+            )))
+            .scale(transform.scale.into())
+            .build()
+    }
+}
+
+impl LoadedLevelAsset {
+    pub fn node_id(&self, node_index: usize) -> Result<GameNodeId> {
+        self.node_ids.get(node_index).copied().ok_or_else(|| {
+            anyhow::anyhow!(
+                "Asset {:?} doesn't have a node with index {}",
+                self.name,
+                node_index
+            )
+        })
+    }
+}
+
+pub fn find_loaded_level_asset<'a>(
+    loaded_assets: &'a [LoadedLevelAsset],
+    name: &str,
+) -> Result<&'a LoadedLevelAsset> {
+    loaded_assets
+        .iter()
+        .find(|loaded_asset| loaded_asset.name == name)
+        .ok_or_else(|| anyhow::anyhow!("Level asset {:?} wasn't loaded", name))
+}
+
+pub fn load_level_asset_scene(
+    base_renderer_state: &BaseRendererState,
+    asset: &LevelAssetDescription,
+    logger: &mut Logger,
+) -> Result<(Scene, RenderBuffers)> {
+    let (document, buffers, images) = gltf::import(&asset.path)
+        .map_err(|err| anyhow::anyhow!("Failed to import {}: {}", asset.path, err))?;
+    validate_animation_property_counts(&document, logger);
+    build_scene(base_renderer_state, (&document, &buffers, &images), logger)
+}
+
+// merges the asset into the scene, the nodes of the merged scene are appended to the end
+pub fn merge_level_asset(
+    scene: &mut Scene,
+    renderer_state: &mut RendererState,
+    asset: &LevelAssetDescription,
+    logger: &mut Logger,
+) -> Result<LoadedLevelAsset> {
+    let skip_nodes = scene.node_count();
+    let skin_index_offset = scene.skins.len();
+    let animation_index_offset = scene.animations.len();
+
+    let (other_scene, other_render_buffers) =
+        load_level_asset_scene(&renderer_state.base, asset, logger)?;
+    scene.merge_scene(renderer_state, other_scene, other_render_buffers);
+
+    Ok(LoadedLevelAsset {
+        name: asset.name.clone(),
+        node_ids: scene
+            .nodes()
+            .skip(skip_nodes)
+            .map(|node| node.id())
+            .collect(),
+        skin_index_offset,
+        skin_count: scene.skins.len() - skin_index_offset,
+        animation_index_offset,
+        animation_count: scene.animations.len() - animation_index_offset,
+    })
+}
+
+// another copy of an asset that was already merged into the scene, with its own nodes, skins and
+// animation states. the meshes are shared with the original
+pub fn duplicate_level_asset(
+    scene: &mut Scene,
+    loaded_asset: &LoadedLevelAsset,
+) -> LoadedLevelAsset {
+    let skin_index_offset = scene.skins.len();
+    let animation_index_offset = scene.animations.len();
+    let node_ids = scene.duplicate_nodes(
+        &loaded_asset.node_ids,
+        loaded_asset.skin_index_offset..loaded_asset.skin_index_offset + loaded_asset.skin_count,
+        loaded_asset.animation_index_offset
+            ..loaded_asset.animation_index_offset + loaded_asset.animation_count,
+    );
+    LoadedLevelAsset {
+        name: loaded_asset.name.clone(),
+        node_ids,
+        skin_index_offset,
+        skin_count: loaded_asset.skin_count,
+        animation_index_offset,
+        animation_count: loaded_asset.animation_count,
+    }
+}
+
+pub fn apply_level_asset_settings(
+    scene: &mut Scene,
+    physics_state: &mut PhysicsState,
+    renderer_state: &RendererState,
+    asset: &LevelAssetDescription,
+    loaded_asset: &LoadedLevelAsset,
+) {
+    if let Some(asset_transform) = asset.transform {
+        let asset_transform = crate::transform::Transform::from(asset_transform);
+        let root_node_ids: Vec<_> = loaded_asset
+            .node_ids
+            .iter()
+            .copied()
+            .filter(|node_id| scene.get_node_parent(*node_id).is_none())
+            .collect();
+        for node_id in root_node_ids {
+            if let Some(node) = scene.get_node_mut(node_id) {
+                node.transform = asset_transform * node.transform;
+            }
+        }
+    }
+
+    if asset.static_colliders {
+        for node_id in loaded_asset.node_ids.iter().copied() {
+            physics_state.add_static_box(scene, renderer_state, node_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINIMAL_LEVEL: &str = r#"(
+        assets: [(name: "base", path: "./base.gltf")],
+        skybox: (background: Equirectangular(image_path: "./sky.jpg")),
+        player_spawn: (position: (0.0, 0.0, 0.0), speed: 1.0),
+        point_lights: [(position: (0.0, 1.0, 0.0), color: (1.0, 1.0, 1.0), intensity: 1.0)],
+    )"#;
+
+    fn parse_level(level_file_string: &str) -> LevelDescription {
+        ron::from_str(level_file_string).unwrap()
+    }
+
+    #[test]
+    fn load_default_level() {
+        let level = LevelDescription::load(DEFAULT_LEVEL_PATH).unwrap();
+        assert_eq!(level.assets[0].name, "legendary_robot");
+        assert!(level.character.is_some());
+        assert!(level.enemies.is_some());
+    }
+
+    #[test]
+    fn load_reports_missing_file() {
+        let err = LevelDescription::load("./src/levels/missing_level.ron").unwrap_err();
+        assert!(err.to_string().starts_with("Failed to read level file"));
+    }
+
+    #[test]
+    fn validate_accepts_defaults() {
+        let level = parse_level(MINIMAL_LEVEL);
+        assert!(level.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_duplicate_asset_names() {
+        let mut level = parse_level(MINIMAL_LEVEL);
+        level.assets.push(level.assets[0].clone());
+        assert!(level.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_unknown_asset_references() {
+        let mut level = parse_level(MINIMAL_LEVEL);
+        level.character = Some(CharacterDescription {
+            asset: String::from("missing"),
+            root_node: 0,
+            skin: 0,
+            position: None,
+        });
+        assert!(level.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_point_light_range_inside_shadow_near_plane() {
+        let mut level = parse_level(MINIMAL_LEVEL);
+        level.point_lights[0].range = POINT_SHADOW_MAP_NEAR_PLANE_DISTANCE;
+        assert!(level.validate().is_err());
+    }
+
+    #[test]
+    fn replace_base_asset_drops_index_references() {
+        let mut level = LevelDescription::load(DEFAULT_LEVEL_PATH).unwrap();
+        level.replace_base_asset("./other.gltf");
+
+        assert_eq!(level.assets[0].path, "./other.gltf");
+        assert!(level.assets[0].transform.is_none());
+        // both referenced the robot, the revolver is a different asset
+        assert!(level.character.is_none());
+        assert!(level.enemies.is_none());
+        assert!(level.revolver.is_some());
+        assert!(level.animations.is_empty());
+        assert!(level.validate().is_ok());
+    }
+
+    #[test]
+    fn replace_base_asset_without_assets() {
+        let mut level = parse_level(MINIMAL_LEVEL);
+        level.assets.clear();
+        level.replace_base_asset("./other.gltf");
+
+        assert_eq!(level.assets.len(), 1);
+        assert_eq!(level.assets[0].path, "./other.gltf");
+    }
+}
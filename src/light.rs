@@ -7,6 +7,12 @@ pub struct PointLightComponent {
     pub node_id: GameNodeId,
     pub color: Vector3<f32>,
     pub intensity: f32,
+    // radius of the emitter in world units, bigger lights cast softer shadows
+    pub size: f32,
+    // distance in world units at which the light fades out completely, it only lights the clusters it reaches
+    pub range: f32,
+    // minimum time between two shadow map updates, 0 means the shadows update every frame they change
+    pub shadow_update_interval_seconds: f32,
 }
 
 #[derive(Clone, Debug)]
@@ -15,4 +21,9 @@ pub struct DirectionalLightComponent {
     pub direction: Vector3<f32>,
     pub color: Vector3<f32>,
     pub intensity: f32,
+    // width of the penumbra per unit of distance between the shadow caster and the receiver,
+    // roughly the angular diameter of the light in radians
+    pub size: f32,
+    // minimum time between two shadow map updates, 0 means the shadows update every frame they change
+    pub shadow_update_interval_seconds: f32,
 }
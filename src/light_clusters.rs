@@ -0,0 +1,293 @@
+use anyhow::Result;
+use wgpu::util::DeviceExt;
+
+use super::*;
+
+// the camera's view frustum is split into screen space tiles that are sliced along the view depth
+const CLUSTER_COUNT_X: u32 = 16;
+const CLUSTER_COUNT_Y: u32 = 9;
+const CLUSTER_COUNT_Z: u32 = 24;
+// the first depth slice covers everything closer than this, the last one everything farther than
+// CLUSTER_FAR_DISTANCE, and the rest are spaced exponentially in between
+const CLUSTER_NEAR_DISTANCE: f32 = 0.5;
+const CLUSTER_FAR_DISTANCE: f32 = 1000.0;
+// must match MAX_LIGHTS_PER_CLUSTER in light_clustering.wgsl and textured_mesh.wgsl
+const MAX_LIGHTS_PER_CLUSTER: usize = 127;
+// the light count followed by the light indices, see LightCluster in light_clustering.wgsl
+const LIGHT_CLUSTER_SIZE: usize = (1 + MAX_LIGHTS_PER_CLUSTER) * std::mem::size_of::<u32>();
+const CLUSTERING_WORKGROUP_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightClusterConfigUniform {
+    // x, y, z, point light count
+    cluster_counts: [u32; 4],
+    // of the shading texture, in pixels
+    target_size: [f32; 2],
+    near_distance: f32,
+    far_distance: f32,
+}
+
+// clustered forward shading for the point lights. a compute pass lists the lights whose range reaches
+// each cluster of the camera's view and the pbr fragment shader only evaluates the lights of its own
+// cluster, so the cost of a light depends on how much of the screen it covers instead of on the total
+// number of lights. see light_clustering.wgsl
+pub struct LightClusters {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    config_buffer: wgpu::Buffer,
+    clusters_buffer: wgpu::Buffer,
+    point_light_count: usize,
+}
+
+impl LightClusters {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_buffer: &wgpu::Buffer,
+        point_lights_buffer: &GpuBuffer,
+    ) -> Result<Self> {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Light Clustering Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                std::fs::read_to_string("./src/shaders/light_clustering.wgsl")?.into(),
+            ),
+        });
+
+        let buffer_entry = |binding: u32, ty: wgpu::BufferBindingType| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                buffer_entry(0, wgpu::BufferBindingType::Uniform),
+                buffer_entry(1, wgpu::BufferBindingType::Uniform),
+                buffer_entry(2, wgpu::BufferBindingType::Storage { read_only: true }),
+                buffer_entry(3, wgpu::BufferBindingType::Storage { read_only: false }),
+            ],
+            label: Some("light_clustering_bind_group_layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Light Clustering Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Light Clustering Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "build_clusters",
+        });
+
+        let config_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light Cluster Config Buffer"),
+            contents: bytemuck::cast_slice(&[LightClusterConfigUniform {
+                cluster_counts: [CLUSTER_COUNT_X, CLUSTER_COUNT_Y, CLUSTER_COUNT_Z, 0],
+                target_size: [1.0, 1.0],
+                near_distance: CLUSTER_NEAR_DISTANCE,
+                far_distance: CLUSTER_FAR_DISTANCE,
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let clusters_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light Clusters Buffer"),
+            contents: &vec![0u8; Self::cluster_count() as usize * LIGHT_CLUSTER_SIZE],
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let bind_group = Self::make_bind_group(
+            device,
+            &bind_group_layout,
+            camera_buffer,
+            &config_buffer,
+            point_lights_buffer,
+            &clusters_buffer,
+        );
+
+        Ok(Self {
+            pipeline,
+            bind_group_layout,
+            bind_group,
+            config_buffer,
+            clusters_buffer,
+            point_light_count: 0,
+        })
+    }
+
+    fn cluster_count() -> u32 {
+        CLUSTER_COUNT_X * CLUSTER_COUNT_Y * CLUSTER_COUNT_Z
+    }
+
+    // the point lights buffer gets recreated when it grows
+    pub fn set_point_lights_buffer(
+        &mut self,
+        device: &wgpu::Device,
+        camera_buffer: &wgpu::Buffer,
+        point_lights_buffer: &GpuBuffer,
+    ) {
+        self.bind_group = Self::make_bind_group(
+            device,
+            &self.bind_group_layout,
+            camera_buffer,
+            &self.config_buffer,
+            point_lights_buffer,
+            &self.clusters_buffer,
+        );
+    }
+
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        point_light_count: usize,
+        (shading_texture_width, shading_texture_height): (u32, u32),
+        logger: &mut Logger,
+    ) {
+        // only warn when the count first goes over the limit, not every frame
+        if point_light_count > MAX_LIGHTS_PER_CLUSTER
+            && self.point_light_count <= MAX_LIGHTS_PER_CLUSTER
+        {
+            logger.log(&format!(
+                "{point_light_count} point lights is over the limit of {MAX_LIGHTS_PER_CLUSTER} per cluster, the clusters they all reach will drop some of them"
+            ));
+        }
+        self.point_light_count = point_light_count;
+        queue.write_buffer(
+            &self.config_buffer,
+            0,
+            bytemuck::cast_slice(&[LightClusterConfigUniform {
+                cluster_counts: [
+                    CLUSTER_COUNT_X,
+                    CLUSTER_COUNT_Y,
+                    CLUSTER_COUNT_Z,
+                    point_light_count as u32,
+                ],
+                target_size: [shading_texture_width as f32, shading_texture_height as f32],
+                near_distance: CLUSTER_NEAR_DISTANCE,
+                far_distance: CLUSTER_FAR_DISTANCE,
+            }]),
+        );
+    }
+
+    // must run after the camera buffer is written for the frame and before the meshes are shaded
+    pub fn dispatch(&self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Light Clustering Encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Light Clustering Compute Pass"),
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &self.bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                Self::cluster_count().div_ceil(CLUSTERING_WORKGROUP_SIZE),
+                1,
+                1,
+            );
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    // for the camera and lights bind group of the mesh pipelines
+    pub fn config_buffer(&self) -> &wgpu::Buffer {
+        &self.config_buffer
+    }
+
+    pub fn clusters_buffer(&self) -> &wgpu::Buffer {
+        &self.clusters_buffer
+    }
+
+    fn make_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        camera_buffer: &wgpu::Buffer,
+        config_buffer: &wgpu::Buffer,
+        point_lights_buffer: &GpuBuffer,
+        clusters_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: config_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: point_lights_buffer.src().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: clusters_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("light_clustering_bind_group"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // mirrors depth_slice_range in light_clustering.wgsl, with the camera's far plane at infinity
+    fn depth_slice_range(slice: u32) -> (f32, f32) {
+        if slice == 0 {
+            return (0.0, CLUSTER_NEAR_DISTANCE);
+        }
+        let inner_slice_count = (CLUSTER_COUNT_Z - 2) as f32;
+        let depth_ratio = CLUSTER_FAR_DISTANCE / CLUSTER_NEAR_DISTANCE;
+        let start =
+            CLUSTER_NEAR_DISTANCE * depth_ratio.powf((slice - 1) as f32 / inner_slice_count);
+        if slice == CLUSTER_COUNT_Z - 1 {
+            return (start, f32::INFINITY);
+        }
+        let end = CLUSTER_NEAR_DISTANCE * depth_ratio.powf(slice as f32 / inner_slice_count);
+        (start, end)
+    }
+
+    // mirrors the depth slice lookup of light_cluster_index in textured_mesh.wgsl
+    fn depth_slice(view_depth: f32) -> u32 {
+        if view_depth < CLUSTER_NEAR_DISTANCE {
+            return 0;
+        }
+        let inner_slice_count = (CLUSTER_COUNT_Z - 2) as f32;
+        let depth_ratio = CLUSTER_FAR_DISTANCE / CLUSTER_NEAR_DISTANCE;
+        (((view_depth / CLUSTER_NEAR_DISTANCE).ln() / depth_ratio.ln() * inner_slice_count) as u32
+            + 1)
+        .min(CLUSTER_COUNT_Z - 1)
+    }
+
+    #[test]
+    fn depth_slices_match_shading_lookup() {
+        // the slices cover the whole depth range without gaps
+        assert_eq!(depth_slice_range(0).0, 0.0);
+        for slice in 1..CLUSTER_COUNT_Z {
+            assert_eq!(depth_slice_range(slice - 1).1, depth_slice_range(slice).0);
+        }
+
+        let mut view_depth = 0.01;
+        while view_depth < CLUSTER_FAR_DISTANCE * 4.0 {
+            let slice = depth_slice(view_depth);
+            let (start, end) = depth_slice_range(slice);
+            // allow for the rounding of pow and log at the slice boundaries
+            let tolerance = view_depth * 1e-4;
+            assert!(
+                start - tolerance <= view_depth && view_depth <= end + tolerance,
+                "depth {view_depth} is in slice {slice} which covers {start}..{end}"
+            );
+            view_depth *= 1.01;
+        }
+    }
+}
@@ -1,17 +1,29 @@
 mod animation;
 mod audio;
+mod auto_exposure;
 mod ball;
+mod bloom;
 mod buffer;
 mod camera;
 mod character;
+mod cli;
+mod culling;
+mod enemy;
 mod game;
 mod game_state;
 mod gameloop;
 mod gltf_loader;
+mod gpu_driven;
 mod helpers;
+mod hud;
+mod instancing;
+mod level;
 mod light;
+mod light_clusters;
 mod logger;
 mod mesh;
+mod msaa;
+mod navigation;
 mod physics;
 mod physics_ball;
 mod player_controller;
@@ -19,23 +31,39 @@ mod renderer;
 mod revolver;
 mod scene;
 mod skinning;
+mod ssao;
+mod taa;
 mod texture;
 mod time_tracker;
+mod tone_mapping;
 mod transform;
+mod ui;
 
 use animation::*;
 use audio::*;
+use auto_exposure::*;
 use ball::*;
+use bloom::*;
 use buffer::*;
 use camera::*;
 use character::*;
+use cli::*;
+use culling::*;
+use enemy::*;
 use game::*;
 use game_state::*;
 use gltf_loader::*;
+use gpu_driven::*;
 use helpers::*;
+use hud::*;
+use instancing::*;
+use level::*;
 use light::*;
+use light_clusters::*;
 use logger::*;
 use mesh::*;
+use msaa::*;
+use navigation::*;
 use physics::*;
 use physics_ball::*;
 use player_controller::*;
@@ -44,66 +72,99 @@ use renderer::*;
 use revolver::*;
 use scene::*;
 use skinning::*;
+use ssao::*;
+use taa::*;
 use texture::*;
 use time_tracker::*;
+use tone_mapping::*;
 use transform::*;
+use ui::*;
 
 use cgmath::prelude::*;
+use clap::{CommandFactory, Parser};
 
 async fn start() {
+    let args = CliArgs::parse();
+    if let Err(err) = args.validate() {
+        // same output and exit code as the errors clap finds while parsing
+        CliArgs::command()
+            .error(clap::ErrorKind::ValueValidation, err)
+            .exit();
+    }
+
     let event_loop = winit::event_loop::EventLoop::new();
     let window = {
         let window = winit::window::WindowBuilder::new()
-            // .with_inner_size(winit::dpi::LogicalSize::new(1000.0f32, 1000.0f32))
-            // .with_fullscreen(Some(winit::window::Fullscreen::Borderless(None)))
-            .with_inner_size(winit::dpi::PhysicalSize::new(1920.0, 1080.0))
+            .with_inner_size(winit::dpi::PhysicalSize::new(args.width, args.height))
             .with_title("David's window name")
             // .with_visible(false)
             .build(&event_loop)
             .expect("Failed to create window");
 
-        Some(window)
-        // for selecting video modes!
-        // if cfg!(target_os = "macos") {
-        //     Some(window)
-        // } else {
-        //     let monitor = window.current_monitor().unwrap();
-        //     let video_modes: Vec<winit::monitor::VideoMode> = monitor.video_modes().collect();
-        //     let video_mode_labels: Vec<String> = video_modes
-        //         .iter()
-        //         .map(|video_mode| format!("{:}", video_mode))
-        //         .collect();
-        //     // println!("{:}", video_modes[0]);
+        if args.fullscreen {
+            window.set_fullscreen(Some(winit::window::Fullscreen::Borderless(None)));
+            Some(window)
+        } else if args.select_video_mode && !cfg!(target_os = "macos") {
+            let monitor = window.current_monitor().unwrap();
+            let video_modes: Vec<winit::monitor::VideoMode> = monitor.video_modes().collect();
+            let video_mode_labels: Vec<String> = video_modes
+                .iter()
+                .map(|video_mode| format!("{:}", video_mode))
+                .collect();
 
-        //     let selected_video_mode_index = dialoguer::Select::new()
-        //         .items(&video_mode_labels)
-        //         .default(0)
-        //         .interact_opt()
-        //         .expect("Dialoguer failed");
+            let selected_video_mode_index = dialoguer::Select::new()
+                .items(&video_mode_labels)
+                .default(0)
+                .interact_opt()
+                .expect("Dialoguer failed");
 
-        //     match selected_video_mode_index {
-        //         Some(selected_video_mode_index) => {
-        //             window.set_fullscreen(Some(winit::window::Fullscreen::Exclusive(
-        //                 video_modes[selected_video_mode_index].clone(),
-        //             )));
-        //             Some(window)
-        //         }
-        //         None => {
-        //             println!("No video mode selected");
-        //             None
-        //         }
-        //     }
-        // }
+            match selected_video_mode_index {
+                Some(selected_video_mode_index) => {
+                    window.set_fullscreen(Some(winit::window::Fullscreen::Exclusive(
+                        video_modes[selected_video_mode_index].clone(),
+                    )));
+                    Some(window)
+                }
+                None => {
+                    println!("No video mode selected");
+                    None
+                }
+            }
+        } else {
+            if args.select_video_mode {
+                println!("Video mode selection isn't supported on macos");
+            }
+            Some(window)
+        }
     };
     if let Some(window) = window {
         let mut logger = Logger::new();
         let mut base_render_state = BaseRendererState::new(&window).await;
 
         let run_result = async {
-            let (game_scene, render_buffers) = init_scene(&mut base_render_state, &mut logger)?;
-            let mut renderer_state =
-                RendererState::new(render_buffers, base_render_state, &mut logger).await?;
-            let game_state = init_game_state(game_scene, &mut renderer_state, &mut logger)?;
+            let level = args.load_level()?;
+            let (game_scene, render_buffers) =
+                init_scene(&mut base_render_state, &level, &mut logger)?;
+            let (skybox_background, skybox_hdr_environment) = level.skybox();
+            let color_grading_lut = level.color_grading_lut()?;
+            let mut renderer_state = RendererState::new(
+                render_buffers,
+                base_render_state,
+                skybox_background,
+                skybox_hdr_environment,
+                color_grading_lut,
+                args.renderer_settings(&level),
+                &mut logger,
+            )
+            .await?;
+            let damage_vignette = DamageVignette::new(&mut renderer_state)?;
+            let game_state = init_game_state(
+                game_scene,
+                &level,
+                damage_vignette,
+                &mut renderer_state,
+                &mut logger,
+            )?;
             gameloop::run(window, event_loop, game_state, renderer_state, logger); // this will block while the game is running
             anyhow::Ok(())
         }
@@ -114,7 +175,8 @@ async fn start() {
                 "Error setting up game / render state: {}\n{}",
                 err,
                 err.backtrace()
-            )
+            );
+            std::process::exit(1);
         }
     }
 }
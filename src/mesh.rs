@@ -46,7 +46,7 @@ pub struct GpuPbrMeshInstance {
     base_color_factor: [f32; 4],
     emissive_factor: [f32; 4],
     mrno: [f32; 4], // metallic_factor, roughness_factor, normal scale, occlusion strength
-    alpha_cutoff: f32,
+    alpha_and_shadows: [f32; 4], // alpha_cutoff, shadow depth bias, shadow normal bias, receive shadows (0 or 1)
 }
 
 impl GpuPbrMeshInstance {
@@ -55,7 +55,7 @@ impl GpuPbrMeshInstance {
         12 => Float32x4,
         13 => Float32x4,
         14 => Float32x4,
-        15 => Float32,
+        15 => Float32x4,
     ];
 
     pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
@@ -66,7 +66,11 @@ impl GpuPbrMeshInstance {
         }
     }
 
-    pub fn new(transform: crate::transform::Transform, pbr_params: DynamicPbrParams) -> Self {
+    pub fn new(
+        transform: crate::transform::Transform,
+        pbr_params: DynamicPbrParams,
+        receive_shadows: bool,
+    ) -> Self {
         let DynamicPbrParams {
             base_color_factor,
             emissive_factor,
@@ -75,6 +79,8 @@ impl GpuPbrMeshInstance {
             normal_scale,
             occlusion_strength,
             alpha_cutoff,
+            shadow_depth_bias,
+            shadow_normal_bias,
         } = pbr_params;
         Self {
             model_transform: GpuMatrix4(transform.matrix()),
@@ -91,7 +97,12 @@ impl GpuPbrMeshInstance {
                 normal_scale,
                 occlusion_strength,
             ],
-            alpha_cutoff,
+            alpha_and_shadows: [
+                alpha_cutoff,
+                shadow_depth_bias,
+                shadow_normal_bias,
+                if receive_shadows { 1.0 } else { 0.0 },
+            ],
         }
     }
 }
@@ -120,6 +131,47 @@ impl GpuUnlitMeshInstance {
 
 pub type GpuWireframeMeshInstance = GpuUnlitMeshInstance;
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GpuMorphTargetDisplacement {
+    pub position: [f32; 4],
+    pub normal: [f32; 4],
+    pub tangent: [f32; 4],
+}
+
+// matches the MorphTargets struct in textured_mesh.wgsl: a 16 byte header with the target and
+// vertex counts followed by the displacements of every vertex for target 0, then target 1, etc.
+pub fn make_morph_targets_buffer(
+    device: &wgpu::Device,
+    vertex_count: usize,
+    displacements: &[GpuMorphTargetDisplacement],
+) -> GpuBuffer {
+    let target_count = if vertex_count == 0 {
+        0
+    } else {
+        displacements.len() / vertex_count
+    };
+    let header: [u32; 4] = [target_count as u32, vertex_count as u32, 0, 0];
+    // storage buffer bindings can't be empty
+    let default_displacements = [GpuMorphTargetDisplacement::default()];
+    let displacements = if displacements.is_empty() {
+        &default_displacements
+    } else {
+        displacements
+    };
+    let contents: Vec<u8> = bytemuck::cast_slice(&header)
+        .iter()
+        .chain(bytemuck::cast_slice(displacements).iter())
+        .copied()
+        .collect();
+    GpuBuffer::from_bytes(
+        device,
+        &contents,
+        std::mem::size_of::<GpuMorphTargetDisplacement>(),
+        wgpu::BufferUsages::STORAGE,
+    )
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct DynamicPbrParams {
     pub base_color_factor: Vector4<f32>,
@@ -129,6 +181,10 @@ pub struct DynamicPbrParams {
     pub normal_scale: f32,
     pub occlusion_strength: f32,
     pub alpha_cutoff: f32,
+    // subtracted from the receiver's depth in the shadow maps
+    pub shadow_depth_bias: f32,
+    // world units that the receiver is pushed out along its normal before looking up the shadow maps
+    pub shadow_normal_bias: f32,
 }
 
 impl Default for DynamicPbrParams {
@@ -141,6 +197,8 @@ impl Default for DynamicPbrParams {
             normal_scale: 1.0,
             occlusion_strength: 1.0,
             alpha_cutoff: -1.0,
+            shadow_depth_bias: 0.0001,
+            shadow_normal_bias: 0.0,
         }
     }
 }
@@ -0,0 +1,287 @@
+use anyhow::Result;
+
+use super::*;
+
+// wgpu only accepts 1 or 4 samples for render pass attachments
+pub const SUPPORTED_MSAA_SAMPLE_COUNTS: [u32; 2] = [1, 4];
+
+// the multisampled copies of the shading, motion vector and depth textures, resolved into the
+// single sampled ones that the rest of the frame reads
+struct MultisampledTargets {
+    shading_texture: Texture,
+    motion_vector_texture: Texture,
+    depth_texture: Texture,
+    depth_resolve_bind_group: wgpu::BindGroup,
+}
+
+// multisample anti-aliasing for the meshes that are drawn into the shading texture.
+// with a sample count of 1 it passes the single sampled textures through and does nothing
+pub struct Msaa {
+    sample_count: u32,
+    depth_resolve_pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    targets: Option<MultisampledTargets>,
+}
+
+impl Msaa {
+    // the device is requested without TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES, so it validates the
+    // textures against the guaranteed format features, except on downlevel backends where it uses the
+    // adapter's. the formats must support multisampling in both to get 4x, the only count above 1
+    pub fn max_supported_sample_count(adapter: &wgpu::Adapter) -> u32 {
+        let supported = [
+            (wgpu::TextureFormat::Rgba16Float, true),
+            (MOTION_VECTOR_TEXTURE_FORMAT, true),
+            (Texture::DEPTH_FORMAT, false),
+        ]
+        .iter()
+        .all(|(format, needs_resolve)| {
+            let flags = format.describe().guaranteed_format_features.flags
+                & adapter.get_texture_format_features(*format).flags;
+            flags.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE)
+                && (!needs_resolve
+                    || flags.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE))
+        });
+        if supported {
+            *SUPPORTED_MSAA_SAMPLE_COUNTS.last().unwrap()
+        } else {
+            1
+        }
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        sample_count: u32,
+        shading_texture: &Texture,
+    ) -> Result<Self> {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("MSAA Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                std::fs::read_to_string("./src/shaders/msaa.wgsl")?.into(),
+            ),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: true,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Depth,
+                },
+                count: None,
+            }],
+            label: Some("msaa_bind_group_layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("MSAA Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let depth_resolve_pipeline =
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("MSAA Depth Resolve Pipeline"),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "depth_resolve_fs_main",
+                    targets: &[],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            });
+
+        let targets = Self::make_targets(
+            device,
+            &bind_group_layout,
+            sample_count,
+            shading_texture.size,
+        );
+
+        Ok(Self {
+            sample_count,
+            depth_resolve_pipeline,
+            bind_group_layout,
+            targets,
+        })
+    }
+
+    // the multisampled textures always match the size of the shading texture
+    pub fn resize(&mut self, device: &wgpu::Device, shading_texture: &Texture) {
+        self.targets = Self::make_targets(
+            device,
+            &self.bind_group_layout,
+            self.sample_count,
+            shading_texture.size,
+        );
+    }
+
+    // draws into the multisampled shading texture and resolves it into shading_view at the end of the pass
+    pub fn shading_attachment<'a>(
+        &'a self,
+        shading_view: &'a wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) -> wgpu::RenderPassColorAttachment<'a> {
+        Self::color_attachment(
+            self.targets
+                .as_ref()
+                .map(|targets| &targets.shading_texture.view),
+            shading_view,
+            load,
+        )
+    }
+
+    pub fn motion_vector_attachment<'a>(
+        &'a self,
+        motion_vector_view: &'a wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) -> wgpu::RenderPassColorAttachment<'a> {
+        Self::color_attachment(
+            self.targets
+                .as_ref()
+                .map(|targets| &targets.motion_vector_texture.view),
+            motion_vector_view,
+            load,
+        )
+    }
+
+    // the multisampled depth texture can't be resolved by a render pass, see resolve_depth
+    pub fn depth_view<'a>(&'a self, depth_view: &'a wgpu::TextureView) -> &'a wgpu::TextureView {
+        self.targets
+            .as_ref()
+            .map(|targets| &targets.depth_texture.view)
+            .unwrap_or(depth_view)
+    }
+
+    // copies the farthest sample of each pixel into depth_texture, see msaa.wgsl.
+    // must run before anything reads depth_texture after the meshes were drawn
+    pub fn resolve_depth(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        depth_texture: &Texture,
+    ) {
+        let targets = match &self.targets {
+            Some(targets) => targets,
+            None => return,
+        };
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("MSAA Depth Resolve Encoder"),
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("MSAA Depth Resolve Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(0.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            render_pass.set_pipeline(&self.depth_resolve_pipeline);
+            render_pass.set_bind_group(0, &targets.depth_resolve_bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    fn color_attachment<'a>(
+        multisampled_view: Option<&'a wgpu::TextureView>,
+        view: &'a wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) -> wgpu::RenderPassColorAttachment<'a> {
+        match multisampled_view {
+            Some(multisampled_view) => wgpu::RenderPassColorAttachment {
+                view: multisampled_view,
+                resolve_target: Some(view),
+                ops: wgpu::Operations { load, store: true },
+            },
+            None => wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations { load, store: true },
+            },
+        }
+    }
+
+    fn make_targets(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+        size: wgpu::Extent3d,
+    ) -> Option<MultisampledTargets> {
+        if sample_count == 1 {
+            return None;
+        }
+        let make_texture = |label: &str, format: wgpu::TextureFormat| {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size,
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            });
+            let view = texture.create_view(&Default::default());
+            // multisampled textures can only be read with textureLoad
+            let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Nearest,
+                min_filter: wgpu::FilterMode::Nearest,
+                ..Default::default()
+            });
+            Texture {
+                texture,
+                view,
+                sampler,
+                size,
+            }
+        };
+        let shading_texture = make_texture(
+            "multisampled_shading_texture",
+            wgpu::TextureFormat::Rgba16Float,
+        );
+        let motion_vector_texture = make_texture(
+            "multisampled_motion_vector_texture",
+            MOTION_VECTOR_TEXTURE_FORMAT,
+        );
+        let depth_texture = make_texture("multisampled_depth_texture", Texture::DEPTH_FORMAT);
+        let depth_resolve_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+            }],
+            label: Some("msaa_depth_resolve_bind_group"),
+        });
+        Some(MultisampledTargets {
+            shading_texture,
+            motion_vector_texture,
+            depth_texture,
+            depth_resolve_bind_group,
+        })
+    }
+}
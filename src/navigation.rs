@@ -0,0 +1,425 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use cgmath::Vector3;
+use rapier3d::prelude::*;
+
+use super::*;
+
+// integer move costs so the open set can be ordered without float comparisons
+const STRAIGHT_MOVE_COST: u32 = 10;
+const DIAGONAL_MOVE_COST: u32 = 14;
+
+// 2d grid on the xz plane, a cell is blocked if an agent standing in it would overlap
+// one of the level's static colliders
+#[derive(Debug, Clone)]
+pub struct NavGrid {
+    // world space xz of the min corner of cell (0, 0)
+    origin_x: f32,
+    origin_z: f32,
+    cell_size: f32,
+    width: usize,
+    depth: usize,
+    floor_height: f32,
+    blocked: Vec<bool>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct NavCell {
+    pub x: usize,
+    pub z: usize,
+}
+
+impl NavGrid {
+    pub fn new(
+        origin_x: f32,
+        origin_z: f32,
+        cell_size: f32,
+        width: usize,
+        depth: usize,
+        floor_height: f32,
+    ) -> Self {
+        Self {
+            origin_x,
+            origin_z,
+            cell_size,
+            width,
+            depth,
+            floor_height,
+            blocked: vec![false; width * depth],
+        }
+    }
+
+    // covers the static colliders plus any extra points, like the enemy spawn points
+    pub fn from_static_colliders(
+        physics_state: &PhysicsState,
+        description: &NavGridDescription,
+        extra_points: &[Vector3<f32>],
+    ) -> Self {
+        let static_colliders: Vec<_> = physics_state
+            .static_box_set
+            .values()
+            .flatten()
+            .filter_map(|collider_handle| physics_state.collider_set.get(*collider_handle))
+            .collect();
+
+        let (min_x, min_z, max_x, max_z) = static_colliders
+            .iter()
+            .map(|collider| {
+                let aabb = collider.compute_aabb();
+                (aabb.mins.x, aabb.mins.z, aabb.maxs.x, aabb.maxs.z)
+            })
+            .chain(
+                extra_points
+                    .iter()
+                    .map(|point| (point.x, point.z, point.x, point.z)),
+            )
+            .fold(
+                (
+                    f32::INFINITY,
+                    f32::INFINITY,
+                    f32::NEG_INFINITY,
+                    f32::NEG_INFINITY,
+                ),
+                |acc, bounds| {
+                    (
+                        acc.0.min(bounds.0),
+                        acc.1.min(bounds.1),
+                        acc.2.max(bounds.2),
+                        acc.3.max(bounds.3),
+                    )
+                },
+            );
+
+        let cell_size = description.cell_size;
+        if !min_x.is_finite() {
+            return Self::new(0.0, 0.0, cell_size, 0, 0, description.floor_height);
+        }
+        let padding = description.padding_cells as f32 * cell_size;
+        let origin_x = min_x - padding;
+        let origin_z = min_z - padding;
+        let width = ((max_x + padding - origin_x) / cell_size).ceil() as usize;
+        let depth = ((max_z + padding - origin_z) / cell_size).ceil() as usize;
+        let mut result = Self::new(
+            origin_x,
+            origin_z,
+            cell_size,
+            width,
+            depth,
+            description.floor_height,
+        );
+
+        // the agent is a box that spans from the top of a step to the top of its head
+        let agent_bottom = description.floor_height + description.step_height;
+        let agent_top = description.floor_height + description.agent_height;
+        let agent_shape = Cuboid::new(vector![
+            cell_size / 2.0 + description.agent_radius,
+            (agent_top - agent_bottom) / 2.0,
+            cell_size / 2.0 + description.agent_radius
+        ]);
+        let agent_center_y = (agent_top + agent_bottom) / 2.0;
+
+        for collider in static_colliders {
+            let aabb = collider.compute_aabb();
+            if aabb.maxs.y < agent_bottom || aabb.mins.y > agent_top {
+                continue;
+            }
+            let (min_cell, max_cell) = (
+                result.clamped_cell_at(
+                    aabb.mins.x - description.agent_radius,
+                    aabb.mins.z - description.agent_radius,
+                ),
+                result.clamped_cell_at(
+                    aabb.maxs.x + description.agent_radius,
+                    aabb.maxs.z + description.agent_radius,
+                ),
+            );
+            for z in min_cell.z..=max_cell.z {
+                for x in min_cell.x..=max_cell.x {
+                    let cell = NavCell { x, z };
+                    if result.is_blocked(cell) {
+                        continue;
+                    }
+                    let cell_center = result.cell_center(cell);
+                    let agent_position =
+                        Isometry::translation(cell_center.x, agent_center_y, cell_center.z);
+                    // unsupported shape pairs are treated as blocking
+                    let intersects = rapier3d::parry::query::intersection_test(
+                        &agent_position,
+                        &agent_shape,
+                        collider.position(),
+                        collider.shape(),
+                    )
+                    .unwrap_or(true);
+                    if intersects {
+                        result.set_blocked(cell, true);
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
+    pub fn is_blocked(&self, cell: NavCell) -> bool {
+        self.blocked[cell.z * self.width + cell.x]
+    }
+
+    pub fn set_blocked(&mut self, cell: NavCell, blocked: bool) {
+        self.blocked[cell.z * self.width + cell.x] = blocked;
+    }
+
+    pub fn cell_at(&self, position: Vector3<f32>) -> Option<NavCell> {
+        let x = ((position.x - self.origin_x) / self.cell_size).floor();
+        let z = ((position.z - self.origin_z) / self.cell_size).floor();
+        (x >= 0.0 && z >= 0.0 && (x as usize) < self.width && (z as usize) < self.depth).then(
+            || NavCell {
+                x: x as usize,
+                z: z as usize,
+            },
+        )
+    }
+
+    fn clamped_cell_at(&self, world_x: f32, world_z: f32) -> NavCell {
+        let clamp = |value: f32, origin: f32, count: usize| {
+            (((value - origin) / self.cell_size).floor().max(0.0) as usize)
+                .min(count.saturating_sub(1))
+        };
+        NavCell {
+            x: clamp(world_x, self.origin_x, self.width),
+            z: clamp(world_z, self.origin_z, self.depth),
+        }
+    }
+
+    pub fn cell_center(&self, cell: NavCell) -> Vector3<f32> {
+        Vector3::new(
+            self.origin_x + (cell.x as f32 + 0.5) * self.cell_size,
+            self.floor_height,
+            self.origin_z + (cell.z as f32 + 0.5) * self.cell_size,
+        )
+    }
+
+    // A* over the grid, returns the waypoints after the start position with the last one
+    // being the goal. positions outside of the grid or inside obstacles snap to the nearest
+    // walkable cell
+    pub fn find_path(&self, from: Vector3<f32>, to: Vector3<f32>) -> Option<Vec<Vector3<f32>>> {
+        if self.width == 0 || self.depth == 0 {
+            return None;
+        }
+        let start = self.nearest_walkable_cell(self.clamped_cell_at(from.x, from.z))?;
+        let goal = self.nearest_walkable_cell(self.clamped_cell_at(to.x, to.z))?;
+
+        let cell_count = self.width * self.depth;
+        let index = |cell: NavCell| cell.z * self.width + cell.x;
+        let heuristic = |cell: NavCell| {
+            let dx = (cell.x as i64 - goal.x as i64).unsigned_abs() as u32;
+            let dz = (cell.z as i64 - goal.z as i64).unsigned_abs() as u32;
+            STRAIGHT_MOVE_COST * dx.max(dz) + (DIAGONAL_MOVE_COST - STRAIGHT_MOVE_COST) * dx.min(dz)
+        };
+
+        let mut cost_so_far = vec![u32::MAX; cell_count];
+        let mut came_from: Vec<Option<NavCell>> = vec![None; cell_count];
+        let mut open_set = BinaryHeap::new();
+        cost_so_far[index(start)] = 0;
+        open_set.push(Reverse((heuristic(start), start.z, start.x)));
+
+        let mut found = false;
+        while let Some(Reverse((_, z, x))) = open_set.pop() {
+            let current = NavCell { x, z };
+            if current == goal {
+                found = true;
+                break;
+            }
+            let current_cost = cost_so_far[index(current)];
+            for (neighbor, move_cost) in self.walkable_neighbors(current) {
+                let new_cost = current_cost + move_cost;
+                if new_cost < cost_so_far[index(neighbor)] {
+                    cost_so_far[index(neighbor)] = new_cost;
+                    came_from[index(neighbor)] = Some(current);
+                    open_set.push(Reverse((
+                        new_cost + heuristic(neighbor),
+                        neighbor.z,
+                        neighbor.x,
+                    )));
+                }
+            }
+        }
+        if !found {
+            return None;
+        }
+
+        let mut cells = vec![goal];
+        while let Some(previous) = came_from[index(*cells.last().unwrap())] {
+            cells.push(previous);
+        }
+        cells.reverse();
+
+        // skip the waypoints that can be walked past in a straight line
+        let mut waypoints = Vec::new();
+        let mut anchor = from;
+        let mut cell_index = 1;
+        while cell_index < cells.len() {
+            let mut furthest_visible = cell_index;
+            while furthest_visible + 1 < cells.len()
+                && self.has_line_of_sight(anchor, self.cell_center(cells[furthest_visible + 1]))
+            {
+                furthest_visible += 1;
+            }
+            anchor = self.cell_center(cells[furthest_visible]);
+            waypoints.push(anchor);
+            cell_index = furthest_visible + 1;
+        }
+        // walk the last stretch to the actual target if it's reachable in a straight line
+        let to_on_floor = Vector3::new(to.x, self.floor_height, to.z);
+        if (to_on_floor - anchor).magnitude() > 0.001 && self.has_line_of_sight(anchor, to) {
+            waypoints.push(to_on_floor);
+        }
+        if waypoints.is_empty() {
+            waypoints.push(self.cell_center(goal));
+        }
+        Some(waypoints)
+    }
+
+    fn walkable_neighbors(&self, cell: NavCell) -> Vec<(NavCell, u32)> {
+        let mut neighbors = Vec::with_capacity(8);
+        for dz in -1i64..=1 {
+            for dx in -1i64..=1 {
+                if dx == 0 && dz == 0 {
+                    continue;
+                }
+                let neighbor = match self.offset_cell(cell, dx, dz) {
+                    Some(neighbor) => neighbor,
+                    None => continue,
+                };
+                if self.is_blocked(neighbor) {
+                    continue;
+                }
+                let is_diagonal = dx != 0 && dz != 0;
+                if is_diagonal {
+                    // don't cut corners
+                    let side_a = self.offset_cell(cell, dx, 0);
+                    let side_b = self.offset_cell(cell, 0, dz);
+                    if side_a.map_or(true, |side| self.is_blocked(side))
+                        || side_b.map_or(true, |side| self.is_blocked(side))
+                    {
+                        continue;
+                    }
+                }
+                neighbors.push((
+                    neighbor,
+                    if is_diagonal {
+                        DIAGONAL_MOVE_COST
+                    } else {
+                        STRAIGHT_MOVE_COST
+                    },
+                ));
+            }
+        }
+        neighbors
+    }
+
+    fn offset_cell(&self, cell: NavCell, dx: i64, dz: i64) -> Option<NavCell> {
+        let x = cell.x as i64 + dx;
+        let z = cell.z as i64 + dz;
+        (x >= 0 && z >= 0 && (x as usize) < self.width && (z as usize) < self.depth).then(|| {
+            NavCell {
+                x: x as usize,
+                z: z as usize,
+            }
+        })
+    }
+
+    // breadth-first search outwards in rings
+    fn nearest_walkable_cell(&self, cell: NavCell) -> Option<NavCell> {
+        let max_radius = self.width.max(self.depth) as i64;
+        for radius in 0..=max_radius {
+            for dz in -radius..=radius {
+                for dx in -radius..=radius {
+                    if dx.abs() != radius && dz.abs() != radius {
+                        continue;
+                    }
+                    if let Some(candidate) = self.offset_cell(cell, dx, dz) {
+                        if !self.is_blocked(candidate) {
+                            return Some(candidate);
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    // samples the segment at a quarter of the cell size
+    pub fn has_line_of_sight(&self, from: Vector3<f32>, to: Vector3<f32>) -> bool {
+        let delta = Vector3::new(to.x - from.x, 0.0, to.z - from.z);
+        let step_count = (delta.magnitude() / (self.cell_size * 0.25))
+            .ceil()
+            .max(1.0) as usize;
+        (0..=step_count).all(|step| {
+            let sample = from + delta * (step as f32 / step_count as f32);
+            match self.cell_at(sample) {
+                Some(cell) => !self.is_blocked(cell),
+                None => false,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_grid_with_wall() -> NavGrid {
+        // 10x10 unit cells with a wall along x = 5 that has a gap at z = 9
+        let mut grid = NavGrid::new(0.0, 0.0, 1.0, 10, 10, 0.0);
+        for z in 0..9 {
+            grid.set_blocked(NavCell { x: 5, z }, true);
+        }
+        grid
+    }
+
+    #[test]
+    fn straight_path_when_unobstructed() {
+        let grid = NavGrid::new(0.0, 0.0, 1.0, 10, 10, 0.0);
+        let path = grid
+            .find_path(Vector3::new(0.5, 0.0, 0.5), Vector3::new(8.5, 0.0, 8.5))
+            .unwrap();
+        assert_eq!(path.len(), 1);
+        assert!((path[0] - Vector3::new(8.5, 0.0, 8.5)).magnitude() < 0.001);
+    }
+
+    #[test]
+    fn path_goes_around_wall() {
+        let grid = make_grid_with_wall();
+        let from = Vector3::new(1.5, 0.0, 1.5);
+        let to = Vector3::new(8.5, 0.0, 1.5);
+        let path = grid.find_path(from, to).unwrap();
+        assert!(path.len() > 1);
+        assert!(path.iter().any(|waypoint| waypoint.z >= 9.0));
+        let mut previous = from;
+        for waypoint in path.iter().copied() {
+            assert!(grid.has_line_of_sight(previous, waypoint));
+            previous = waypoint;
+        }
+        assert!((previous - to).magnitude() < 0.001);
+    }
+
+    #[test]
+    fn no_path_when_fully_blocked() {
+        let mut grid = make_grid_with_wall();
+        grid.set_blocked(NavCell { x: 5, z: 9 }, true);
+        assert!(grid
+            .find_path(Vector3::new(1.5, 0.0, 1.5), Vector3::new(8.5, 0.0, 1.5))
+            .is_none());
+    }
+
+    #[test]
+    fn goal_inside_obstacle_snaps_to_walkable_cell() {
+        let grid = make_grid_with_wall();
+        let path = grid
+            .find_path(Vector3::new(1.5, 0.0, 4.5), Vector3::new(5.5, 0.0, 4.5))
+            .unwrap();
+        let last = *path.last().unwrap();
+        assert!(!grid.is_blocked(grid.cell_at(last).unwrap()));
+    }
+}
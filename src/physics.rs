@@ -130,6 +130,15 @@ impl PhysicsState {
         }
     }
 
+    pub fn remove_collider(&mut self, collider_handle: ColliderHandle) {
+        self.collider_set.remove(
+            collider_handle,
+            &mut self.island_manager,
+            &mut self.rigid_body_set,
+            true,
+        );
+    }
+
     pub fn remove_rigid_body(&mut self, rigid_body_handle: RigidBodyHandle) {
         self.rigid_body_set.remove(
             rigid_body_handle,
@@ -26,6 +26,7 @@ pub struct PlayerController {
     pub view_direction: ControlledViewDirection,
     pub speed: f32,
     pub rigid_body_handle: RigidBodyHandle,
+    pub collider_handle: ColliderHandle,
 
     pub last_jump_time: Option<Instant>,
 }
@@ -74,7 +75,7 @@ impl PlayerController {
             .restitution(0.0)
             .build();
         let rigid_body_handle = physics_state.rigid_body_set.insert(rigid_body);
-        physics_state.collider_set.insert_with_parent(
+        let collider_handle = physics_state.collider_set.insert_with_parent(
             collider,
             rigid_body_handle,
             &mut physics_state.rigid_body_set,
@@ -94,10 +95,16 @@ impl PlayerController {
             view_direction,
             speed,
             rigid_body_handle,
+            collider_handle,
             last_jump_time: None,
         }
     }
 
+    // a freshly created controller doesn't know that the window already has focus
+    pub fn keep_window_focus_from(&mut self, previous: &PlayerController) {
+        self.window_focused = previous.window_focused;
+    }
+
     pub fn process_device_events(&mut self, event: &DeviceEvent, logger: &mut Logger) {
         match event {
             DeviceEvent::MouseMotion { delta: (d_x, d_y) } if self.window_focused => {
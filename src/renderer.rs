@@ -1,17 +1,38 @@
+use std::collections::{hash_map::DefaultHasher, HashMap};
+use std::hash::Hasher;
 use std::num::{NonZeroU32, NonZeroU64};
+use std::ops::Range;
+use std::rc::Rc;
+use std::time::Instant;
 
 use super::*;
 
 use anyhow::Result;
+use clap::ArgEnum;
 
-use cgmath::{Deg, Matrix4, One, Vector3};
+use cgmath::{Deg, Matrix4, One, Vector2, Vector3};
 use wgpu::util::DeviceExt;
 
-pub const MAX_LIGHT_COUNT: usize = 32;
+// must match MAX_DIRECTIONAL_LIGHTS in textured_mesh.wgsl. the point lights aren't limited, see LightClusters
+pub const MAX_DIRECTIONAL_LIGHT_COUNT: usize = 32;
 pub const NEAR_PLANE_DISTANCE: f32 = 0.001;
 pub const FAR_PLANE_DISTANCE: f32 = 100000.0;
 pub const FOV_Y: Deg<f32> = Deg(45.0);
 pub const DEFAULT_WIREFRAME_COLOR: [f32; 4] = [0.0, 1.0, 1.0, 1.0];
+pub const MAX_SHADOW_CASCADES: usize = 4;
+// TODO: this currently puts on hard limit on number of directional lights at a time
+pub const MAX_SHADOW_CASTING_DIRECTIONAL_LIGHTS: usize = 2;
+pub const DIRECTIONAL_SHADOW_MAP_RESOLUTION: u32 = 2048;
+// how far along the light direction casters are picked up around each cascade
+pub const DIRECTIONAL_SHADOW_DEPTH: f32 = 1000.0;
+// the cascade splits are computed from here instead of NEAR_PLANE_DISTANCE,
+// otherwise the logarithmic splits would make the first cascades tiny
+pub const SHADOW_CASCADE_NEAR_DISTANCE: f32 = 0.5;
+// TODO: this currently puts on hard limit on number of point lights at a time
+pub const MAX_SHADOW_CASTING_POINT_LIGHTS: usize = 2;
+pub const POINT_SHADOW_MAP_RESOLUTION: u32 = 1024;
+// the far plane is the light's range, casters farther away than that are culled
+pub const POINT_SHADOW_MAP_NEAR_PLANE_DISTANCE: f32 = 0.1;
 
 #[repr(C)]
 #[derive(Copy, Clone, PartialEq, Debug)]
@@ -29,36 +50,43 @@ unsafe impl bytemuck::Zeroable for GpuMatrix3 {}
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
-pub struct Float16(half::f16);
+pub struct Float16(pub half::f16);
 
 unsafe impl bytemuck::Pod for Float16 {}
 unsafe impl bytemuck::Zeroable for Float16 {}
 
+// the exposure must stay first, it's overwritten by the auto exposure compute pass
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ToneMappingConfigUniform {
+    exposure: f32,
+    tone_mapping_operator: u32,
+    // 0 or 1
+    enable_color_grading: u32,
+    color_grading_lut_size: f32,
+    color_grading_lut_domain_min: [f32; 4],
+    color_grading_lut_domain_max: [f32; 4],
+    // 0 when bloom is off
+    bloom_intensity: f32,
+    padding: [f32; 3],
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct PointLightUniform {
     position: [f32; 4],
     color: [f32; 4],
+    // size, range, casts shadows, unused
+    shadow_config: [f32; 4],
 }
 
-impl Default for PointLightUniform {
-    fn default() -> Self {
-        Self {
-            position: [0.0, 0.0, 0.0, 1.0],
-            color: [0.0, 0.0, 0.0, 1.0],
-        }
-    }
-}
-
-fn make_point_light_uniform_buffer(game_state: &GameState) -> Vec<PointLightUniform> {
-    let mut light_uniforms = Vec::new();
-
-    let active_light_count = game_state.point_lights.len();
-    let mut active_lights = game_state
+fn make_point_light_uniform_buffer(inputs: RenderInputs) -> Vec<PointLightUniform> {
+    inputs
         .point_lights
         .iter()
-        .flat_map(|point_light| {
-            game_state
+        .enumerate()
+        .flat_map(|(light_index, point_light)| {
+            inputs
                 .scene
                 .get_node(point_light.node_id)
                 .map(|light_node| {
@@ -71,41 +99,67 @@ fn make_point_light_uniform_buffer(game_state: &GameState) -> Vec<PointLightUnif
                             point_light.color.z,
                             point_light.intensity,
                         ],
+                        // only the first lights have a shadow map
+                        shadow_config: [
+                            point_light.size,
+                            point_light.range,
+                            if light_index < MAX_SHADOW_CASTING_POINT_LIGHTS {
+                                1.0
+                            } else {
+                                0.0
+                            },
+                            0.0,
+                        ],
                     }
                 })
         })
-        .collect::<Vec<_>>();
-    light_uniforms.append(&mut active_lights);
-
-    let mut inactive_lights = (0..(MAX_LIGHT_COUNT as usize - active_light_count))
-        .map(|_| PointLightUniform::default())
-        .collect::<Vec<_>>();
-    light_uniforms.append(&mut inactive_lights);
-
-    light_uniforms
+        .collect()
 }
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct DirectionalLightUniform {
-    world_space_to_light_space: [[f32; 4]; 4],
+    // one per cascade
+    world_space_to_light_space: [[[f32; 4]; 4]; MAX_SHADOW_CASCADES],
+    // far distance of each cascade along the camera's view direction
+    cascade_splits: [f32; MAX_SHADOW_CASCADES],
+    // cascade count, blend fraction
+    cascade_config: [f32; 4],
+    // size, unused, unused, unused
+    shadow_config: [f32; 4],
     position: [f32; 4],
     direction: [f32; 4],
     color: [f32; 4],
 }
 
-impl From<&DirectionalLightComponent> for DirectionalLightUniform {
-    fn from(light: &DirectionalLightComponent) -> Self {
+impl DirectionalLightUniform {
+    fn new(
+        light: &DirectionalLightComponent,
+        cascades: &[ShaderCameraView],
+        cascade_splits: &[f32],
+        cascade_blend_fraction: f32,
+    ) -> Self {
         let DirectionalLightComponent {
             position,
             direction,
             color,
             intensity,
+            size,
+            ..
         } = light;
-        let view_proj_matrices =
-            build_directional_light_camera_view(-light.direction, 100.0, 100.0, 1000.0);
+        let mut world_space_to_light_space = [Matrix4::one().into(); MAX_SHADOW_CASCADES];
+        for (matrix, cascade) in world_space_to_light_space.iter_mut().zip(cascades.iter()) {
+            *matrix = (cascade.proj * cascade.view).into();
+        }
+        let mut splits = [0.0; MAX_SHADOW_CASCADES];
+        for (split, cascade_split) in splits.iter_mut().zip(cascade_splits.iter()) {
+            *split = *cascade_split;
+        }
         Self {
-            world_space_to_light_space: (view_proj_matrices.proj * view_proj_matrices.view).into(),
+            world_space_to_light_space,
+            cascade_splits: splits,
+            cascade_config: [cascades.len() as f32, cascade_blend_fraction, 0.0, 0.0],
+            shadow_config: [*size, 0.0, 0.0, 0.0],
             position: [position.x, position.y, position.z, 1.0],
             direction: [direction.x, direction.y, direction.z, 1.0],
             color: [color.x, color.y, color.z, *intensity],
@@ -116,7 +170,10 @@ impl From<&DirectionalLightComponent> for DirectionalLightUniform {
 impl Default for DirectionalLightUniform {
     fn default() -> Self {
         Self {
-            world_space_to_light_space: Matrix4::one().into(),
+            world_space_to_light_space: [Matrix4::one().into(); MAX_SHADOW_CASCADES],
+            cascade_splits: [0.0; MAX_SHADOW_CASCADES],
+            cascade_config: [0.0; 4],
+            shadow_config: [0.0; 4],
             position: [0.0, 0.0, 0.0, 1.0],
             direction: [0.0, -1.0, 0.0, 1.0],
             color: [0.0, 0.0, 0.0, 1.0],
@@ -126,17 +183,32 @@ impl Default for DirectionalLightUniform {
 
 fn make_directional_light_uniform_buffer(
     lights: &[DirectionalLightComponent],
+    // one list of cascades per light
+    light_cascades: &[Vec<ShaderCameraView>],
+    cascade_splits: &[f32],
+    cascade_blend_fraction: f32,
 ) -> Vec<DirectionalLightUniform> {
     let mut light_uniforms = Vec::new();
 
     let active_light_count = lights.len();
     let mut active_lights = lights
         .iter()
-        .map(DirectionalLightUniform::from)
+        .enumerate()
+        .map(|(light_index, light)| {
+            DirectionalLightUniform::new(
+                light,
+                light_cascades
+                    .get(light_index)
+                    .map(|cascades| cascades.as_slice())
+                    .unwrap_or(&[]),
+                cascade_splits,
+                cascade_blend_fraction,
+            )
+        })
         .collect::<Vec<_>>();
     light_uniforms.append(&mut active_lights);
 
-    let mut inactive_lights = (0..(MAX_LIGHT_COUNT as usize - active_light_count))
+    let mut inactive_lights = (0..(MAX_DIRECTIONAL_LIGHT_COUNT - active_light_count))
         .map(|_| DirectionalLightUniform::default())
         .collect::<Vec<_>>();
     light_uniforms.append(&mut inactive_lights);
@@ -167,6 +239,160 @@ pub enum SkyboxHDREnvironment<'a> {
     Equirectangular { image_path: &'a str },
 }
 
+// supersampling is separate, through the render scale
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ArgEnum)]
+pub enum AntiAliasingMode {
+    #[default]
+    None,
+    Taa,
+}
+
+impl AntiAliasingMode {
+    const ALL: [Self; 2] = [Self::None, Self::Taa];
+
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|mode| *mode == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+// the initial values of the settings that can be changed at runtime
+#[derive(Debug, Copy, Clone)]
+pub struct RendererSettings {
+    pub render_scale: f32,
+    pub anti_aliasing_mode: AntiAliasingMode,
+    // can't be changed at runtime, the mesh pipelines are created for it
+    pub msaa_sample_count: u32,
+    // draw the opaque and alpha masked meshes with gpu culling and indirect draws, see GpuDrivenMeshes
+    pub gpu_driven_rendering: bool,
+    pub tone_mapping_exposure: f32,
+    pub tone_mapping_operator: ToneMappingOperator,
+    pub enable_bloom: bool,
+    pub bloom: BloomSettings,
+    pub enable_ssao: bool,
+    pub ssao: SsaoSettings,
+    pub enable_shadows: bool,
+    pub enable_auto_exposure: bool,
+    pub auto_exposure: AutoExposureSettings,
+    pub shadow_cascade_count: u32,
+    pub shadow_cascade_split_lambda: f32,
+    pub shadow_distance: f32,
+    pub shadow_cascade_blend_fraction: f32,
+}
+
+impl Default for RendererSettings {
+    fn default() -> Self {
+        Self {
+            render_scale: INITIAL_RENDER_SCALE,
+            anti_aliasing_mode: AntiAliasingMode::default(),
+            msaa_sample_count: INITIAL_MSAA_SAMPLE_COUNT,
+            gpu_driven_rendering: false,
+            tone_mapping_exposure: INITIAL_TONE_MAPPING_EXPOSURE,
+            tone_mapping_operator: ToneMappingOperator::default(),
+            enable_bloom: true,
+            bloom: BloomSettings {
+                radius: INITIAL_BLOOM_RADIUS,
+                intensity: INITIAL_BLOOM_INTENSITY,
+            },
+            enable_ssao: true,
+            ssao: SsaoSettings {
+                radius: INITIAL_SSAO_RADIUS,
+                intensity: INITIAL_SSAO_INTENSITY,
+            },
+            enable_shadows: true,
+            enable_auto_exposure: true,
+            auto_exposure: AutoExposureSettings {
+                min_ev: INITIAL_AUTO_EXPOSURE_MIN_EV,
+                max_ev: INITIAL_AUTO_EXPOSURE_MAX_EV,
+                bright_adaptation_speed: INITIAL_AUTO_EXPOSURE_BRIGHT_ADAPTATION_SPEED,
+                dark_adaptation_speed: INITIAL_AUTO_EXPOSURE_DARK_ADAPTATION_SPEED,
+                exposure_compensation_ev: 0.0,
+            },
+            shadow_cascade_count: INITIAL_SHADOW_CASCADE_COUNT,
+            shadow_cascade_split_lambda: INITIAL_SHADOW_CASCADE_SPLIT_LAMBDA,
+            shadow_distance: INITIAL_SHADOW_DISTANCE,
+            shadow_cascade_blend_fraction: INITIAL_SHADOW_CASCADE_BLEND_FRACTION,
+        }
+    }
+}
+
+// which meshes render_pbr_meshes draws and which bind groups the pipelines expect
+#[derive(Debug, Copy, Clone)]
+enum PbrMeshPass {
+    // the opaque and alpha masked meshes, with the mesh pipeline layout
+    Shading,
+    // only the opaque meshes, with the shadow map pipeline layout
+    DepthPrepass,
+    // only the alpha masked meshes, with the shadow map pipeline layout. the textures bind group is
+    // used to discard the pixels under the alpha cutoff
+    MaskedDepthPrepass,
+    // the opaque and alpha masked meshes, with the shadow map pipeline layout. blended meshes
+    // don't cast shadows
+    Shadow,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct PbrInstanceCullingInfo {
+    // world space
+    bounds: BoundingSphere,
+    cast_shadows: bool,
+}
+
+// cache state of one layer of a shadow map texture, which is either a face of a point light's cubemap or a directional light cascade.
+// the layer is only re-rendered when its view changed or when a shadow caster inside of it changed
+#[derive(Debug, Copy, Clone, Default)]
+struct ShadowMapLayerState {
+    // the view that the layer was last rendered with, None if it was never rendered or was invalidated
+    rendered_view: Option<ShaderCameraView>,
+    // stays set until the layer is re-rendered so changes aren't missed while the updates are throttled
+    is_dirty: bool,
+    last_render_time: Option<Instant>,
+    // the view depth range that a directional light cascade covers when the layer was last rendered
+    rendered_split_range: Option<(f32, f32)>,
+}
+
+impl ShadowMapLayerState {
+    // returns true if the layer needs to be re-rendered with the given view this frame.
+    // split_range is the view depth range of a directional light cascade, the shader picks the cascade
+    // by the current splits so a cascade whose range changed is re-rendered right away, ignoring the throttling
+    fn schedule_update(
+        &mut self,
+        view: ShaderCameraView,
+        split_range: Option<(f32, f32)>,
+        changed_shadow_casters: &[BoundingSphere],
+        update_interval_seconds: f32,
+        now: Instant,
+    ) -> bool {
+        let view_changed = self
+            .rendered_view
+            .map(|rendered_view| rendered_view.proj * rendered_view.view != view.proj * view.view)
+            .unwrap_or(true);
+        if view_changed {
+            self.is_dirty = true;
+        } else if !self.is_dirty {
+            let frustum = Frustum::from(view);
+            self.is_dirty = changed_shadow_casters
+                .iter()
+                .any(|bounds| frustum.contains_sphere(bounds));
+        }
+        let can_update = self
+            .last_render_time
+            .map(|last_render_time| {
+                now.duration_since(last_render_time).as_secs_f32() >= update_interval_seconds
+            })
+            .unwrap_or(true);
+        let split_range_changed = split_range != self.rendered_split_range;
+        if !split_range_changed && (!self.is_dirty || !can_update) {
+            return false;
+        }
+        self.rendered_view = Some(view);
+        self.rendered_split_range = split_range;
+        self.is_dirty = false;
+        self.last_render_time = Some(now);
+        true
+    }
+}
+
 #[derive(Debug)]
 pub struct RenderBuffers {
     pub binded_pbr_meshes: Vec<BindedPbrMesh>,
@@ -176,14 +402,41 @@ pub struct RenderBuffers {
     pub textures: Vec<Texture>,
 }
 
+// the parts of the game state that the renderer reads, so that frames can also be rendered
+// without a running game, e.g. by the headless renderer in the tests
+#[derive(Copy, Clone)]
+pub struct RenderInputs<'a> {
+    pub scene: &'a Scene,
+    pub camera_node_id: GameNodeId,
+    pub point_lights: &'a [PointLightComponent],
+    pub directional_lights: &'a [DirectionalLightComponent],
+    pub ui: &'a UiDrawList,
+}
+
+impl<'a> From<&'a GameState> for RenderInputs<'a> {
+    fn from(game_state: &'a GameState) -> Self {
+        Self {
+            scene: &game_state.scene,
+            camera_node_id: game_state.player_node_id,
+            point_lights: &game_state.point_lights,
+            directional_lights: &game_state.directional_lights,
+            ui: &game_state.ui,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct BindedPbrMesh {
     pub geometry_buffers: GeometryBuffers,
-    pub textures_bind_group: wgpu::BindGroup,
+    // shared by the meshes with the same material, see GpuDrivenMeshes
+    pub textures_bind_group: Rc<wgpu::BindGroup>,
     pub dynamic_pbr_params: DynamicPbrParams,
 
     pub alpha_mode: AlphaMode,
+    // rendered without back-face culling, see double_sided_mesh_pipeline
+    pub double_sided: bool,
     pub primitive_mode: PrimitiveMode,
+    pub morph_target_count: usize,
 }
 
 #[derive(Debug)]
@@ -221,10 +474,11 @@ pub struct BindedWireframeMesh {
     pub instance_buffer: GpuBuffer,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum AlphaMode {
     Opaque,
     Mask,
+    Blend,
 }
 
 #[derive(Debug)]
@@ -236,7 +490,8 @@ pub struct BaseRendererState {
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub adapter: wgpu::Adapter,
-    pub surface: wgpu::Surface,
+    // None when running headless, see new_headless
+    pub surface: Option<wgpu::Surface>,
     pub surface_config: wgpu::SurfaceConfiguration,
     pub limits: wgpu::Limits,
     pub window_size: winit::dpi::PhysicalSize<u32>,
@@ -265,11 +520,45 @@ impl BaseRendererState {
             .await
             .expect("Failed to find an appropriate adapter");
 
+        Self::with_adapter(adapter, Some(surface), window_size).await
+    }
+
+    // creates a renderer that isn't attached to any window. frames are rendered into
+    // an offscreen texture and can be read back with RendererState::render_to_image.
+    // falls back to a software adapter if no hardware adapter is available, e.g. lavapipe on ci.
+    // the gl backend is left out since it can't run all of the shaders
+    #[cfg(test)]
+    pub async fn new_headless(size: winit::dpi::PhysicalSize<u32>) -> Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::Backends::PRIMARY);
+        let mut adapter = None;
+        for force_fallback_adapter in [false, true] {
+            adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::default(),
+                    compatible_surface: None,
+                    force_fallback_adapter,
+                })
+                .await;
+            if adapter.is_some() {
+                break;
+            }
+        }
+        let adapter =
+            adapter.ok_or_else(|| anyhow::anyhow!("Failed to find an appropriate adapter"))?;
+
+        Ok(Self::with_adapter(adapter, None, size).await)
+    }
+
+    pub async fn with_adapter(
+        adapter: wgpu::Adapter,
+        surface: Option<wgpu::Surface>,
+        window_size: winit::dpi::PhysicalSize<u32>,
+    ) -> Self {
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
-                    features: wgpu::Features::empty(),
+                    features: adapter.features() & GpuDrivenMeshes::OPTIONAL_FEATURES,
                     limits: wgpu::Limits::default(),
                 },
                 None,
@@ -277,10 +566,13 @@ impl BaseRendererState {
             .await
             .expect("Failed to create device");
 
-        let swapchain_format = *surface
-            .get_supported_formats(&adapter)
-            .get(0)
-            .expect("Window surface is incompatible with the graphics adapter");
+        let swapchain_format = match &surface {
+            Some(surface) => *surface
+                .get_supported_formats(&adapter)
+                .get(0)
+                .expect("Window surface is incompatible with the graphics adapter"),
+            None => wgpu::TextureFormat::Rgba8UnormSrgb,
+        };
 
         let surface_config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -291,7 +583,9 @@ impl BaseRendererState {
             // present_mode: wgpu::PresentMode::Immediate,
         };
 
-        surface.configure(&device, &surface_config);
+        if let Some(surface) = &surface {
+            surface.configure(&device, &surface_config);
+        }
 
         let single_texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -436,22 +730,68 @@ impl BaseRendererState {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    // morph target displacements, see make_morph_targets_buffer
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 10,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("four_texture_bind_group_layout"),
             });
 
         let bones_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: true,
-                        min_binding_size: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: true,
+                            min_binding_size: None,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    // morph target weights of every instance of the mesh
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: true,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // the previous frame's bone transforms, laid out like binding 0
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: true,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // the previous frame's model transform of every instance of the mesh
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: true,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
                 label: Some("bones_bind_group_layout"),
             });
 
@@ -477,14 +817,36 @@ pub struct RendererState {
     pub base: BaseRendererState,
 
     tone_mapping_exposure: f32,
-    bloom_threshold: f32,
-    bloom_ramp_size: f32,
+    tone_mapping_operator: ToneMappingOperator,
+    enable_color_grading: bool,
+    // None when the level doesn't have a color grading lut, then color_grading_lut_texture is a placeholder
+    color_grading_lut_domain: Option<([f32; 3], [f32; 3])>,
+    bloom_settings: BloomSettings,
+    ssao_settings: SsaoSettings,
     render_scale: f32,
+    anti_aliasing_mode: AntiAliasingMode,
+    // the camera of the last rendered frame, for the motion vectors
+    previous_camera_view: Option<ShaderCameraView>,
     enable_bloom: bool,
+    enable_ssao: bool,
     enable_shadows: bool,
     enable_wireframe_mode: bool,
+    enable_auto_exposure: bool,
+    auto_exposure_settings: AutoExposureSettings,
+    last_update_time: Option<Instant>,
+    shadow_cascade_count: u32,
+    shadow_cascade_split_lambda: f32,
+    shadow_distance: f32,
+    shadow_cascade_blend_fraction: f32,
 
     mesh_pipeline: wgpu::RenderPipeline,
+    double_sided_mesh_pipeline: wgpu::RenderPipeline,
+    depth_prepass_pipeline: wgpu::RenderPipeline,
+    double_sided_depth_prepass_pipeline: wgpu::RenderPipeline,
+    masked_depth_prepass_pipeline: wgpu::RenderPipeline,
+    double_sided_masked_depth_prepass_pipeline: wgpu::RenderPipeline,
+    transparent_mesh_pipeline: wgpu::RenderPipeline,
+    double_sided_transparent_mesh_pipeline: wgpu::RenderPipeline,
     unlit_mesh_pipeline: wgpu::RenderPipeline,
     wireframe_pipeline: wgpu::RenderPipeline,
     skybox_pipeline: wgpu::RenderPipeline,
@@ -492,25 +854,27 @@ pub struct RendererState {
     surface_blit_pipeline: wgpu::RenderPipeline,
     point_shadow_map_pipeline: wgpu::RenderPipeline,
     directional_shadow_map_pipeline: wgpu::RenderPipeline,
-    bloom_threshold_pipeline: wgpu::RenderPipeline,
-    bloom_blur_pipeline: wgpu::RenderPipeline,
 
+    // rebuilt when the point lights buffer grows
+    camera_and_lights_bind_group_layout: wgpu::BindGroupLayout,
     camera_and_lights_bind_group: wgpu::BindGroup,
     bones_bind_group: wgpu::BindGroup,
-    bloom_config_bind_group: wgpu::BindGroup,
     tone_mapping_config_bind_group: wgpu::BindGroup,
+    color_grading_lut_bind_group: wgpu::BindGroup,
 
+    environment_textures_bind_group_layout: wgpu::BindGroupLayout,
     environment_textures_bind_group: wgpu::BindGroup,
     shading_and_bloom_textures_bind_group: wgpu::BindGroup,
     tone_mapping_texture_bind_group: wgpu::BindGroup,
     shading_texture_bind_group: wgpu::BindGroup,
-    bloom_pingpong_texture_bind_groups: [wgpu::BindGroup; 2],
 
     camera_buffer: wgpu::Buffer,
-    point_lights_buffer: wgpu::Buffer,
+    point_lights_buffer: GpuBuffer,
     directional_lights_buffer: wgpu::Buffer,
     bones_buffer: GpuBuffer,
-    bloom_config_buffer: wgpu::Buffer,
+    morph_target_weights_buffer: GpuBuffer,
+    previous_bones_buffer: GpuBuffer,
+    previous_model_transforms_buffer: GpuBuffer,
     tone_mapping_config_buffer: wgpu::Buffer,
 
     point_shadow_map_textures: Texture,
@@ -518,12 +882,51 @@ pub struct RendererState {
     shading_texture: Texture,
     tone_mapping_texture: Texture,
     depth_texture: Texture,
-    bloom_pingpong_textures: [Texture; 2],
+    color_grading_lut_texture: Texture,
+    // kept to rebuild environment_textures_bind_group when the ssao output changes size
+    skybox_texture: Texture,
+    diffuse_env_map: Texture,
+    specular_env_map: Texture,
+    brdf_lut: Texture,
 
     all_bone_transforms: AllBoneTransforms,
+    // binded_pbr_mesh_index -> offset in bytes into morph_target_weights_buffer
+    morph_target_weight_offsets: Vec<u32>,
+    // binded_pbr_mesh_index -> offset in bytes into previous_model_transforms_buffer
+    previous_model_transform_offsets: Vec<u32>,
+    // the global transforms of the pbr mesh nodes from the last update, for the motion vectors
+    previous_node_transforms: HashMap<GameNodeId, Matrix4<f32>>,
+    // (binded_pbr_mesh_index, instance index), sorted back to front
+    transparent_draw_queue: Vec<(usize, u32)>,
+    // one list of cascades per shadow casting directional light, refitted to the camera every frame.
+    // these are the views that the shadow maps were last rendered with, which lag behind while the updates are throttled
+    directional_light_cascades: Vec<Vec<ShaderCameraView>>,
+    // one per layer of point_shadow_map_textures and directional_shadow_map_textures
+    point_shadow_map_layers: Vec<ShadowMapLayerState>,
+    directional_shadow_map_layers: Vec<ShadowMapLayerState>,
+    // (layer index, view) of the shadow map layers that get re-rendered this frame
+    point_shadow_map_layers_to_render: Vec<(usize, ShaderCameraView)>,
+    directional_shadow_map_layers_to_render: Vec<(usize, ShaderCameraView)>,
+    // one per instance in the instance buffers of binded_pbr_meshes, same order
+    pbr_instance_culling_infos: Vec<Vec<PbrInstanceCullingInfo>>,
+    // (binded_pbr_mesh_index, node id) -> (hash of everything that affects the instance's shadow, bounds),
+    // compared against the previous frame to find out which shadow map layers need to be re-rendered
+    shadow_caster_states: HashMap<(usize, GameNodeId), (u64, BoundingSphere)>,
 
     pub skybox_mesh_buffers: GeometryBuffers,
 
+    ui_renderer: UiRenderer,
+    auto_exposure: AutoExposure,
+    ssao: Ssao,
+    taa: Taa,
+    msaa: Msaa,
+    bloom: Bloom,
+    light_clusters: LightClusters,
+    gpu_driven_meshes: Option<GpuDrivenMeshes>,
+    // same as bones_bind_group but with the previous model transforms that the gpu culling compacts,
+    // along with the binding sizes it was made with
+    gpu_driven_bones_bind_group: Option<(wgpu::BindGroup, [usize; 4])>,
+
     pub buffers: RenderBuffers,
 }
 
@@ -531,6 +934,10 @@ impl RendererState {
     pub async fn new(
         buffers: RenderBuffers,
         base: BaseRendererState,
+        skybox_background: SkyboxBackground<'_>,
+        skybox_hdr_environment: Option<SkyboxHDREnvironment<'_>>,
+        color_grading_lut: Option<ColorGradingLut>,
+        settings: RendererSettings,
         logger: &mut Logger,
     ) -> Result<Self> {
         let adapter = &base.adapter;
@@ -548,16 +955,41 @@ impl RendererState {
         let adapter_backend = adapter_info.backend;
         logger.log(&format!("Using {adapter_name} ({adapter_backend:?})"));
         logger.log(&format!("Using {adapter_name} ({adapter_backend:?})"));
+        let msaa_sample_count = settings
+            .msaa_sample_count
+            .min(Msaa::max_supported_sample_count(adapter));
+        if msaa_sample_count != settings.msaa_sample_count {
+            logger.log(&format!(
+                "{}x MSAA is not supported by the adapter, using {}x",
+                settings.msaa_sample_count, msaa_sample_count
+            ));
+        }
+        let gpu_driven_rendering =
+            settings.gpu_driven_rendering && GpuDrivenMeshes::is_supported(adapter, device);
+        if settings.gpu_driven_rendering && !gpu_driven_rendering {
+            logger.log(
+                "GPU-driven rendering is not supported by the adapter, using the regular path",
+            );
+        }
+        if gpu_driven_rendering && !GpuDrivenMeshes::supports_multi_draw(device) {
+            logger.log("Multi draw indirect is not supported by the adapter, using one indirect draw per gpu-driven mesh");
+        }
         logger.log("Controls:");
         vec![
             "Move Around:             WASD, Space Bar, Ctrl",
             "Look Around:             Mouse",
             "Adjust Speed:            Scroll",
             "Adjust Render Scale:     Z / X",
+            "Cycle Anti-Aliasing:     N",
             "Adjust Exposure:         E / R",
-            "Adjust Bloom Threshold:  T / Y",
+            "Toggle Auto Exposure:    U",
+            "Cycle Tone Mapping:      O",
+            "Toggle Color Grading:    G",
+            "Adjust Bloom Intensity:  T / Y",
+            "Adjust Bloom Radius:     H / J",
             "Pause/Resume Animations: P",
             "Toggle Bloom Effect:     B",
+            "Toggle SSAO:             K",
             "Toggle Shadows:          M",
             "Toggle Wireframe:        F",
             "Toggle Collision Boxes:  C",
@@ -619,6 +1051,29 @@ impl RendererState {
                 label: Some("single_cube_texture_bind_group_layout"),
             });
 
+        let single_3d_texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D3,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+                label: Some("single_3d_texture_bind_group_layout"),
+            });
+
         let environment_textures_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[
@@ -718,6 +1173,16 @@ impl RendererState {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 12,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("environment_textures_bind_group_layout"),
             });
@@ -776,11 +1241,12 @@ impl RendererState {
                         },
                         count: None,
                     },
+                    // the point lights, as many as there are in the scene
                     wgpu::BindGroupLayoutEntry {
                         binding: 1,
                         visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                         ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
                             has_dynamic_offset: false,
                             min_binding_size: None,
                         },
@@ -796,6 +1262,27 @@ impl RendererState {
                         },
                         count: None,
                     },
+                    // the light cluster config and the point lights of each cluster, see LightClusters
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("camera_and_lights_uniform_bind_group_layout"),
             });
@@ -805,6 +1292,15 @@ impl RendererState {
             blend: Some(wgpu::BlendState::REPLACE),
             write_mask: wgpu::ColorWrites::ALL,
         })];
+        // the opaque meshes also write their motion vectors for taa
+        let mesh_fragment_shader_color_targets = &[
+            fragment_shader_color_targets[0].clone(),
+            Some(wgpu::ColorTargetState {
+                format: MOTION_VECTOR_TEXTURE_FORMAT,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            }),
+        ];
         let mesh_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Mesh Pipeline Layout"),
             bind_group_layouts: &[
@@ -826,7 +1322,7 @@ impl RendererState {
             fragment: Some(wgpu::FragmentState {
                 module: &textured_mesh_shader,
                 entry_point: "fs_main",
-                targets: fragment_shader_color_targets,
+                targets: mesh_fragment_shader_color_targets,
             }),
             primitive: wgpu::PrimitiveState {
                 topology: wgpu::PrimitiveTopology::TriangleList,
@@ -844,8 +1340,9 @@ impl RendererState {
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             }),
+            // also used by the unlit, wireframe and transparent pipelines, they draw into the same targets
             multisample: wgpu::MultisampleState {
-                count: 1,
+                count: msaa_sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
@@ -853,6 +1350,54 @@ impl RendererState {
         };
         let mesh_pipeline = device.create_render_pipeline(&mesh_pipeline_descriptor);
 
+        let mut double_sided_mesh_pipeline_descriptor = mesh_pipeline_descriptor.clone();
+        double_sided_mesh_pipeline_descriptor.label = Some("Double Sided Mesh Pipeline");
+        double_sided_mesh_pipeline_descriptor.primitive.cull_mode = None;
+        let double_sided_mesh_pipeline =
+            device.create_render_pipeline(&double_sided_mesh_pipeline_descriptor);
+
+        // blended on top of the opaque geometry, reads the depth buffer but doesn't write to it
+        let transparent_fragment_shader_color_targets = &[Some(wgpu::ColorTargetState {
+            format: wgpu::TextureFormat::Rgba16Float,
+            // the alpha channel is read back as coverage in blit.wgsl, so it's accumulated with the
+            // 'over' operator instead of being multiplied by itself like ALPHA_BLENDING would
+            blend: Some(wgpu::BlendState {
+                color: wgpu::BlendState::ALPHA_BLENDING.color,
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            }),
+            write_mask: wgpu::ColorWrites::ALL,
+        })];
+        let mut transparent_mesh_pipeline_descriptor = mesh_pipeline_descriptor.clone();
+        transparent_mesh_pipeline_descriptor.label = Some("Transparent Mesh Pipeline");
+        transparent_mesh_pipeline_descriptor.fragment = Some(wgpu::FragmentState {
+            module: &textured_mesh_shader,
+            entry_point: "transparent_fs_main",
+            targets: transparent_fragment_shader_color_targets,
+        });
+        transparent_mesh_pipeline_descriptor.depth_stencil = Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::GreaterEqual,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        });
+        let transparent_mesh_pipeline =
+            device.create_render_pipeline(&transparent_mesh_pipeline_descriptor);
+
+        let mut double_sided_transparent_mesh_pipeline_descriptor =
+            transparent_mesh_pipeline_descriptor.clone();
+        double_sided_transparent_mesh_pipeline_descriptor.label =
+            Some("Double Sided Transparent Mesh Pipeline");
+        double_sided_transparent_mesh_pipeline_descriptor
+            .primitive
+            .cull_mode = None;
+        let double_sided_transparent_mesh_pipeline =
+            device.create_render_pipeline(&double_sided_transparent_mesh_pipeline_descriptor);
+
         let unlit_mesh_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Unlit Mesh Pipeline Layout"),
@@ -888,71 +1433,6 @@ impl RendererState {
         };
         let wireframe_pipeline = device.create_render_pipeline(&wireframe_pipeline_descriptor);
 
-        let bloom_threshold_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: None,
-                bind_group_layouts: &[
-                    single_texture_bind_group_layout,
-                    &single_uniform_bind_group_layout,
-                ],
-                push_constant_ranges: &[],
-            });
-        let bloom_threshold_pipeline_descriptor = wgpu::RenderPipelineDescriptor {
-            label: Some("Bloom Threshold Pipeline"),
-            layout: Some(&bloom_threshold_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &blit_shader,
-                entry_point: "vs_main",
-                buffers: &[],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &blit_shader,
-                entry_point: "bloom_threshold_fs_main",
-                targets: fragment_shader_color_targets,
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                ..Default::default()
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
-        };
-        let bloom_threshold_pipeline =
-            device.create_render_pipeline(&bloom_threshold_pipeline_descriptor);
-
-        let bloom_blur_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: None,
-                bind_group_layouts: &[
-                    single_texture_bind_group_layout,
-                    &single_uniform_bind_group_layout,
-                ],
-                push_constant_ranges: &[],
-            });
-        let bloom_blur_pipeline_descriptor = wgpu::RenderPipelineDescriptor {
-            label: Some("Bloom Blur Pipeline"),
-            layout: Some(&bloom_blur_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &blit_shader,
-                entry_point: "vs_main",
-                buffers: &[],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &blit_shader,
-                entry_point: "bloom_blur_fs_main",
-                targets: fragment_shader_color_targets,
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                ..Default::default()
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
-        };
-        let bloom_blur_pipeline = device.create_render_pipeline(&bloom_blur_pipeline_descriptor);
-
         let surface_blit_color_targets = &[Some(wgpu::ColorTargetState {
             format: surface_config.format,
             blend: Some(wgpu::BlendState::REPLACE),
@@ -990,14 +1470,7 @@ impl RendererState {
 
         let tone_mapping_colors_targets = &[Some(wgpu::ColorTargetState {
             format: wgpu::TextureFormat::Rgba16Float,
-            blend: Some(wgpu::BlendState {
-                color: wgpu::BlendComponent {
-                    src_factor: wgpu::BlendFactor::One,
-                    dst_factor: wgpu::BlendFactor::One,
-                    operation: wgpu::BlendOperation::Add,
-                },
-                alpha: wgpu::BlendComponent::REPLACE,
-            }),
+            blend: Some(wgpu::BlendState::REPLACE),
             write_mask: wgpu::ColorWrites::ALL,
         })];
         let tone_mapping_pipeline_layout =
@@ -1006,6 +1479,7 @@ impl RendererState {
                 bind_group_layouts: &[
                     two_texture_bind_group_layout,
                     &single_uniform_bind_group_layout,
+                    &single_3d_texture_bind_group_layout,
                 ],
                 push_constant_ranges: &[],
             });
@@ -1054,8 +1528,26 @@ impl RendererState {
                 push_constant_ranges: &[],
             });
 
-        let skybox_pipeline_descriptor = wgpu::RenderPipelineDescriptor {
-            label: Some("Skybox Render Pipeline"),
+        // the skybox is drawn into the shading texture under the shaded geometry, so it only shows
+        // through where the geometry doesn't fully cover it. the alpha (coverage) is kept as is
+        let skybox_color_targets = &[Some(wgpu::ColorTargetState {
+            format: wgpu::TextureFormat::Rgba16Float,
+            blend: Some(wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::OneMinusDstAlpha,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            }),
+            write_mask: wgpu::ColorWrites::ALL,
+        })];
+        let skybox_pipeline_descriptor = wgpu::RenderPipelineDescriptor {
+            label: Some("Skybox Render Pipeline"),
             layout: Some(&skybox_render_pipeline_layout),
             vertex: wgpu::VertexState {
                 module: &skybox_shader,
@@ -1065,7 +1557,7 @@ impl RendererState {
             fragment: Some(wgpu::FragmentState {
                 module: &skybox_shader,
                 entry_point: "cubemap_fs_main",
-                targets: fragment_shader_color_targets,
+                targets: skybox_color_targets,
             }),
             primitive: skybox_pipeline_primitive_state,
             depth_stencil: skybox_depth_stencil_state,
@@ -1223,6 +1715,8 @@ impl RendererState {
                 bind_group_layouts: &[
                     &camera_and_lights_bind_group_layout,
                     bones_bind_group_layout,
+                    // only for the morph target displacements
+                    pbr_textures_bind_group_layout,
                 ],
                 push_constant_ranges: &[],
             });
@@ -1300,7 +1794,52 @@ impl RendererState {
         let directional_shadow_map_pipeline =
             device.create_render_pipeline(&directional_shadow_map_pipeline_descriptor);
 
-        let initial_render_scale = INITIAL_RENDER_SCALE;
+        // fills the depth buffer with the opaque and alpha masked meshes before they're shaded, for the ssao pass.
+        // uses the same vertex shader as the shadow maps since only the positions are needed
+        let mut depth_prepass_pipeline_descriptor =
+            directional_shadow_map_pipeline_descriptor.clone();
+        depth_prepass_pipeline_descriptor.label = Some("Depth Prepass Pipeline");
+        depth_prepass_pipeline_descriptor.primitive.cull_mode = Some(wgpu::Face::Back);
+        depth_prepass_pipeline_descriptor.depth_stencil =
+            mesh_pipeline_descriptor.depth_stencil.clone();
+        depth_prepass_pipeline_descriptor.multisample = mesh_pipeline_descriptor.multisample;
+        let depth_prepass_pipeline =
+            device.create_render_pipeline(&depth_prepass_pipeline_descriptor);
+
+        let mut double_sided_depth_prepass_pipeline_descriptor =
+            depth_prepass_pipeline_descriptor.clone();
+        double_sided_depth_prepass_pipeline_descriptor.label =
+            Some("Double Sided Depth Prepass Pipeline");
+        double_sided_depth_prepass_pipeline_descriptor
+            .primitive
+            .cull_mode = None;
+        let double_sided_depth_prepass_pipeline =
+            device.create_render_pipeline(&double_sided_depth_prepass_pipeline_descriptor);
+
+        let mut masked_depth_prepass_pipeline_descriptor =
+            depth_prepass_pipeline_descriptor.clone();
+        masked_depth_prepass_pipeline_descriptor.label = Some("Masked Depth Prepass Pipeline");
+        masked_depth_prepass_pipeline_descriptor.vertex.entry_point =
+            "masked_depth_prepass_vs_main";
+        masked_depth_prepass_pipeline_descriptor.fragment = Some(wgpu::FragmentState {
+            module: &textured_mesh_shader,
+            entry_point: "masked_depth_prepass_fs_main",
+            targets: &[],
+        });
+        let masked_depth_prepass_pipeline =
+            device.create_render_pipeline(&masked_depth_prepass_pipeline_descriptor);
+
+        let mut double_sided_masked_depth_prepass_pipeline_descriptor =
+            masked_depth_prepass_pipeline_descriptor.clone();
+        double_sided_masked_depth_prepass_pipeline_descriptor.label =
+            Some("Double Sided Masked Depth Prepass Pipeline");
+        double_sided_masked_depth_prepass_pipeline_descriptor
+            .primitive
+            .cull_mode = None;
+        let double_sided_masked_depth_prepass_pipeline =
+            device.create_render_pipeline(&double_sided_masked_depth_prepass_pipeline_descriptor);
+
+        let initial_render_scale = settings.render_scale;
 
         let cube_mesh = BasicMesh::new("./src/models/cube.obj")?;
 
@@ -1313,20 +1852,6 @@ impl RendererState {
             initial_render_scale,
             "shading_texture",
         );
-        let bloom_pingpong_textures = [
-            Texture::create_scaled_surface_texture(
-                device,
-                surface_config,
-                initial_render_scale,
-                "bloom_texture_1",
-            ),
-            Texture::create_scaled_surface_texture(
-                device,
-                surface_config,
-                initial_render_scale,
-                "bloom_texture_2",
-            ),
-        ];
         let tone_mapping_texture = Texture::create_scaled_surface_texture(
             device,
             surface_config,
@@ -1362,6 +1887,11 @@ impl RendererState {
                 ],
                 label: Some("tone_mapping_texture_bind_group"),
             });
+        let bloom = Bloom::new(
+            device,
+            single_texture_bind_group_layout,
+            (shading_texture.size.width, shading_texture.size.height),
+        )?;
         let shading_and_bloom_textures_bind_group =
             device.create_bind_group(&wgpu::BindGroupDescriptor {
                 layout: two_texture_bind_group_layout,
@@ -1376,78 +1906,23 @@ impl RendererState {
                     },
                     wgpu::BindGroupEntry {
                         binding: 2,
-                        resource: wgpu::BindingResource::TextureView(
-                            &bloom_pingpong_textures[0].view,
-                        ),
+                        resource: wgpu::BindingResource::TextureView(bloom.output_view()),
                     },
                     wgpu::BindGroupEntry {
                         binding: 3,
-                        resource: wgpu::BindingResource::Sampler(
-                            &bloom_pingpong_textures[0].sampler,
-                        ),
+                        resource: wgpu::BindingResource::Sampler(bloom.output_sampler()),
                     },
                 ],
                 label: Some("surface_blit_textures_bind_group"),
             });
-        let bloom_pingpong_texture_bind_groups = [
-            device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: single_texture_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(
-                            &bloom_pingpong_textures[0].view,
-                        ),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(
-                            &bloom_pingpong_textures[0].sampler,
-                        ),
-                    },
-                ],
-                label: Some("bloom_texture_bind_group_1"),
-            }),
-            device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: single_texture_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(
-                            &bloom_pingpong_textures[1].view,
-                        ),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(
-                            &bloom_pingpong_textures[1].sampler,
-                        ),
-                    },
-                ],
-                label: Some("bloom_texture_bind_group_2"),
-            }),
-        ];
-
-        let bloom_config_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Bloom Config Buffer"),
-            contents: bytemuck::cast_slice(&[0f32, 0f32, 0f32]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
-        let bloom_config_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &single_uniform_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: bloom_config_buffer.as_entire_binding(),
-            }],
-            label: Some("bloom_config_bind_group"),
-        });
-
         let tone_mapping_config_buffer =
             device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("Tone Mapping Config Buffer"),
-                contents: bytemuck::cast_slice(&[0f32]),
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                contents: &[0u8; std::mem::size_of::<ToneMappingConfigUniform>()],
+                // also written by the auto exposure compute pass
+                usage: wgpu::BufferUsages::UNIFORM
+                    | wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_DST,
             });
 
         let tone_mapping_config_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -1459,6 +1934,30 @@ impl RendererState {
             label: Some("tone_mapping_config_bind_group"),
         });
 
+        let color_grading_lut_domain = color_grading_lut
+            .as_ref()
+            .map(|lut| (lut.domain_min, lut.domain_max));
+        // the tone mapping pipeline always needs a lut bound, even if color grading is off
+        let color_grading_lut_texture = Texture::from_color_grading_lut(
+            device,
+            queue,
+            &color_grading_lut.unwrap_or_else(|| ColorGradingLut::identity(2)),
+        );
+        let color_grading_lut_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &single_3d_texture_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&color_grading_lut_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&color_grading_lut_texture.sampler),
+                },
+            ],
+            label: Some("color_grading_lut_bind_group"),
+        });
+
         let depth_texture = Texture::create_depth_texture(
             device,
             surface_config,
@@ -1466,20 +1965,29 @@ impl RendererState {
             "depth_texture",
         );
 
-        let (skybox_background, skybox_hdr_environment) = get_skybox_path();
-
         let skybox_texture = match skybox_background {
             SkyboxBackground::Equirectangular { image_path } => {
                 let er_skybox_texture_bytes = std::fs::read(image_path)?;
-                let er_skybox_texture = Texture::from_encoded_image(
-                    device,
-                    queue,
-                    &er_skybox_texture_bytes,
-                    image_path,
-                    None,
-                    false,
-                    &Default::default(),
-                )?;
+                let er_skybox_texture = if image_path.to_lowercase().ends_with(".hdr") {
+                    Texture::from_encoded_hdr_image(
+                        device,
+                        queue,
+                        &er_skybox_texture_bytes,
+                        image_path,
+                        false,
+                        &Default::default(),
+                    )?
+                } else {
+                    Texture::from_encoded_image(
+                        device,
+                        queue,
+                        &er_skybox_texture_bytes,
+                        image_path,
+                        None,
+                        false,
+                        &Default::default(),
+                    )?
+                };
 
                 Texture::create_cubemap_from_equirectangular(
                     device,
@@ -1518,28 +2026,11 @@ impl RendererState {
         let skybox_rad_texture = match skybox_hdr_environment {
             Some(SkyboxHDREnvironment::Equirectangular { image_path }) => {
                 let skybox_rad_texture_bytes = std::fs::read(image_path)?;
-                let skybox_rad_texture_decoded = stb::image::stbi_loadf_from_memory(
-                    &skybox_rad_texture_bytes,
-                    stb::image::Channels::RgbAlpha,
-                )
-                .ok_or_else(|| anyhow::anyhow!("Failed to decode image: {}", image_path))?;
-                let skybox_rad_texture_decoded_vec = skybox_rad_texture_decoded.1.into_vec();
-                let skybox_rad_texture_decoded_vec: Vec<_> = skybox_rad_texture_decoded_vec
-                    .iter()
-                    .copied()
-                    .map(|v| Float16(half::f16::from_f32(v)))
-                    .collect();
-
-                let skybox_rad_texture_er = Texture::from_decoded_image(
+                let skybox_rad_texture_er = Texture::from_encoded_hdr_image(
                     device,
                     queue,
-                    bytemuck::cast_slice(&skybox_rad_texture_decoded_vec),
-                    (
-                        skybox_rad_texture_decoded.0.width as u32,
-                        skybox_rad_texture_decoded.0.height as u32,
-                    ),
-                    image_path.into(),
-                    wgpu::TextureFormat::Rgba16Float.into(),
+                    &skybox_rad_texture_bytes,
+                    image_path,
                     false,
                     &Default::default(),
                 )?;
@@ -1586,17 +2077,15 @@ impl RendererState {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        let initial_point_lights_buffer: Vec<u8> = (0..(MAX_LIGHT_COUNT
-            * std::mem::size_of::<PointLightUniform>()))
-            .map(|_| 0u8)
-            .collect();
-        let point_lights_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Point Lights Buffer"),
-            contents: &initial_point_lights_buffer,
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+        // grows with the number of point lights
+        let point_lights_buffer = GpuBuffer::empty(
+            device,
+            1,
+            std::mem::size_of::<PointLightUniform>(),
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        );
 
-        let initial_directional_lights_buffer: Vec<u8> = (0..(MAX_LIGHT_COUNT
+        let initial_directional_lights_buffer: Vec<u8> = (0..(MAX_DIRECTIONAL_LIGHT_COUNT
             * std::mem::size_of::<DirectionalLightUniform>()))
             .map(|_| 0u8)
             .collect();
@@ -1607,24 +2096,16 @@ impl RendererState {
                 usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             });
 
-        let camera_and_lights_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_and_lights_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: camera_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: point_lights_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: directional_lights_buffer.as_entire_binding(),
-                },
-            ],
-            label: Some("camera_and_lights_bind_group"),
-        });
+        let light_clusters = LightClusters::new(device, &camera_buffer, &point_lights_buffer)?;
+
+        let camera_and_lights_bind_group = Self::make_camera_and_lights_bind_group(
+            device,
+            &camera_and_lights_bind_group_layout,
+            &camera_buffer,
+            &point_lights_buffer,
+            &directional_lights_buffer,
+            &light_clusters,
+        );
 
         let bones_buffer = GpuBuffer::empty(
             device,
@@ -1633,109 +2114,116 @@ impl RendererState {
             wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
         );
 
-        let bones_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: bones_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                    buffer: bones_buffer.src(),
-                    offset: 0,
-                    size: NonZeroU64::new(bones_buffer.length_bytes().try_into().unwrap()),
-                }),
-            }],
-            label: Some("bones_bind_group"),
-        });
+        let morph_target_weights_buffer = GpuBuffer::empty(
+            device,
+            1,
+            std::mem::size_of::<f32>(),
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        );
+
+        let previous_bones_buffer = GpuBuffer::empty(
+            device,
+            1,
+            std::mem::size_of::<GpuMatrix4>(),
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        );
+
+        let previous_model_transforms_buffer = GpuBuffer::empty(
+            device,
+            1,
+            std::mem::size_of::<GpuMatrix4>(),
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        );
+
+        let bones_bind_group = Self::make_bones_bind_group(
+            device,
+            bones_bind_group_layout,
+            &bones_buffer,
+            &morph_target_weights_buffer,
+            morph_target_weights_buffer.length_bytes(),
+            &previous_bones_buffer,
+            &previous_model_transforms_buffer,
+            previous_model_transforms_buffer.length_bytes(),
+        );
 
         let point_shadow_map_textures = Texture::create_cube_depth_texture_array(
             device,
-            1024,
+            POINT_SHADOW_MAP_RESOLUTION,
             Some("point_shadow_map_texture"),
-            2, // TODO: this currently puts on hard limit on number of point lights at a time
+            MAX_SHADOW_CASTING_POINT_LIGHTS as u32,
         );
 
+        // the cascades of each light are next to each other in the array
         let directional_shadow_map_textures = Texture::create_depth_texture_array(
             device,
-            2048,
+            DIRECTIONAL_SHADOW_MAP_RESOLUTION,
             Some("directional_shadow_map_texture"),
-            2, // TODO: this currently puts on hard limit on number of directional lights at a time
+            (MAX_SHADOW_CASTING_DIRECTIONAL_LIGHTS as u32) * settings.shadow_cascade_count,
         );
 
-        let environment_textures_bind_group =
-            device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &environment_textures_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&skybox_texture.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&skybox_texture.sampler),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: wgpu::BindingResource::TextureView(&diffuse_env_map.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 3,
-                        resource: wgpu::BindingResource::Sampler(&diffuse_env_map.sampler),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 4,
-                        resource: wgpu::BindingResource::TextureView(&specular_env_map.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 5,
-                        resource: wgpu::BindingResource::Sampler(&specular_env_map.sampler),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 6,
-                        resource: wgpu::BindingResource::TextureView(&brdf_lut.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 7,
-                        resource: wgpu::BindingResource::Sampler(&brdf_lut.sampler),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 8,
-                        resource: wgpu::BindingResource::TextureView(
-                            &point_shadow_map_textures.view,
-                        ),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 9,
-                        resource: wgpu::BindingResource::Sampler(
-                            &point_shadow_map_textures.sampler,
-                        ),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 10,
-                        resource: wgpu::BindingResource::TextureView(
-                            &directional_shadow_map_textures.view,
-                        ),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 11,
-                        resource: wgpu::BindingResource::Sampler(
-                            &directional_shadow_map_textures.sampler,
-                        ),
-                    },
-                ],
-                label: Some("skybox_texture_bind_group"),
-            });
+        let ssao = Ssao::new(device, &depth_texture)?;
+        let taa = Taa::new(device, &shading_texture, &depth_texture)?;
+        let msaa = Msaa::new(device, msaa_sample_count, &shading_texture)?;
+        let gpu_driven_meshes = gpu_driven_rendering
+            .then(|| GpuDrivenMeshes::new(device))
+            .transpose()?;
+
+        let environment_textures_bind_group = Self::make_environment_textures_bind_group(
+            device,
+            &environment_textures_bind_group_layout,
+            [
+                &skybox_texture,
+                &diffuse_env_map,
+                &specular_env_map,
+                &brdf_lut,
+                &point_shadow_map_textures,
+                &directional_shadow_map_textures,
+            ],
+            ssao.output_view(),
+        );
+
+        let ui_renderer = UiRenderer::new(
+            device,
+            queue,
+            surface_config.format,
+            single_texture_bind_group_layout,
+        )?;
+
+        let auto_exposure =
+            AutoExposure::new(device, &shading_texture.view, &tone_mapping_config_buffer)?;
 
         Ok(Self {
             base,
 
-            tone_mapping_exposure: INITIAL_TONE_MAPPING_EXPOSURE,
-            bloom_threshold: INITIAL_BLOOM_THRESHOLD,
-            bloom_ramp_size: INITIAL_BLOOM_RAMP_SIZE,
+            tone_mapping_exposure: settings.tone_mapping_exposure,
+            tone_mapping_operator: settings.tone_mapping_operator,
+            enable_color_grading: color_grading_lut_domain.is_some(),
+            color_grading_lut_domain,
+            bloom_settings: settings.bloom,
+            ssao_settings: settings.ssao,
             render_scale: initial_render_scale,
-            enable_bloom: true,
-            enable_shadows: true,
+            anti_aliasing_mode: settings.anti_aliasing_mode,
+            previous_camera_view: None,
+            enable_bloom: settings.enable_bloom,
+            enable_ssao: settings.enable_ssao,
+            enable_shadows: settings.enable_shadows,
             enable_wireframe_mode: false,
+            enable_auto_exposure: settings.enable_auto_exposure,
+            auto_exposure_settings: settings.auto_exposure,
+            last_update_time: None,
+            shadow_cascade_count: settings.shadow_cascade_count,
+            shadow_cascade_split_lambda: settings.shadow_cascade_split_lambda,
+            shadow_distance: settings.shadow_distance,
+            shadow_cascade_blend_fraction: settings.shadow_cascade_blend_fraction,
 
             mesh_pipeline,
+            double_sided_mesh_pipeline,
+            depth_prepass_pipeline,
+            double_sided_depth_prepass_pipeline,
+            masked_depth_prepass_pipeline,
+            double_sided_masked_depth_prepass_pipeline,
+            transparent_mesh_pipeline,
+            double_sided_transparent_mesh_pipeline,
             unlit_mesh_pipeline,
             wireframe_pipeline,
             skybox_pipeline,
@@ -1743,25 +2231,26 @@ impl RendererState {
             surface_blit_pipeline,
             point_shadow_map_pipeline,
             directional_shadow_map_pipeline,
-            bloom_threshold_pipeline,
-            bloom_blur_pipeline,
 
+            camera_and_lights_bind_group_layout,
             camera_and_lights_bind_group,
             bones_bind_group,
-            bloom_config_bind_group,
             tone_mapping_config_bind_group,
+            color_grading_lut_bind_group,
 
+            environment_textures_bind_group_layout,
             environment_textures_bind_group,
             shading_and_bloom_textures_bind_group,
             tone_mapping_texture_bind_group,
             shading_texture_bind_group,
-            bloom_pingpong_texture_bind_groups,
 
             camera_buffer,
             point_lights_buffer,
             directional_lights_buffer,
             bones_buffer,
-            bloom_config_buffer,
+            morph_target_weights_buffer,
+            previous_bones_buffer,
+            previous_model_transforms_buffer,
             tone_mapping_config_buffer,
 
             point_shadow_map_textures,
@@ -1769,10 +2258,24 @@ impl RendererState {
             shading_texture,
             tone_mapping_texture,
             depth_texture,
-            bloom_pingpong_textures,
+            color_grading_lut_texture,
+            skybox_texture,
+            diffuse_env_map,
+            specular_env_map,
+            brdf_lut,
 
             skybox_mesh_buffers,
 
+            ui_renderer,
+            auto_exposure,
+            ssao,
+            taa,
+            msaa,
+            bloom,
+            light_clusters,
+            gpu_driven_meshes,
+            gpu_driven_bones_bind_group: None,
+
             buffers,
 
             all_bone_transforms: AllBoneTransforms {
@@ -1780,21 +2283,118 @@ impl RendererState {
                 animated_bone_transforms: vec![],
                 identity_slice: (0, 0),
             },
+            morph_target_weight_offsets: vec![],
+            previous_model_transform_offsets: vec![],
+            previous_node_transforms: HashMap::new(),
+            transparent_draw_queue: vec![],
+            directional_light_cascades: vec![],
+            point_shadow_map_layers: vec![
+                ShadowMapLayerState::default();
+                6 * MAX_SHADOW_CASTING_POINT_LIGHTS
+            ],
+            directional_shadow_map_layers: vec![
+                ShadowMapLayerState::default();
+                MAX_SHADOW_CASTING_DIRECTIONAL_LIGHTS
+                    * settings.shadow_cascade_count as usize
+            ],
+            point_shadow_map_layers_to_render: vec![],
+            directional_shadow_map_layers_to_render: vec![],
+            pbr_instance_culling_infos: vec![],
+            shadow_caster_states: HashMap::new(),
         })
     }
 
-    pub fn bind_basic_unlit_mesh(&mut self, mesh: &BasicMesh) -> usize {
-        let geometry_buffers = self.bind_geometry_buffers_for_basic_mesh(mesh);
-
-        self.buffers.binded_unlit_meshes.push(geometry_buffers);
-        let unlit_mesh_index = self.buffers.binded_unlit_meshes.len() - 1;
+    fn make_camera_and_lights_bind_group(
+        device: &wgpu::Device,
+        camera_and_lights_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_buffer: &wgpu::Buffer,
+        point_lights_buffer: &GpuBuffer,
+        directional_lights_buffer: &wgpu::Buffer,
+        light_clusters: &LightClusters,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: camera_and_lights_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: point_lights_buffer.src().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: directional_lights_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: light_clusters.config_buffer().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: light_clusters.clusters_buffer().as_entire_binding(),
+                },
+            ],
+            label: Some("camera_and_lights_bind_group"),
+        })
+    }
 
-        let (wireframe_index_buffer, wireframe_instance_buffer) =
-            self.bind_wireframe_buffers_for_basic_mesh(mesh);
-        self.buffers
-            .binded_wireframe_meshes
-            .push(BindedWireframeMesh {
-                source_mesh_type: MeshType::Unlit,
+    // the textures are bound in pairs with their samplers, in this order:
+    // skybox, diffuse env map, specular env map, brdf lut, point shadow maps, directional shadow maps
+    fn make_environment_textures_bind_group(
+        device: &wgpu::Device,
+        environment_textures_bind_group_layout: &wgpu::BindGroupLayout,
+        textures: [&Texture; 6],
+        ssao_view: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        let mut entries: Vec<_> = textures
+            .iter()
+            .enumerate()
+            .flat_map(|(index, texture)| {
+                [
+                    wgpu::BindGroupEntry {
+                        binding: 2 * index as u32,
+                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2 * index as u32 + 1,
+                        resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                    },
+                ]
+            })
+            .collect();
+        entries.push(wgpu::BindGroupEntry {
+            binding: 12,
+            resource: wgpu::BindingResource::TextureView(ssao_view),
+        });
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: environment_textures_bind_group_layout,
+            entries: &entries,
+            label: Some("skybox_texture_bind_group"),
+        })
+    }
+
+    // returns the previous buffers, e.g. to put them back if the new scene fails to load
+    pub fn replace_buffers(&mut self, buffers: RenderBuffers) -> RenderBuffers {
+        if let Some(gpu_driven_meshes) = &mut self.gpu_driven_meshes {
+            gpu_driven_meshes.invalidate_arenas();
+        }
+        std::mem::replace(&mut self.buffers, buffers)
+    }
+
+    pub fn bind_basic_unlit_mesh(&mut self, mesh: &BasicMesh) -> usize {
+        let geometry_buffers = self.bind_geometry_buffers_for_basic_mesh(mesh);
+
+        self.buffers.binded_unlit_meshes.push(geometry_buffers);
+        let unlit_mesh_index = self.buffers.binded_unlit_meshes.len() - 1;
+
+        let (wireframe_index_buffer, wireframe_instance_buffer) =
+            self.bind_wireframe_buffers_for_basic_mesh(mesh);
+        self.buffers
+            .binded_wireframe_meshes
+            .push(BindedWireframeMesh {
+                source_mesh_type: MeshType::Unlit,
                 source_mesh_index: unlit_mesh_index,
                 index_buffer: wireframe_index_buffer,
                 index_buffer_format: wgpu::IndexFormat::Uint16,
@@ -1813,14 +2413,16 @@ impl RendererState {
     ) -> Result<usize> {
         let geometry_buffers = self.bind_geometry_buffers_for_basic_mesh(mesh);
 
-        let textures_bind_group = self.make_pbr_textures_bind_group(material)?;
+        let textures_bind_group = Rc::new(self.make_pbr_textures_bind_group(material)?);
 
         self.buffers.binded_pbr_meshes.push(BindedPbrMesh {
             geometry_buffers,
             dynamic_pbr_params,
             textures_bind_group,
             alpha_mode: AlphaMode::Opaque,
+            double_sided: false,
             primitive_mode: PrimitiveMode::Triangles,
+            morph_target_count: 0,
         });
         let pbr_mesh_index = self.buffers.binded_pbr_meshes.len() - 1;
 
@@ -1847,18 +2449,19 @@ impl RendererState {
         device: &wgpu::Device,
         mesh: &BasicMesh,
     ) -> GeometryBuffers {
+        // copied into the arenas of GpuDrivenMeshes
         let vertex_buffer = GpuBuffer::from_bytes(
             device,
             bytemuck::cast_slice(&mesh.vertices),
             std::mem::size_of::<Vertex>(),
-            wgpu::BufferUsages::VERTEX,
+            wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
         );
 
         let index_buffer = GpuBuffer::from_bytes(
             device,
             bytemuck::cast_slice(&mesh.indices),
             std::mem::size_of::<u16>(),
-            wgpu::BufferUsages::INDEX,
+            wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_SRC,
         );
 
         let instance_buffer = GpuBuffer::empty(
@@ -1988,6 +2591,7 @@ impl RendererState {
                 &auto_generated_ambient_occlusion_map
             }
         };
+        let morph_targets_buffer = make_morph_targets_buffer(device, 0, &[]);
         let textures_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &self.base.pbr_textures_bind_group_layout,
             entries: &[
@@ -2031,6 +2635,10 @@ impl RendererState {
                     binding: 9,
                     resource: wgpu::BindingResource::Sampler(&ambient_occlusion_map.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: morph_targets_buffer.src().as_entire_binding(),
+                },
             ],
             label: Some("InstancedMeshComponent textures_bind_group"),
         });
@@ -2051,31 +2659,121 @@ impl RendererState {
     }
 
     pub fn increment_exposure(&mut self, increase: bool, logger: &mut Logger) {
+        if self.enable_auto_exposure {
+            let delta = 0.25;
+            let change = if increase { delta } else { -delta };
+            let compensation = &mut self.auto_exposure_settings.exposure_compensation_ev;
+            *compensation = (*compensation + change).max(-10.0).min(10.0);
+            logger.log(&format!("Exposure compensation: {:?} EV", compensation));
+            return;
+        }
         let delta = 0.05;
         let change = if increase { delta } else { -delta };
         self.tone_mapping_exposure = (self.tone_mapping_exposure + change).max(0.0).min(20.0);
         logger.log(&format!("Exposure: {:?}", self.tone_mapping_exposure));
     }
 
-    pub fn increment_bloom_threshold(&mut self, increase: bool, logger: &mut Logger) {
+    pub fn increment_bloom_intensity(&mut self, increase: bool, logger: &mut Logger) {
+        let delta = 0.01;
+        let change = if increase { delta } else { -delta };
+        let intensity = &mut self.bloom_settings.intensity;
+        *intensity = (*intensity + change).max(0.0).min(1.0);
+        logger.log(&format!("Bloom Intensity: {:?}", intensity));
+    }
+
+    pub fn increment_bloom_radius(&mut self, increase: bool, logger: &mut Logger) {
         let delta = 0.05;
         let change = if increase { delta } else { -delta };
-        self.bloom_threshold = (self.bloom_threshold + change).max(0.0).min(20.0);
-        logger.log(&format!("Bloom Threshold: {:?}", self.bloom_threshold));
+        let radius = &mut self.bloom_settings.radius;
+        *radius = (*radius + change).max(0.0).min(1.0);
+        logger.log(&format!("Bloom Radius: {:?}", radius));
     }
 
     pub fn toggle_bloom(&mut self) {
         self.enable_bloom = !self.enable_bloom;
     }
 
+    pub fn toggle_ssao(&mut self) {
+        self.enable_ssao = !self.enable_ssao;
+    }
+
+    pub fn cycle_anti_aliasing_mode(&mut self, logger: &mut Logger) {
+        self.anti_aliasing_mode = self.anti_aliasing_mode.next();
+        // the history wasn't kept up to date while taa was off
+        self.taa.reset_history();
+        logger.log(&format!("Anti-aliasing: {:?}", self.anti_aliasing_mode));
+    }
+
     pub fn toggle_shadows(&mut self) {
         self.enable_shadows = !self.enable_shadows;
+        // the shadow maps weren't kept up to date while the shadows were off
+        self.invalidate_shadow_maps();
+    }
+
+    pub fn invalidate_shadow_maps(&mut self) {
+        self.point_shadow_map_layers
+            .iter_mut()
+            .chain(self.directional_shadow_map_layers.iter_mut())
+            .for_each(|layer| *layer = ShadowMapLayerState::default());
+    }
+
+    // forget everything carried over from the previous frames, for when the next frame shows an
+    // unrelated scene like after a restart. node ids are reused by the new scene so their previous
+    // transforms would produce bogus motion vectors
+    pub fn reset_frame_history(&mut self) {
+        self.taa.reset_history();
+        self.auto_exposure.reset(&self.base.queue);
+        self.previous_camera_view = None;
+        self.previous_node_transforms.clear();
+        self.invalidate_shadow_maps();
+        if let Some(gpu_driven_meshes) = &mut self.gpu_driven_meshes {
+            gpu_driven_meshes.invalidate_arenas();
+        }
+    }
+
+    pub fn toggle_auto_exposure(&mut self) {
+        self.enable_auto_exposure = !self.enable_auto_exposure;
+        if self.enable_auto_exposure {
+            self.auto_exposure.reset(&self.base.queue);
+        }
+    }
+
+    pub fn cycle_tone_mapping_operator(&mut self, logger: &mut Logger) {
+        self.tone_mapping_operator = self.tone_mapping_operator.next();
+        logger.log(&format!("Tone mapping: {:?}", self.tone_mapping_operator));
+    }
+
+    pub fn toggle_color_grading(&mut self, logger: &mut Logger) {
+        if self.color_grading_lut_domain.is_none() {
+            logger.log("Color grading: no lut loaded");
+            return;
+        }
+        self.enable_color_grading = !self.enable_color_grading;
     }
 
     pub fn toggle_wireframe_mode(&mut self) {
         self.enable_wireframe_mode = !self.enable_wireframe_mode;
     }
 
+    // the returned id can be drawn with UiDrawList::image
+    pub fn add_ui_image(&mut self, image: &image::RgbaImage, label: &str) -> Result<UiImageId> {
+        let texture = Texture::from_decoded_image(
+            &self.base.device,
+            &self.base.queue,
+            image,
+            image.dimensions(),
+            Some(label),
+            None,
+            false,
+            &Default::default(),
+        )?;
+        Ok(self.ui_renderer.add_image(
+            &self.base.device,
+            &texture,
+            &self.base.single_texture_bind_group_layout,
+        ))
+    }
+
     pub fn resize(&mut self, new_window_size: winit::dpi::PhysicalSize<u32>) {
         let surface = &mut self.base.surface;
         let surface_config = &mut self.base.surface_config;
@@ -2085,27 +2783,15 @@ impl RendererState {
         self.base.window_size = new_window_size;
         surface_config.width = new_window_size.width;
         surface_config.height = new_window_size.height;
-        surface.configure(device, surface_config);
+        if let Some(surface) = surface {
+            surface.configure(device, surface_config);
+        }
         self.shading_texture = Texture::create_scaled_surface_texture(
             device,
             surface_config,
             self.render_scale,
             "shading_texture",
         );
-        self.bloom_pingpong_textures = [
-            Texture::create_scaled_surface_texture(
-                device,
-                surface_config,
-                self.render_scale,
-                "bloom_texture_1",
-            ),
-            Texture::create_scaled_surface_texture(
-                device,
-                surface_config,
-                self.render_scale,
-                "bloom_texture_2",
-            ),
-        ];
         self.tone_mapping_texture = Texture::create_scaled_surface_texture(
             device,
             surface_config,
@@ -2118,6 +2804,36 @@ impl RendererState {
             self.render_scale,
             "depth_texture",
         );
+        self.auto_exposure.resize(
+            device,
+            &self.shading_texture.view,
+            &self.tone_mapping_config_buffer,
+        );
+        self.ssao.resize(device, &self.depth_texture);
+        self.taa
+            .resize(device, &self.shading_texture, &self.depth_texture);
+        self.msaa.resize(device, &self.shading_texture);
+        self.environment_textures_bind_group = Self::make_environment_textures_bind_group(
+            device,
+            &self.environment_textures_bind_group_layout,
+            [
+                &self.skybox_texture,
+                &self.diffuse_env_map,
+                &self.specular_env_map,
+                &self.brdf_lut,
+                &self.point_shadow_map_textures,
+                &self.directional_shadow_map_textures,
+            ],
+            self.ssao.output_view(),
+        );
+        self.bloom.resize(
+            device,
+            single_texture_bind_group_layout,
+            (
+                self.shading_texture.size.width,
+                self.shading_texture.size.height,
+            ),
+        );
         self.shading_texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: single_texture_bind_group_layout,
             entries: &[
@@ -2165,87 +2881,91 @@ impl RendererState {
                     },
                     wgpu::BindGroupEntry {
                         binding: 2,
-                        resource: wgpu::BindingResource::TextureView(
-                            &self.bloom_pingpong_textures[0].view,
-                        ),
+                        resource: wgpu::BindingResource::TextureView(self.bloom.output_view()),
                     },
                     wgpu::BindGroupEntry {
                         binding: 3,
-                        resource: wgpu::BindingResource::Sampler(
-                            &self.bloom_pingpong_textures[0].sampler,
-                        ),
+                        resource: wgpu::BindingResource::Sampler(self.bloom.output_sampler()),
                     },
                 ],
                 label: Some("surface_blit_textures_bind_group"),
             });
-        self.bloom_pingpong_texture_bind_groups = [
-            device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: single_texture_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(
-                            &self.bloom_pingpong_textures[0].view,
-                        ),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(
-                            &self.bloom_pingpong_textures[0].sampler,
-                        ),
-                    },
-                ],
-                label: Some("bloom_texture_bind_group_1"),
-            }),
-            device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: single_texture_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(
-                            &self.bloom_pingpong_textures[1].view,
-                        ),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(
-                            &self.bloom_pingpong_textures[1].sampler,
-                        ),
-                    },
-                ],
-                label: Some("bloom_texture_bind_group_2"),
-            }),
-        ];
     }
 
-    pub fn update(&mut self, game_state: &mut GameState, logger: &mut Logger) {
+    pub fn update(&mut self, inputs: RenderInputs, logger: &mut Logger) {
         // send data to gpu
-        let scene = &mut game_state.scene;
+        let scene = inputs.scene;
+        let camera_transform = scene.get_global_transform_for_node(inputs.camera_node_id);
+        let camera_position = camera_transform.position();
+        let mut transparent_instances: Vec<(usize, u32, f32)> = Vec::new();
         let limits = &mut self.base.limits;
-        let queue = &mut self.base.queue;
+        let queue = &self.base.queue;
         let device = &self.base.device;
         let bones_bind_group_layout = &self.base.bones_bind_group_layout;
-        self.all_bone_transforms =
-            get_all_bone_data(scene, limits.min_storage_buffer_offset_alignment);
-        self.bones_buffer
-            .write(device, queue, &self.all_bone_transforms.buffer);
+        let previous_all_bone_transforms = std::mem::replace(
+            &mut self.all_bone_transforms,
+            get_all_bone_data(scene, limits.min_storage_buffer_offset_alignment),
+        );
+        // the gpu-driven bones bind group is only recreated when one of its buffers is reallocated
+        let mut bones_buffers_resized =
+            self.bones_buffer
+                .write(device, queue, &self.all_bone_transforms.buffer);
+        // laid out like the current bones so it can be bound with the same offset. meshes that weren't
+        // skinned with the same bones in the last frame keep their current transforms
+        let mut previous_bones = self.all_bone_transforms.buffer.clone();
+        for bone_slice in &self.all_bone_transforms.animated_bone_transforms {
+            let previous_bone_slice = previous_all_bone_transforms
+                .animated_bone_transforms
+                .iter()
+                .find(|previous_bone_slice| {
+                    previous_bone_slice.binded_pbr_mesh_index == bone_slice.binded_pbr_mesh_index
+                        && previous_bone_slice.end_index - previous_bone_slice.start_index
+                            == bone_slice.end_index - bone_slice.start_index
+                });
+            if let Some(previous_bone_slice) = previous_bone_slice {
+                previous_bones[bone_slice.start_index..bone_slice.end_index].copy_from_slice(
+                    &previous_all_bone_transforms.buffer
+                        [previous_bone_slice.start_index..previous_bone_slice.end_index],
+                );
+            }
+        }
+        bones_buffers_resized |= self
+            .previous_bones_buffer
+            .write(device, queue, &previous_bones);
         // logger.log(&format!("get_all_bone_data length -> {:?}", yo.elapsed()));
         // logger.log(&format!(
         //     "self.bones_buffer.length_bytes() -> {:?}",
         //     self.bones_buffer.length_bytes()
         // ));
-        self.bones_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: bones_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                    buffer: self.bones_buffer.src(),
-                    offset: 0,
-                    size: NonZeroU64::new(self.bones_buffer.length_bytes().try_into().unwrap()),
-                }),
-            }],
-            label: Some("bones_bind_group"),
-        });
+        let morph_target_weight_alignment =
+            limits.min_storage_buffer_offset_alignment as usize / std::mem::size_of::<f32>();
+        let mut morph_target_weights: Vec<f32> = Vec::new();
+        let mut morph_target_weight_offsets = vec![0u32; self.buffers.binded_pbr_meshes.len()];
+        let mut max_morph_target_weights_slice_length = 1;
+        let mut pbr_instance_culling_infos = vec![vec![]; self.buffers.binded_pbr_meshes.len()];
+        let model_transform_alignment = (limits.min_storage_buffer_offset_alignment as usize
+            / std::mem::size_of::<GpuMatrix4>())
+        .max(1);
+        let mut previous_model_transforms: Vec<GpuMatrix4> = Vec::new();
+        let mut previous_model_transform_offsets = vec![0u32; self.buffers.binded_pbr_meshes.len()];
+        let mut max_previous_model_transforms_slice_length = 1;
+        let mut node_transforms = HashMap::new();
+        let mut shadow_caster_states = HashMap::new();
+        let is_gpu_driven_mesh: Vec<_> = self
+            .buffers
+            .binded_pbr_meshes
+            .iter()
+            .map(|binded_pbr_mesh| {
+                self.gpu_driven_meshes.is_some() && GpuDrivenMeshes::can_draw_mesh(binded_pbr_mesh)
+            })
+            .collect();
+        let mut gpu_driven_instances = vec![vec![]; self.buffers.binded_pbr_meshes.len()];
+        let scene_instances = SceneInstances::gather(
+            scene,
+            self.buffers.binded_pbr_meshes.len(),
+            self.buffers.binded_unlit_meshes.len(),
+            self.enable_wireframe_mode,
+        );
         self.buffers
             .binded_pbr_meshes
             .iter_mut()
@@ -2256,38 +2976,151 @@ impl RendererState {
                     BindedPbrMesh {
                         geometry_buffers,
                         dynamic_pbr_params,
+                        alpha_mode,
+                        morph_target_count,
                         ..
                     },
                 )| {
-                    let gpu_instances: Vec<_> = scene
-                        .nodes()
-                        .filter(|_| !self.enable_wireframe_mode)
-                        .filter_map(|node| match &node.mesh {
-                            Some(GameNodeMesh {
-                                mesh_indices,
-                                mesh_type: GameNodeMeshType::Pbr { material_override },
-                                wireframe: false,
-                                ..
-                            }) => mesh_indices
-                                .iter()
-                                .find(|node_mesh_index| **node_mesh_index == binded_pbr_mesh_index)
-                                .map(|_| {
-                                    (
-                                        node.id(),
-                                        material_override,
-                                        node.skin_index.unwrap_or(usize::MAX),
-                                    )
-                                }),
+                    let instances: Vec<_> = scene_instances.pbr[binded_pbr_mesh_index]
+                        .iter()
+                        .filter_map(|NodeInstance { node, transform }| match &node.mesh {
+                            Some(
+                                node_mesh @ GameNodeMesh {
+                                    mesh_type: GameNodeMeshType::Pbr { material_override },
+                                    ..
+                                },
+                            ) => Some((
+                                *transform,
+                                material_override.unwrap_or(*dynamic_pbr_params),
+                                node.id(),
+                                (node_mesh.cast_shadows, node_mesh.receive_shadows),
+                            )),
                             _ => None,
                         })
-                        .map(|(node_id, material_override, _)| {
-                            let transform = scene.get_global_transform_for_node(node_id);
-                            GpuPbrMeshInstance::new(
-                                transform,
-                                material_override.unwrap_or(*dynamic_pbr_params),
-                            )
+                        .collect();
+                    if *morph_target_count > 0 && !instances.is_empty() {
+                        morph_target_weight_offsets[binded_pbr_mesh_index] =
+                            (morph_target_weights.len() * std::mem::size_of::<f32>()) as u32;
+                        for NodeInstance { node, .. } in &scene_instances.pbr[binded_pbr_mesh_index]
+                        {
+                            let node_weights = &node.morph_target_weights;
+                            morph_target_weights.extend((0..*morph_target_count).map(
+                                |target_index| {
+                                    node_weights.get(target_index).copied().unwrap_or(0.0)
+                                },
+                            ));
+                        }
+                        max_morph_target_weights_slice_length =
+                            max_morph_target_weights_slice_length
+                                .max(*morph_target_count * instances.len());
+                        while morph_target_weights.len() % morph_target_weight_alignment != 0 {
+                            morph_target_weights.push(0.0);
+                        }
+                    }
+                    if !instances.is_empty() {
+                        previous_model_transform_offsets[binded_pbr_mesh_index] =
+                            (previous_model_transforms.len() * std::mem::size_of::<GpuMatrix4>())
+                                as u32;
+                        for (transform, _, node_id, _) in &instances {
+                            let model_transform = transform.matrix();
+                            previous_model_transforms.push(GpuMatrix4(
+                                self.previous_node_transforms
+                                    .get(node_id)
+                                    .copied()
+                                    .unwrap_or(model_transform),
+                            ));
+                            node_transforms.insert(*node_id, model_transform);
+                        }
+                        max_previous_model_transforms_slice_length =
+                            max_previous_model_transforms_slice_length.max(instances.len());
+                        while previous_model_transforms.len() % model_transform_alignment != 0 {
+                            previous_model_transforms.push(GpuMatrix4(Matrix4::one()));
+                        }
+                    }
+                    if *alpha_mode == AlphaMode::Blend {
+                        let (bounding_box_min, bounding_box_max) = geometry_buffers.bounding_box;
+                        let bounding_box_center = (bounding_box_min + bounding_box_max) / 2.0;
+                        transparent_instances.extend(instances.iter().enumerate().map(
+                            |(instance_index, (transform, _, _, _))| {
+                                let world_center = (transform.matrix()
+                                    * bounding_box_center.extend(1.0))
+                                .truncate();
+                                (
+                                    binded_pbr_mesh_index,
+                                    instance_index as u32,
+                                    (world_center - camera_position).magnitude2(),
+                                )
+                            },
+                        ));
+                    }
+                    let gpu_instances: Vec<_> = instances
+                        .iter()
+                        .map(|(transform, pbr_params, _, (_, receive_shadows))| {
+                            GpuPbrMeshInstance::new(*transform, *pbr_params, *receive_shadows)
+                        })
+                        .collect();
+                    let bone_transforms = self
+                        .all_bone_transforms
+                        .animated_bone_transforms
+                        .iter()
+                        .find(|bone_slice| {
+                            bone_slice.binded_pbr_mesh_index == binded_pbr_mesh_index
                         })
+                        .map(|bone_slice| {
+                            &self.all_bone_transforms.buffer
+                                [bone_slice.start_index..bone_slice.end_index]
+                        });
+                    // animations can move the vertices of skinned meshes outside of their bind pose bounding box
+                    let bounding_box = bone_transforms
+                        .map(|bone_transforms| {
+                            get_skinned_bounding_box(geometry_buffers.bounding_box, bone_transforms)
+                        })
+                        .unwrap_or(geometry_buffers.bounding_box);
+                    pbr_instance_culling_infos[binded_pbr_mesh_index] = instances
+                        .iter()
+                        .zip(gpu_instances.iter())
+                        .map(
+                            |((transform, _, node_id, (cast_shadows, _)), gpu_instance)| {
+                                let bounds =
+                                    BoundingSphere::from_transformed_aabb(bounding_box, transform);
+                                if *cast_shadows {
+                                    let mut hasher = DefaultHasher::new();
+                                    hasher.write(bytemuck::bytes_of(gpu_instance));
+                                    if let Some(bone_transforms) = bone_transforms {
+                                        hasher.write(bone_transforms);
+                                    }
+                                    if *morph_target_count > 0 {
+                                        hasher.write(bytemuck::cast_slice(
+                                            &scene.get_node(*node_id).unwrap().morph_target_weights,
+                                        ));
+                                    }
+                                    shadow_caster_states.insert(
+                                        (binded_pbr_mesh_index, *node_id),
+                                        (hasher.finish(), bounds),
+                                    );
+                                }
+                                PbrInstanceCullingInfo {
+                                    bounds,
+                                    cast_shadows: *cast_shadows,
+                                }
+                            },
+                        )
                         .collect();
+                    // the gpu-driven meshes are drawn from a single buffer of all their instances
+                    if is_gpu_driven_mesh[binded_pbr_mesh_index] {
+                        gpu_driven_instances[binded_pbr_mesh_index] = gpu_instances
+                            .iter()
+                            .zip(pbr_instance_culling_infos[binded_pbr_mesh_index].iter())
+                            .map(|(gpu_instance, culling_info)| {
+                                (
+                                    *gpu_instance,
+                                    culling_info.bounds,
+                                    culling_info.cast_shadows,
+                                )
+                            })
+                            .collect();
+                        return;
+                    }
                     let previous_buffer_capacity_bytes =
                         geometry_buffers.instance_buffer.capacity_bytes();
                     let resized = geometry_buffers.instance_buffer.write(
@@ -2304,6 +3137,105 @@ impl RendererState {
                     }
                 },
             );
+        // make sure the last slice can be bound with the full binding size
+        morph_target_weights.extend((0..max_morph_target_weights_slice_length).map(|_| 0.0));
+        bones_buffers_resized |= self.morph_target_weights_buffer.write(
+            device,
+            queue,
+            bytemuck::cast_slice(&morph_target_weights),
+        );
+        self.morph_target_weight_offsets = morph_target_weight_offsets;
+        // make sure the last slice can be bound with the full binding size
+        previous_model_transforms.extend(
+            (0..max_previous_model_transforms_slice_length).map(|_| GpuMatrix4(Matrix4::one())),
+        );
+        let previous_model_transforms_buffer_resized = self.previous_model_transforms_buffer.write(
+            device,
+            queue,
+            bytemuck::cast_slice(&previous_model_transforms),
+        );
+        self.previous_model_transform_offsets = previous_model_transform_offsets;
+        self.previous_node_transforms = node_transforms;
+        self.pbr_instance_culling_infos = pbr_instance_culling_infos;
+        // the old and new bounds of every caster that was added, removed or changed since the last frame
+        let changed_shadow_casters: Vec<_> = shadow_caster_states
+            .iter()
+            .filter_map(
+                |(key, (hash, bounds))| match self.shadow_caster_states.get(key) {
+                    Some((previous_hash, _)) if previous_hash == hash => None,
+                    Some((_, previous_bounds)) => Some([Some(*bounds), Some(*previous_bounds)]),
+                    None => Some([Some(*bounds), None]),
+                },
+            )
+            .chain(
+                self.shadow_caster_states
+                    .iter()
+                    .filter(|(key, _)| !shadow_caster_states.contains_key(key))
+                    .map(|(_, (_, previous_bounds))| [Some(*previous_bounds), None]),
+            )
+            .flatten()
+            .flatten()
+            .collect();
+        self.shadow_caster_states = shadow_caster_states;
+        self.bones_bind_group = Self::make_bones_bind_group(
+            device,
+            bones_bind_group_layout,
+            &self.bones_buffer,
+            &self.morph_target_weights_buffer,
+            max_morph_target_weights_slice_length * std::mem::size_of::<f32>(),
+            &self.previous_bones_buffer,
+            &self.previous_model_transforms_buffer,
+            max_previous_model_transforms_slice_length * std::mem::size_of::<GpuMatrix4>(),
+        );
+        let bones_bind_group_offsets: Vec<_> = (0..self.buffers.binded_pbr_meshes.len())
+            .map(|binded_pbr_mesh_index| self.get_bones_bind_group_offsets(binded_pbr_mesh_index))
+            .collect();
+        if let Some(gpu_driven_meshes) = &mut self.gpu_driven_meshes {
+            let culled_previous_model_transforms_resized = gpu_driven_meshes.update(
+                device,
+                queue,
+                &self.buffers.binded_pbr_meshes,
+                &gpu_driven_instances,
+                &self.previous_model_transform_offsets,
+                &bones_bind_group_offsets,
+                &self.previous_model_transforms_buffer,
+                previous_model_transforms_buffer_resized,
+                logger,
+            );
+            // the binding sizes follow the bones and the instances, so they can change without a reallocation.
+            // the culled previous model transforms are bound whole and indexed from the start
+            let binding_sizes = [
+                self.bones_buffer.length_bytes(),
+                max_morph_target_weights_slice_length * std::mem::size_of::<f32>(),
+                self.previous_bones_buffer.length_bytes(),
+                gpu_driven_meshes
+                    .culled_previous_model_transforms_buffer()
+                    .capacity_bytes(),
+            ];
+            let is_outdated = match &self.gpu_driven_bones_bind_group {
+                Some((_, previous_binding_sizes)) => {
+                    bones_buffers_resized
+                        || culled_previous_model_transforms_resized
+                        || *previous_binding_sizes != binding_sizes
+                }
+                None => true,
+            };
+            if is_outdated {
+                self.gpu_driven_bones_bind_group = Some((
+                    Self::make_bones_bind_group(
+                        device,
+                        bones_bind_group_layout,
+                        &self.bones_buffer,
+                        &self.morph_target_weights_buffer,
+                        binding_sizes[1],
+                        &self.previous_bones_buffer,
+                        gpu_driven_meshes.culled_previous_model_transforms_buffer(),
+                        binding_sizes[3],
+                    ),
+                    binding_sizes,
+                ));
+            }
+        }
         self.buffers
             .binded_unlit_meshes
             .iter_mut()
@@ -2315,28 +3247,18 @@ impl RendererState {
                         instance_buffer, ..
                     },
                 )| {
-                    let gpu_instances: Vec<_> = scene
-                        .nodes()
-                        .filter(|_| !self.enable_wireframe_mode)
-                        .filter_map(|node| match &node.mesh {
+                    let gpu_instances: Vec<_> = scene_instances.unlit[binded_unlit_mesh_index]
+                        .iter()
+                        .filter_map(|NodeInstance { node, transform }| match &node.mesh {
                             Some(GameNodeMesh {
-                                mesh_indices,
                                 mesh_type: GameNodeMeshType::Unlit { color },
-                                wireframe: false,
-                            }) => mesh_indices
-                                .iter()
-                                .find(|node_mesh_index| {
-                                    **node_mesh_index == binded_unlit_mesh_index
-                                })
-                                .map(|_| (node.id(), color)),
+                                ..
+                            }) => Some(GpuUnlitMeshInstance {
+                                color: [color.x, color.y, color.z, 1.0],
+                                model_transform: GpuMatrix4(transform.matrix()),
+                            }),
                             _ => None,
                         })
-                        .map(|(node_id, color)| GpuUnlitMeshInstance {
-                            color: [color.x, color.y, color.z, 1.0],
-                            model_transform: GpuMatrix4(
-                                scene.get_global_transform_for_node(node_id).matrix(),
-                            ),
-                        })
                         .collect();
                     let previous_buffer_capacity_bytes = instance_buffer.capacity_bytes();
                     let resized =
@@ -2357,29 +3279,10 @@ impl RendererState {
                  instance_buffer,
                  ..
              }| {
-                let gpu_instances: Vec<_> = scene
-                    .nodes()
-                    .filter(|node| {
-                        if self.enable_wireframe_mode {
-                            true
-                        } else {
-                            node.mesh.is_some() && node.mesh.as_ref().unwrap().wireframe
-                        }
-                    })
-                    .filter(|node| match &node.mesh {
-                        Some(GameNodeMesh {
-                            mesh_indices,
-                            mesh_type,
-                            ..
-                        }) => {
-                            *source_mesh_type == (*mesh_type).into()
-                                && mesh_indices
-                                    .iter()
-                                    .any(|node_mesh_index| *node_mesh_index == *source_mesh_index)
-                        }
-                        _ => false,
-                    })
-                    .map(|node| {
+                let gpu_instances: Vec<_> = scene_instances
+                    .wireframe(source_mesh_type, *source_mesh_index)
+                    .iter()
+                    .map(|NodeInstance { node, transform }| {
                         let color = match node.mesh.as_ref().unwrap().mesh_type {
                             GameNodeMeshType::Unlit { color } => {
                                 Some([color.x, color.y, color.z, 1.0])
@@ -2428,9 +3331,7 @@ impl RendererState {
                         .unwrap_or(DEFAULT_WIREFRAME_COLOR);
                         GpuWireframeMeshInstance {
                             color,
-                            model_transform: GpuMatrix4(
-                                scene.get_global_transform_for_node(node.id()).matrix(),
-                            ),
+                            model_transform: GpuMatrix4(transform.matrix()),
                         }
                     })
                     .collect();
@@ -2447,6 +3348,19 @@ impl RendererState {
             },
         );
 
+        // furthest first so blending composes correctly
+        transparent_instances.sort_by(|(_, _, distance_a), (_, _, distance_b)| {
+            distance_b
+                .partial_cmp(distance_a)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        self.transparent_draw_queue = transparent_instances
+            .into_iter()
+            .map(|(binded_pbr_mesh_index, instance_index, _)| {
+                (binded_pbr_mesh_index, instance_index)
+            })
+            .collect();
+
         // let total_instance_buffer_memory_usage = self
         //     .buffers
         //     .binded_pbr_meshes
@@ -2503,131 +3417,328 @@ impl RendererState {
         //     "total_vertex_buffer_memory_usage={:?}",
         //     total_vertex_buffer_memory_usage
         // ));
-        queue.write_buffer(
-            &self.point_lights_buffer,
-            0,
-            bytemuck::cast_slice(&make_point_light_uniform_buffer(game_state)),
+        let point_light_uniforms = make_point_light_uniform_buffer(inputs);
+        if self.point_lights_buffer.write(
+            device,
+            queue,
+            bytemuck::cast_slice(&point_light_uniforms),
+        ) {
+            self.camera_and_lights_bind_group = Self::make_camera_and_lights_bind_group(
+                device,
+                &self.camera_and_lights_bind_group_layout,
+                &self.camera_buffer,
+                &self.point_lights_buffer,
+                &self.directional_lights_buffer,
+                &self.light_clusters,
+            );
+            self.light_clusters.set_point_lights_buffer(
+                device,
+                &self.camera_buffer,
+                &self.point_lights_buffer,
+            );
+        }
+        self.light_clusters.update(
+            queue,
+            point_light_uniforms.len(),
+            (
+                self.shading_texture.size.width,
+                self.shading_texture.size.height,
+            ),
+            logger,
+        );
+        let now = Instant::now();
+        self.point_shadow_map_layers_to_render.clear();
+        self.directional_shadow_map_layers_to_render.clear();
+        if self.enable_shadows {
+            for (light_index, light) in inputs
+                .point_lights
+                .iter()
+                .take(MAX_SHADOW_CASTING_POINT_LIGHTS)
+                .enumerate()
+            {
+                if let Some(light_node) = scene.get_node(light.node_id) {
+                    build_cubemap_face_camera_views(
+                        light_node.transform.position(),
+                        POINT_SHADOW_MAP_NEAR_PLANE_DISTANCE,
+                        light.range,
+                        false,
+                    )
+                    .into_iter()
+                    .enumerate()
+                    .for_each(|(face_index, face_view)| {
+                        let layer_index = 6 * light_index + face_index;
+                        if self.point_shadow_map_layers[layer_index].schedule_update(
+                            face_view,
+                            None,
+                            &changed_shadow_casters,
+                            light.shadow_update_interval_seconds,
+                            now,
+                        ) {
+                            self.point_shadow_map_layers_to_render
+                                .push((layer_index, face_view));
+                        }
+                    });
+                }
+            }
+        }
+        let cascade_splits = build_shadow_cascade_splits(
+            SHADOW_CASCADE_NEAR_DISTANCE,
+            self.shadow_distance,
+            self.shadow_cascade_count,
+            self.shadow_cascade_split_lambda,
         );
+        self.directional_light_cascades = inputs
+            .directional_lights
+            .iter()
+            .take(MAX_SHADOW_CASTING_DIRECTIONAL_LIGHTS)
+            .enumerate()
+            .map(|(light_index, light)| {
+                build_directional_light_cascade_camera_views(
+                    -light.direction,
+                    camera_transform,
+                    self.base.window_size.width as f32 / self.base.window_size.height as f32,
+                    FOV_Y.into(),
+                    NEAR_PLANE_DISTANCE,
+                    &cascade_splits,
+                    DIRECTIONAL_SHADOW_DEPTH,
+                    DIRECTIONAL_SHADOW_MAP_RESOLUTION,
+                )
+                .into_iter()
+                .enumerate()
+                .map(|(cascade_index, cascade)| {
+                    let layer_index =
+                        light_index * self.shadow_cascade_count as usize + cascade_index;
+                    let layer = &mut self.directional_shadow_map_layers[layer_index];
+                    if self.enable_shadows
+                        && layer.schedule_update(
+                            cascade,
+                            Some((
+                                cascade_index
+                                    .checked_sub(1)
+                                    .map(|previous_index| cascade_splits[previous_index])
+                                    .unwrap_or(0.0),
+                                cascade_splits[cascade_index],
+                            )),
+                            &changed_shadow_casters,
+                            light.shadow_update_interval_seconds,
+                            now,
+                        )
+                    {
+                        self.directional_shadow_map_layers_to_render
+                            .push((layer_index, cascade));
+                    }
+                    // the shader has to use the same view that's in the shadow map
+                    layer.rendered_view.unwrap_or(cascade)
+                })
+                .collect()
+            })
+            .collect();
         queue.write_buffer(
             &self.directional_lights_buffer,
             0,
             bytemuck::cast_slice(&make_directional_light_uniform_buffer(
-                &game_state.directional_lights,
+                inputs.directional_lights,
+                &self.directional_light_cascades,
+                &cascade_splits,
+                self.shadow_cascade_blend_fraction,
             )),
         );
+        // the exposure gets overwritten on the gpu when auto exposure is on
+        let (color_grading_lut_domain_min, color_grading_lut_domain_max) = self
+            .color_grading_lut_domain
+            .unwrap_or(([0.0; 3], [1.0; 3]));
         queue.write_buffer(
             &self.tone_mapping_config_buffer,
             0,
-            bytemuck::cast_slice(&[self.tone_mapping_exposure]),
+            bytemuck::cast_slice(&[ToneMappingConfigUniform {
+                exposure: self.tone_mapping_exposure,
+                tone_mapping_operator: self.tone_mapping_operator.shader_index(),
+                enable_color_grading: self.enable_color_grading as u32,
+                color_grading_lut_size: self.color_grading_lut_texture.size.width as f32,
+                color_grading_lut_domain_min: [
+                    color_grading_lut_domain_min[0],
+                    color_grading_lut_domain_min[1],
+                    color_grading_lut_domain_min[2],
+                    0.0,
+                ],
+                color_grading_lut_domain_max: [
+                    color_grading_lut_domain_max[0],
+                    color_grading_lut_domain_max[1],
+                    color_grading_lut_domain_max[2],
+                    0.0,
+                ],
+                bloom_intensity: if self.enable_bloom {
+                    self.bloom_settings.intensity
+                } else {
+                    0.0
+                },
+                padding: [0.0; 3],
+            }]),
         );
+        let frame_time_seconds = self
+            .last_update_time
+            .map(|last_update_time| now.duration_since(last_update_time).as_secs_f32())
+            .unwrap_or(0.0);
+        self.last_update_time = Some(now);
+        if self.enable_auto_exposure {
+            self.auto_exposure
+                .update(queue, &self.auto_exposure_settings, frame_time_seconds);
+        }
     }
 
-    pub fn render(&mut self, game_state: &GameState) -> Result<(), wgpu::SurfaceError> {
-        let surface_texture = self.base.surface.get_current_texture()?;
+    pub fn render(&mut self, inputs: RenderInputs) -> Result<(), wgpu::SurfaceError> {
+        let surface_texture = match self
+            .base
+            .surface
+            .as_ref()
+            .expect("Tried to render to the surface of a headless renderer")
+            .get_current_texture()
+        {
+            Ok(surface_texture) => surface_texture,
+            Err(err) => {
+                // the shadow map updates that were scheduled for this frame didn't happen
+                self.invalidate_shadow_maps();
+                return Err(err);
+            }
+        };
         let surface_texture_view = surface_texture
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
 
-        if self.enable_shadows {
-            game_state
-                .directional_lights
-                .iter()
-                .enumerate()
-                .for_each(|(light_index, light)| {
-                    let view_proj_matrices =
-                        build_directional_light_camera_view(-light.direction, 100.0, 100.0, 1000.0);
-                    let texture_view = self.directional_shadow_map_textures.texture.create_view(
-                        &wgpu::TextureViewDescriptor {
-                            dimension: Some(wgpu::TextureViewDimension::D2),
-                            base_array_layer: light_index.try_into().unwrap(),
-                            array_layer_count: NonZeroU32::new(1),
-                            ..Default::default()
-                        },
-                    );
-                    let shadow_render_pass_desc = wgpu::RenderPassDescriptor {
-                        label: Some("Shadow Render Pass"),
-                        color_attachments: &[],
-                        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                            view: &texture_view,
-                            depth_ops: Some(wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(1.0),
-                                store: true,
-                            }),
-                            stencil_ops: None,
-                        }),
-                    };
-                    self.base.queue.write_buffer(
-                        &self.camera_buffer,
-                        0,
-                        bytemuck::cast_slice(&[CameraUniform::from(view_proj_matrices)]),
-                    );
-                    self.render_pbr_meshes(
-                        game_state,
-                        &shadow_render_pass_desc,
-                        &self.directional_shadow_map_pipeline,
-                        true,
-                    );
-                });
-            (0..game_state.point_lights.len()).for_each(|light_index| {
-                if let Some(light_node) = game_state
-                    .scene
-                    .get_node(game_state.point_lights[light_index].node_id)
-                {
-                    build_cubemap_face_camera_views(
-                        light_node.transform.position(),
-                        0.1,
-                        1000.0,
-                        false,
-                    )
-                    .iter()
-                    .copied()
-                    .enumerate()
-                    .map(|(i, view_proj_matrices)| {
-                        (
-                            view_proj_matrices,
-                            self.point_shadow_map_textures.texture.create_view(
-                                &wgpu::TextureViewDescriptor {
-                                    dimension: Some(wgpu::TextureViewDimension::D2),
-                                    base_array_layer: (6 * light_index + i).try_into().unwrap(),
-                                    array_layer_count: NonZeroU32::new(1),
-                                    ..Default::default()
-                                },
-                            ),
-                        )
-                    })
-                    .for_each(
-                        |(face_view_proj_matrices, face_texture_view)| {
-                            let shadow_render_pass_desc = wgpu::RenderPassDescriptor {
-                                label: Some("Shadow Render Pass"),
-                                color_attachments: &[],
-                                depth_stencil_attachment: Some(
-                                    wgpu::RenderPassDepthStencilAttachment {
-                                        view: &face_texture_view,
-                                        depth_ops: Some(wgpu::Operations {
-                                            load: wgpu::LoadOp::Clear(1.0),
-                                            store: true,
-                                        }),
-                                        stencil_ops: None,
-                                    },
-                                ),
-                            };
-                            self.base.queue.write_buffer(
-                                &self.camera_buffer,
-                                0,
-                                bytemuck::cast_slice(&[CameraUniform::from(
-                                    face_view_proj_matrices,
-                                )]),
-                            );
-                            self.render_pbr_meshes(
-                                game_state,
-                                &shadow_render_pass_desc,
-                                &self.point_shadow_map_pipeline,
-                                true,
-                            );
-                        },
-                    );
+        self.render_to_view(inputs, &surface_texture_view);
+
+        surface_texture.present();
+        Ok(())
+    }
+
+    // renders a frame into an offscreen texture the size of the window / surface config
+    // and copies it back to the cpu. works for both windowed and headless renderers
+    #[cfg(test)]
+    pub fn render_to_image(&mut self, inputs: RenderInputs) -> Result<image::RgbaImage> {
+        let width = self.base.surface_config.width;
+        let height = self.base.surface_config.height;
+        let format = self.base.surface_config.format;
+
+        let offscreen_texture = self.base.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen_texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+        let offscreen_texture_view =
+            offscreen_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        self.render_to_view(inputs, &offscreen_texture_view);
+
+        // rows in the copy buffer need to be aligned to COPY_BYTES_PER_ROW_ALIGNMENT
+        let bytes_per_pixel = 4;
+        let unpadded_bytes_per_row = width * bytes_per_pixel;
+        let row_alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row =
+            ((unpadded_bytes_per_row + row_alignment - 1) / row_alignment) * row_alignment;
+
+        let readback_buffer = self.base.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("offscreen_readback_buffer"),
+            size: (padded_bytes_per_row * height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder =
+            self.base
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Offscreen Readback Encoder"),
+                });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &offscreen_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &readback_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.base.queue.submit(std::iter::once(encoder.finish()));
+
+        let buffer_slice = readback_buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.base.device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+
+        let is_bgra = matches!(
+            format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+        let mut pixels: Vec<u8> = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        {
+            let padded_pixels = buffer_slice.get_mapped_range();
+            for row in padded_pixels.chunks(padded_bytes_per_row as usize) {
+                let row = &row[..unpadded_bytes_per_row as usize];
+                if is_bgra {
+                    for bgra in row.chunks(4) {
+                        pixels.extend_from_slice(&[bgra[2], bgra[1], bgra[0], bgra[3]]);
+                    }
+                } else {
+                    pixels.extend_from_slice(row);
                 }
-            });
+            }
+        }
+        readback_buffer.unmap();
+
+        image::RgbaImage::from_raw(width, height, pixels)
+            .ok_or_else(|| anyhow::anyhow!("Readback buffer didn't match the image dimensions"))
+    }
+
+    fn render_to_view(&mut self, inputs: RenderInputs, final_view: &wgpu::TextureView) {
+        for (layer_index, view) in &self.directional_shadow_map_layers_to_render {
+            let texture_view = self.directional_shadow_map_textures.texture.create_view(
+                &wgpu::TextureViewDescriptor {
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: (*layer_index).try_into().unwrap(),
+                    array_layer_count: NonZeroU32::new(1),
+                    ..Default::default()
+                },
+            );
+            self.render_shadow_map_layer(
+                &texture_view,
+                *view,
+                &self.directional_shadow_map_pipeline,
+            );
+        }
+        for (layer_index, view) in &self.point_shadow_map_layers_to_render {
+            let texture_view =
+                self.point_shadow_map_textures
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor {
+                        dimension: Some(wgpu::TextureViewDimension::D2),
+                        base_array_layer: (*layer_index).try_into().unwrap(),
+                        array_layer_count: NonZeroU32::new(1),
+                        ..Default::default()
+                    });
+            self.render_shadow_map_layer(&texture_view, *view, &self.point_shadow_map_pipeline);
         }
 
         let black = wgpu::Color {
@@ -2637,48 +3748,124 @@ impl RendererState {
             a: 1.0,
         };
 
+        // the alpha channel of the shading texture is the coverage of the shaded geometry,
+        // the skybox is blended under it and auto exposure only meters the covered pixels
+        let transparent_black = wgpu::Color { a: 0.0, ..black };
+
+        let player_transform = inputs
+            .scene
+            .get_global_transform_for_node(inputs.camera_node_id);
+        let jitter = match self.anti_aliasing_mode {
+            AntiAliasingMode::Taa => self.taa.jitter(),
+            AntiAliasingMode::None => Vector2::new(0.0, 0.0),
+        };
+        let camera_view = ShaderCameraView::from_transform(
+            player_transform,
+            self.base.window_size.width as f32 / self.base.window_size.height as f32,
+            NEAR_PLANE_DISTANCE,
+            FAR_PLANE_DISTANCE,
+            FOV_Y.into(),
+            true,
+            jitter,
+        );
+        self.base.queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[CameraUniform::from(camera_view)
+                .with_previous_view(self.previous_camera_view.unwrap_or(camera_view))]),
+        );
+        self.previous_camera_view = Some(camera_view);
+        let camera_frustum = Frustum::from(camera_view);
+        let visible_instance_ranges =
+            self.get_visible_instance_ranges(&camera_frustum, PbrMeshPass::Shading);
+        if let Some(gpu_driven_meshes) = &self.gpu_driven_meshes {
+            gpu_driven_meshes.cull(&self.base.device, &self.base.queue, &camera_frustum, false);
+        }
+        self.light_clusters
+            .dispatch(&self.base.device, &self.base.queue);
+
+        if self.enable_ssao {
+            let depth_prepass_desc = wgpu::RenderPassDescriptor {
+                label: Some("Depth Prepass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: self.msaa.depth_view(&self.depth_texture.view),
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(0.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            };
+            self.render_pbr_meshes(
+                &depth_prepass_desc,
+                &self.depth_prepass_pipeline,
+                &self.double_sided_depth_prepass_pipeline,
+                PbrMeshPass::DepthPrepass,
+                &visible_instance_ranges,
+            );
+            let masked_depth_prepass_desc = wgpu::RenderPassDescriptor {
+                label: Some("Masked Depth Prepass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: self.msaa.depth_view(&self.depth_texture.view),
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            };
+            self.render_pbr_meshes(
+                &masked_depth_prepass_desc,
+                &self.masked_depth_prepass_pipeline,
+                &self.double_sided_masked_depth_prepass_pipeline,
+                PbrMeshPass::MaskedDepthPrepass,
+                &visible_instance_ranges,
+            );
+            self.msaa
+                .resolve_depth(&self.base.device, &self.base.queue, &self.depth_texture);
+            self.ssao
+                .update(&self.base.queue, camera_view, &self.ssao_settings);
+            self.ssao.render(&self.base.device, &self.base.queue);
+        } else {
+            self.ssao.clear(&self.base.device, &self.base.queue);
+        }
+
         let shading_render_pass_desc = wgpu::RenderPassDescriptor {
             label: Some("Shading Render Pass"),
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &self.shading_texture.view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(black),
-                    store: true,
-                },
-            })],
+            color_attachments: &[
+                Some(self.msaa.shading_attachment(
+                    &self.shading_texture.view,
+                    wgpu::LoadOp::Clear(transparent_black),
+                )),
+                Some(self.msaa.motion_vector_attachment(
+                    self.taa.motion_vector_view(),
+                    wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                )),
+            ],
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &self.depth_texture.view,
+                view: self.msaa.depth_view(&self.depth_texture.view),
                 depth_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(0.0),
+                    // the opaque and alpha masked meshes are already in there from the depth prepass
+                    load: if self.enable_ssao {
+                        wgpu::LoadOp::Load
+                    } else {
+                        wgpu::LoadOp::Clear(0.0)
+                    },
                     store: true,
                 }),
                 stencil_ops: None,
             }),
         };
 
-        let player_transform = game_state
-            .scene
-            .get_global_transform_for_node(game_state.player_node_id);
-        self.base.queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[CameraUniform::from(ShaderCameraView::from_transform(
-                player_transform,
-                self.base.window_size.width as f32 / self.base.window_size.height as f32,
-                NEAR_PLANE_DISTANCE,
-                FAR_PLANE_DISTANCE,
-                FOV_Y.into(),
-                true,
-            ))]),
-        );
-
         // TODO: this can use the same render pass as unlit + wireframe
         self.render_pbr_meshes(
-            game_state,
             &shading_render_pass_desc,
             &self.mesh_pipeline,
-            false,
+            &self.double_sided_mesh_pipeline,
+            PbrMeshPass::Shading,
+            &visible_instance_ranges,
         );
 
         let mut unlit_and_wireframe_encoder =
@@ -2692,16 +3879,12 @@ impl RendererState {
             let mut render_pass =
                 unlit_and_wireframe_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                     label: Some("Unlit And Wireframe Render Pass"),
-                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                        view: &self.shading_texture.view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Load,
-                            store: true,
-                        },
-                    })],
+                    color_attachments: &[Some(
+                        self.msaa
+                            .shading_attachment(&self.shading_texture.view, wgpu::LoadOp::Load),
+                    )],
                     depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                        view: &self.depth_texture.view,
+                        view: self.msaa.depth_view(&self.depth_texture.view),
                         depth_ops: Some(wgpu::Operations {
                             load: wgpu::LoadOp::Load,
                             store: true,
@@ -2716,20 +3899,15 @@ impl RendererState {
                 .binded_unlit_meshes
                 .iter()
                 .enumerate()
-                .filter(|_| !self.enable_wireframe_mode)
-                .filter(|(binded_unlit_mesh_index, _)| {
-                    game_state.scene.nodes().any(|node| match &node.mesh {
-                        Some(GameNodeMesh {
-                            mesh_indices,
-                            mesh_type: GameNodeMeshType::Unlit { .. },
-                            wireframe: false,
-                            ..
-                        }) => mesh_indices
-                            .iter()
-                            .any(|node_mesh_index| node_mesh_index == binded_unlit_mesh_index),
-                        _ => false,
-                    })
-                })
+                // the instances were gathered in update, so an empty buffer means nothing to draw
+                .filter(
+                    |(
+                        _,
+                        BindedUnlitMesh {
+                            instance_buffer, ..
+                        },
+                    )| { instance_buffer.length() > 0 },
+                )
                 .for_each(
                     |(
                         _,
@@ -2743,7 +3921,7 @@ impl RendererState {
                     )| {
                         {
                             render_pass.set_bind_group(0, &self.camera_and_lights_bind_group, &[]);
-                            render_pass.set_bind_group(1, &self.bones_bind_group, &[0]);
+                            render_pass.set_bind_group(1, &self.bones_bind_group, &[0; 4]);
 
                             render_pass.set_vertex_buffer(0, vertex_buffer.src().slice(..));
                             render_pass.set_vertex_buffer(1, instance_buffer.src().slice(..));
@@ -2767,26 +3945,8 @@ impl RendererState {
                 .iter()
                 .filter(
                     |BindedWireframeMesh {
-                         source_mesh_type,
-                         source_mesh_index,
-                         ..
-                     }| {
-                        self.enable_wireframe_mode
-                            || game_state.scene.nodes().any(|node| match &node.mesh {
-                                Some(GameNodeMesh {
-                                    mesh_indices,
-                                    mesh_type,
-                                    wireframe: true,
-                                    ..
-                                }) => {
-                                    *source_mesh_type == (*mesh_type).into()
-                                        && mesh_indices.iter().any(|node_mesh_index| {
-                                            *node_mesh_index == *source_mesh_index
-                                        })
-                                }
-                                _ => false,
-                            })
-                    },
+                         instance_buffer, ..
+                     }| instance_buffer.length() > 0,
                 )
                 .for_each(
                     |BindedWireframeMesh {
@@ -2829,7 +3989,12 @@ impl RendererState {
                         render_pass.set_bind_group(
                             1,
                             &self.bones_bind_group,
-                            &[bone_transforms_buffer_start_index],
+                            &[
+                                bone_transforms_buffer_start_index,
+                                0,
+                                bone_transforms_buffer_start_index,
+                                0,
+                            ],
                         );
 
                         render_pass.set_vertex_buffer(0, vertex_buffer.src().slice(..));
@@ -2849,101 +4014,19 @@ impl RendererState {
             .queue
             .submit(std::iter::once(unlit_and_wireframe_encoder.finish()));
 
-        if self.enable_bloom {
-            self.base.queue.write_buffer(
-                &self.bloom_config_buffer,
-                0,
-                bytemuck::cast_slice(&[0.0f32, self.bloom_threshold, self.bloom_ramp_size]),
-            );
-
-            let mut bloom_threshold_encoder =
-                self.base
-                    .device
-                    .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                        label: Some("Bloom Threshold Encoder"),
-                    });
-            {
-                let mut bloom_threshold_render_pass =
-                    bloom_threshold_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        label: None,
-                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                            view: &self.bloom_pingpong_textures[0].view,
-                            resolve_target: None,
-                            ops: wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(black),
-                                store: true,
-                            },
-                        })],
-                        depth_stencil_attachment: None,
-                    });
-
-                bloom_threshold_render_pass.set_pipeline(&self.bloom_threshold_pipeline);
-                bloom_threshold_render_pass.set_bind_group(
-                    0,
-                    &self.shading_texture_bind_group,
-                    &[],
-                );
-                bloom_threshold_render_pass.set_bind_group(1, &self.bloom_config_bind_group, &[]);
-                bloom_threshold_render_pass.draw(0..3, 0..1);
-            }
-
-            self.base
-                .queue
-                .submit(std::iter::once(bloom_threshold_encoder.finish()));
-
-            let do_bloom_blur_pass = |src_texture: &wgpu::BindGroup,
-                                      dst_texture: &wgpu::TextureView,
-                                      horizontal: bool| {
-                let mut encoder =
-                    self.base
-                        .device
-                        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                            label: Some("Bloom Blur Encoder"),
-                        });
-                {
-                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        label: None,
-                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                            view: dst_texture,
-                            resolve_target: None,
-                            ops: wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(black),
-                                store: true,
-                            },
-                        })],
-                        depth_stencil_attachment: None,
-                    });
-
-                    self.base.queue.write_buffer(
-                        &self.bloom_config_buffer,
-                        0,
-                        bytemuck::cast_slice(&[
-                            if horizontal { 0.0f32 } else { 1.0f32 },
-                            self.bloom_threshold,
-                            self.bloom_ramp_size,
-                        ]),
-                    );
-                    render_pass.set_pipeline(&self.bloom_blur_pipeline);
-                    render_pass.set_bind_group(0, src_texture, &[]);
-                    render_pass.set_bind_group(1, &self.bloom_config_bind_group, &[]);
-                    render_pass.draw(0..3, 0..1);
-                }
+        self.render_transparent_pbr_meshes(&camera_frustum);
 
-                self.base.queue.submit(std::iter::once(encoder.finish()));
-            };
+        // taa and the skybox read the single sampled depth
+        self.msaa
+            .resolve_depth(&self.base.device, &self.base.queue, &self.depth_texture);
 
-            // do 10 gaussian blur passes, switching between horizontal and vertical and ping ponging between
-            // the two textures, effectively doing 5 full blurs
-            let blur_passes = 10;
-            (0..blur_passes).for_each(|i| {
-                do_bloom_blur_pass(
-                    &self.bloom_pingpong_texture_bind_groups[i % 2],
-                    &self.bloom_pingpong_textures[(i + 1) % 2].view,
-                    i % 2 == 0,
-                );
-            });
+        if self.anti_aliasing_mode == AntiAliasingMode::Taa {
+            self.taa
+                .render(&self.base.device, &self.base.queue, &self.shading_texture);
         }
 
+        // after taa so the skybox doesn't need motion vectors, but before bloom and tone mapping
+        // so it's exposed together with the shaded geometry
         let mut skybox_encoder =
             self.base
                 .device
@@ -2955,10 +4038,10 @@ impl RendererState {
                 skybox_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                     label: None,
                     color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                        view: &self.tone_mapping_texture.view,
+                        view: &self.shading_texture.view,
                         resolve_target: None,
                         ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(black),
+                            load: wgpu::LoadOp::Load,
                             store: true,
                         },
                     })],
@@ -2992,6 +4075,26 @@ impl RendererState {
             .queue
             .submit(std::iter::once(skybox_encoder.finish()));
 
+        if self.enable_bloom {
+            self.bloom.render(
+                &self.base.device,
+                &self.base.queue,
+                &self.shading_texture_bind_group,
+                &self.bloom_settings,
+            );
+        }
+
+        if self.enable_auto_exposure {
+            self.auto_exposure.dispatch(
+                &self.base.device,
+                &self.base.queue,
+                (
+                    self.shading_texture.size.width,
+                    self.shading_texture.size.height,
+                ),
+            );
+        }
+
         let mut tone_mapping_encoder =
             self.base
                 .device
@@ -3006,7 +4109,7 @@ impl RendererState {
                         view: &self.tone_mapping_texture.view,
                         resolve_target: None,
                         ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Load,
+                            load: wgpu::LoadOp::Clear(black),
                             store: true,
                         },
                     })],
@@ -3019,6 +4122,7 @@ impl RendererState {
                 &[],
             );
             tone_mapping_render_pass.set_bind_group(1, &self.tone_mapping_config_bind_group, &[]);
+            tone_mapping_render_pass.set_bind_group(2, &self.color_grading_lut_bind_group, &[]);
             tone_mapping_render_pass.draw(0..3, 0..1);
         }
 
@@ -3038,7 +4142,7 @@ impl RendererState {
                 surface_blit_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                     label: None,
                     color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                        view: &surface_texture_view,
+                        view: final_view,
                         resolve_target: None,
                         ops: wgpu::Operations {
                             load: wgpu::LoadOp::Clear(black),
@@ -3057,17 +4161,103 @@ impl RendererState {
             .queue
             .submit(std::iter::once(surface_blit_encoder.finish()));
 
-        surface_texture.present();
-        Ok(())
+        self.ui_renderer.render(
+            &self.base.device,
+            &self.base.queue,
+            final_view,
+            (
+                self.base.surface_config.width,
+                self.base.surface_config.height,
+            ),
+            inputs.ui,
+        );
+    }
+
+    fn render_shadow_map_layer(
+        &self,
+        texture_view: &wgpu::TextureView,
+        view: ShaderCameraView,
+        pipeline: &wgpu::RenderPipeline,
+    ) {
+        let shadow_render_pass_desc = wgpu::RenderPassDescriptor {
+            label: Some("Shadow Render Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: texture_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        };
+        self.base.queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[CameraUniform::from(view)]),
+        );
+        if let Some(gpu_driven_meshes) = &self.gpu_driven_meshes {
+            gpu_driven_meshes.cull(
+                &self.base.device,
+                &self.base.queue,
+                &Frustum::from(view),
+                true,
+            );
+        }
+        self.render_pbr_meshes(
+            &shadow_render_pass_desc,
+            pipeline,
+            pipeline,
+            PbrMeshPass::Shadow,
+            &self.get_visible_instance_ranges(&Frustum::from(view), PbrMeshPass::Shadow),
+        );
+    }
+
+    // the instances of each pbr mesh whose bounds intersect the frustum, as runs of instance indices.
+    // this is the per-view instance list that render_pbr_meshes draws. the gpu-driven meshes are
+    // left empty, they're culled by GpuDrivenMeshes::cull instead
+    fn get_visible_instance_ranges(
+        &self,
+        frustum: &Frustum,
+        pass: PbrMeshPass,
+    ) -> Vec<Vec<Range<u32>>> {
+        let shadow_casters_only = matches!(pass, PbrMeshPass::Shadow);
+        self.pbr_instance_culling_infos
+            .iter()
+            .enumerate()
+            .map(|(binded_pbr_mesh_index, culling_infos)| {
+                if self.is_gpu_driven(binded_pbr_mesh_index) {
+                    return vec![];
+                }
+                get_instance_ranges(culling_infos.iter().map(|culling_info| {
+                    (!shadow_casters_only || culling_info.cast_shadows)
+                        && frustum.contains_sphere(&culling_info.bounds)
+                }))
+            })
+            .collect()
+    }
+
+    fn is_gpu_driven(&self, binded_pbr_mesh_index: usize) -> bool {
+        self.gpu_driven_meshes
+            .as_ref()
+            .map(|gpu_driven_meshes| gpu_driven_meshes.draws_mesh(binded_pbr_mesh_index))
+            .unwrap_or(false)
     }
 
     fn render_pbr_meshes<'a>(
         &'a self,
-        game_state: &GameState,
         render_pass_descriptor: &wgpu::RenderPassDescriptor<'a, 'a>,
         pipeline: &'a wgpu::RenderPipeline,
-        is_shadow: bool,
+        double_sided_pipeline: &'a wgpu::RenderPipeline,
+        pass: PbrMeshPass,
+        visible_instance_ranges: &[Vec<Range<u32>>],
     ) {
+        let is_drawn_in_pass = |binded_pbr_mesh: &BindedPbrMesh| match pass {
+            PbrMeshPass::Shading => binded_pbr_mesh.alpha_mode != AlphaMode::Blend,
+            PbrMeshPass::DepthPrepass => binded_pbr_mesh.alpha_mode == AlphaMode::Opaque,
+            PbrMeshPass::MaskedDepthPrepass => binded_pbr_mesh.alpha_mode == AlphaMode::Mask,
+            PbrMeshPass::Shadow => binded_pbr_mesh.alpha_mode != AlphaMode::Blend,
+        };
         let device = &self.base.device;
         let queue = &self.base.queue;
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
@@ -3075,30 +4265,12 @@ impl RendererState {
         });
         {
             let mut render_pass = encoder.begin_render_pass(render_pass_descriptor);
-            render_pass.set_pipeline(pipeline);
             self.buffers
                 .binded_pbr_meshes
                 .iter()
                 .enumerate()
-                .filter(|(binded_pbr_mesh_index, _)| {
-                    game_state
-                        .scene
-                        .nodes()
-                        .filter(|node| {
-                            let mesh_is_wireframe =
-                                node.mesh.is_some() && node.mesh.as_ref().unwrap().wireframe;
-                            is_shadow || (!mesh_is_wireframe && !self.enable_wireframe_mode)
-                        })
-                        .any(|node| match &node.mesh {
-                            Some(GameNodeMesh {
-                                mesh_indices,
-                                mesh_type: GameNodeMeshType::Pbr { .. },
-                                ..
-                            }) => mesh_indices
-                                .iter()
-                                .any(|node_mesh_index| node_mesh_index == binded_pbr_mesh_index),
-                            _ => false,
-                        })
+                .filter(|(binded_pbr_mesh_index, binded_pbr_mesh)| {
+                    is_drawn_in_pass(binded_pbr_mesh) && !self.is_gpu_driven(*binded_pbr_mesh_index)
                 })
                 .for_each(
                     |(
@@ -3106,32 +4278,29 @@ impl RendererState {
                         BindedPbrMesh {
                             geometry_buffers,
                             textures_bind_group,
+                            double_sided,
                             ..
                         },
                     )| {
-                        render_pass.set_bind_group(0, &self.camera_and_lights_bind_group, &[]);
-                        let bone_transforms_buffer_start_index = self
-                            .all_bone_transforms
-                            .animated_bone_transforms
-                            .iter()
-                            .find(|bone_slice| {
-                                bone_slice.binded_pbr_mesh_index == binded_pbr_mesh_index
-                            })
-                            .map(|bone_slice| bone_slice.start_index.try_into().unwrap())
-                            .unwrap_or(0);
-                        render_pass.set_bind_group(
-                            if is_shadow { 1 } else { 3 },
+                        // meshes that were binded since the last update don't have any instances yet
+                        let instance_ranges = match visible_instance_ranges
+                            .get(binded_pbr_mesh_index)
+                        {
+                            Some(instance_ranges) if !instance_ranges.is_empty() => instance_ranges,
+                            _ => return,
+                        };
+                        render_pass.set_pipeline(if *double_sided {
+                            double_sided_pipeline
+                        } else {
+                            pipeline
+                        });
+                        self.set_pbr_mesh_bind_groups(
+                            &mut render_pass,
+                            pass,
+                            textures_bind_group,
                             &self.bones_bind_group,
-                            &[bone_transforms_buffer_start_index],
+                            &self.get_bones_bind_group_offsets(binded_pbr_mesh_index),
                         );
-                        if !is_shadow {
-                            render_pass.set_bind_group(1, textures_bind_group, &[]);
-                            render_pass.set_bind_group(
-                                2,
-                                &self.environment_textures_bind_group,
-                                &[],
-                            );
-                        }
 
                         render_pass
                             .set_vertex_buffer(0, geometry_buffers.vertex_buffer.src().slice(..));
@@ -3141,15 +4310,319 @@ impl RendererState {
                             geometry_buffers.index_buffer.src().slice(..),
                             geometry_buffers.index_buffer_format,
                         );
-                        render_pass.draw_indexed(
-                            0..geometry_buffers.index_buffer.length() as u32,
-                            0,
-                            0..geometry_buffers.instance_buffer.length() as u32,
-                        );
+                        for instance_range in instance_ranges {
+                            render_pass.draw_indexed(
+                                0..geometry_buffers.index_buffer.length() as u32,
+                                0,
+                                instance_range.clone(),
+                            );
+                        }
                     },
                 );
+
+            if let (Some(gpu_driven_meshes), Some((gpu_driven_bones_bind_group, _))) =
+                (&self.gpu_driven_meshes, &self.gpu_driven_bones_bind_group)
+            {
+                for group in gpu_driven_meshes.draw_groups() {
+                    let binded_pbr_mesh = match self
+                        .buffers
+                        .binded_pbr_meshes
+                        .get(group.binded_pbr_mesh_index)
+                    {
+                        Some(binded_pbr_mesh) if is_drawn_in_pass(binded_pbr_mesh) => {
+                            binded_pbr_mesh
+                        }
+                        _ => continue,
+                    };
+                    render_pass.set_pipeline(if binded_pbr_mesh.double_sided {
+                        double_sided_pipeline
+                    } else {
+                        pipeline
+                    });
+                    self.set_pbr_mesh_bind_groups(
+                        &mut render_pass,
+                        pass,
+                        &binded_pbr_mesh.textures_bind_group,
+                        gpu_driven_bones_bind_group,
+                        &group.bones_bind_group_offsets,
+                    );
+                    gpu_driven_meshes.draw(&mut render_pass, group);
+                }
+            }
         }
 
         queue.submit(std::iter::once(encoder.finish()));
     }
+
+    fn set_pbr_mesh_bind_groups<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pass: PbrMeshPass,
+        textures_bind_group: &'a wgpu::BindGroup,
+        bones_bind_group: &'a wgpu::BindGroup,
+        bones_bind_group_offsets: &[u32],
+    ) {
+        render_pass.set_bind_group(0, &self.camera_and_lights_bind_group, &[]);
+        if matches!(pass, PbrMeshPass::Shading) {
+            render_pass.set_bind_group(1, textures_bind_group, &[]);
+            render_pass.set_bind_group(2, &self.environment_textures_bind_group, &[]);
+            render_pass.set_bind_group(3, bones_bind_group, bones_bind_group_offsets);
+        } else {
+            // the shadow map pipeline layout
+            render_pass.set_bind_group(1, bones_bind_group, bones_bind_group_offsets);
+            render_pass.set_bind_group(2, textures_bind_group, &[]);
+        }
+    }
+
+    // the dynamic offsets of bones_bind_group for each of its bindings
+    fn get_bones_bind_group_offsets(&self, binded_pbr_mesh_index: usize) -> [u32; 4] {
+        let bone_transforms_buffer_start_index = self
+            .all_bone_transforms
+            .animated_bone_transforms
+            .iter()
+            .find(|bone_slice| bone_slice.binded_pbr_mesh_index == binded_pbr_mesh_index)
+            .map(|bone_slice| bone_slice.start_index.try_into().unwrap())
+            .unwrap_or(0);
+        // meshes that were binded since the last update don't have any weights or transforms yet
+        let morph_target_weights_offset = self
+            .morph_target_weight_offsets
+            .get(binded_pbr_mesh_index)
+            .copied()
+            .unwrap_or(0);
+        let previous_model_transforms_offset = self
+            .previous_model_transform_offsets
+            .get(binded_pbr_mesh_index)
+            .copied()
+            .unwrap_or(0);
+        [
+            bone_transforms_buffer_start_index,
+            morph_target_weights_offset,
+            bone_transforms_buffer_start_index,
+            previous_model_transforms_offset,
+        ]
+    }
+
+    // the morph target weights and previous model transforms are bound with a fixed size,
+    // the longest slice of any mesh, and moved to each mesh's slice with the dynamic offsets
+    #[allow(clippy::too_many_arguments)]
+    fn make_bones_bind_group(
+        device: &wgpu::Device,
+        bones_bind_group_layout: &wgpu::BindGroupLayout,
+        bones_buffer: &GpuBuffer,
+        morph_target_weights_buffer: &GpuBuffer,
+        morph_target_weights_binding_size: usize,
+        previous_bones_buffer: &GpuBuffer,
+        previous_model_transforms_buffer: &GpuBuffer,
+        previous_model_transforms_binding_size: usize,
+    ) -> wgpu::BindGroup {
+        fn buffer_entry(binding: u32, buffer: &GpuBuffer, size: usize) -> wgpu::BindGroupEntry<'_> {
+            wgpu::BindGroupEntry {
+                binding,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: buffer.src(),
+                    offset: 0,
+                    size: NonZeroU64::new(size.try_into().unwrap()),
+                }),
+            }
+        }
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bones_bind_group_layout,
+            entries: &[
+                buffer_entry(0, bones_buffer, bones_buffer.length_bytes()),
+                buffer_entry(
+                    1,
+                    morph_target_weights_buffer,
+                    morph_target_weights_binding_size,
+                ),
+                buffer_entry(
+                    2,
+                    previous_bones_buffer,
+                    previous_bones_buffer.length_bytes(),
+                ),
+                buffer_entry(
+                    3,
+                    previous_model_transforms_buffer,
+                    previous_model_transforms_binding_size,
+                ),
+            ],
+            label: Some("bones_bind_group"),
+        })
+    }
+
+    fn render_transparent_pbr_meshes(&self, camera_frustum: &Frustum) {
+        if self.transparent_draw_queue.is_empty() {
+            return;
+        }
+
+        let mut encoder =
+            self.base
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Transparent Mesh Encoder"),
+                });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Transparent Mesh Render Pass"),
+                color_attachments: &[Some(
+                    self.msaa
+                        .shading_attachment(&self.shading_texture.view, wgpu::LoadOp::Load),
+                )],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: self.msaa.depth_view(&self.depth_texture.view),
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            render_pass.set_bind_group(0, &self.camera_and_lights_bind_group, &[]);
+            render_pass.set_bind_group(2, &self.environment_textures_bind_group, &[]);
+
+            // one draw per instance since the sorted order interleaves instances of different meshes
+            for &(binded_pbr_mesh_index, instance_index) in &self.transparent_draw_queue {
+                let is_visible = self
+                    .pbr_instance_culling_infos
+                    .get(binded_pbr_mesh_index)
+                    .and_then(|culling_infos| culling_infos.get(instance_index as usize))
+                    .map(|culling_info| camera_frustum.contains_sphere(&culling_info.bounds))
+                    .unwrap_or(false);
+                if !is_visible {
+                    continue;
+                }
+                let BindedPbrMesh {
+                    geometry_buffers,
+                    textures_bind_group,
+                    double_sided,
+                    ..
+                } = &self.buffers.binded_pbr_meshes[binded_pbr_mesh_index];
+                render_pass.set_pipeline(if *double_sided {
+                    &self.double_sided_transparent_mesh_pipeline
+                } else {
+                    &self.transparent_mesh_pipeline
+                });
+                render_pass.set_bind_group(1, textures_bind_group, &[]);
+                render_pass.set_bind_group(
+                    3,
+                    &self.bones_bind_group,
+                    &self.get_bones_bind_group_offsets(binded_pbr_mesh_index),
+                );
+
+                render_pass.set_vertex_buffer(0, geometry_buffers.vertex_buffer.src().slice(..));
+                render_pass.set_vertex_buffer(1, geometry_buffers.instance_buffer.src().slice(..));
+                render_pass.set_index_buffer(
+                    geometry_buffers.index_buffer.src().slice(..),
+                    geometry_buffers.index_buffer_format,
+                );
+                render_pass.draw_indexed(
+                    0..geometry_buffers.index_buffer.length() as u32,
+                    0,
+                    instance_index..instance_index + 1,
+                );
+            }
+        }
+
+        self.base.queue.submit(std::iter::once(encoder.finish()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn headless_render_to_image() {
+        // every face of the skybox is the same color so every pixel of the frame is known. with a
+        // linear tone mapping at an exposure of 1 and nothing else in the scene it comes out unchanged
+        const SKY_COLOR: [u8; 3] = [200, 120, 40];
+        let face_image_path = std::env::temp_dir().join("headless_render_to_image_sky.png");
+        image::RgbaImage::from_pixel(
+            4,
+            4,
+            image::Rgba([SKY_COLOR[0], SKY_COLOR[1], SKY_COLOR[2], 255]),
+        )
+        .save(&face_image_path)
+        .unwrap();
+        let face_image_path = face_image_path.to_str().unwrap();
+
+        let size = winit::dpi::PhysicalSize::new(64, 48);
+        let base = pollster::block_on(BaseRendererState::new_headless(size))
+            .expect("headless_render_to_image needs a GPU adapter, even a software one");
+        let mut logger = Logger::new();
+        let mut renderer_state = pollster::block_on(RendererState::new(
+            RenderBuffers {
+                binded_pbr_meshes: vec![],
+                binded_unlit_meshes: vec![],
+                binded_wireframe_meshes: vec![],
+                textures: vec![],
+            },
+            base,
+            SkyboxBackground::Cube {
+                face_image_paths: [face_image_path; 6],
+            },
+            None,
+            None,
+            RendererSettings {
+                tone_mapping_exposure: 1.0,
+                tone_mapping_operator: ToneMappingOperator::LinearClamp,
+                enable_bloom: false,
+                enable_auto_exposure: false,
+                ..RendererSettings::default()
+            },
+            &mut logger,
+        ))
+        .unwrap();
+
+        let scene = Scene::new(
+            vec![GameNodeDesc::default()],
+            vec![],
+            vec![],
+            HashMap::new(),
+        );
+        let camera_node_id = scene.nodes().next().unwrap().id();
+        let ui = UiDrawList::new();
+        let inputs = RenderInputs {
+            scene: &scene,
+            camera_node_id,
+            point_lights: &[],
+            directional_lights: &[],
+            ui: &ui,
+        };
+        renderer_state.update(inputs, &mut logger);
+        let image = renderer_state.render_to_image(inputs).unwrap();
+
+        assert_eq!(image.dimensions(), (size.width, size.height));
+        // the skybox covers the whole frame. the hdr targets are 16 bit floats so allow some rounding
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let matches_sky = pixel.0[..3]
+                .iter()
+                .zip(SKY_COLOR)
+                .all(|(channel, expected)| (*channel as i32 - expected as i32).abs() <= 2);
+            assert!(
+                matches_sky && pixel.0[3] == 255,
+                "pixel ({}, {}) is {:?}, expected the sky color {:?}",
+                x,
+                y,
+                pixel.0,
+                SKY_COLOR
+            );
+        }
+    }
+    #[test]
+    fn throttled_cascade_is_rerendered_when_its_split_changes() {
+        let view =
+            build_cubemap_face_camera_views(Vector3::new(0.0, 0.0, 0.0), 0.1, 10.0, false)[0];
+        let now = Instant::now();
+        let mut layer = ShadowMapLayerState::default();
+        assert!(layer.schedule_update(view, Some((0.0, 5.0)), &[], 10.0, now));
+
+        // throttled and nothing changed
+        assert!(!layer.schedule_update(view, Some((0.0, 5.0)), &[], 10.0, now));
+        // still throttled, but the shader now expects the cascade to cover a different range
+        assert!(layer.schedule_update(view, Some((0.0, 8.0)), &[], 10.0, now));
+        assert_eq!(layer.rendered_split_range, Some((0.0, 8.0)));
+    }
 }
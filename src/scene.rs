@@ -1,4 +1,6 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::ops::Range;
 
 use cgmath::{Matrix4, Vector3};
 
@@ -11,8 +13,14 @@ pub struct Scene {
     pub animations: Vec<Animation>,
     // node index -> parent node index
     parent_index_map: HashMap<usize, usize>,
+    // parent node index -> child node indices, the inverse of parent_index_map
+    children_index_map: HashMap<usize, Vec<usize>>,
     // skeleton skin node index -> parent_index_map
     skeleton_parent_index_maps: HashMap<usize, HashMap<usize, usize>>,
+    // node index -> global transform, filled in lazily by get_global_transform_for_node.
+    // None means the node or one of its ancestors changed since it was computed, and if a node
+    // is None then so are all of its descendants
+    global_transform_cache: RefCell<Vec<Option<crate::transform::Transform>>>,
 }
 
 #[derive(Debug, Clone)]
@@ -20,6 +28,7 @@ pub struct GameNodeDesc {
     pub transform: crate::transform::Transform,
     pub skin_index: Option<usize>,
     pub mesh: Option<GameNodeMesh>,
+    pub morph_target_weights: Vec<f32>,
 }
 
 #[derive(Debug, Clone)]
@@ -27,6 +36,8 @@ pub struct GameNode {
     pub transform: crate::transform::Transform,
     pub skin_index: Option<usize>,
     pub mesh: Option<GameNodeMesh>,
+    // one weight per morph target of the node's mesh, missing weights are treated as 0
+    pub morph_target_weights: Vec<f32>,
     id: GameNodeId,
 }
 
@@ -38,6 +49,9 @@ pub struct GameNodeMesh {
     pub mesh_type: GameNodeMeshType,
     pub mesh_indices: Vec<usize>,
     pub wireframe: bool,
+    // only pbr meshes are rendered into the shadow maps, unlit and wireframe meshes never cast shadows
+    pub cast_shadows: bool,
+    pub receive_shadows: bool,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -124,18 +138,46 @@ impl Scene {
             skins,
             animations,
             parent_index_map,
+            children_index_map: HashMap::new(),
             skeleton_parent_index_maps: HashMap::new(),
+            global_transform_cache: RefCell::new(Vec::new()),
         };
 
         nodes_desc.iter().for_each(|node_desc| {
             scene.add_node(node_desc.clone());
         });
 
+        scene.rebuild_children_index_map();
         scene.rebuild_skeleton_parent_index_maps();
 
         scene
     }
 
+    fn rebuild_children_index_map(&mut self) {
+        self.children_index_map = HashMap::new();
+        for (child_index, parent_index) in &self.parent_index_map {
+            self.children_index_map
+                .entry(*parent_index)
+                .or_default()
+                .push(*child_index);
+        }
+    }
+
+    // clears the cached global transforms of the node and all of its descendants
+    fn invalidate_global_transforms(&mut self, node_index: usize) {
+        let global_transform_cache = self.global_transform_cache.get_mut();
+        let mut node_indices = vec![node_index];
+        while let Some(node_index) = node_indices.pop() {
+            // already cleared, and so are its descendants
+            if global_transform_cache[node_index].take().is_none() {
+                continue;
+            }
+            if let Some(child_indices) = self.children_index_map.get(&node_index) {
+                node_indices.extend(child_indices);
+            }
+        }
+    }
+
     fn rebuild_skeleton_parent_index_maps(&mut self) {
         self.skeleton_parent_index_maps = HashMap::new();
         let skinned_nodes = self
@@ -251,10 +293,77 @@ impl Scene {
             }
         }
 
+        self.global_transform_cache
+            .get_mut()
+            .extend(other_scene.nodes.iter().map(|_| None));
         self.nodes.append(&mut other_scene.nodes);
         self.skins.append(&mut other_scene.skins);
         self.animations.append(&mut other_scene.animations);
+        self.rebuild_children_index_map();
+        self.rebuild_skeleton_parent_index_maps();
+    }
+
+    // copies the nodes with the parent links between them, along with the skins and animations in the
+    // given ranges, which are remapped to the copies. the copies share the meshes of the originals.
+    // the skins and animations are appended to the end, the returned ids are in the same order as node_ids
+    pub fn duplicate_nodes(
+        &mut self,
+        node_ids: &[GameNodeId],
+        skin_indices: Range<usize>,
+        animation_indices: Range<usize>,
+    ) -> Vec<GameNodeId> {
+        let skin_index_offset = self.skins.len();
+        let mut copy_ids: HashMap<GameNodeId, GameNodeId> = HashMap::new();
+        for node_id in node_ids {
+            let node = match self.get_node(*node_id) {
+                Some(node) => node,
+                None => continue,
+            };
+            let node_desc = GameNodeDesc {
+                transform: node.transform,
+                skin_index: node.skin_index.map(|skin_index| {
+                    if skin_indices.contains(&skin_index) {
+                        skin_index - skin_indices.start + skin_index_offset
+                    } else {
+                        skin_index
+                    }
+                }),
+                mesh: node.mesh.clone(),
+                morph_target_weights: node.morph_target_weights.clone(),
+            };
+            let copy_id = self.add_node(node_desc).id();
+            copy_ids.insert(*node_id, copy_id);
+        }
+        for (node_id, copy_id) in &copy_ids {
+            if let Some(parent_id) = self.get_node_parent(*node_id) {
+                let GameNodeId(copy_index, _) = *copy_id;
+                let GameNodeId(parent_index, _) = copy_ids.get(&parent_id).unwrap_or(&parent_id);
+                self.parent_index_map.insert(copy_index, *parent_index);
+            }
+        }
+        let copy_id = |node_id: &GameNodeId| copy_ids.get(node_id).copied().unwrap_or(*node_id);
+        let skins: Vec<_> = self.skins[skin_indices]
+            .iter()
+            .map(|skin| Skin {
+                bone_node_ids: skin.bone_node_ids.iter().map(copy_id).collect(),
+                ..skin.clone()
+            })
+            .collect();
+        let animations: Vec<_> = self.animations[animation_indices]
+            .iter()
+            .map(|animation| {
+                let mut animation = animation.clone();
+                for channel in &mut animation.channels {
+                    channel.node_id = copy_id(&channel.node_id);
+                }
+                animation
+            })
+            .collect();
+        self.skins.extend(skins);
+        self.animations.extend(animations);
+        self.rebuild_children_index_map();
         self.rebuild_skeleton_parent_index_maps();
+        node_ids.iter().map(copy_id).collect()
     }
 
     pub fn get_skeleton_skin_node_id(&self, node_id: GameNodeId) -> Option<GameNodeId> {
@@ -270,6 +379,8 @@ impl Scene {
             })
     }
 
+    // the global transforms are cached now, this is only used to check them
+    #[cfg(test)]
     pub fn get_node_ancestry_list(&self, node_id: GameNodeId) -> Vec<GameNodeId> {
         let GameNodeId(node_index, _) = node_id;
         get_node_ancestry_list(node_index, &self.parent_index_map)
@@ -306,13 +417,47 @@ impl Scene {
         &self,
         node_id: GameNodeId,
     ) -> crate::transform::Transform {
-        let node_ancestry_list = self.get_node_ancestry_list(node_id);
-        node_ancestry_list
+        let GameNodeId(node_index, _) = node_id;
+        self.get_global_transform_for_node_index(node_index)
+    }
+
+    // every node with its global transform, see get_global_transform_for_node
+    pub fn nodes_with_global_transforms(&self) -> Vec<(&GameNode, crate::transform::Transform)> {
+        self.nodes
             .iter()
-            .rev()
-            .fold(crate::transform::Transform::new(), |acc, node_id| {
-                acc * self.get_node(*node_id).unwrap().transform
+            .enumerate()
+            .filter_map(|(node_index, (node, _))| {
+                node.as_ref()
+                    .map(|node| (node, self.get_global_transform_for_node_index(node_index)))
             })
+            .collect()
+    }
+
+    // walks up to the closest ancestor that's still cached and recomputes the transforms
+    // from there back down to the node, caching each of them along the way
+    fn get_global_transform_for_node_index(
+        &self,
+        node_index: usize,
+    ) -> crate::transform::Transform {
+        let mut global_transform_cache = self.global_transform_cache.borrow_mut();
+        let mut uncached_ancestry_list = Vec::new();
+        let mut ancestor_transform = crate::transform::Transform::new();
+        let mut current_index = Some(node_index);
+        while let Some(index) = current_index {
+            if let Some(global_transform) = global_transform_cache[index] {
+                ancestor_transform = global_transform;
+                break;
+            }
+            uncached_ancestry_list.push(index);
+            current_index = self.parent_index_map.get(&index).copied();
+        }
+        for index in uncached_ancestry_list.into_iter().rev() {
+            if let Some(node) = &self.nodes[index].0 {
+                ancestor_transform = ancestor_transform * node.transform;
+            }
+            global_transform_cache[index] = Some(ancestor_transform);
+        }
+        ancestor_transform
     }
 
     pub fn add_node(&mut self, node: GameNodeDesc) -> &GameNode {
@@ -320,6 +465,7 @@ impl Scene {
             transform,
             skin_index,
             mesh,
+            morph_target_weights,
         } = node;
         let empty_node = self
             .nodes
@@ -339,9 +485,11 @@ impl Scene {
                     transform,
                     skin_index,
                     mesh,
+                    morph_target_weights,
                     id: GameNodeId(empty_node_index, new_gen),
                 };
                 self.nodes[empty_node_index] = (Some(new_node), new_gen);
+                self.invalidate_global_transforms(empty_node_index);
                 self.nodes[empty_node_index].0.as_ref().unwrap()
             }
             None => {
@@ -349,9 +497,11 @@ impl Scene {
                     transform,
                     skin_index,
                     mesh,
+                    morph_target_weights,
                     id: GameNodeId(self.nodes.len(), 0),
                 };
                 self.nodes.push((Some(new_node), 0));
+                self.global_transform_cache.get_mut().push(None);
                 self.nodes[self.nodes.len() - 1].0.as_ref().unwrap()
             }
         }
@@ -367,8 +517,13 @@ impl Scene {
         }
     }
 
+    // the caller might change the transform, so the cached global transforms of the node
+    // and its descendants are thrown away
     pub fn get_node_mut(&mut self, node_id: GameNodeId) -> Option<&mut GameNode> {
         let GameNodeId(node_index, node_gen) = node_id;
+        if self.nodes[node_index].1 == node_gen {
+            self.invalidate_global_transforms(node_index);
+        }
         let (actual_node, actual_node_gen) = &mut self.nodes[node_index];
         if *actual_node_gen == node_gen {
             actual_node.as_mut()
@@ -382,6 +537,7 @@ impl Scene {
     }
 
     pub fn _get_node_mut_by_index(&mut self, node_index: usize) -> Option<&mut GameNode> {
+        self.invalidate_global_transforms(node_index);
         self.nodes[node_index].0.as_mut()
     }
 
@@ -389,6 +545,7 @@ impl Scene {
         // make sure it still exists
         if let Some(node) = self.get_node(node_id) {
             let GameNodeId(node_index, _) = node.id;
+            self.invalidate_global_transforms(node_index);
             self.nodes[node_index].0.take();
             self.parent_index_map.remove(&node_index);
             let child_entry_option =
@@ -400,11 +557,12 @@ impl Scene {
             if let Some((child_index, _)) = child_entry_option {
                 self.parent_index_map.remove(&child_index);
             }
+            self.rebuild_children_index_map();
             self.rebuild_skeleton_parent_index_maps();
         }
     }
 
-    pub fn _get_node_parent(&self, node_id: GameNodeId) -> Option<GameNodeId> {
+    pub fn get_node_parent(&self, node_id: GameNodeId) -> Option<GameNodeId> {
         let GameNodeId(node_index, _) = node_id;
         self.parent_index_map
             .get(&node_index)
@@ -417,7 +575,18 @@ impl Scene {
         {
             let GameNodeId(node_index, _) = node.id();
             let GameNodeId(parent_node_index, _) = parent_node.id();
-            self.parent_index_map.insert(node_index, parent_node_index);
+            if let Some(old_parent_index) =
+                self.parent_index_map.insert(node_index, parent_node_index)
+            {
+                if let Some(siblings) = self.children_index_map.get_mut(&old_parent_index) {
+                    siblings.retain(|child_index| *child_index != node_index);
+                }
+            }
+            self.children_index_map
+                .entry(parent_node_index)
+                .or_default()
+                .push(node_index);
+            self.invalidate_global_transforms(node_index);
         }
     }
 
@@ -469,6 +638,7 @@ impl Default for GameNodeDesc {
             transform: crate::transform::Transform::new(),
             skin_index: None,
             mesh: None,
+            morph_target_weights: vec![],
         }
     }
 }
@@ -478,6 +648,7 @@ pub struct GameNodeDescBuilder {
     transform: crate::transform::Transform,
     skin_index: Option<usize>,
     mesh: Option<GameNodeMesh>,
+    morph_target_weights: Vec<f32>,
 }
 
 impl GameNodeDescBuilder {
@@ -486,11 +657,13 @@ impl GameNodeDescBuilder {
             transform,
             skin_index,
             mesh,
+            morph_target_weights,
         } = GameNodeDesc::default();
         Self {
             transform,
             skin_index,
             mesh,
+            morph_target_weights,
         }
     }
 
@@ -515,6 +688,7 @@ impl GameNodeDescBuilder {
             transform: self.transform,
             skin_index: self.skin_index,
             mesh: self.mesh,
+            morph_target_weights: self.morph_target_weights,
         }
     }
 }
@@ -527,12 +701,15 @@ impl GameNodeMesh {
                 material_override: None,
             },
             wireframe: false,
+            cast_shadows: true,
+            receive_shadows: true,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use cgmath::{Deg, Quaternion, Rotation3};
 
     use super::*;
 
@@ -569,6 +746,87 @@ mod tests {
         assert_node_exists(&scene, node_3_id);
     }
 
+    #[test]
+    fn cached_global_transforms_match_folded_ancestry() {
+        // a root with two chains of three under it
+        let nodes: Vec<_> = (0..7)
+            .map(|node_index| {
+                GameNodeDescBuilder::new()
+                    .transform(
+                        TransformBuilder::new()
+                            .position(Vector3::new(node_index as f32, 1.0, -0.5))
+                            .rotation(Quaternion::from_angle_y(Deg(15.0 * node_index as f32)))
+                            .scale(Vector3::new(1.0, 0.5 + node_index as f32 * 0.25, 1.0))
+                            .build(),
+                    )
+                    .build()
+            })
+            .collect();
+        let parent_index_map: HashMap<_, _> =
+            [(1, 0), (2, 1), (3, 2), (4, 0), (5, 4), (6, 5)].into();
+        let mut scene = Scene::new(nodes, vec![], vec![], parent_index_map);
+        let node_ids: Vec<_> = scene.nodes().map(|node| node.id()).collect();
+        assert_global_transforms_match(&scene);
+
+        // like the writes in step_animations
+        scene
+            .get_node_mut(node_ids[1])
+            .unwrap()
+            .transform
+            .set_rotation(Quaternion::from_angle_x(Deg(40.0)));
+        scene
+            .get_node_mut(node_ids[5])
+            .unwrap()
+            .transform
+            .set_position(Vector3::new(0.0, -3.0, 2.0));
+        assert_global_transforms_match(&scene);
+
+        // move the second chain under the end of the first
+        scene.set_node_parent(node_ids[4], node_ids[3]);
+        assert_global_transforms_match(&scene);
+        scene
+            .get_node_mut(node_ids[2])
+            .unwrap()
+            .transform
+            .set_scale(Vector3::new(2.0, 2.0, 2.0));
+        assert_global_transforms_match(&scene);
+
+        scene.remove_node(node_ids[6]);
+        scene.add_node(
+            GameNodeDescBuilder::new()
+                .transform(
+                    TransformBuilder::new()
+                        .position(Vector3::new(5.0, 0.0, 0.0))
+                        .build(),
+                )
+                .build(),
+        );
+        assert_global_transforms_match(&scene);
+    }
+
+    // what get_global_transform_for_node did before the transforms were cached
+    fn assert_global_transforms_match(scene: &Scene) {
+        for node in scene.nodes() {
+            let folded_transform = scene
+                .get_node_ancestry_list(node.id())
+                .iter()
+                .rev()
+                .fold(crate::transform::Transform::new(), |acc, node_id| {
+                    acc * scene.get_node(*node_id).unwrap().transform
+                });
+            assert_eq!(
+                scene.get_global_transform_for_node(node.id()).matrix(),
+                folded_transform.matrix()
+            );
+        }
+        for (node, global_transform) in scene.nodes_with_global_transforms() {
+            assert_eq!(
+                global_transform.matrix(),
+                scene.get_global_transform_for_node(node.id()).matrix()
+            );
+        }
+    }
+
     fn assert_node_exists(scene: &Scene, node_id: GameNodeId) {
         assert_eq!(scene.get_node(node_id).map(|node| node.id), Some(node_id));
     }
@@ -0,0 +1,144 @@
+// builds a histogram of the log luminance of the shaded pixels, then averages it into the exposure
+// that's used by tone_mapping_fs_main. based on https://bruop.github.io/exposure/
+
+struct AutoExposureConfig {
+    min_log2_luminance: f32,
+    log2_luminance_range: f32,
+    min_ev: f32,
+    max_ev: f32,
+    // per second, how quickly the exposure follows the scene when it gets brighter or darker
+    bright_adaptation_speed: f32,
+    dark_adaptation_speed: f32,
+    frame_time_seconds: f32,
+    exposure_compensation: f32,
+}
+
+struct AutoExposureState {
+    adapted_ev: f32,
+    // 0 until the first frame was measured, the first measurement is used as is
+    is_initialized: u32,
+}
+
+// only the exposure is written, the rest of the tone mapping config is left alone
+struct ToneMappingConfig {
+    exposure: f32,
+}
+
+struct Histogram {
+    bins: array<atomic<u32>, 256>,
+}
+
+@group(0) @binding(0)
+var shading_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var<storage, read_write> histogram: Histogram;
+@group(0) @binding(2)
+var<storage, read_write> state: AutoExposureState;
+@group(0) @binding(3)
+var<uniform> config: AutoExposureConfig;
+@group(0) @binding(4)
+var<storage, read_write> tone_mapping_config: ToneMappingConfig;
+
+let BIN_COUNT: u32 = 256u;
+// ev100 = log2(average luminance * S / K) with the usual S = 100 and K = 12.5
+let EV100_OFFSET: f32 = 3.0;
+// the average luminance is mapped to middle gray
+let KEY_VALUE: f32 = 0.18;
+
+var<workgroup> shared_bins: array<atomic<u32>, 256>;
+var<workgroup> shared_weighted_counts: array<f32, 256>;
+var<workgroup> shared_counts: array<f32, 256>;
+// the pixels in bin 0 aren't averaged but they still count as measured
+var<workgroup> dark_pixel_count: f32;
+
+// bin 0 is for the pixels that are too dark to be measured, the rest cover the luminance range evenly in log space
+fn luminance_to_bin(color: vec3<f32>) -> u32 {
+    let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    if (luminance < exp2(config.min_log2_luminance)) {
+        return 0u;
+    }
+    let t = clamp((log2(luminance) - config.min_log2_luminance) / config.log2_luminance_range, 0.0, 1.0);
+    return u32(t * f32(BIN_COUNT - 2u)) + 1u;
+}
+
+@compute @workgroup_size(16, 16)
+fn build_histogram(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    atomicStore(&shared_bins[local_index], 0u);
+    workgroupBarrier();
+
+    let dimensions = vec2<u32>(textureDimensions(shading_texture));
+    if (global_id.x < dimensions.x && global_id.y < dimensions.y) {
+        let shaded = textureLoad(shading_texture, vec2<i32>(global_id.xy), 0);
+        // the alpha channel is the coverage of the shaded geometry, the skybox isn't metered
+        if (shaded.a > 0.0) {
+            atomicAdd(&shared_bins[luminance_to_bin(shaded.rgb)], 1u);
+        }
+    }
+    workgroupBarrier();
+
+    atomicAdd(&histogram.bins[local_index], atomicLoad(&shared_bins[local_index]));
+}
+
+fn ev_to_exposure(ev: f32) -> f32 {
+    let average_luminance = exp2(ev - EV100_OFFSET);
+    return config.exposure_compensation * KEY_VALUE / average_luminance;
+}
+
+@compute @workgroup_size(256)
+fn average_histogram(
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    let count = f32(atomicLoad(&histogram.bins[local_index]));
+    // clear it for the next frame
+    atomicStore(&histogram.bins[local_index], 0u);
+    if (local_index == 0u) {
+        shared_weighted_counts[local_index] = 0.0;
+        shared_counts[local_index] = 0.0;
+        dark_pixel_count = count;
+    } else {
+        shared_weighted_counts[local_index] = count * f32(local_index - 1u);
+        shared_counts[local_index] = count;
+    }
+    workgroupBarrier();
+
+    for (var stride = BIN_COUNT / 2u; stride > 0u; stride = stride / 2u) {
+        if (local_index < stride) {
+            shared_weighted_counts[local_index] = shared_weighted_counts[local_index] + shared_weighted_counts[local_index + stride];
+            shared_counts[local_index] = shared_counts[local_index] + shared_counts[local_index + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if (local_index != 0u) {
+        return;
+    }
+
+    // nothing was measured, like when only the sky is in view, so the exposure stays where it was
+    // instead of adapting towards min_ev
+    if (shared_counts[0] + dark_pixel_count <= 0.0) {
+        if (state.is_initialized != 0u) {
+            tone_mapping_config.exposure = ev_to_exposure(state.adapted_ev);
+        }
+        return;
+    }
+    var target_log2_luminance = config.min_log2_luminance;
+    if (shared_counts[0] > 0.0) {
+        let average_bin = shared_weighted_counts[0] / shared_counts[0];
+        target_log2_luminance = config.min_log2_luminance + average_bin / f32(BIN_COUNT - 2u) * config.log2_luminance_range;
+    }
+    let target_ev = clamp(target_log2_luminance + EV100_OFFSET, config.min_ev, config.max_ev);
+
+    var adapted_ev = target_ev;
+    if (state.is_initialized != 0u) {
+        let previous_ev = state.adapted_ev;
+        let speed = select(config.dark_adaptation_speed, config.bright_adaptation_speed, target_ev > previous_ev);
+        adapted_ev = previous_ev + (target_ev - previous_ev) * (1.0 - exp(-config.frame_time_seconds * speed));
+    }
+    state.adapted_ev = adapted_ev;
+    state.is_initialized = 1u;
+
+    tone_mapping_config.exposure = ev_to_exposure(adapted_ev);
+}
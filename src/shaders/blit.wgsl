@@ -1,17 +1,23 @@
-struct BloomConfigUniform {
-    direction: f32, // 0,0 or 1.0
-    threshold: f32,
-    ramp_size: f32,
-}
-@group(1) @binding(0)
-var<uniform> bloom_config: BloomConfigUniform;
-
+// see ToneMappingConfigUniform in renderer.rs
 struct ToneMappingConfigUniform {
     exposure: f32,
+    // see ToneMappingOperator in tone_mapping.rs
+    tone_mapping_operator: u32,
+    enable_color_grading: u32,
+    color_grading_lut_size: f32,
+    color_grading_lut_domain_min: vec4<f32>,
+    color_grading_lut_domain_max: vec4<f32>,
+    // 0 when bloom is off
+    bloom_intensity: f32,
 }
 @group(1) @binding(0)
 var<uniform> tone_mapping_config: ToneMappingConfigUniform;
 
+@group(2) @binding(0)
+var color_grading_lut_texture: texture_3d<f32>;
+@group(2) @binding(1)
+var color_grading_lut_sampler: sampler;
+
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
     @location(2) tex_coords: vec2<f32>,
@@ -68,71 +74,125 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     return textureSample(texture_1, sampler_1, in.tex_coords);
 }
 
-@fragment
-fn tone_mapping_fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let exposure = tone_mapping_config.exposure;
-    let shaded_color = textureSample(texture_1, sampler_1, in.tex_coords).rgb;
-    let bloom_color = textureSample(texture_2, sampler_2, in.tex_coords).rgb;
-    let final_color_hdr = shaded_color + bloom_color;
-    // return final_color_hdr / (final_color_hdr + 1.0);
-    return vec4<f32>(1.0 - exp(-final_color_hdr * exposure), 1.0);
-    // return vec4<f32>(final_color_hdr, 1.0);
+// https://github.com/TheRealMJP/BakingLab/blob/master/BakingLab/ACES.hlsl
+fn aces_fitted(color: vec3<f32>) -> vec3<f32> {
+    let input_matrix = mat3x3<f32>(
+        vec3<f32>(0.59719, 0.07600, 0.02840),
+        vec3<f32>(0.35458, 0.90834, 0.13383),
+        vec3<f32>(0.04823, 0.01566, 0.83777),
+    );
+    let output_matrix = mat3x3<f32>(
+        vec3<f32>(1.60475, -0.10208, -0.00327),
+        vec3<f32>(-0.53108, 1.10813, -0.07276),
+        vec3<f32>(-0.07367, -0.00605, 1.07602),
+    );
+    let v = input_matrix * color;
+    let rrt_and_odt_fit = (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.4329510) + 0.238081);
+    return clamp(output_matrix * rrt_and_odt_fit, vec3<f32>(0.0), vec3<f32>(1.0));
 }
 
-@fragment
-fn bloom_threshold_fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let threshold = bloom_config.threshold;
-    let ramp_size = bloom_config.ramp_size;
-    let hdr_color = textureSample(texture_1, sampler_1, in.tex_coords);
-    let brightness = dot(hdr_color.rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+fn hable_partial(x: vec3<f32>) -> vec3<f32> {
+    let a = 0.15;
+    let b = 0.50;
+    let c = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+    return ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f;
+}
 
-    let ramp_start = threshold - ramp_size;
-    let t = clamp((brightness - ramp_start) / ramp_size, 0.0, 1.0);
+// http://filmicworlds.com/blog/filmic-tonemapping-operators/
+fn hable(color: vec3<f32>) -> vec3<f32> {
+    let exposure_bias = 2.0;
+    let white_point = vec3<f32>(11.2);
+    return hable_partial(color * exposure_bias) / hable_partial(white_point);
+}
 
-    return vec4<f32>(t * hdr_color.rgb, 1.0);
+// https://iolite-engine.com/blog_posts/minimal_agx_implementation
+fn agx(color: vec3<f32>) -> vec3<f32> {
+    let inset_matrix = mat3x3<f32>(
+        vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let outset_matrix = mat3x3<f32>(
+        vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    let log_color = clamp(log2(max(inset_matrix * color, vec3<f32>(1e-10))), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    let x = (log_color - min_ev) / (max_ev - min_ev);
+    // 6th order polynomial fit of the default contrast curve
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let curve = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+    // the curve outputs display encoded colors, decode them back to linear
+    return pow(max(outset_matrix * curve, vec3<f32>(0.0)), vec3<f32>(2.2));
 }
 
-@fragment
-fn bloom_blur_fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    var gaussian_blur_weights: array<f32, 5>;
-    gaussian_blur_weights[0] = 0.227027;
-    gaussian_blur_weights[1] = 0.1945946;
-    gaussian_blur_weights[2] = 0.1216216;
-    gaussian_blur_weights[3] = 0.054054;
-    gaussian_blur_weights[4] = 0.016216;
-
-    let tex_dimensions = textureDimensions(texture_1);
-    let tex_dimension_f32 = vec2<f32>(f32(tex_dimensions.x), f32(tex_dimensions.y));
-    let tex_offset = 1.0 / tex_dimension_f32;
-    var result = textureSample(texture_1, sampler_1, in.tex_coords).rgb * gaussian_blur_weights[0];
-    if (bloom_config.direction == 0.0) {
-        for (var i = 1; i < 5; i = i + 1) {
-            result = result + textureSample(
-                texture_1,
-                sampler_1,
-                in.tex_coords + vec2<f32>(tex_offset.x * f32(i), 0.0)
-            ).rgb * gaussian_blur_weights[i];
-            result = result + textureSample(
-                texture_1,
-                sampler_1,
-                in.tex_coords - vec2<f32>(tex_offset.x * f32(i), 0.0)
-            ).rgb * gaussian_blur_weights[i];
+fn tone_map(color: vec3<f32>, tone_mapping_operator: u32) -> vec3<f32> {
+    switch (tone_mapping_operator) {
+        case 1u: {
+            return aces_fitted(color);
         }
-    } else {
-        for (var i = 1; i < 5; i = i + 1) {
-            result = result + textureSample(
-                texture_1,
-                sampler_1,
-                in.tex_coords + vec2<f32>(0.0, tex_offset.y * f32(i))
-            ).rgb * gaussian_blur_weights[i];
-            result = result + textureSample(
-                texture_1,
-                sampler_1,
-                in.tex_coords - vec2<f32>(0.0, tex_offset.y * f32(i))
-            ).rgb * gaussian_blur_weights[i];
+        case 2u: {
+            return color / (color + 1.0);
         }
+        case 3u: {
+            return hable(color);
+        }
+        case 4u: {
+            return agx(color);
+        }
+        case 5u: {
+            return clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+        default: {
+            return 1.0 - exp(-color);
+        }
+    }
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let lower = color * 12.92;
+    let higher = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(higher, lower, color <= vec3<f32>(0.0031308));
+}
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    let lower = color / 12.92;
+    let higher = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(higher, lower, color <= vec3<f32>(0.04045));
+}
+
+// the lut maps display encoded colors, so the lookup happens in srgb space
+fn color_grade(color: vec3<f32>) -> vec3<f32> {
+    let lut_size = tone_mapping_config.color_grading_lut_size;
+    let domain_min = tone_mapping_config.color_grading_lut_domain_min.xyz;
+    let domain_max = tone_mapping_config.color_grading_lut_domain_max.xyz;
+    let normalized = clamp((linear_to_srgb(color) - domain_min) / (domain_max - domain_min), vec3<f32>(0.0), vec3<f32>(1.0));
+    // sample between the centers of the first and last texels
+    let lut_coords = normalized * ((lut_size - 1.0) / lut_size) + 0.5 / lut_size;
+    let graded = textureSampleLevel(color_grading_lut_texture, color_grading_lut_sampler, lut_coords, 0.0).rgb;
+    return srgb_to_linear(clamp(graded, vec3<f32>(0.0), vec3<f32>(1.0)));
+}
+
+@fragment
+fn tone_mapping_fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let exposure = tone_mapping_config.exposure;
+    let shaded = textureSample(texture_1, sampler_1, in.tex_coords);
+    let bloom_color = textureSample(texture_2, sampler_2, in.tex_coords).rgb;
+    // energy conserving, the bloom replaces part of the image instead of being added on top
+    let final_color_hdr = mix(shaded.rgb, bloom_color, tone_mapping_config.bloom_intensity);
+    var final_color = tone_map(final_color_hdr * exposure, tone_mapping_config.tone_mapping_operator);
+    if (tone_mapping_config.enable_color_grading != 0u) {
+        final_color = color_grade(final_color);
     }
-    return vec4<f32>(result, 1.0);
+    // the skybox was already blended under the shaded geometry in hdr
+    return vec4<f32>(final_color, 1.0);
 }
 
 // BRDF LUT:
@@ -0,0 +1,107 @@
+// progressive downsample / upsample bloom, based on the call of duty: advanced warfare presentation:
+// https://www.iryoku.com/next-generation-post-processing-in-call-of-duty-advanced-warfare
+// and https://learnopengl.com/Guest-Articles/2022/Phys.-Based-Bloom
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+// one large triangle over the clip space, see vs_main in blit.wgsl
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let tc = vec2<f32>(
+        f32(i32(vertex_index) / 2) * 2.0,
+        f32(i32(vertex_index) & 1) * 2.0
+    );
+    out.position = vec4<f32>(
+        tc.x * 2.0 - 1.0,
+        1.0 - tc.y * 2.0,
+        0.0,
+        1.0
+    );
+    out.tex_coords = tc;
+    return out;
+}
+
+@group(0) @binding(0)
+var source_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+
+fn sample_source(tex_coords: vec2<f32>, texel_offset: vec2<f32>) -> vec3<f32> {
+    let texel_size = 1.0 / vec2<f32>(textureDimensions(source_texture));
+    return textureSample(source_texture, source_sampler, tex_coords + texel_offset * texel_size).rgb;
+}
+
+// weights a group of samples by their inverse luminance so a single very bright pixel can't flicker
+// across the whole bloom, only used when reading from the full resolution shading texture
+fn karis_average(a: vec3<f32>, b: vec3<f32>, c: vec3<f32>, d: vec3<f32>) -> vec3<f32> {
+    let luminance_weights = vec3<f32>(0.2126, 0.7152, 0.0722);
+    let weight_a = 1.0 / (1.0 + dot(a, luminance_weights));
+    let weight_b = 1.0 / (1.0 + dot(b, luminance_weights));
+    let weight_c = 1.0 / (1.0 + dot(c, luminance_weights));
+    let weight_d = 1.0 / (1.0 + dot(d, luminance_weights));
+    return (a * weight_a + b * weight_b + c * weight_c + d * weight_d) / (weight_a + weight_b + weight_c + weight_d);
+}
+
+// 13 bilinear taps, laid out like this:
+// a . b . c
+// . j . k .
+// d . e . f
+// . l . m .
+// g . h . i
+fn downsample(tex_coords: vec2<f32>, use_karis_average: bool) -> vec3<f32> {
+    let a = sample_source(tex_coords, vec2<f32>(-2.0, -2.0));
+    let b = sample_source(tex_coords, vec2<f32>(0.0, -2.0));
+    let c = sample_source(tex_coords, vec2<f32>(2.0, -2.0));
+    let d = sample_source(tex_coords, vec2<f32>(-2.0, 0.0));
+    let e = sample_source(tex_coords, vec2<f32>(0.0, 0.0));
+    let f = sample_source(tex_coords, vec2<f32>(2.0, 0.0));
+    let g = sample_source(tex_coords, vec2<f32>(-2.0, 2.0));
+    let h = sample_source(tex_coords, vec2<f32>(0.0, 2.0));
+    let i = sample_source(tex_coords, vec2<f32>(2.0, 2.0));
+    let j = sample_source(tex_coords, vec2<f32>(-1.0, -1.0));
+    let k = sample_source(tex_coords, vec2<f32>(1.0, -1.0));
+    let l = sample_source(tex_coords, vec2<f32>(-1.0, 1.0));
+    let m = sample_source(tex_coords, vec2<f32>(1.0, 1.0));
+
+    // the center box gets half of the weight and the four overlapping corner boxes share the rest
+    if (use_karis_average) {
+        return karis_average(j, k, l, m) * 0.5
+            + karis_average(a, b, d, e) * 0.125
+            + karis_average(b, c, e, f) * 0.125
+            + karis_average(d, e, g, h) * 0.125
+            + karis_average(e, f, h, i) * 0.125;
+    }
+    return (j + k + l + m) * 0.125
+        + (a + c + g + i) * 0.03125
+        + (b + d + f + h) * 0.0625
+        + e * 0.125;
+}
+
+@fragment
+fn downsample_first_fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample(in.tex_coords, true), 1.0);
+}
+
+@fragment
+fn downsample_fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample(in.tex_coords, false), 1.0);
+}
+
+// 3x3 tent filter, blended into the next larger mip by the pipeline's blend constant
+@fragment
+fn upsample_fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let result = sample_source(in.tex_coords, vec2<f32>(-1.0, -1.0))
+        + sample_source(in.tex_coords, vec2<f32>(0.0, -1.0)) * 2.0
+        + sample_source(in.tex_coords, vec2<f32>(1.0, -1.0))
+        + sample_source(in.tex_coords, vec2<f32>(-1.0, 0.0)) * 2.0
+        + sample_source(in.tex_coords, vec2<f32>(0.0, 0.0)) * 4.0
+        + sample_source(in.tex_coords, vec2<f32>(1.0, 0.0)) * 2.0
+        + sample_source(in.tex_coords, vec2<f32>(-1.0, 1.0))
+        + sample_source(in.tex_coords, vec2<f32>(0.0, 1.0)) * 2.0
+        + sample_source(in.tex_coords, vec2<f32>(1.0, 1.0));
+    return vec4<f32>(result / 16.0, 1.0);
+}
@@ -0,0 +1,94 @@
+// frustum culling for the gpu-driven meshes, see gpu_driven.rs. every visible instance is copied into
+// its mesh's range of the culled buffers, which starts at the first_instance of the mesh's indirect
+// draw command, and counted into that command
+
+// same layout as GpuPbrMeshInstance
+struct Instance {
+    model_transform: mat4x4<f32>,
+    base_color_factor: vec4<f32>,
+    emissive_factor: vec4<f32>,
+    mrno: vec4<f32>,
+    alpha_and_shadows: vec4<f32>,
+}
+struct Instances {
+    values: array<Instance>,
+}
+struct InstanceCullingInfo {
+    // world space, w is the radius
+    bounding_sphere: vec4<f32>,
+    // NO_DRAW_COMMAND for the padding between the meshes' instances
+    draw_command_index: u32,
+    cast_shadows: u32,
+}
+struct InstanceCullingInfos {
+    values: array<InstanceCullingInfo>,
+}
+struct ModelTransforms {
+    values: array<mat4x4<f32>>,
+}
+// same layout as GpuDrawIndexedIndirect
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+struct DrawCommands {
+    values: array<DrawIndexedIndirect>,
+}
+// same layout as GpuCullingConfig
+struct CullingConfig {
+    // pointing inwards, see Frustum in culling.rs
+    frustum_planes: array<vec4<f32>, 6>,
+    // the buffers can be longer than this
+    instance_count: u32,
+    shadow_casters_only: u32,
+}
+
+let NO_DRAW_COMMAND = 4294967295u;
+
+@group(0) @binding(0)
+var<uniform> config: CullingConfig;
+@group(0) @binding(1)
+var<storage, read> instances: Instances;
+@group(0) @binding(2)
+var<storage, read> instance_culling_infos: InstanceCullingInfos;
+@group(0) @binding(3)
+var<storage, read> previous_model_transforms: ModelTransforms;
+@group(0) @binding(4)
+var<storage, read_write> culled_instances: Instances;
+@group(0) @binding(5)
+var<storage, read_write> culled_previous_model_transforms: ModelTransforms;
+@group(0) @binding(6)
+var<storage, read_write> draw_commands: DrawCommands;
+
+@compute @workgroup_size(64)
+fn cull_instances(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let instance_index = global_id.x;
+    if (instance_index >= config.instance_count) {
+        return;
+    }
+    let culling_info = instance_culling_infos.values[instance_index];
+    if (culling_info.draw_command_index == NO_DRAW_COMMAND) {
+        return;
+    }
+    if (config.shadow_casters_only != 0u && culling_info.cast_shadows == 0u) {
+        return;
+    }
+    let center = culling_info.bounding_sphere.xyz;
+    let radius = culling_info.bounding_sphere.w;
+    for (var i = 0; i < 6; i = i + 1) {
+        let plane = config.frustum_planes[i];
+        if (dot(plane.xyz, center) + plane.w < -radius) {
+            return;
+        }
+    }
+    // the order of the culled instances within a mesh isn't stable, which is fine for the
+    // opaque and alpha masked meshes since nothing is blended
+    let draw_command_index = culling_info.draw_command_index;
+    let culled_index = draw_commands.values[draw_command_index].first_instance
+        + atomicAdd(&draw_commands.values[draw_command_index].instance_count, 1u);
+    culled_instances.values[culled_index] = instances.values[instance_index];
+    culled_previous_model_transforms.values[culled_index] = previous_model_transforms.values[instance_index];
+}
@@ -0,0 +1,123 @@
+// bins the point lights into the clusters of the camera's view frustum, see light_clusters.rs.
+// the pbr fragment shader then only evaluates the lights of the cluster it falls in
+
+struct CameraUniform {
+    proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    rotation_only_view: mat4x4<f32>,
+    position: vec4<f32>,
+    near_plane_distance: f32,
+    far_plane_distance: f32,
+    // in ndc, already applied to proj
+    jitter: vec2<f32>,
+    previous_view_proj: mat4x4<f32>,
+}
+// same as in textured_mesh.wgsl
+struct LightClusterConfig {
+    // x, y, z, point light count
+    cluster_counts: vec4<u32>,
+    // of the shading texture, in pixels
+    target_size: vec2<f32>,
+    // the first depth slice ends at near_distance, the last one starts at far_distance
+    near_distance: f32,
+    far_distance: f32,
+}
+struct PointLight {
+    position: vec4<f32>,
+    color: vec4<f32>,
+    // size, range, casts shadows, unused
+    shadow_config: vec4<f32>,
+}
+struct PointLights {
+    values: array<PointLight>,
+}
+// must match MAX_LIGHTS_PER_CLUSTER in light_clusters.rs, the lights past it are dropped from the cluster
+let MAX_LIGHTS_PER_CLUSTER = 127u;
+struct LightCluster {
+    light_count: u32,
+    light_indices: array<u32, MAX_LIGHTS_PER_CLUSTER>,
+}
+struct LightClusters {
+    values: array<LightCluster>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(0) @binding(1)
+var<uniform> config: LightClusterConfig;
+@group(0) @binding(2)
+var<storage, read> point_lights: PointLights;
+@group(0) @binding(3)
+var<storage, read_write> light_clusters: LightClusters;
+
+let epsilon: f32 = 0.00001;
+
+// the view depth range of a depth slice. the slices between near_distance and far_distance are
+// spaced exponentially so that the clusters stay roughly as deep as they are wide
+fn depth_slice_range(slice: u32) -> vec2<f32> {
+    if (slice == 0u) {
+        return vec2<f32>(0.0, config.near_distance);
+    }
+    let inner_slice_count = f32(config.cluster_counts.z - 2u);
+    let depth_ratio = config.far_distance / config.near_distance;
+    let start = config.near_distance * pow(depth_ratio, f32(slice - 1u) / inner_slice_count);
+    if (slice == config.cluster_counts.z - 1u) {
+        return vec2<f32>(start, camera.far_plane_distance);
+    }
+    let end = config.near_distance * pow(depth_ratio, f32(slice) / inner_slice_count);
+    return vec2<f32>(start, end);
+}
+
+@compute @workgroup_size(64)
+fn build_clusters(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let cluster_index = global_id.x;
+    let cluster_counts = config.cluster_counts.xyz;
+    if (cluster_index >= cluster_counts.x * cluster_counts.y * cluster_counts.z) {
+        return;
+    }
+    let cluster_x = cluster_index % cluster_counts.x;
+    let cluster_y = (cluster_index / cluster_counts.x) % cluster_counts.y;
+    let cluster_z = cluster_index / (cluster_counts.x * cluster_counts.y);
+
+    // the tiles are counted from the top left corner of the screen, like the fragment coordinates
+    let tile_size = 2.0 / vec2<f32>(f32(cluster_counts.x), f32(cluster_counts.y));
+    let ndc_min = vec2<f32>(
+        f32(cluster_x) * tile_size.x - 1.0,
+        1.0 - f32(cluster_y + 1u) * tile_size.y
+    );
+    let ndc_max = ndc_min + tile_size;
+    // view space x and y per unit of view depth at the tile's edges, the view looks down -z
+    let proj_scale = vec2<f32>(camera.proj[0][0], camera.proj[1][1]);
+    let slope_min = (ndc_min - camera.jitter) / proj_scale;
+    let slope_max = (ndc_max - camera.jitter) / proj_scale;
+    let depth_range = depth_slice_range(cluster_z);
+    let aabb_min = vec3<f32>(
+        min(slope_min * depth_range.x, slope_min * depth_range.y),
+        -depth_range.y
+    );
+    let aabb_max = vec3<f32>(
+        max(slope_max * depth_range.x, slope_max * depth_range.y),
+        -depth_range.x
+    );
+
+    var light_count = 0u;
+    for (var light_index = 0u; light_index < config.cluster_counts.w; light_index = light_index + 1u) {
+        if (light_count >= MAX_LIGHTS_PER_CLUSTER) {
+            break;
+        }
+        let light = point_lights.values[light_index];
+        let light_color_scaled = light.color.xyz * light.color.w;
+        if (light_color_scaled.x < epsilon && light_color_scaled.y < epsilon && light_color_scaled.z < epsilon) {
+            continue;
+        }
+        let range = light.shadow_config.y;
+        let center = (camera.view * vec4<f32>(light.position.xyz, 1.0)).xyz;
+        let to_closest_point = clamp(center, aabb_min, aabb_max) - center;
+        if (dot(to_closest_point, to_closest_point) > range * range) {
+            continue;
+        }
+        light_clusters.values[cluster_index].light_indices[light_count] = light_index;
+        light_count = light_count + 1u;
+    }
+    light_clusters.values[cluster_index].light_count = light_count;
+}
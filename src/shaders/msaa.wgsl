@@ -0,0 +1,38 @@
+// resolves the multisampled depth buffer into the single sampled one that ssao, taa and the skybox read.
+// the color is resolved by the render passes, depth can't be
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+}
+
+// one large triangle over the clip space, see vs_main in blit.wgsl
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let tc = vec2<f32>(
+        f32(i32(vertex_index) / 2) * 2.0,
+        f32(i32(vertex_index) & 1) * 2.0
+    );
+    out.position = vec4<f32>(
+        tc.x * 2.0 - 1.0,
+        1.0 - tc.y * 2.0,
+        0.0,
+        1.0
+    );
+    return out;
+}
+
+@group(0) @binding(0)
+var multisampled_depth_texture: texture_depth_multisampled_2d;
+
+// keeps the farthest sample (the smallest, the depth is reversed) so the skybox still shows through
+// on the edges where the shaded geometry only partially covers the pixel
+@fragment
+fn depth_resolve_fs_main(in: VertexOutput) -> @builtin(frag_depth) f32 {
+    let pixel = vec2<i32>(in.position.xy);
+    var depth = 1.0;
+    for (var i = 0; i < textureNumSamples(multisampled_depth_texture); i = i + 1) {
+        depth = min(depth, textureLoad(multisampled_depth_texture, pixel, i));
+    }
+    return depth;
+}
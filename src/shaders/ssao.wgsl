@@ -0,0 +1,166 @@
+// hemisphere sampled ambient occlusion in view space, based on https://learnopengl.com/Advanced-Lighting/SSAO
+// the normals are reconstructed from the depth buffer so only the depth prepass is needed
+
+struct SsaoConfig {
+    proj: mat4x4<f32>,
+    inverse_proj: mat4x4<f32>,
+    radius: f32,
+    intensity: f32,
+    blur_direction: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+// one large triangle over the clip space, see vs_main in blit.wgsl
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let tc = vec2<f32>(
+        f32(i32(vertex_index) / 2) * 2.0,
+        f32(i32(vertex_index) & 1) * 2.0
+    );
+    out.position = vec4<f32>(
+        tc.x * 2.0 - 1.0,
+        1.0 - tc.y * 2.0,
+        0.0,
+        1.0
+    );
+    out.tex_coords = tc;
+    return out;
+}
+
+@group(0) @binding(0)
+var depth_texture: texture_depth_2d;
+@group(0) @binding(1)
+var source_texture: texture_2d<f32>;
+@group(0) @binding(2)
+var<uniform> config: SsaoConfig;
+
+let SAMPLE_COUNT: i32 = 16;
+let BLUR_RADIUS: i32 = 4;
+// the depth buffer is cleared to 0 (reverse z), so this is the sky
+let SKY_DEPTH: f32 = 0.0;
+let two_pi: f32 = 6.283185307179586;
+let golden_angle: f32 = 2.399963229728653;
+
+fn clamp_pixel(pixel: vec2<i32>) -> vec2<i32> {
+    return clamp(pixel, vec2<i32>(0, 0), textureDimensions(depth_texture) - vec2<i32>(1, 1));
+}
+
+fn load_depth(pixel: vec2<i32>) -> f32 {
+    return textureLoad(depth_texture, clamp_pixel(pixel), 0);
+}
+
+fn view_position_from_depth(pixel: vec2<i32>, depth: f32) -> vec3<f32> {
+    let tex_coords = (vec2<f32>(pixel) + 0.5) / vec2<f32>(textureDimensions(depth_texture));
+    let ndc = vec4<f32>(tex_coords.x * 2.0 - 1.0, 1.0 - tex_coords.y * 2.0, depth, 1.0);
+    let view_position = config.inverse_proj * ndc;
+    return view_position.xyz / view_position.w;
+}
+
+fn load_view_position(pixel: vec2<i32>) -> vec3<f32> {
+    return view_position_from_depth(pixel, load_depth(pixel));
+}
+
+// picks the neighbor on the closer side in each direction so the normals don't bleed across depth edges
+fn reconstruct_view_normal(pixel: vec2<i32>, center: vec3<f32>) -> vec3<f32> {
+    let left = load_view_position(pixel - vec2<i32>(1, 0));
+    let right = load_view_position(pixel + vec2<i32>(1, 0));
+    let up = load_view_position(pixel - vec2<i32>(0, 1));
+    let down = load_view_position(pixel + vec2<i32>(0, 1));
+    var dx = center - left;
+    if (abs(right.z - center.z) < abs(center.z - left.z)) {
+        dx = right - center;
+    }
+    var dy = center - up;
+    if (abs(down.z - center.z) < abs(center.z - up.z)) {
+        dy = down - center;
+    }
+    // pixel y goes down the screen, so this faces the camera
+    return normalize(cross(dy, dx));
+}
+
+// http://www.iryoku.com/next-generation-post-processing-in-call-of-duty-advanced-warfare
+fn interleaved_gradient_noise(pixel: vec2<f32>) -> f32 {
+    return fract(52.9829189 * fract(dot(pixel, vec2<f32>(0.06711056, 0.00583715))));
+}
+
+@fragment
+fn ambient_occlusion_fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.position.xy);
+    let depth = load_depth(pixel);
+    if (depth == SKY_DEPTH) {
+        return vec4<f32>(1.0);
+    }
+    let center = view_position_from_depth(pixel, depth);
+    let normal = reconstruct_view_normal(pixel, center);
+
+    // a random rotation of the sample kernel per pixel, the blur removes the resulting noise
+    let noise_angle = interleaved_gradient_noise(in.position.xy) * two_pi;
+    let random_vector = vec3<f32>(cos(noise_angle), sin(noise_angle), 0.0);
+    let tangent = normalize(random_vector - normal * dot(random_vector, normal));
+    let bitangent = cross(normal, tangent);
+    let tbn = mat3x3<f32>(tangent, bitangent, normal);
+
+    let dimensions = vec2<f32>(textureDimensions(depth_texture));
+    let bias = config.radius * 0.05;
+    var occlusion = 0.0;
+    for (var i = 0; i < SAMPLE_COUNT; i = i + 1) {
+        // a spiral over the hemisphere, with more of the samples close to the center
+        let t = (f32(i) + 0.5) / f32(SAMPLE_COUNT);
+        let phi = f32(i) * golden_angle;
+        let cos_theta = 1.0 - t * 0.9;
+        let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+        let direction = vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+        let sample_position = center + tbn * direction * (config.radius * mix(0.1, 1.0, t * t));
+
+        let sample_clip_position = config.proj * vec4<f32>(sample_position, 1.0);
+        let sample_ndc = sample_clip_position.xy / sample_clip_position.w;
+        let sample_tex_coords = vec2<f32>(sample_ndc.x * 0.5 + 0.5, 0.5 - sample_ndc.y * 0.5);
+        if (any(sample_tex_coords < vec2<f32>(0.0)) || any(sample_tex_coords > vec2<f32>(1.0))) {
+            continue;
+        }
+        let scene_position = load_view_position(vec2<i32>(sample_tex_coords * dimensions));
+
+        // the camera looks down -z, so larger z is closer to it.
+        // occluders much further away than the radius don't count
+        let range_check = smoothstep(0.0, 1.0, config.radius / abs(center.z - scene_position.z));
+        if (scene_position.z >= sample_position.z + bias) {
+            occlusion = occlusion + range_check;
+        }
+    }
+
+    let ambient_occlusion = 1.0 - config.intensity * occlusion / f32(SAMPLE_COUNT);
+    return vec4<f32>(ambient_occlusion, 0.0, 0.0, 1.0);
+}
+
+// separable blur that ignores samples from a different depth, so the occlusion doesn't leak across edges
+@fragment
+fn blur_fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.position.xy);
+    let depth = load_depth(pixel);
+    if (depth == SKY_DEPTH) {
+        return vec4<f32>(1.0);
+    }
+    let center_z = view_position_from_depth(pixel, depth).z;
+    let direction = vec2<i32>(config.blur_direction);
+
+    var total = textureLoad(source_texture, pixel, 0).r;
+    var total_weight = 1.0;
+    for (var i = 1; i <= BLUR_RADIUS; i = i + 1) {
+        let spatial_weight = exp(-f32(i * i) / f32(BLUR_RADIUS * BLUR_RADIUS));
+        for (var side = -1; side <= 1; side = side + 2) {
+            let sample_pixel = clamp_pixel(pixel + direction * i * side);
+            let sample_z = load_view_position(sample_pixel).z;
+            let depth_weight = exp(-abs(sample_z - center_z) * 20.0 / abs(center_z));
+            let weight = spatial_weight * depth_weight;
+            total = total + textureLoad(source_texture, sample_pixel, 0).r * weight;
+            total_weight = total_weight + weight;
+        }
+    }
+
+    return vec4<f32>(total / total_weight, 0.0, 0.0, 1.0);
+}
@@ -0,0 +1,94 @@
+// temporal anti-aliasing resolve with neighborhood clamping, based on
+// https://www.elopezr.com/temporal-aa-and-the-quest-for-the-holy-trail/
+
+struct TaaConfig {
+    history_valid: u32,
+    current_frame_weight: f32,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+// one large triangle over the clip space, see vs_main in blit.wgsl
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let tc = vec2<f32>(
+        f32(i32(vertex_index) / 2) * 2.0,
+        f32(i32(vertex_index) & 1) * 2.0
+    );
+    out.position = vec4<f32>(
+        tc.x * 2.0 - 1.0,
+        1.0 - tc.y * 2.0,
+        0.0,
+        1.0
+    );
+    out.tex_coords = tc;
+    return out;
+}
+
+@group(0) @binding(0)
+var current_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var history_texture: texture_2d<f32>;
+@group(0) @binding(2)
+var history_sampler: sampler;
+@group(0) @binding(3)
+var motion_vector_texture: texture_2d<f32>;
+@group(0) @binding(4)
+var depth_texture: texture_depth_2d;
+@group(0) @binding(5)
+var<uniform> config: TaaConfig;
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+@fragment
+fn resolve_fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.position.xy);
+    let max_pixel = textureDimensions(current_texture) - vec2<i32>(1, 1);
+    let current = textureLoad(current_texture, pixel, 0);
+
+    // the bounding box of the 3x3 neighborhood in color space, and the motion of the closest pixel
+    // so the edges of moving objects get reprojected along with them.
+    // the depth is reversed, the closest pixel has the largest depth
+    var neighborhood_min = current;
+    var neighborhood_max = current;
+    var closest_depth = -1.0;
+    var closest_pixel = pixel;
+    for (var y = -1; y <= 1; y = y + 1) {
+        for (var x = -1; x <= 1; x = x + 1) {
+            let neighbor_pixel = clamp(pixel + vec2<i32>(x, y), vec2<i32>(0, 0), max_pixel);
+            let neighbor = textureLoad(current_texture, neighbor_pixel, 0);
+            neighborhood_min = min(neighborhood_min, neighbor);
+            neighborhood_max = max(neighborhood_max, neighbor);
+            let depth = textureLoad(depth_texture, neighbor_pixel, 0);
+            if (depth > closest_depth) {
+                closest_depth = depth;
+                closest_pixel = neighbor_pixel;
+            }
+        }
+    }
+
+    let motion_vector = textureLoad(motion_vector_texture, closest_pixel, 0).xy;
+    let history_tex_coords = in.tex_coords - motion_vector;
+    if (config.history_valid == 0u
+        || any(history_tex_coords < vec2<f32>(0.0))
+        || any(history_tex_coords > vec2<f32>(1.0))) {
+        return current;
+    }
+
+    let history = clamp(
+        textureSampleLevel(history_texture, history_sampler, history_tex_coords, 0.0),
+        neighborhood_min,
+        neighborhood_max
+    );
+
+    // weighted by inverse luminance so a single very bright sample can't dominate the hdr average and flicker
+    let current_weight = config.current_frame_weight / (1.0 + luminance(current.rgb));
+    let history_weight = (1.0 - config.current_frame_weight) / (1.0 + luminance(history.rgb));
+    return (current * current_weight + history * history_weight) / (current_weight + history_weight);
+}
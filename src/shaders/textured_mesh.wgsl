@@ -5,42 +5,116 @@ struct CameraUniform {
     position: vec4<f32>,
     near_plane_distance: f32,
     far_plane_distance: f32,
+    // in ndc, already applied to proj
+    jitter: vec2<f32>,
+    previous_view_proj: mat4x4<f32>,
 }
 @group(0) @binding(0)
 var<uniform> camera: CameraUniform;
 
-let MAX_LIGHTS = 32u;
+let MAX_DIRECTIONAL_LIGHTS = 32u;
+// must match MAX_LIGHTS_PER_CLUSTER in light_clusters.rs
+let MAX_LIGHTS_PER_CLUSTER = 127u;
+let MAX_SHADOW_CASCADES = 4u;
 let MAX_BONES = 512u;
 
 struct PointLight {
     position: vec4<f32>,
     color: vec4<f32>,
+    // size, range, casts shadows, unused
+    shadow_config: vec4<f32>,
 }
 struct DirectionalLight {
-    world_space_to_light_space: mat4x4<f32>,
+    // one per cascade
+    world_space_to_light_space: array<mat4x4<f32>, MAX_SHADOW_CASCADES>,
+    // far distance of each cascade along the camera's view direction
+    cascade_splits: vec4<f32>,
+    // cascade count, blend fraction
+    cascade_config: vec4<f32>,
+    // size, unused, unused, unused
+    shadow_config: vec4<f32>,
     position: vec4<f32>,
     direction: vec4<f32>,
     color: vec4<f32>,
 }
 
-struct PointLightsUniform {
-    values: array<PointLight, MAX_LIGHTS>,
+struct PointLights {
+    values: array<PointLight>,
 }
 struct DirectionalLightsUniform {
-    values: array<DirectionalLight, MAX_LIGHTS>,
+    values: array<DirectionalLight, MAX_DIRECTIONAL_LIGHTS>,
+}
+// same as in light_clustering.wgsl
+struct LightClusterConfig {
+    // x, y, z, point light count
+    cluster_counts: vec4<u32>,
+    // of the shading texture, in pixels
+    target_size: vec2<f32>,
+    // the first depth slice ends at near_distance, the last one starts at far_distance
+    near_distance: f32,
+    far_distance: f32,
+}
+// indices into point_lights
+struct LightCluster {
+    light_count: u32,
+    light_indices: array<u32, MAX_LIGHTS_PER_CLUSTER>,
+}
+struct LightClusters {
+    values: array<LightCluster>,
 }
 struct BonesUniform {
     value: array<mat4x4<f32>>,
 }
+struct MorphTargetDisplacement {
+    position: vec4<f32>,
+    normal: vec4<f32>,
+    tangent: vec4<f32>,
+}
+// displacements of every vertex for target 0, then target 1, etc.
+struct MorphTargets {
+    target_count: u32,
+    vertex_count: u32,
+    displacements: array<MorphTargetDisplacement>,
+}
+// target_count weights per instance
+struct MorphTargetWeights {
+    values: array<f32>,
+}
+// one per instance
+struct ModelTransforms {
+    values: array<mat4x4<f32>>,
+}
 
 @group(0) @binding(1)
-var<uniform> point_lights: PointLightsUniform;
+var<storage, read> point_lights: PointLights;
 @group(0) @binding(2)
 var<uniform> directional_lights: DirectionalLightsUniform;
+@group(0) @binding(3)
+var<uniform> light_cluster_config: LightClusterConfig;
+@group(0) @binding(4)
+var<storage, read> light_clusters: LightClusters;
 @group(3) @binding(0)
 var<storage, read> bones_uniform: BonesUniform;
 @group(1) @binding(0)
 var<storage, read> shadow_bones_uniform: BonesUniform;
+@group(3) @binding(1)
+var<storage, read> morph_target_weights: MorphTargetWeights;
+// the bone and model transforms of the previous frame, for the motion vectors
+@group(3) @binding(2)
+var<storage, read> previous_bones_uniform: BonesUniform;
+@group(3) @binding(3)
+var<storage, read> previous_model_transforms: ModelTransforms;
+@group(1) @binding(1)
+var<storage, read> shadow_morph_target_weights: MorphTargetWeights;
+@group(1) @binding(10)
+var<storage, read> morph_targets: MorphTargets;
+@group(2) @binding(10)
+var<storage, read> shadow_morph_targets: MorphTargets;
+// the base color of the pbr textures bind group, for the alpha masked meshes in the depth prepass
+@group(2) @binding(0)
+var shadow_diffuse_texture: texture_2d<f32>;
+@group(2) @binding(1)
+var shadow_diffuse_sampler: sampler;
 
 struct VertexInput {
     @location(0) object_position: vec3<f32>,
@@ -61,11 +135,12 @@ struct Instance {
     @location(12) base_color_factor: vec4<f32>,
     @location(13) emissive_factor: vec4<f32>,
     @location(14) mrno: vec4<f32>, // metallicness_factor, roughness_factor, normal scale, occlusion strength
-    @location(15) alpha_cutoff: f32,
+    @location(15) alpha_and_shadows: vec4<f32>, // alpha_cutoff, shadow depth bias, shadow normal bias, receive shadows
 }
 
+// the depth prepass uses shadow_map_vs_main and masked_depth_prepass_vs_main, they must all output exactly the same depth
 struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
+    @builtin(position) @invariant clip_position: vec4<f32>,
     @location(0) world_position: vec3<f32>,
     @location(1) world_normal: vec3<f32>,
     @location(2) world_tangent: vec3<f32>,
@@ -80,14 +155,22 @@ struct VertexOutput {
     @location(11) occlusion_strength: f32,
     @location(12) alpha_cutoff: f32,
     @location(13) object_tangent: vec3<f32>,
+    @location(14) shadow_params: vec3<f32>, // shadow depth bias, shadow normal bias, receive shadows
+    @location(15) previous_clip_position: vec4<f32>,
 }
 
 struct FragmentOutput {
     @location(0) color: vec4<f32>,
 }
 
+struct OpaqueFragmentOutput {
+    @location(0) color: vec4<f32>,
+    // in uv units, from where the surface was in the previous frame to where it is now
+    @location(1) motion_vector: vec2<f32>,
+}
+
 struct ShadowMappingVertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
+    @builtin(position) @invariant clip_position: vec4<f32>,
     @location(0) world_position: vec3<f32>,
 }
 
@@ -95,19 +178,31 @@ struct ShadowMappingFragmentOutput {
     @builtin(frag_depth) depth: f32,
 }
 
+// the alpha masked meshes in the depth prepass need their base color alpha to discard the same pixels
+// as the shading pass
+struct MaskedDepthPrepassVertexOutput {
+    @builtin(position) @invariant clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) alpha: f32, // base color factor alpha * vertex color alpha
+    @location(2) alpha_cutoff: f32,
+}
+
 fn do_vertex_shade(
     vshader_input: VertexInput,
     camera_proj: mat4x4<f32>,
     camera_view: mat4x4<f32>,
     model_transform: mat4x4<f32>,
     skin_transform: mat4x4<f32>,
+    previous_model_transform: mat4x4<f32>,
+    previous_skin_transform: mat4x4<f32>,
     base_color_factor: vec4<f32>,
     emissive_factor: vec4<f32>,
     metallicness_factor: f32,
     roughness_factor: f32,
     normal_scale: f32,
     occlusion_strength: f32,
-    alpha_cutoff: f32
+    alpha_cutoff: f32,
+    shadow_params: vec3<f32>
 ) -> VertexOutput {
     var out: VertexOutput;
     out.world_normal = vshader_input.object_normal;
@@ -122,6 +217,8 @@ fn do_vertex_shade(
     let world_bitangent = normalize((skinned_model_transform * vec4<f32>(vshader_input.object_bitangent, 0.0)).xyz);
 
     out.clip_position = clip_position;
+    // the previous morph target weights aren't kept, morphing doesn't contribute to the motion
+    out.previous_clip_position = camera.previous_view_proj * previous_model_transform * previous_skin_transform * object_position;
     out.world_position = world_position.xyz;
     out.world_normal = world_normal;
     out.world_tangent = world_tangent;
@@ -136,15 +233,44 @@ fn do_vertex_shade(
     out.normal_scale = normal_scale;
     out.occlusion_strength = occlusion_strength;
     out.alpha_cutoff = alpha_cutoff;
+    out.shadow_params = shadow_params;
 
     return out;
 }
 
+fn apply_morph_targets(
+    vshader_input: VertexInput,
+    vertex_index: u32,
+    instance_index: u32,
+) -> VertexInput {
+    var out = vshader_input;
+    let target_count = morph_targets.target_count;
+    for (var target_index = 0u; target_index < target_count; target_index = target_index + 1u) {
+        let weight = morph_target_weights.values[instance_index * target_count + target_index];
+        let displacement = morph_targets.displacements[target_index * morph_targets.vertex_count + vertex_index];
+        out.object_position = out.object_position + weight * displacement.position.xyz;
+        out.object_normal = out.object_normal + weight * displacement.normal.xyz;
+        out.object_tangent = out.object_tangent + weight * displacement.tangent.xyz;
+    }
+    if (target_count > 0u) {
+        // keep the handedness of the original tangent space
+        let handedness = sign(dot(
+            cross(vshader_input.object_normal, vshader_input.object_tangent),
+            vshader_input.object_bitangent
+        ));
+        out.object_bitangent = handedness * cross(out.object_normal, out.object_tangent);
+    }
+    return out;
+}
+
 @vertex
 fn vs_main(
-    vshader_input: VertexInput,
+    unmorphed_vshader_input: VertexInput,
     instance: Instance,
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
 ) -> VertexOutput {
+    let vshader_input = apply_morph_targets(unmorphed_vshader_input, vertex_index, instance_index);
     let model_transform = mat4x4<f32>(
         instance.model_transform_0,
         instance.model_transform_1,
@@ -160,27 +286,42 @@ fn vs_main(
     let skin_transform_3 = bone_weights.w * bones_uniform.value[bone_indices.w];
     let skin_transform = skin_transform_0 + skin_transform_1 + skin_transform_2 + skin_transform_3;
 
+    let previous_skin_transform = bone_weights.x * previous_bones_uniform.value[bone_indices.x]
+        + bone_weights.y * previous_bones_uniform.value[bone_indices.y]
+        + bone_weights.z * previous_bones_uniform.value[bone_indices.z]
+        + bone_weights.w * previous_bones_uniform.value[bone_indices.w];
+
     return do_vertex_shade(
         vshader_input,
         camera.proj,
         camera.view,
         model_transform,
         skin_transform,
+        previous_model_transforms.values[instance_index],
+        previous_skin_transform,
         instance.base_color_factor,
         instance.emissive_factor,
         instance.mrno[0],
         instance.mrno[1],
         instance.mrno[2],
         instance.mrno[3],
-        instance.alpha_cutoff
+        instance.alpha_and_shadows.x,
+        instance.alpha_and_shadows.yzw
     );
 }
 
-@vertex
-fn shadow_map_vs_main(
+// only the positions, bones and morph targets matter for the depth
+struct DepthOnlyVertex {
+    object_position: vec4<f32>,
+    skinned_model_transform: mat4x4<f32>,
+}
+
+fn get_depth_only_vertex(
     vshader_input: VertexInput,
     instance: Instance,
-) -> ShadowMappingVertexOutput {
+    vertex_index: u32,
+    instance_index: u32,
+) -> DepthOnlyVertex {
     let model_transform = mat4x4<f32>(
         instance.model_transform_0,
         instance.model_transform_1,
@@ -188,6 +329,14 @@ fn shadow_map_vs_main(
         instance.model_transform_3,
     );
 
+    var morphed_object_position = vshader_input.object_position;
+    let target_count = shadow_morph_targets.target_count;
+    for (var target_index = 0u; target_index < target_count; target_index = target_index + 1u) {
+        let weight = shadow_morph_target_weights.values[instance_index * target_count + target_index];
+        let displacement = shadow_morph_targets.displacements[target_index * shadow_morph_targets.vertex_count + vertex_index];
+        morphed_object_position = morphed_object_position + weight * displacement.position.xyz;
+    }
+
     let bone_indices = vshader_input.bone_indices;
     let bone_weights = vshader_input.bone_weights; // one f32 per weight
     let skin_transform_0 = bone_weights.x * shadow_bones_uniform.value[bone_indices.x];
@@ -196,12 +345,23 @@ fn shadow_map_vs_main(
     let skin_transform_3 = bone_weights.w * shadow_bones_uniform.value[bone_indices.w];
     let skin_transform = skin_transform_0 + skin_transform_1 + skin_transform_2 + skin_transform_3;
 
-    let object_position = vec4<f32>(vshader_input.object_position, 1.0);
-    let camera_view_proj = camera.proj * camera.view;
-    let skinned_model_transform = model_transform * skin_transform;
-    let world_position = skinned_model_transform * object_position;
-    let clip_position = camera_view_proj * skinned_model_transform * object_position;
+    var out: DepthOnlyVertex;
+    out.object_position = vec4<f32>(morphed_object_position, 1.0);
+    out.skinned_model_transform = model_transform * skin_transform;
+    return out;
+}
 
+@vertex
+fn shadow_map_vs_main(
+    vshader_input: VertexInput,
+    instance: Instance,
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
+) -> ShadowMappingVertexOutput {
+    let vertex = get_depth_only_vertex(vshader_input, instance, vertex_index, instance_index);
+    let camera_view_proj = camera.proj * camera.view;
+    let world_position = vertex.skinned_model_transform * vertex.object_position;
+    let clip_position = camera_view_proj * vertex.skinned_model_transform * vertex.object_position;
 
     var out: ShadowMappingVertexOutput;
     out.clip_position = clip_position;
@@ -209,6 +369,38 @@ fn shadow_map_vs_main(
     return out;
 }
 
+@vertex
+fn masked_depth_prepass_vs_main(
+    vshader_input: VertexInput,
+    instance: Instance,
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
+) -> MaskedDepthPrepassVertexOutput {
+    let vertex = get_depth_only_vertex(vshader_input, instance, vertex_index, instance_index);
+    let camera_view_proj = camera.proj * camera.view;
+
+    var out: MaskedDepthPrepassVertexOutput;
+    out.clip_position = camera_view_proj * vertex.skinned_model_transform * vertex.object_position;
+    out.tex_coords = vshader_input.object_tex_coords;
+    out.alpha = instance.base_color_factor.a * vshader_input.object_color.a;
+    out.alpha_cutoff = instance.alpha_and_shadows.x;
+    return out;
+}
+
+@fragment
+fn masked_depth_prepass_fs_main(
+    in: MaskedDepthPrepassVertexOutput
+) {
+    let base_color_alpha = textureSample(
+        shadow_diffuse_texture,
+        shadow_diffuse_sampler,
+        in.tex_coords
+    ).a;
+    if (base_color_alpha * in.alpha <= in.alpha_cutoff) {
+        discard;
+    }
+}
+
 @fragment
 fn point_shadow_map_fs_main(
     in: ShadowMappingVertexOutput
@@ -264,6 +456,9 @@ var point_shadow_map_sampler: sampler;
 var directional_shadow_map_textures: texture_2d_array<f32>;
 @group(2) @binding(11)
 var directional_shadow_map_sampler: sampler;
+// the blurred output of the ssao pass, same resolution as the shading texture and the motion vectors
+@group(2) @binding(12)
+var screen_space_ambient_occlusion_texture: texture_2d<f32>;
 
 
 let pi: f32 = 3.141592653589793;
@@ -271,6 +466,14 @@ let two_pi: f32 = 6.283185307179586;
 let half_pi: f32 = 1.570796326794897;
 let epsilon: f32 = 0.00001;
 
+// bounds of the pcf filter radius, in shadow map texels. the lower bound keeps contact shadows from aliasing
+// and the upper bound keeps the cost of the wide penumbras of distant blockers from blowing up
+let MIN_PENUMBRA_TEXELS: f32 = 1.0;
+let MAX_PENUMBRA_TEXELS: f32 = 24.0;
+// samples per side of the square grids used by pcss
+let BLOCKER_SEARCH_SAMPLE_COUNT: i32 = 5;
+let PCF_SAMPLE_COUNT: i32 = 9;
+
 // https://learnopengl.com/PBR/Theory
 fn normal_distribution_func_tr_ggx(
     a: f32,
@@ -367,6 +570,235 @@ fn rand(co: vec2<f32>) -> f32 {
     return fract(sin(sn) * c);
 }
 
+// position of sample (x, y) in a square grid of sample_count x sample_count samples spanning [-1, 1]
+fn pcss_grid_offset(x: i32, y: i32, sample_count: i32) -> vec2<f32> {
+    return vec2<f32>(
+        2.0 * f32(x) / f32(sample_count - 1) - 1.0,
+        2.0 * f32(y) / f32(sample_count - 1) - 1.0,
+    );
+}
+
+// percentage-closer soft shadows, the same as the directional lights do it but with the filter on the plane
+// that faces the light. all of the sizes are in world units at the receiver's distance from the light.
+// the depths are stored as a fraction of the light's range, the far plane of its shadow map.
+// 1.0 means fully lit
+fn point_shadow_occlusion(
+    light_index: u32,
+    from_shadow_vec: vec3<f32>,
+    light_size: f32,
+    light_range: f32,
+    depth_bias: f32,
+) -> f32 {
+    let receiver_distance = length(from_shadow_vec);
+    let current_depth = receiver_distance / light_range;
+    let direction = from_shadow_vec / receiver_distance;
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(direction.y) > 0.99) {
+        up = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let tangent = normalize(cross(up, direction));
+    let bitangent = cross(direction, tangent);
+    // each cube face covers 90 degrees
+    let texel_size = 2.0 * receiver_distance / f32(textureDimensions(point_shadow_map_textures).x);
+
+    // 1. find the average distance of the blockers around the receiver
+    let search_radius = clamp(light_size, texel_size * MIN_PENUMBRA_TEXELS, texel_size * MAX_PENUMBRA_TEXELS);
+    var blocker_distance_acc = 0.0;
+    var blocker_count = 0.0;
+    for (var x = 0; x < BLOCKER_SEARCH_SAMPLE_COUNT; x = x + 1) {
+        for (var y = 0; y < BLOCKER_SEARCH_SAMPLE_COUNT; y = y + 1) {
+            let grid_offset = search_radius * pcss_grid_offset(x, y, BLOCKER_SEARCH_SAMPLE_COUNT);
+            let offset = tangent * grid_offset.x + bitangent * grid_offset.y;
+            let sampled_depth = textureSampleLevel(
+                point_shadow_map_textures,
+                point_shadow_map_sampler,
+                world_normal_to_cubemap_vec(from_shadow_vec + offset),
+                i32(light_index),
+                0.0
+            ).r;
+            // assume the receiver can slope away by up to 45 degrees to avoid acne in the wide filters
+            let bias = depth_bias + length(offset) / light_range;
+            if (sampled_depth < current_depth - bias) {
+                blocker_distance_acc = blocker_distance_acc + sampled_depth * light_range;
+                blocker_count = blocker_count + 1.0;
+            }
+        }
+    }
+    if (blocker_count < 1.0) {
+        return 1.0;
+    }
+
+    // 2. similar triangles between the light, the blockers and the receiver
+    let blocker_distance = blocker_distance_acc / blocker_count;
+    let penumbra_size = light_size * (receiver_distance - blocker_distance) / max(blocker_distance, epsilon);
+    let filter_radius = clamp(penumbra_size, texel_size * MIN_PENUMBRA_TEXELS, texel_size * MAX_PENUMBRA_TEXELS);
+
+    // 3. filter over the penumbra
+    var shadow_occlusion_acc = 0.0;
+    for (var x = 0; x < PCF_SAMPLE_COUNT; x = x + 1) {
+        for (var y = 0; y < PCF_SAMPLE_COUNT; y = y + 1) {
+            let grid_offset = filter_radius * pcss_grid_offset(x, y, PCF_SAMPLE_COUNT);
+            let offset = tangent * grid_offset.x + bitangent * grid_offset.y;
+            let sampled_depth = textureSampleLevel(
+                point_shadow_map_textures,
+                point_shadow_map_sampler,
+                world_normal_to_cubemap_vec(from_shadow_vec + offset),
+                i32(light_index),
+                0.0
+            ).r;
+            let bias = depth_bias + length(offset) / light_range;
+            if (current_depth - bias < sampled_depth) {
+                shadow_occlusion_acc = shadow_occlusion_acc + 1.0;
+            }
+        }
+    }
+    return shadow_occlusion_acc / f32(PCF_SAMPLE_COUNT * PCF_SAMPLE_COUNT);
+}
+
+// 1.0 means fully lit
+fn directional_shadow_cascade_occlusion(
+    light_index: u32,
+    cascade_index: u32,
+    world_position: vec3<f32>,
+    depth_bias: f32,
+) -> f32 {
+    let cascade_count = u32(directional_lights.values[light_index].cascade_config.x);
+    // indexed through the uniform instead of a local copy, arrays can only be indexed dynamically by reference
+    let world_space_to_light_space = directional_lights.values[light_index].world_space_to_light_space[cascade_index];
+    let light_space_position_nopersp = world_space_to_light_space * vec4<f32>(world_position, 1.0);
+    let light_space_position = light_space_position_nopersp / light_space_position_nopersp.w;
+    let light_space_position_uv = vec2<f32>(
+        light_space_position.x * 0.5 + 0.5,
+        1.0 - (light_space_position.y * 0.5 + 0.5),
+    );
+    let current_depth = light_space_position.z;
+
+    if (light_space_position.x < -1.0 || light_space_position.x > 1.0 || light_space_position.y < -1.0 || light_space_position.y > 1.0 || light_space_position.z < 0.0 || light_space_position.z > 1.0) {
+        return 1.0;
+    }
+
+    // the projection is orthographic so the scale between world units and shadow map units is the same everywhere
+    let uv_per_world_unit = 0.5 * length(vec3<f32>(world_space_to_light_space[0].x, world_space_to_light_space[1].x, world_space_to_light_space[2].x));
+    let depth_per_world_unit = length(vec3<f32>(world_space_to_light_space[0].z, world_space_to_light_space[1].z, world_space_to_light_space[2].z));
+    let texel_size = 1.0 / f32(textureDimensions(directional_shadow_map_textures).x);
+    let layer_index = i32(light_index * cascade_count + cascade_index);
+    let light_size = directional_lights.values[light_index].shadow_config.x;
+
+    // percentage-closer soft shadows, https://developer.download.nvidia.com/whitepapers/2008/PCSS_Integration.pdf
+    // 1. find the average depth of the blockers around the receiver
+    let search_radius = texel_size * MAX_PENUMBRA_TEXELS;
+    var blocker_depth_acc = 0.0;
+    var blocker_count = 0.0;
+    for (var x = 0; x < BLOCKER_SEARCH_SAMPLE_COUNT; x = x + 1) {
+        for (var y = 0; y < BLOCKER_SEARCH_SAMPLE_COUNT; y = y + 1) {
+            let offset = search_radius * pcss_grid_offset(x, y, BLOCKER_SEARCH_SAMPLE_COUNT);
+            let sampled_depth = textureSampleLevel(
+                directional_shadow_map_textures,
+                directional_shadow_map_sampler,
+                light_space_position_uv + offset,
+                layer_index,
+                0.0
+            ).r;
+            // assume the receiver can slope away by up to 45 degrees to avoid acne in the wide filters
+            let bias = depth_bias + length(offset) / uv_per_world_unit * depth_per_world_unit;
+            if (sampled_depth < current_depth - bias) {
+                blocker_depth_acc = blocker_depth_acc + sampled_depth;
+                blocker_count = blocker_count + 1.0;
+            }
+        }
+    }
+    if (blocker_count < 1.0) {
+        return 1.0;
+    }
+
+    // 2. the penumbra grows with the distance between the blockers and the receiver
+    let blocker_distance = (current_depth - blocker_depth_acc / blocker_count) / depth_per_world_unit;
+    let penumbra_size = light_size * blocker_distance * uv_per_world_unit;
+    let filter_radius = clamp(penumbra_size, texel_size * MIN_PENUMBRA_TEXELS, texel_size * MAX_PENUMBRA_TEXELS);
+
+    // 3. filter over the penumbra
+    var shadow_occlusion_acc = 0.0;
+    for (var x = 0; x < PCF_SAMPLE_COUNT; x = x + 1) {
+        for (var y = 0; y < PCF_SAMPLE_COUNT; y = y + 1) {
+            let offset = filter_radius * pcss_grid_offset(x, y, PCF_SAMPLE_COUNT);
+            let sampled_depth = textureSampleLevel(
+                directional_shadow_map_textures,
+                directional_shadow_map_sampler,
+                light_space_position_uv + offset,
+                layer_index,
+                0.0
+            ).r;
+            let bias = depth_bias + length(offset) / uv_per_world_unit * depth_per_world_unit;
+            if (current_depth - bias < sampled_depth) {
+                shadow_occlusion_acc = shadow_occlusion_acc + 1.0;
+            }
+        }
+    }
+    return shadow_occlusion_acc / f32(PCF_SAMPLE_COUNT * PCF_SAMPLE_COUNT);
+}
+
+// picks the cascade based on the distance from the camera and blends into the next cascade near the
+// end of the current one. past the last cascade everything is lit
+fn directional_shadow_occlusion(
+    light_index: u32,
+    world_position: vec3<f32>,
+    depth_bias: f32,
+) -> f32 {
+    let cascade_config = directional_lights.values[light_index].cascade_config;
+    let cascade_splits = directional_lights.values[light_index].cascade_splits;
+    let cascade_count = u32(cascade_config.x);
+    let blend_fraction = cascade_config.y;
+    let view_depth = -(camera.view * vec4<f32>(world_position, 1.0)).z;
+
+    var cascade_index = 0u;
+    loop {
+        if (cascade_index >= cascade_count || view_depth < cascade_splits[cascade_index]) {
+            break;
+        }
+        cascade_index = cascade_index + 1u;
+    }
+    if (cascade_index >= cascade_count) {
+        return 1.0;
+    }
+
+    let occlusion = directional_shadow_cascade_occlusion(light_index, cascade_index, world_position, depth_bias);
+
+    var cascade_start = 0.0;
+    if (cascade_index > 0u) {
+        cascade_start = cascade_splits[cascade_index - 1u];
+    }
+    let cascade_end = cascade_splits[cascade_index];
+    let blend_start = cascade_end - (cascade_end - cascade_start) * blend_fraction;
+    if (blend_fraction <= 0.0 || view_depth < blend_start) {
+        return occlusion;
+    }
+    var next_occlusion = 1.0;
+    if (cascade_index + 1u < cascade_count) {
+        next_occlusion = directional_shadow_cascade_occlusion(light_index, cascade_index + 1u, world_position, depth_bias);
+    }
+    return mix(occlusion, next_occlusion, (view_depth - blend_start) / (cascade_end - blend_start));
+}
+
+// same depth slices as depth_slice_range in light_clustering.wgsl
+fn light_cluster_index(screen_position: vec2<f32>, world_position: vec3<f32>) -> u32 {
+    let cluster_counts = light_cluster_config.cluster_counts.xyz;
+    let tile = min(
+        vec2<u32>(screen_position / light_cluster_config.target_size * vec2<f32>(f32(cluster_counts.x), f32(cluster_counts.y))),
+        cluster_counts.xy - 1u
+    );
+    let view_depth = -(camera.view * vec4<f32>(world_position, 1.0)).z;
+    var slice = 0u;
+    if (view_depth >= light_cluster_config.near_distance) {
+        let inner_slice_count = f32(cluster_counts.z - 2u);
+        let depth_ratio = light_cluster_config.far_distance / light_cluster_config.near_distance;
+        slice = min(
+            u32(log(view_depth / light_cluster_config.near_distance) / log(depth_ratio) * inner_slice_count) + 1u,
+            cluster_counts.z - 1u
+        );
+    }
+    return tile.x + tile.y * cluster_counts.x + slice * cluster_counts.x * cluster_counts.y;
+}
+
 fn compute_direct_lighting(
     world_normal: vec3<f32>,
     to_viewer_vec: vec3<f32>,
@@ -414,6 +846,8 @@ fn compute_direct_lighting(
 }
 
 fn do_fragment_shade(
+    // fragment coordinates in pixels
+    screen_position: vec2<f32>,
     world_position: vec3<f32>,
     world_normal: vec3<f32>,
     tex_coords: vec2<f32>,
@@ -424,7 +858,11 @@ fn do_fragment_shade(
     metallicness_factor: f32,
     roughness_factor: f32,
     occlusion_strength: f32,
-    alpha_cutoff: f32
+    alpha_cutoff: f32,
+    shadow_world_position: vec3<f32>,
+    shadow_depth_bias: f32,
+    receive_shadows: bool,
+    screen_space_ambient_occlusion: f32
 ) -> FragmentOutput {
 
     // let roughness = 0.12;
@@ -469,90 +907,20 @@ fn do_fragment_shade(
     let a = roughness;
     let f0 = surface_reflection_at_zero_incidence;
 
-
-    let random_seed = vec2<f32>(
-        round(100000.0 * (world_position.x + world_position.y)),
-        round(100000.0 * (world_position.y + world_position.z)),
-    );
-
     var total_light_irradiance = vec3<f32>(0.0);
-    for (var light_index = 0u; light_index < MAX_LIGHTS; light_index = light_index + 1u) {
+    // only the point lights that reach this fragment's cluster, see light_clustering.wgsl
+    let cluster_index = light_cluster_index(screen_position, world_position);
+    let cluster_light_count = light_clusters.values[cluster_index].light_count;
+    for (var cluster_light_index = 0u; cluster_light_index < cluster_light_count; cluster_light_index = cluster_light_index + 1u) {
+        let light_index = light_clusters.values[cluster_index].light_indices[cluster_light_index];
         let light = point_lights.values[light_index];
         let light_color_scaled = light.color.xyz * light.color.w;
 
-        if (light_color_scaled.x < epsilon && light_color_scaled.y < epsilon && light_color_scaled.z < epsilon) {
-            continue;
-        }
-
-        let from_shadow_vec = world_position - light.position.xyz;
-        let shadow_camera_far_plane_distance = 1000.0;
-        let current_depth = length(from_shadow_vec) / shadow_camera_far_plane_distance;
-        let bias = 0.0001;
-
-        // soft shadows
-        // irregular shadow sampling
-        var shadow_occlusion_acc = 0.0;
-        let sample_count = 4.0;
-        let max_offset_x = 0.01 + 0.04 * rand(random_seed * 1.0);
-        let max_offset_y = 0.01 + 0.04 * rand(random_seed * 2.0);
-        let max_offset_z = 0.01 + 0.04 * rand(random_seed * 3.0);
-        for (var x = 0.0; x < sample_count; x = x + 1.0) {
-            for (var y = 0.0; y < sample_count; y = y + 1.0) {
-                for (var z = 0.0; z < sample_count; z = z + 1.0) {
-                    let irregular_offset = vec3<f32>(
-                        max_offset_x * ((2.0 * x / (sample_count - 1.0)) - 1.0),
-                        max_offset_y * ((2.0 * y / (sample_count - 1.0)) - 1.0),
-                        max_offset_z * ((2.0 * z / (sample_count - 1.0)) - 1.0),
-                    );
-                    let closest_depth = textureSample(
-                        point_shadow_map_textures,
-                        point_shadow_map_sampler,
-                        world_normal_to_cubemap_vec(from_shadow_vec + irregular_offset),
-                        i32(light_index)
-                    ).r;
-                    if (current_depth - bias < closest_depth) {
-                        shadow_occlusion_acc = shadow_occlusion_acc + 1.0;
-                    }
-                }
-            }
+        var shadow_occlusion_factor = 1.0;
+        if (receive_shadows && light.shadow_config.z > 0.5) {
+            let from_shadow_vec = shadow_world_position - light.position.xyz;
+            shadow_occlusion_factor = point_shadow_occlusion(light_index, from_shadow_vec, light.shadow_config.x, light.shadow_config.y, shadow_depth_bias);
         }
-        let shadow_occlusion_factor = shadow_occlusion_acc / (sample_count * sample_count * sample_count);
-
-        // regular shadow sampling
-        // var shadow_occlusion_acc = 0.0;
-        // let bias = 0.0001;
-        // let sample_count = 4.0;
-        // let offset = 0.01;
-
-        // let max_offset = 0.1;
-        // for (var x = -max_offset; x < max_offset; x = x + max_offset / (sample_count * 0.5)) {
-        //     for (var y = -max_offset; y < max_offset; y = y + max_offset / (sample_count * 0.5)) {
-        //         for (var z = -max_offset; z < max_offset; z = z + max_offset / (sample_count * 0.5)) {
-        //             let closest_depth = textureSample(
-        //                 shadow_map_textures,
-        //                 shadow_map_sampler,
-        //                 world_normal_to_cubemap_vec(from_shadow_vec + vec3<f32>(x, y, z)),
-        //                 i32(light_index)
-        //             ).r;
-        //             if (current_depth - bias < closest_depth) {
-        //                 shadow_occlusion_acc = shadow_occlusion_acc + 1.0;
-        //             }
-        //         }
-        //     }
-        // }
-        // let shadow_occlusion_factor = shadow_occlusion_acc / (sample_count * sample_count * sample_count);
-
-        // hard shadows
-        // var shadow_occlusion_factor = 1.0;
-        // let closest_depth = textureSample(
-        //     point_shadow_map_textures,
-        //     point_shadow_map_sampler,
-        //     world_normal_to_cubemap_vec(from_shadow_vec),
-        //     i32(light_index)
-        // ).r;
-        // if (current_depth - bias < closest_depth) {
-        //     shadow_occlusion_factor = 1.0;
-        // }
 
         if (shadow_occlusion_factor < epsilon) {
                 continue;
@@ -567,7 +935,10 @@ fn do_fragment_shade(
         // let light_attenuation_factor_d100 = 1.0 / (1.0 + 0.045 * distance_from_light + 0.0075 * distance_from_light * distance_from_light);
         let light_attenuation_factor_d600 = 1.0 / (1.0 + 0.007 * distance_from_light + 0.0002 * distance_from_light * distance_from_light);
         // let light_attenuation_factor_d3250 = 1.0 / (1.0 + 0.0014 * distance_from_light + 0.000007 * distance_from_light * distance_from_light);
-        let light_attenuation_factor = light_attenuation_factor_d600;
+        // fades out smoothly at the light's range so the edges of the clusters don't show
+        let range_fraction = distance_from_light / light.shadow_config.y;
+        let range_window = clamp(1.0 - range_fraction * range_fraction * range_fraction * range_fraction, 0.0, 1.0);
+        let light_attenuation_factor = light_attenuation_factor_d600 * range_window * range_window;
 
         let light_irradiance = compute_direct_lighting(
             world_normal,
@@ -583,7 +954,7 @@ fn do_fragment_shade(
         total_light_irradiance = total_light_irradiance + light_irradiance * shadow_occlusion_factor;
     }
 
-    for (var light_index = 0u; light_index < MAX_LIGHTS; light_index = light_index + 1u) {
+    for (var light_index = 0u; light_index < MAX_DIRECTIONAL_LIGHTS; light_index = light_index + 1u) {
         let light = directional_lights.values[light_index];
         let light_color_scaled = light.color.xyz * light.color.w;
 
@@ -591,62 +962,10 @@ fn do_fragment_shade(
             continue;
         }
 
-        // let from_shadow_vec = world_position - light.position.xyz;
-        // let shadow_camera_far_plane_distance = 40.0;
-        // let current_depth = length(from_shadow_vec) / shadow_camera_far_plane_distance;
-        let light_space_position_nopersp = light.world_space_to_light_space * vec4<f32>(world_position, 1.0);
-        let light_space_position = light_space_position_nopersp / light_space_position_nopersp.w;
-        let light_space_position_uv = vec2<f32>(
-            light_space_position.x * 0.5 + 0.5,
-            1.0 - (light_space_position.y * 0.5 + 0.5),
-        );
-        let current_depth = light_space_position.z;
-        let bias = 0.0001;
-
-        // soft shadows
-        var shadow_occlusion_acc = 0.0;
-        let sample_count = 4.0;
-        let max_offset_x = 0.0001 + 0.0005 * rand(random_seed * 1.0);
-        let max_offset_y = 0.0001 + 0.0005 * rand(random_seed * 2.0);
-        for (var x = 0.0; x < sample_count; x = x + 1.0) {
-            for (var y = 0.0; y < sample_count; y = y + 1.0) {
-                let irregular_offset = vec2<f32>(
-                    max_offset_x * ((2.0 * x / (sample_count - 1.0)) - 1.0),
-                    max_offset_y * ((2.0 * y / (sample_count - 1.0)) - 1.0)
-                );
-                let closest_depth = textureSample(
-                    directional_shadow_map_textures,
-                    directional_shadow_map_sampler,
-                    light_space_position_uv + irregular_offset,
-                    i32(light_index)
-                ).r;
-                if (light_space_position.x >= -1.0 && light_space_position.x <= 1.0 && light_space_position.y >= -1.0 && light_space_position.y <= 1.0 && light_space_position.z >= 0.0 && light_space_position.z <= 1.0) {
-                    if (current_depth - bias < closest_depth) {
-                        shadow_occlusion_acc = shadow_occlusion_acc + 1.0;
-                    }
-                } else {
-                    shadow_occlusion_acc = shadow_occlusion_acc + 1.0;
-                }
-            }
+        var shadow_occlusion_factor = 1.0;
+        if (receive_shadows) {
+            shadow_occlusion_factor = directional_shadow_occlusion(light_index, shadow_world_position, shadow_depth_bias);
         }
-        let shadow_occlusion_factor = shadow_occlusion_acc / (sample_count * sample_count);
-
-        // hard shadows
-        // var shadow_occlusion_factor = 1.0;
-        // let closest_depth = textureSample(
-        //     directional_shadow_map_textures,
-        //     directional_shadow_map_sampler,
-        //     light_space_position_uv,
-        //     i32(light_index)
-        // ).r;
-        // if (light_space_position.x >= -1.0 && light_space_position.x <= 1.0 && light_space_position.y >= -1.0 && light_space_position.y <= 1.0 && light_space_position.z >= 0.0 && light_space_position.z <= 1.0) {
-        //     if (current_depth - bias < closest_depth) {
-        //         shadow_occlusion_factor = 1.0;
-        //     }
-        // } else {
-        //     shadow_occlusion_factor = 1.0;
-        // }
-
 
         if (shadow_occlusion_factor < epsilon) {
                 continue;
@@ -700,7 +1019,7 @@ fn do_fragment_shade(
         ambient_irradiance_pre_ao,
         ambient_irradiance_pre_ao * ambient_occlusion,
         occlusion_strength
-    );
+    ) * screen_space_ambient_occlusion;
     // let ambient_irradiance = ambient_irradiance_pre_ao;
 
     let combined_irradiance_hdr = ambient_irradiance + total_light_irradiance + emissive;
@@ -709,10 +1028,12 @@ fn do_fragment_shade(
 
     // let hi = textureSample(shadow_map_texture, shadow_map_sampler, vec2<f32>(0.1, 0.1));
 
+    let alpha = base_color_t.a * base_color_factor.a * vertex_color.a;
+
     // let final_color = vec4<f32>(combined_irradiance_ldr, 1.0);
-    let final_color = vec4<f32>(combined_irradiance_hdr, 1.0);
+    let final_color = vec4<f32>(combined_irradiance_hdr, alpha);
 
-    if (base_color_t.a <= alpha_cutoff) {
+    if (alpha <= alpha_cutoff) {
         discard;
     }
 
@@ -721,8 +1042,11 @@ fn do_fragment_shade(
     return out;
 }
 
-@fragment
-fn fs_main(in: VertexOutput) -> FragmentOutput {
+fn shade_vertex_output(
+    in: VertexOutput,
+    front_facing: bool,
+    screen_space_ambient_occlusion: f32
+) -> FragmentOutput {
     let tbn = (mat3x3<f32>(
         in.world_tangent,
         in.world_bitangent,
@@ -745,14 +1069,19 @@ fn fs_main(in: VertexOutput) -> FragmentOutput {
             tangent_space_normal * vec3<f32>(in.normal_scale, in.normal_scale, 1.0)
         )
     );
+    // back faces are only visible on double sided meshes, light them as if they were front faces
+    let facing_normal = select(-transformed_normal, transformed_normal, front_facing);
+    // pushed along the geometric normal, the normal map would make the offset noisy
+    let shadow_world_position = in.world_position + select(-in.world_normal, in.world_normal, front_facing) * in.shadow_params.y;
 
     //  var out: FragmentOutput;
     // out.color = vec4<f32>(in.object_tangent, 1.0);;
     // return out;
 
     return do_fragment_shade(
+        in.clip_position.xy,
         in.world_position,
-        transformed_normal,
+        facing_normal,
         in.tex_coords,
         in.vertex_color,
         camera.position.xyz,
@@ -761,6 +1090,42 @@ fn fs_main(in: VertexOutput) -> FragmentOutput {
         in.metallicness_factor,
         in.roughness_factor,
         in.occlusion_strength,
-        in.alpha_cutoff
+        in.alpha_cutoff,
+        shadow_world_position,
+        in.shadow_params.x,
+        in.shadow_params.z > 0.5,
+        screen_space_ambient_occlusion
     );
-}
\ No newline at end of file
+}
+
+@fragment
+fn fs_main(
+    in: VertexOutput,
+    @builtin(front_facing) front_facing: bool,
+) -> OpaqueFragmentOutput {
+    let screen_space_ambient_occlusion = textureLoad(
+        screen_space_ambient_occlusion_texture,
+        vec2<i32>(in.clip_position.xy),
+        0
+    ).r;
+    let shaded = shade_vertex_output(in, front_facing, screen_space_ambient_occlusion);
+    var out: OpaqueFragmentOutput;
+    // the alpha channel of the shading texture marks coverage, opaque surfaces cover fully
+    out.color = vec4<f32>(shaded.color.rgb, 1.0);
+    let ndc_to_uv = vec2<f32>(0.5, -0.5);
+    let current_tex_coords = in.clip_position.xy / vec2<f32>(textureDimensions(screen_space_ambient_occlusion_texture))
+        - camera.jitter * ndc_to_uv;
+    let previous_ndc = in.previous_clip_position.xy / in.previous_clip_position.w;
+    let previous_tex_coords = previous_ndc * ndc_to_uv + 0.5;
+    out.motion_vector = current_tex_coords - previous_tex_coords;
+    return out;
+}
+
+@fragment
+fn transparent_fs_main(
+    in: VertexOutput,
+    @builtin(front_facing) front_facing: bool,
+) -> FragmentOutput {
+    // the ssao pass only sees the opaque geometry, which is behind the transparent surfaces
+    return shade_vertex_output(in, front_facing, 1.0);
+}
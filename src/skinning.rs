@@ -1,6 +1,6 @@
 use std::collections::{hash_map::Entry, HashMap};
 
-use cgmath::Matrix4;
+use cgmath::{Matrix4, Vector3};
 
 use super::*;
 
@@ -136,3 +136,71 @@ pub fn get_bone_skeleton_space_transform(
     // see https://www.khronos.org/files/gltf20-reference-guide.pdf
     bone_space_to_skeleton_space.matrix() * skeleton_space_to_bone_space
 }
+
+// the object space bounding box of a skinned mesh in the pose of bone_transforms, a slice of
+// AllBoneTransforms::buffer. a skinned vertex is a weighted average of its position transformed by each
+// of its bones, so it stays within the bind pose box transformed by every bone
+pub fn get_skinned_bounding_box(
+    (min, max): (Vector3<f32>, Vector3<f32>),
+    bone_transforms: &[u8],
+) -> (Vector3<f32>, Vector3<f32>) {
+    let corners: Vec<_> = (0..8)
+        .map(|corner| {
+            Vector3::new(
+                if corner & 1 == 0 { min.x } else { max.x },
+                if corner & 2 == 0 { min.y } else { max.y },
+                if corner & 4 == 0 { min.z } else { max.z },
+            )
+        })
+        .collect();
+    bone_transforms
+        .chunks_exact(std::mem::size_of::<GpuMatrix4>())
+        // the buffer is only byte aligned
+        .map(bytemuck::pod_read_unaligned::<GpuMatrix4>)
+        .flat_map(|GpuMatrix4(bone_transform)| {
+            corners
+                .iter()
+                .map(move |corner| (bone_transform * corner.extend(1.0)).truncate())
+        })
+        .fold(None, |bounds, point| match bounds {
+            None => Some((point, point)),
+            Some((min, max)) => Some((
+                Vector3::new(min.x.min(point.x), min.y.min(point.y), min.z.min(point.z)),
+                Vector3::new(max.x.max(point.x), max.y.max(point.y), max.z.max(point.z)),
+            )),
+        })
+        .unwrap_or((min, max))
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, SquareMatrix};
+
+    use super::*;
+
+    #[test]
+    fn skinned_bounding_box_covers_every_bone() {
+        let bind_pose_bounds = (Vector3::new(-1.0, 0.0, -1.0), Vector3::new(1.0, 2.0, 1.0));
+        let bone_transforms = [
+            GpuMatrix4(Matrix4::identity()),
+            GpuMatrix4(Matrix4::from_translation(Vector3::new(0.0, 0.0, 5.0))),
+            GpuMatrix4(Matrix4::from_angle_z(Deg(180.0))),
+        ];
+        let (min, max) =
+            get_skinned_bounding_box(bind_pose_bounds, bytemuck::cast_slice(&bone_transforms));
+        let expected = (Vector3::new(-1.0, -2.0, -1.0), Vector3::new(1.0, 2.0, 6.0));
+        for (value, expected_value) in [min, max].iter().flat_map(|v| [v.x, v.y, v.z]).zip(
+            [expected.0, expected.1]
+                .iter()
+                .flat_map(|v| [v.x, v.y, v.z]),
+        ) {
+            assert!((value - expected_value).abs() < 1e-5);
+        }
+
+        // no bones, nothing to transform
+        assert_eq!(
+            get_skinned_bounding_box(bind_pose_bounds, &[]),
+            bind_pose_bounds
+        );
+    }
+}
@@ -0,0 +1,359 @@
+use anyhow::Result;
+use cgmath::{Matrix4, SquareMatrix};
+use wgpu::util::DeviceExt;
+
+use super::*;
+
+const AMBIENT_OCCLUSION_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+// one per pass: the ambient occlusion pass doesn't blur, then a horizontal and a vertical blur
+const PASS_BLUR_DIRECTIONS: [[f32; 2]; 3] = [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]];
+
+#[derive(Debug, Copy, Clone)]
+pub struct SsaoSettings {
+    // in world units, how far away an occluder can be and still darken a pixel
+    pub radius: f32,
+    // 0 to 1, how dark a fully occluded pixel gets
+    pub intensity: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsaoConfigUniform {
+    proj: [[f32; 4]; 4],
+    inverse_proj: [[f32; 4]; 4],
+    radius: f32,
+    intensity: f32,
+    // in pixels, see PASS_BLUR_DIRECTIONS
+    blur_direction: [f32; 2],
+}
+
+// ambient occlusion from the depth buffer of the depth prepass, see ssao.wgsl.
+// the result is blurred with a depth aware blur and read by the pbr shader to darken the ambient lighting
+pub struct Ssao {
+    ambient_occlusion_pipeline: wgpu::RenderPipeline,
+    blur_pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    // one per pass, they only differ by the blur direction
+    config_buffers: [wgpu::Buffer; 3],
+    // reads the depth, writes ambient_occlusion_texture
+    ambient_occlusion_bind_group: wgpu::BindGroup,
+    // reads ambient_occlusion_texture, writes blur_texture
+    horizontal_blur_bind_group: wgpu::BindGroup,
+    // reads blur_texture, writes ambient_occlusion_texture
+    vertical_blur_bind_group: wgpu::BindGroup,
+    ambient_occlusion_texture: Texture,
+    blur_texture: Texture,
+}
+
+impl Ssao {
+    pub fn new(device: &wgpu::Device, depth_texture: &Texture) -> Result<Self> {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("SSAO Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                std::fs::read_to_string("./src/shaders/ssao.wgsl")?.into(),
+            ),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("ssao_bind_group_layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("SSAO Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let make_pipeline = |label: &str, entry_point: &str| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: AMBIENT_OCCLUSION_TEXTURE_FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let ambient_occlusion_pipeline = make_pipeline(
+            "SSAO Ambient Occlusion Pipeline",
+            "ambient_occlusion_fs_main",
+        );
+        let blur_pipeline = make_pipeline("SSAO Blur Pipeline", "blur_fs_main");
+
+        let config_buffers = PASS_BLUR_DIRECTIONS.map(|blur_direction| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("SSAO Config Buffer"),
+                contents: bytemuck::cast_slice(&[SsaoConfigUniform {
+                    proj: Matrix4::one().into(),
+                    inverse_proj: Matrix4::one().into(),
+                    radius: 0.0,
+                    intensity: 0.0,
+                    blur_direction,
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            })
+        });
+
+        let (ambient_occlusion_texture, blur_texture) =
+            Self::make_textures(device, depth_texture.size);
+        let [ambient_occlusion_bind_group, horizontal_blur_bind_group, vertical_blur_bind_group] =
+            Self::make_bind_groups(
+                device,
+                &bind_group_layout,
+                &config_buffers,
+                depth_texture,
+                &ambient_occlusion_texture,
+                &blur_texture,
+            );
+
+        Ok(Self {
+            ambient_occlusion_pipeline,
+            blur_pipeline,
+            bind_group_layout,
+            config_buffers,
+            ambient_occlusion_bind_group,
+            horizontal_blur_bind_group,
+            vertical_blur_bind_group,
+            ambient_occlusion_texture,
+            blur_texture,
+        })
+    }
+
+    // the depth texture gets recreated when the window or the render scale changes.
+    // the output view changes too so anything that binds it must be recreated after this
+    pub fn resize(&mut self, device: &wgpu::Device, depth_texture: &Texture) {
+        let (ambient_occlusion_texture, blur_texture) =
+            Self::make_textures(device, depth_texture.size);
+        let [ambient_occlusion_bind_group, horizontal_blur_bind_group, vertical_blur_bind_group] =
+            Self::make_bind_groups(
+                device,
+                &self.bind_group_layout,
+                &self.config_buffers,
+                depth_texture,
+                &ambient_occlusion_texture,
+                &blur_texture,
+            );
+        self.ambient_occlusion_texture = ambient_occlusion_texture;
+        self.blur_texture = blur_texture;
+        self.ambient_occlusion_bind_group = ambient_occlusion_bind_group;
+        self.horizontal_blur_bind_group = horizontal_blur_bind_group;
+        self.vertical_blur_bind_group = vertical_blur_bind_group;
+    }
+
+    // 1 is unoccluded, the same resolution as the depth texture
+    pub fn output_view(&self) -> &wgpu::TextureView {
+        &self.ambient_occlusion_texture.view
+    }
+
+    pub fn update(
+        &self,
+        queue: &wgpu::Queue,
+        camera_view: ShaderCameraView,
+        settings: &SsaoSettings,
+    ) {
+        let proj = camera_view.proj;
+        let inverse_proj = proj.invert().unwrap();
+        for (config_buffer, blur_direction) in self.config_buffers.iter().zip(PASS_BLUR_DIRECTIONS)
+        {
+            queue.write_buffer(
+                config_buffer,
+                0,
+                bytemuck::cast_slice(&[SsaoConfigUniform {
+                    proj: proj.into(),
+                    inverse_proj: inverse_proj.into(),
+                    radius: settings.radius,
+                    intensity: settings.intensity,
+                    blur_direction,
+                }]),
+            );
+        }
+    }
+
+    // must run after the depth prepass and before the opaque meshes are shaded
+    pub fn render(&self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("SSAO Encoder"),
+        });
+
+        for (label, pipeline, bind_group, target) in [
+            (
+                "SSAO Ambient Occlusion Pass",
+                &self.ambient_occlusion_pipeline,
+                &self.ambient_occlusion_bind_group,
+                &self.ambient_occlusion_texture,
+            ),
+            (
+                "SSAO Horizontal Blur Pass",
+                &self.blur_pipeline,
+                &self.horizontal_blur_bind_group,
+                &self.blur_texture,
+            ),
+            (
+                "SSAO Vertical Blur Pass",
+                &self.blur_pipeline,
+                &self.vertical_blur_bind_group,
+                &self.ambient_occlusion_texture,
+            ),
+        ] {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(label),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    // leaves the output unoccluded, for when ssao is turned off
+    pub fn clear(&self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("SSAO Clear Encoder"),
+        });
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("SSAO Clear Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.ambient_occlusion_texture.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    fn make_textures(device: &wgpu::Device, size: wgpu::Extent3d) -> (Texture, Texture) {
+        let make_texture = |label: &str| {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: AMBIENT_OCCLUSION_TEXTURE_FORMAT,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            });
+            let view = texture.create_view(&Default::default());
+            // everything reads it with textureLoad
+            let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Nearest,
+                min_filter: wgpu::FilterMode::Nearest,
+                ..Default::default()
+            });
+            Texture {
+                texture,
+                view,
+                sampler,
+                size,
+            }
+        };
+        (
+            make_texture("ambient_occlusion_texture"),
+            make_texture("ambient_occlusion_blur_texture"),
+        )
+    }
+
+    fn make_bind_groups(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        config_buffers: &[wgpu::Buffer; 3],
+        depth_texture: &Texture,
+        ambient_occlusion_texture: &Texture,
+        blur_texture: &Texture,
+    ) -> [wgpu::BindGroup; 3] {
+        // the first pass doesn't read an ambient occlusion texture, the blur texture is just there to fill the slot
+        let sources = [blur_texture, ambient_occlusion_texture, blur_texture];
+        let mut bind_groups = config_buffers
+            .iter()
+            .zip(sources)
+            .map(|(config_buffer, source)| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(&source.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: config_buffer.as_entire_binding(),
+                        },
+                    ],
+                    label: Some("ssao_bind_group"),
+                })
+            });
+        [
+            bind_groups.next().unwrap(),
+            bind_groups.next().unwrap(),
+            bind_groups.next().unwrap(),
+        ]
+    }
+}
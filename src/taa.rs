@@ -0,0 +1,363 @@
+use anyhow::Result;
+use cgmath::Vector2;
+use wgpu::util::DeviceExt;
+
+use super::*;
+
+pub const MOTION_VECTOR_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+const HISTORY_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+// the jitter pattern repeats after this many frames
+const JITTER_SEQUENCE_LENGTH: u32 = 8;
+// how much of the current frame goes into the history each frame, lower is smoother but blurrier in motion
+const CURRENT_FRAME_WEIGHT: f32 = 0.1;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TaaConfigUniform {
+    history_valid: u32,
+    current_frame_weight: f32,
+    padding: [f32; 2],
+}
+
+// https://en.wikipedia.org/wiki/Halton_sequence, index starts at 1
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+// temporal anti-aliasing: the projection is jittered by a different sub-pixel offset every frame and
+// the shading texture is blended with the reprojected result of the previous frames, see taa.wgsl
+pub struct Taa {
+    resolve_pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    config_buffer: wgpu::Buffer,
+    // written by the mesh pipeline, in uv units from the previous frame's position to the current one
+    motion_vector_texture: Texture,
+    // ping-ponged, the one at history_index holds the last resolved frame
+    history_textures: [Texture; 2],
+    // bind_groups[i] reads history_textures[i] and is used to write the other one
+    bind_groups: [wgpu::BindGroup; 2],
+    history_index: usize,
+    history_valid: bool,
+    frame_index: u32,
+}
+
+impl Taa {
+    pub fn new(
+        device: &wgpu::Device,
+        shading_texture: &Texture,
+        depth_texture: &Texture,
+    ) -> Result<Self> {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("TAA Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                std::fs::read_to_string("./src/shaders/taa.wgsl")?.into(),
+            ),
+        });
+
+        let texture_entry =
+            |binding: u32, sample_type: wgpu::TextureSampleType| wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type,
+                },
+                count: None,
+            };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0, wgpu::TextureSampleType::Float { filterable: false }),
+                texture_entry(1, wgpu::TextureSampleType::Float { filterable: true }),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                texture_entry(3, wgpu::TextureSampleType::Float { filterable: false }),
+                texture_entry(4, wgpu::TextureSampleType::Depth),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("taa_bind_group_layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("TAA Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let resolve_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("TAA Resolve Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "resolve_fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: HISTORY_TEXTURE_FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let config_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("TAA Config Buffer"),
+            contents: &[0u8; std::mem::size_of::<TaaConfigUniform>()],
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let (motion_vector_texture, history_textures, bind_groups) = Self::make_textures(
+            device,
+            &bind_group_layout,
+            &sampler,
+            &config_buffer,
+            shading_texture,
+            depth_texture,
+        );
+
+        Ok(Self {
+            resolve_pipeline,
+            bind_group_layout,
+            sampler,
+            config_buffer,
+            motion_vector_texture,
+            history_textures,
+            bind_groups,
+            history_index: 0,
+            history_valid: false,
+            frame_index: 0,
+        })
+    }
+
+    // the shading and depth textures get recreated when the window or the render scale changes
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        shading_texture: &Texture,
+        depth_texture: &Texture,
+    ) {
+        let (motion_vector_texture, history_textures, bind_groups) = Self::make_textures(
+            device,
+            &self.bind_group_layout,
+            &self.sampler,
+            &self.config_buffer,
+            shading_texture,
+            depth_texture,
+        );
+        self.motion_vector_texture = motion_vector_texture;
+        self.history_textures = history_textures;
+        self.bind_groups = bind_groups;
+        self.reset_history();
+    }
+
+    // the next frame won't be blended with anything, for when the history was not kept up to date
+    pub fn reset_history(&mut self) {
+        self.history_valid = false;
+    }
+
+    pub fn motion_vector_view(&self) -> &wgpu::TextureView {
+        &self.motion_vector_texture.view
+    }
+
+    // sub-pixel offset for the current frame in ndc, from a halton (2, 3) sequence
+    pub fn jitter(&self) -> Vector2<f32> {
+        let size = self.motion_vector_texture.size;
+        let index = self.frame_index % JITTER_SEQUENCE_LENGTH + 1;
+        Vector2::new(
+            (halton(index, 2) - 0.5) * 2.0 / size.width as f32,
+            (halton(index, 3) - 0.5) * 2.0 / size.height as f32,
+        )
+    }
+
+    // blends the shading texture into the history and copies the result back into the shading texture.
+    // must run after the opaque and transparent meshes and before bloom
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        shading_texture: &Texture,
+    ) {
+        queue.write_buffer(
+            &self.config_buffer,
+            0,
+            bytemuck::cast_slice(&[TaaConfigUniform {
+                history_valid: self.history_valid as u32,
+                current_frame_weight: CURRENT_FRAME_WEIGHT,
+                padding: [0.0; 2],
+            }]),
+        );
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("TAA Encoder"),
+        });
+        let output_index = 1 - self.history_index;
+        let output_texture = &self.history_textures[output_index];
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("TAA Resolve Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &output_texture.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&self.resolve_pipeline);
+            render_pass.set_bind_group(0, &self.bind_groups[self.history_index], &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+        encoder.copy_texture_to_texture(
+            output_texture.texture.as_image_copy(),
+            shading_texture.texture.as_image_copy(),
+            shading_texture.size,
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        self.history_index = output_index;
+        self.history_valid = true;
+        self.frame_index = self.frame_index.wrapping_add(1);
+    }
+
+    fn make_textures(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        config_buffer: &wgpu::Buffer,
+        shading_texture: &Texture,
+        depth_texture: &Texture,
+    ) -> (Texture, [Texture; 2], [wgpu::BindGroup; 2]) {
+        let make_texture = |label: &str, format: wgpu::TextureFormat, usage| {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: shading_texture.size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage,
+            });
+            let view = texture.create_view(&Default::default());
+            let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Nearest,
+                min_filter: wgpu::FilterMode::Nearest,
+                ..Default::default()
+            });
+            Texture {
+                texture,
+                view,
+                sampler,
+                size: shading_texture.size,
+            }
+        };
+        let motion_vector_texture = make_texture(
+            "motion_vector_texture",
+            MOTION_VECTOR_TEXTURE_FORMAT,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        );
+        let history_textures = [0, 1].map(|_| {
+            make_texture(
+                "taa_history_texture",
+                HISTORY_TEXTURE_FORMAT,
+                wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::COPY_SRC,
+            )
+        });
+        let bind_groups = [0, 1].map(|history_index| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&shading_texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(
+                            &history_textures[history_index].view,
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&motion_vector_texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: config_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("taa_bind_group"),
+            })
+        });
+        (motion_vector_texture, history_textures, bind_groups)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halton_sequence() {
+        assert_eq!(
+            (1..=4).map(|index| halton(index, 2)).collect::<Vec<_>>(),
+            vec![0.5, 0.25, 0.75, 0.125]
+        );
+        let base_3: Vec<_> = (1..=3).map(|index| halton(index, 3)).collect();
+        for (value, expected) in base_3.iter().zip([1.0 / 3.0, 2.0 / 3.0, 1.0 / 9.0]) {
+            assert!((value - expected).abs() < 1e-6);
+        }
+    }
+}
@@ -74,6 +74,36 @@ impl Texture {
         )
     }
 
+    // supports radiance hdr, decoded to Rgba16Float
+    pub fn from_encoded_hdr_image(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img_bytes: &[u8],
+        label: &str,
+        generate_mipmaps: bool,
+        sampler_descriptor: &SamplerDescriptor,
+    ) -> Result<Self> {
+        let (img_info, img_data) =
+            stb::image::stbi_loadf_from_memory(img_bytes, stb::image::Channels::RgbAlpha)
+                .ok_or_else(|| anyhow::anyhow!("Failed to decode image: {}", label))?;
+        let img_data_f16: Vec<_> = img_data
+            .into_vec()
+            .iter()
+            .copied()
+            .map(|v| Float16(half::f16::from_f32(v)))
+            .collect();
+        Self::from_decoded_image(
+            device,
+            queue,
+            bytemuck::cast_slice(&img_data_f16),
+            (img_info.width as u32, img_info.height as u32),
+            Some(label),
+            wgpu::TextureFormat::Rgba16Float.into(),
+            generate_mipmaps,
+            sampler_descriptor,
+        )
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn from_decoded_image(
         device: &wgpu::Device,
@@ -1158,6 +1188,53 @@ impl Texture {
             size,
         }
     }
+
+    pub fn from_color_grading_lut(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        lut: &ColorGradingLut,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: lut.size,
+            height: lut.size,
+            depth_or_array_layers: lut.size,
+        };
+
+        // the .cube entry order matches the texel order: red is x, green is y and blue is z
+        let texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("Color Grading Lut"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D3,
+                format: wgpu::TextureFormat::Rgba16Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            },
+            bytemuck::cast_slice(
+                &lut.data
+                    .iter()
+                    .flat_map(|&[r, g, b]| [r, g, b, 1.0])
+                    .map(|v| Float16(half::f16::from_f32(v)))
+                    .collect::<Vec<_>>(),
+            ),
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D3),
+            ..Default::default()
+        });
+
+        let sampler = device.create_sampler(&SamplerDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            sampler,
+            size,
+        }
+    }
 }
 
 fn generate_mipmaps_for_texture(
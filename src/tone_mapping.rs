@@ -0,0 +1,228 @@
+use anyhow::{bail, Result};
+use clap::ArgEnum;
+use serde::Deserialize;
+
+const MAX_COLOR_GRADING_LUT_SIZE: u32 = 256;
+
+// the index of each operator is matched by the switch in tone_map in blit.wgsl
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Deserialize, ArgEnum)]
+pub enum ToneMappingOperator {
+    // 1 - exp(-color)
+    #[default]
+    Exponential,
+    // https://github.com/TheRealMJP/BakingLab/blob/master/BakingLab/ACES.hlsl
+    AcesFitted,
+    Reinhard,
+    // uncharted 2, http://filmicworlds.com/blog/filmic-tonemapping-operators/
+    Hable,
+    // https://iolite-engine.com/blog_posts/minimal_agx_implementation
+    #[clap(name = "agx")]
+    AgX,
+    LinearClamp,
+}
+
+impl ToneMappingOperator {
+    const ALL: [Self; 6] = [
+        Self::Exponential,
+        Self::AcesFitted,
+        Self::Reinhard,
+        Self::Hable,
+        Self::AgX,
+        Self::LinearClamp,
+    ];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self.shader_index() as usize + 1) % Self::ALL.len()]
+    }
+
+    pub fn shader_index(self) -> u32 {
+        Self::ALL
+            .iter()
+            .position(|operator| *operator == self)
+            .unwrap() as u32
+    }
+}
+
+// a 3d lut in the .cube format, see https://resolve.cafe/developers/luts/
+// it's applied to the display encoded (srgb) colors after tone mapping
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorGradingLut {
+    pub size: u32,
+    pub domain_min: [f32; 3],
+    pub domain_max: [f32; 3],
+    // size^3 entries, red changes the fastest and blue the slowest
+    pub data: Vec<[f32; 3]>,
+}
+
+impl ColorGradingLut {
+    pub fn identity(size: u32) -> Self {
+        let max = (size - 1) as f32;
+        let data = (0..size * size * size)
+            .map(|i| {
+                [
+                    (i % size) as f32 / max,
+                    (i / size % size) as f32 / max,
+                    (i / (size * size)) as f32 / max,
+                ]
+            })
+            .collect();
+        Self {
+            size,
+            domain_min: [0.0; 3],
+            domain_max: [1.0; 3],
+            data,
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self> {
+        let cube_file_string = std::fs::read_to_string(path)
+            .map_err(|err| anyhow::anyhow!("Failed to read color grading lut {}: {}", path, err))?;
+        Self::from_cube_str(&cube_file_string)
+            .map_err(|err| anyhow::anyhow!("Failed to parse color grading lut {}: {}", path, err))
+    }
+
+    pub fn from_cube_str(cube_file_string: &str) -> Result<Self> {
+        let parse_floats = |values: &[&str], line_number: usize| -> Result<Vec<f32>> {
+            values
+                .iter()
+                .map(|value| {
+                    value.parse::<f32>().map_err(|_| {
+                        anyhow::anyhow!("Invalid number '{}' on line {}", value, line_number)
+                    })
+                })
+                .collect()
+        };
+        let parse_vector = |values: &[&str], line_number: usize| -> Result<[f32; 3]> {
+            match parse_floats(values, line_number)?[..] {
+                [r, g, b] => Ok([r, g, b]),
+                _ => bail!("Expected 3 numbers on line {}", line_number),
+            }
+        };
+
+        let mut size: Option<u32> = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut data: Vec<[f32; 3]> = Vec::new();
+        for (line_index, line) in cube_file_string.lines().enumerate() {
+            let line_number = line_index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[0] {
+                "LUT_3D_SIZE" => {
+                    let parsed_size = words
+                        .get(1)
+                        .and_then(|size| size.parse::<u32>().ok())
+                        .filter(|size| (2..=MAX_COLOR_GRADING_LUT_SIZE).contains(size))
+                        .ok_or_else(|| {
+                            anyhow::anyhow!(
+                                "LUT_3D_SIZE must be between 2 and {} on line {}",
+                                MAX_COLOR_GRADING_LUT_SIZE,
+                                line_number
+                            )
+                        })?;
+                    size = Some(parsed_size);
+                }
+                "LUT_1D_SIZE" => {
+                    bail!("1D luts aren't supported");
+                }
+                "DOMAIN_MIN" => {
+                    domain_min = parse_vector(&words[1..], line_number)?;
+                }
+                "DOMAIN_MAX" => {
+                    domain_max = parse_vector(&words[1..], line_number)?;
+                }
+                "LUT_3D_INPUT_RANGE" => match parse_floats(&words[1..], line_number)?[..] {
+                    [min, max] => {
+                        domain_min = [min; 3];
+                        domain_max = [max; 3];
+                    }
+                    _ => bail!("Expected 2 numbers on line {}", line_number),
+                },
+                keyword if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                    // TITLE and vendor specific keywords don't affect the lut
+                }
+                _ => {
+                    data.push(parse_vector(&words, line_number)?);
+                }
+            }
+        }
+
+        let size = match size {
+            Some(size) => size,
+            None => bail!("Missing LUT_3D_SIZE"),
+        };
+        let expected_entry_count = (size * size * size) as usize;
+        if data.len() != expected_entry_count {
+            bail!(
+                "Expected {} entries for a lut of size {}, found {}",
+                expected_entry_count,
+                size,
+                data.len()
+            );
+        }
+        if (0..3).any(|i| domain_min[i] >= domain_max[i]) {
+            bail!("DOMAIN_MIN must be less than DOMAIN_MAX");
+        }
+        Ok(Self {
+            size,
+            domain_min,
+            domain_max,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_cube_lut() {
+        let lut = ColorGradingLut::from_cube_str(
+            "# inverts the colors
+            TITLE \"invert\"
+            LUT_3D_SIZE 2
+            DOMAIN_MIN 0.0 0.0 0.0
+            DOMAIN_MAX 1.0 2.0 1.0
+
+            1 1 1
+            0 1 1
+            1 0 1
+            0 0 1
+            1 1 0
+            0 1 0
+            1 0 0
+            0 0 0",
+        )
+        .unwrap();
+        assert_eq!(lut.size, 2);
+        assert_eq!(lut.domain_min, [0.0, 0.0, 0.0]);
+        assert_eq!(lut.domain_max, [1.0, 2.0, 1.0]);
+        assert_eq!(lut.data[1], [0.0, 1.0, 1.0]);
+        assert_eq!(lut.data[6], [1.0, 0.0, 0.0]);
+
+        let identity = ColorGradingLut::identity(3);
+        assert_eq!(identity.data.len(), 27);
+        assert_eq!(identity.data[1], [0.5, 0.0, 0.0]);
+        assert_eq!(identity.data[3], [0.0, 0.5, 0.0]);
+        assert_eq!(identity.data[26], [1.0, 1.0, 1.0]);
+
+        assert!(ColorGradingLut::from_cube_str("0 0 0").is_err());
+        assert!(ColorGradingLut::from_cube_str("LUT_3D_SIZE 2\n0 0 0\n1 1 1").is_err());
+        assert!(ColorGradingLut::from_cube_str("LUT_1D_SIZE 2\n0 0 0\n1 1 1").is_err());
+        assert!(ColorGradingLut::from_cube_str("LUT_3D_SIZE 2\n0 0 x").is_err());
+    }
+
+    #[test]
+    fn cycle_tone_mapping_operators() {
+        let mut operator = ToneMappingOperator::default();
+        for _ in 0..ToneMappingOperator::ALL.len() - 1 {
+            operator = operator.next();
+            assert_ne!(operator, ToneMappingOperator::default());
+        }
+        assert_eq!(operator.next(), ToneMappingOperator::default());
+    }
+}
@@ -0,0 +1,581 @@
+use anyhow::Result;
+use cgmath::{Vector2, Vector4};
+
+use super::*;
+
+pub const UI_FONT_GLYPH_WIDTH: u32 = 5;
+pub const UI_FONT_GLYPH_HEIGHT: u32 = 7;
+// horizontal and vertical space between glyphs, in font pixels
+const UI_FONT_GLYPH_SPACING: u32 = 1;
+const UI_FONT_LINE_SPACING: u32 = 3;
+
+// the glyphs are laid out in a grid inside the atlas with a pixel of padding around each one
+const FONT_ATLAS_COLUMNS: u32 = 16;
+const FONT_ATLAS_CELL_WIDTH: u32 = UI_FONT_GLYPH_WIDTH + 2;
+const FONT_ATLAS_CELL_HEIGHT: u32 = UI_FONT_GLYPH_HEIGHT + 2;
+
+// 5x7 bitmap font, lowercase letters are drawn as uppercase ones.
+// the cell after the last glyph is filled in completely and is used for drawing rectangles
+#[rustfmt::skip]
+const FONT_GLYPHS: &[(char, [&str; 7])] = &[
+    (' ', [".....", ".....", ".....", ".....", ".....", ".....", "....."]),
+    ('A', [".###.", "#...#", "#...#", "#####", "#...#", "#...#", "#...#"]),
+    ('B', ["####.", "#...#", "#...#", "####.", "#...#", "#...#", "####."]),
+    ('C', [".###.", "#...#", "#....", "#....", "#....", "#...#", ".###."]),
+    ('D', ["####.", "#...#", "#...#", "#...#", "#...#", "#...#", "####."]),
+    ('E', ["#####", "#....", "#....", "####.", "#....", "#....", "#####"]),
+    ('F', ["#####", "#....", "#....", "####.", "#....", "#....", "#...."]),
+    ('G', [".###.", "#...#", "#....", "#.###", "#...#", "#...#", ".####"]),
+    ('H', ["#...#", "#...#", "#...#", "#####", "#...#", "#...#", "#...#"]),
+    ('I', [".###.", "..#..", "..#..", "..#..", "..#..", "..#..", ".###."]),
+    ('J', ["..###", "...#.", "...#.", "...#.", "...#.", "#..#.", ".##.."]),
+    ('K', ["#...#", "#..#.", "#.#..", "##...", "#.#..", "#..#.", "#...#"]),
+    ('L', ["#....", "#....", "#....", "#....", "#....", "#....", "#####"]),
+    ('M', ["#...#", "##.##", "#.#.#", "#.#.#", "#...#", "#...#", "#...#"]),
+    ('N', ["#...#", "#...#", "##..#", "#.#.#", "#..##", "#...#", "#...#"]),
+    ('O', [".###.", "#...#", "#...#", "#...#", "#...#", "#...#", ".###."]),
+    ('P', ["####.", "#...#", "#...#", "####.", "#....", "#....", "#...."]),
+    ('Q', [".###.", "#...#", "#...#", "#...#", "#.#.#", "#..#.", ".##.#"]),
+    ('R', ["####.", "#...#", "#...#", "####.", "#.#..", "#..#.", "#...#"]),
+    ('S', [".####", "#....", "#....", ".###.", "....#", "....#", "####."]),
+    ('T', ["#####", "..#..", "..#..", "..#..", "..#..", "..#..", "..#.."]),
+    ('U', ["#...#", "#...#", "#...#", "#...#", "#...#", "#...#", ".###."]),
+    ('V', ["#...#", "#...#", "#...#", "#...#", "#...#", ".#.#.", "..#.."]),
+    ('W', ["#...#", "#...#", "#...#", "#.#.#", "#.#.#", "#.#.#", ".#.#."]),
+    ('X', ["#...#", "#...#", ".#.#.", "..#..", ".#.#.", "#...#", "#...#"]),
+    ('Y', ["#...#", "#...#", ".#.#.", "..#..", "..#..", "..#..", "..#.."]),
+    ('Z', ["#####", "....#", "...#.", "..#..", ".#...", "#....", "#####"]),
+    ('0', [".###.", "#...#", "#..##", "#.#.#", "##..#", "#...#", ".###."]),
+    ('1', ["..#..", ".##..", "..#..", "..#..", "..#..", "..#..", ".###."]),
+    ('2', [".###.", "#...#", "....#", "...#.", "..#..", ".#...", "#####"]),
+    ('3', ["#####", "...#.", "..#..", "...#.", "....#", "#...#", ".###."]),
+    ('4', ["...#.", "..##.", ".#.#.", "#..#.", "#####", "...#.", "...#."]),
+    ('5', ["#####", "#....", "####.", "....#", "....#", "#...#", ".###."]),
+    ('6', ["..##.", ".#...", "#....", "####.", "#...#", "#...#", ".###."]),
+    ('7', ["#####", "....#", "...#.", "..#..", ".#...", ".#...", ".#..."]),
+    ('8', [".###.", "#...#", "#...#", ".###.", "#...#", "#...#", ".###."]),
+    ('9', [".###.", "#...#", "#...#", ".####", "....#", "...#.", ".##.."]),
+    ('.', [".....", ".....", ".....", ".....", ".....", ".##..", ".##.."]),
+    (',', [".....", ".....", ".....", ".....", ".##..", "..#..", ".#..."]),
+    (':', [".....", ".##..", ".##..", ".....", ".##..", ".##..", "....."]),
+    (';', [".....", ".##..", ".##..", ".....", ".##..", "..#..", ".#..."]),
+    ('!', ["..#..", "..#..", "..#..", "..#..", "..#..", ".....", "..#.."]),
+    ('?', [".###.", "#...#", "....#", "...#.", "..#..", ".....", "..#.."]),
+    ('-', [".....", ".....", ".....", "#####", ".....", ".....", "....."]),
+    ('+', [".....", "..#..", "..#..", "#####", "..#..", "..#..", "....."]),
+    ('=', [".....", ".....", "#####", ".....", "#####", ".....", "....."]),
+    ('_', [".....", ".....", ".....", ".....", ".....", ".....", "#####"]),
+    ('/', [".....", "....#", "...#.", "..#..", ".#...", "#....", "....."]),
+    ('%', ["##...", "##..#", "...#.", "..#..", ".#...", "#..##", "...##"]),
+    ('(', ["...#.", "..#..", ".#...", ".#...", ".#...", "..#..", "...#."]),
+    (')', [".#...", "..#..", "...#.", "...#.", "...#.", "..#..", ".#..."]),
+    ('<', ["...#.", "..#..", ".#...", "#....", ".#...", "..#..", "...#."]),
+    ('>', [".#...", "..#..", "...#.", "....#", "...#.", "..#..", ".#..."]),
+    ('\'', ["..#..", "..#..", ".#...", ".....", ".....", ".....", "....."]),
+    ('"', [".#.#.", ".#.#.", ".....", ".....", ".....", ".....", "....."]),
+    ('#', [".#.#.", ".#.#.", "#####", ".#.#.", "#####", ".#.#.", ".#.#."]),
+    ('*', [".....", "..#..", "#.#.#", ".###.", "#.#.#", "..#..", "....."]),
+];
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct UiVertex {
+    position: [f32; 2],
+    tex_coords: [f32; 2],
+    color: [f32; 4],
+}
+
+impl UiVertex {
+    const ATTRIBS: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+        0 => Float32x2,
+        1 => Float32x2,
+        2 => Float32x4,
+    ];
+
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<UiVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+// returned by UiRenderer::add_image
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct UiImageId(usize);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum UiQuadTexture {
+    FontAtlas,
+    Image(UiImageId),
+}
+
+#[derive(Copy, Clone, Debug)]
+struct UiQuad {
+    min: Vector2<f32>,
+    max: Vector2<f32>,
+    tex_coords_min: Vector2<f32>,
+    tex_coords_max: Vector2<f32>,
+    color: Vector4<f32>,
+    texture: UiQuadTexture,
+}
+
+// everything that is drawn on top of the frame, rebuilt every frame.
+// positions and sizes are in physical pixels with the origin at the top left of the window,
+// colors are linear, not srgb. later draws go on top of earlier ones
+#[derive(Clone, Debug, Default)]
+pub struct UiDrawList {
+    quads: Vec<UiQuad>,
+}
+
+impl UiDrawList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rect(&mut self, position: Vector2<f32>, size: Vector2<f32>, color: Vector4<f32>) {
+        // sample the middle of the solid cell so the filtering never reaches the padding
+        let solid_cell_center = font_atlas_cell_center(FONT_GLYPHS.len());
+        self.quads.push(UiQuad {
+            min: position,
+            max: position + size,
+            tex_coords_min: solid_cell_center,
+            tex_coords_max: solid_cell_center,
+            color,
+            texture: UiQuadTexture::FontAtlas,
+        });
+    }
+
+    // scale is the size of a font pixel in physical pixels, supports multiple lines
+    pub fn text(&mut self, position: Vector2<f32>, scale: f32, color: Vector4<f32>, text: &str) {
+        let glyph_size = Vector2::new(
+            UI_FONT_GLYPH_WIDTH as f32 * scale,
+            UI_FONT_GLYPH_HEIGHT as f32 * scale,
+        );
+        for (line_index, line) in text.lines().enumerate() {
+            let line_y = position.y
+                + line_index as f32 * (UI_FONT_GLYPH_HEIGHT + UI_FONT_LINE_SPACING) as f32 * scale;
+            for (char_index, character) in line.chars().enumerate() {
+                let glyph_index = match find_glyph_index(character) {
+                    Some(glyph_index) => glyph_index,
+                    None => find_glyph_index('?').unwrap(),
+                };
+                if FONT_GLYPHS[glyph_index].0 == ' ' {
+                    continue;
+                }
+                let glyph_position = Vector2::new(
+                    position.x
+                        + char_index as f32
+                            * (UI_FONT_GLYPH_WIDTH + UI_FONT_GLYPH_SPACING) as f32
+                            * scale,
+                    line_y,
+                );
+                let (tex_coords_min, tex_coords_max) = font_atlas_glyph_tex_coords(glyph_index);
+                self.quads.push(UiQuad {
+                    min: glyph_position,
+                    max: glyph_position + glyph_size,
+                    tex_coords_min,
+                    tex_coords_max,
+                    color,
+                    texture: UiQuadTexture::FontAtlas,
+                });
+            }
+        }
+    }
+
+    pub fn image(
+        &mut self,
+        position: Vector2<f32>,
+        size: Vector2<f32>,
+        image: UiImageId,
+        tint: Vector4<f32>,
+    ) {
+        self.quads.push(UiQuad {
+            min: position,
+            max: position + size,
+            tex_coords_min: Vector2::new(0.0, 0.0),
+            tex_coords_max: Vector2::new(1.0, 1.0),
+            color: tint,
+            texture: UiQuadTexture::Image(image),
+        });
+    }
+
+    // size in physical pixels of the area covered by the text, useful for centering
+    pub fn text_size(text: &str, scale: f32) -> Vector2<f32> {
+        let line_count = text.lines().count() as u32;
+        let longest_line = text
+            .lines()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0) as u32;
+        if line_count == 0 || longest_line == 0 {
+            return Vector2::new(0.0, 0.0);
+        }
+        Vector2::new(
+            (longest_line * (UI_FONT_GLYPH_WIDTH + UI_FONT_GLYPH_SPACING) - UI_FONT_GLYPH_SPACING)
+                as f32
+                * scale,
+            (line_count * (UI_FONT_GLYPH_HEIGHT + UI_FONT_LINE_SPACING) - UI_FONT_LINE_SPACING)
+                as f32
+                * scale,
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.quads.is_empty()
+    }
+}
+
+fn find_glyph_index(character: char) -> Option<usize> {
+    let character = character.to_ascii_uppercase();
+    FONT_GLYPHS
+        .iter()
+        .position(|(glyph_character, _)| *glyph_character == character)
+}
+
+fn font_atlas_size() -> (u32, u32) {
+    // +1 for the solid cell
+    let cell_count = FONT_GLYPHS.len() as u32 + 1;
+    let rows = (cell_count + FONT_ATLAS_COLUMNS - 1) / FONT_ATLAS_COLUMNS;
+    (
+        FONT_ATLAS_COLUMNS * FONT_ATLAS_CELL_WIDTH,
+        rows * FONT_ATLAS_CELL_HEIGHT,
+    )
+}
+
+// top left pixel of the glyph inside of the atlas, skipping the padding
+fn font_atlas_glyph_origin(cell_index: usize) -> (u32, u32) {
+    let cell_index = cell_index as u32;
+    (
+        (cell_index % FONT_ATLAS_COLUMNS) * FONT_ATLAS_CELL_WIDTH + 1,
+        (cell_index / FONT_ATLAS_COLUMNS) * FONT_ATLAS_CELL_HEIGHT + 1,
+    )
+}
+
+fn font_atlas_glyph_tex_coords(cell_index: usize) -> (Vector2<f32>, Vector2<f32>) {
+    let (atlas_width, atlas_height) = font_atlas_size();
+    let (x, y) = font_atlas_glyph_origin(cell_index);
+    (
+        Vector2::new(
+            x as f32 / atlas_width as f32,
+            y as f32 / atlas_height as f32,
+        ),
+        Vector2::new(
+            (x + UI_FONT_GLYPH_WIDTH) as f32 / atlas_width as f32,
+            (y + UI_FONT_GLYPH_HEIGHT) as f32 / atlas_height as f32,
+        ),
+    )
+}
+
+fn font_atlas_cell_center(cell_index: usize) -> Vector2<f32> {
+    let (tex_coords_min, tex_coords_max) = font_atlas_glyph_tex_coords(cell_index);
+    (tex_coords_min + tex_coords_max) / 2.0
+}
+
+fn make_font_atlas_image() -> image::RgbaImage {
+    let (atlas_width, atlas_height) = font_atlas_size();
+    let mut img = image::RgbaImage::new(atlas_width, atlas_height);
+    let solid_glyph_rows = ["#####"; UI_FONT_GLYPH_HEIGHT as usize];
+    let glyph_rows = FONT_GLYPHS
+        .iter()
+        .map(|(_, rows)| rows)
+        .chain(std::iter::once(&solid_glyph_rows));
+    for (cell_index, rows) in glyph_rows.enumerate() {
+        let (origin_x, origin_y) = font_atlas_glyph_origin(cell_index);
+        for (row_index, row) in rows.iter().enumerate() {
+            for (column_index, pixel) in row.chars().enumerate() {
+                if pixel == '#' {
+                    img.put_pixel(
+                        origin_x + column_index as u32,
+                        origin_y + row_index as u32,
+                        [255, 255, 255, 255].into(),
+                    );
+                }
+            }
+        }
+    }
+    img
+}
+
+pub struct UiRenderer {
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: GpuBuffer,
+    font_atlas_bind_group: wgpu::BindGroup,
+    // indexed by UiImageId
+    image_bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl UiRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        target_format: wgpu::TextureFormat,
+        single_texture_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Result<Self> {
+        let ui_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("UI Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                std::fs::read_to_string("./src/shaders/ui.wgsl")?.into(),
+            ),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("UI Pipeline Layout"),
+            bind_group_layouts: &[single_texture_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("UI Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &ui_shader,
+                entry_point: "vs_main",
+                buffers: &[UiVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &ui_shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let font_atlas_image = make_font_atlas_image();
+        let font_atlas_texture = Texture::from_decoded_image(
+            device,
+            queue,
+            &font_atlas_image,
+            font_atlas_image.dimensions(),
+            Some("ui_font_atlas_texture"),
+            wgpu::TextureFormat::Rgba8Unorm.into(),
+            false,
+            &texture::SamplerDescriptor(wgpu::SamplerDescriptor {
+                mag_filter: wgpu::FilterMode::Nearest,
+                min_filter: wgpu::FilterMode::Nearest,
+                ..texture::SamplerDescriptor::default().0
+            }),
+        )?;
+        let font_atlas_bind_group = Self::make_texture_bind_group(
+            device,
+            &font_atlas_texture,
+            single_texture_bind_group_layout,
+        );
+
+        let vertex_buffer = GpuBuffer::empty(
+            device,
+            1024,
+            std::mem::size_of::<UiVertex>(),
+            wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        );
+
+        Ok(Self {
+            pipeline,
+            vertex_buffer,
+            font_atlas_bind_group,
+            image_bind_groups: Vec::new(),
+        })
+    }
+
+    fn make_texture_bind_group(
+        device: &wgpu::Device,
+        texture: &Texture,
+        single_texture_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: single_texture_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: Some("ui_texture_bind_group"),
+        })
+    }
+
+    pub fn add_image(
+        &mut self,
+        device: &wgpu::Device,
+        texture: &Texture,
+        single_texture_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> UiImageId {
+        self.image_bind_groups.push(Self::make_texture_bind_group(
+            device,
+            texture,
+            single_texture_bind_group_layout,
+        ));
+        UiImageId(self.image_bind_groups.len() - 1)
+    }
+
+    // draws on top of whatever is already in the view
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        view: &wgpu::TextureView,
+        (target_width, target_height): (u32, u32),
+        draw_list: &UiDrawList,
+    ) {
+        if draw_list.is_empty() || target_width == 0 || target_height == 0 {
+            return;
+        }
+
+        let to_clip_space = |position: Vector2<f32>| {
+            [
+                position.x / target_width as f32 * 2.0 - 1.0,
+                1.0 - position.y / target_height as f32 * 2.0,
+            ]
+        };
+        let vertices: Vec<UiVertex> = draw_list
+            .quads
+            .iter()
+            .flat_map(|quad| {
+                let corner = |x_max: bool, y_max: bool| UiVertex {
+                    position: to_clip_space(Vector2::new(
+                        if x_max { quad.max.x } else { quad.min.x },
+                        if y_max { quad.max.y } else { quad.min.y },
+                    )),
+                    tex_coords: [
+                        if x_max {
+                            quad.tex_coords_max.x
+                        } else {
+                            quad.tex_coords_min.x
+                        },
+                        if y_max {
+                            quad.tex_coords_max.y
+                        } else {
+                            quad.tex_coords_min.y
+                        },
+                    ],
+                    color: quad.color.into(),
+                };
+                [
+                    corner(false, false),
+                    corner(false, true),
+                    corner(true, true),
+                    corner(false, false),
+                    corner(true, true),
+                    corner(true, false),
+                ]
+            })
+            .collect();
+        self.vertex_buffer
+            .write(device, queue, bytemuck::cast_slice(&vertices));
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("UI Encoder"),
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("UI Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_vertex_buffer(0, self.vertex_buffer.src().slice(..));
+
+            // one draw per run of quads that share a texture
+            let mut run_start = 0;
+            while run_start < draw_list.quads.len() {
+                let texture = draw_list.quads[run_start].texture;
+                let run_length = draw_list.quads[run_start..]
+                    .iter()
+                    .take_while(|quad| quad.texture == texture)
+                    .count();
+                let bind_group = match texture {
+                    UiQuadTexture::FontAtlas => Some(&self.font_atlas_bind_group),
+                    UiQuadTexture::Image(UiImageId(image_index)) => {
+                        self.image_bind_groups.get(image_index)
+                    }
+                };
+                if let Some(bind_group) = bind_group {
+                    render_pass.set_bind_group(0, bind_group, &[]);
+                    render_pass.draw(
+                        (run_start as u32 * 6)..((run_start + run_length) as u32 * 6),
+                        0..1,
+                    );
+                }
+                run_start += run_length;
+            }
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn font_glyphs_are_well_formed() {
+        for (character, rows) in FONT_GLYPHS {
+            for row in rows {
+                assert_eq!(
+                    row.chars().count(),
+                    UI_FONT_GLYPH_WIDTH as usize,
+                    "glyph {:?} has a row with the wrong width",
+                    character
+                );
+                assert!(row.chars().all(|pixel| pixel == '#' || pixel == '.'));
+            }
+            assert_eq!(
+                FONT_GLYPHS
+                    .iter()
+                    .filter(|(other_character, _)| other_character == character)
+                    .count(),
+                1,
+                "glyph {:?} is defined more than once",
+                character
+            );
+        }
+    }
+
+    #[test]
+    fn text_size() {
+        assert_eq!(UiDrawList::text_size("", 2.0), Vector2::new(0.0, 0.0));
+        assert_eq!(UiDrawList::text_size("AB", 2.0), Vector2::new(22.0, 14.0));
+        assert_eq!(
+            UiDrawList::text_size("ABC\nA", 1.0),
+            Vector2::new(17.0, 17.0)
+        );
+    }
+
+    #[test]
+    fn spaces_and_unknown_characters() {
+        let mut draw_list = UiDrawList::new();
+        draw_list.text(
+            Vector2::new(0.0, 0.0),
+            1.0,
+            Vector4::new(1.0, 1.0, 1.0, 1.0),
+            "a b\u{e9}",
+        );
+        // the space is skipped and the unknown character falls back to a question mark
+        assert_eq!(draw_list.quads.len(), 3);
+        assert_eq!(
+            draw_list.quads[2].tex_coords_min,
+            font_atlas_glyph_tex_coords(find_glyph_index('?').unwrap()).0
+        );
+    }
+}